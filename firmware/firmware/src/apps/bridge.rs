@@ -0,0 +1,215 @@
+//! Man-in-the-middle bridge: messages decoded on the Rx port, passed through a filter and sent
+//! again on the Tx port, with the Trx-Rx tie open so that both sides are cut apart.
+//!
+//! While started, the receive task hands every CAN message to [`forward_can`] and every NMEA 0183
+//! sentence to [`forward_nmea0183`]. They are queued with the time they were received for
+//! `bridge::run`, which calls the Rhai filter on each and sends the result. Messages coming in
+//! while the queue is full are dropped and counted, the filter not keeping up. Results the filter
+//! delays are [`Held`] until due, while later messages keep going through.
+
+use crate::apps::rx::{can::Message, nmea0183};
+use alloc::{format, string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use defmt::Format;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Instant;
+
+/// Received messages waiting for the filter.
+pub const RX_QUEUE: usize = 32;
+
+/// Most messages held back by the filter at once. Past that, received messages wait in the queue.
+pub const MAX_HELD: usize = 256;
+
+static RX_MESSAGES: Channel<CriticalSectionRawMutex, (Instant, BridgeMessage), RX_QUEUE> =
+    Channel::new();
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+/// Messages dropped on a full queue since the bridge started.
+static OVERFLOW: AtomicU32 = AtomicU32::new(0);
+
+/// Starts or stops queueing received messages, dropping those still queued.
+pub fn set_started(started: bool) {
+    STARTED.store(started, Ordering::Relaxed);
+    RX_MESSAGES.clear();
+    OVERFLOW.store(0, Ordering::Relaxed);
+}
+
+/// Hands a received CAN message to the bridge, while started.
+pub fn forward_can(msg: &Message) {
+    forward(BridgeMessage::Can(*msg));
+}
+
+/// Hands a received NMEA 0183 sentence to the bridge, while started.
+pub fn forward_nmea0183(sof: u8, sentence: &str) {
+    forward(BridgeMessage::Nmea0183(sof, String::from(sentence)));
+}
+
+fn forward(message: BridgeMessage) {
+    if STARTED.load(Ordering::Relaxed) && RX_MESSAGES.try_send((Instant::now(), message)).is_err() {
+        OVERFLOW.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The oldest queued message and when it was received, if any.
+pub fn receive() -> Option<(Instant, BridgeMessage)> {
+    RX_MESSAGES.try_receive().ok()
+}
+
+/// Messages dropped on a full queue since the bridge started.
+pub fn overflow() -> u32 {
+    OVERFLOW.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Format, Clone, PartialEq, Eq)]
+pub enum BridgeMessage {
+    Can(Message),
+    /// The start of the sentence (`$` or `!`), and the sentence up to the checksum.
+    Nmea0183(u8, String),
+}
+
+/// The line of an NMEA 0183 sentence, with its checksum.
+pub fn nmea0183_line(sof: u8, sentence: &str) -> Vec<u8> {
+    let chksum = nmea0183::checksum(sentence);
+    format!("{}{}*{:02X}\r\n", sof as char, sentence, chksum).into_bytes()
+}
+
+/// The bits of bytes sent 8N1 from the least significant one, `true` for mark.
+pub fn uart_bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes.iter().flat_map(|&byte| {
+        core::iter::once(false)
+            .chain((0..8).map(move |i| byte >> i & 1 == 1))
+            .chain(core::iter::once(true))
+    })
+}
+
+/// Messages held back until they are due, in the order they fall due.
+pub struct Held<T> {
+    entries: Vec<(Instant, T)>,
+}
+
+impl<T> Held<T> {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Holds `item` back until `due`, after those already due by then.
+    pub fn push(&mut self, due: Instant, item: T) {
+        let index = self.entries.partition_point(|(other, _)| *other <= due);
+        self.entries.insert(index, (due, item));
+    }
+
+    /// The first item due at `now`, if any.
+    pub fn pop_due(&mut self, now: Instant) -> Option<T> {
+        match self.entries.first() {
+            Some((due, _)) if *due <= now => Some(self.entries.remove(0).1),
+            _ => None,
+        }
+    }
+}
+
+/// What went through the bridge. Latencies are from the end of a received message to the end
+/// of its forwarded one, in µs, and include the delays asked by the filter.
+#[derive(Debug, Format, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BridgeReport {
+    pub received: u32,
+    pub forwarded: u32,
+    /// Dropped by the filter.
+    pub dropped: u32,
+    /// Forwarded after the filter changed them.
+    pub rewritten: u32,
+    /// Dropped on a full queue.
+    pub overflow: u32,
+    /// Forwarded, but not sent by the transmitter.
+    pub failed: u32,
+    /// Still held back by the filter when the bridge stopped, never sent.
+    pub held: u32,
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+}
+
+impl BridgeReport {
+    /// Records a message handed to the transmitter, done `latency` µs after it was received.
+    pub fn record(&mut self, latency: u64, sent: bool) {
+        if self.forwarded == 0 {
+            self.min = latency;
+        }
+
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+        self.total += latency;
+        self.forwarded += 1;
+
+        if !sent {
+            self.failed += 1;
+        }
+    }
+
+    pub fn mean(&self) -> u64 {
+        self.total / self.forwarded.max(1) as u64
+    }
+}
+
+mod test {
+    #[test]
+    fn test_encoding() {
+        use super::{nmea0183_line, uart_bits};
+        use alloc::vec::Vec;
+
+        assert_eq!(
+            nmea0183_line(b'$', "IIDPT,1.5,0.0"),
+            b"$IIDPT,1.5,0.0*44\r\n"
+        );
+
+        let bits = uart_bits(&[0x41, 0xFF]).collect::<Vec<_>>();
+        assert_eq!(bits.len(), 20);
+        assert_eq!(
+            bits[..10],
+            [false, true, false, false, false, false, false, true, false, true]
+        );
+        assert!(!bits[10] && bits[11..].iter().all(|&bit| bit));
+    }
+
+    #[test]
+    fn test_report() {
+        use super::BridgeReport;
+
+        let mut report = BridgeReport::default();
+        report.record(300, true);
+        report.record(100, false);
+        report.record(200, true);
+
+        assert_eq!((report.forwarded, report.failed), (3, 1));
+        assert_eq!((report.min, report.max, report.mean()), (100, 300, 200));
+    }
+
+    #[test]
+    fn test_held() {
+        use super::Held;
+        use embassy_time::Instant;
+
+        let mut held = Held::new();
+        held.push(Instant::from_millis(20), 'a');
+        held.push(Instant::from_millis(10), 'b');
+        held.push(Instant::from_millis(20), 'c');
+
+        assert_eq!(held.pop_due(Instant::from_millis(5)), None);
+        assert_eq!(held.pop_due(Instant::from_millis(15)), Some('b'));
+        assert_eq!(held.pop_due(Instant::from_millis(15)), None);
+
+        // Same due time, in the order they were held.
+        assert_eq!(held.pop_due(Instant::from_millis(30)), Some('a'));
+        assert_eq!(held.pop_due(Instant::from_millis(30)), Some('c'));
+        assert!(held.is_empty());
+    }
+}
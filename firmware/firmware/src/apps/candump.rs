@@ -0,0 +1,231 @@
+//! The `can-utils` candump log format: one frame per line, as
+//! `(seconds.micros) interface id#data`, for instance `(1436509052.249713) can0 123#DEADBEEF`.
+//!
+//! Standard identifiers are written with 3 hex digits and extended ones with 8, remote frames
+//! as `id#R` followed by their DLC, if not 0.
+
+use crate::apps::rx::can::Message;
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use defmt::Format;
+use embassy_time::Instant;
+
+/// Interface name written to the log.
+pub const INTERFACE: &str = "can0";
+
+/// Largest log kept in memory, which is much larger in PSRAM.
+#[cfg(feature = "heap-in-psram")]
+pub const MAX_LOG: usize = 4 * 1024 * 1024;
+#[cfg(not(feature = "heap-in-psram"))]
+pub const MAX_LOG: usize = 64 * 1024;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum CandumpError {
+    /// The given (1-based) line is not a candump frame.
+    InvalidLine(usize),
+}
+
+impl core::fmt::Display for CandumpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl core::error::Error for CandumpError {}
+
+/// A frame read from a log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Microseconds, from the log's epoch.
+    pub timestamp: u64,
+    pub arb_id: u32,
+    pub extended: bool,
+    pub rtr: bool,
+    /// The payload length, or the requested length for remote frames.
+    pub dlc: u8,
+    pub data: Vec<u8>,
+}
+
+/// Appends the log line of a received message.
+pub fn write_message(log: &mut String, time: Instant, msg: &Message) {
+    write_line(
+        log,
+        time.as_micros(),
+        msg.arb_id(),
+        msg.is_extended(),
+        msg.is_rtr(),
+        msg.dlc(),
+        msg.data(),
+    );
+}
+
+/// Appends the log line of a frame.
+pub fn write_frame(log: &mut String, frame: &Frame) {
+    write_line(
+        log,
+        frame.timestamp,
+        frame.arb_id,
+        frame.extended,
+        frame.rtr,
+        frame.dlc,
+        &frame.data,
+    );
+}
+
+fn write_line(
+    log: &mut String,
+    micros: u64,
+    arb_id: u32,
+    extended: bool,
+    rtr: bool,
+    dlc: u8,
+    data: &[u8],
+) {
+    let _ = write!(
+        log,
+        "({}.{:06}) {} ",
+        micros / 1_000_000,
+        micros % 1_000_000,
+        INTERFACE
+    );
+
+    if extended {
+        let _ = write!(log, "{:08X}#", arb_id);
+    } else {
+        let _ = write!(log, "{:03X}#", arb_id);
+    }
+
+    if rtr {
+        log.push('R');
+
+        if dlc > 0 {
+            let _ = write!(log, "{:X}", dlc.min(8));
+        }
+    }
+
+    for byte in data {
+        let _ = write!(log, "{:02X}", byte);
+    }
+
+    log.push('\n');
+}
+
+/// Parses a log, skipping blank lines.
+pub fn parse_log(log: &str) -> Result<Vec<Frame>, CandumpError> {
+    log.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| parse_line(line).ok_or(CandumpError::InvalidLine(i + 1)))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Frame> {
+    let mut fields = line.split_whitespace();
+    let timestamp = fields.next()?.strip_prefix('(')?.strip_suffix(')')?;
+    let _interface = fields.next()?;
+    let (id, data) = fields.next()?.split_once('#')?;
+
+    let (secs, fraction) = timestamp.split_once('.')?;
+    // Scale the fraction to microseconds, whatever its precision.
+    let fraction = fraction.get(..fraction.len().min(6))?;
+    let micros = fraction.parse::<u64>().ok()? * 10_u64.pow(6 - fraction.len() as u32);
+    let timestamp = secs
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(1_000_000)?
+        .checked_add(micros)?;
+
+    if id.is_empty() || id.len() > 8 {
+        return None;
+    }
+
+    // As in `can-utils`, identifiers of more than 3 digits are extended, whatever their value.
+    let extended = id.len() > 3;
+    let arb_id = u32::from_str_radix(id, 16).ok()?;
+
+    if arb_id > 0x1FFF_FFFF || (!extended && arb_id > 0x7FF) {
+        return None;
+    }
+
+    let (rtr, dlc, data) = match data.strip_prefix('R') {
+        Some("") => (true, 0, Vec::new()),
+        Some(dlc) => (true, u8::from_str_radix(dlc, 16).ok()?, Vec::new()),
+        None => {
+            let data = parse_hex(data)?;
+            (false, data.len() as u8, data)
+        }
+    };
+
+    if dlc > 8 || data.len() > 8 {
+        return None;
+    }
+
+    Some(Frame {
+        timestamp,
+        arb_id,
+        extended,
+        rtr,
+        dlc,
+        data,
+    })
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+mod test {
+    #[test]
+    fn test_round_trip() {
+        use super::{parse_log, write_frame};
+        use alloc::string::String;
+
+        let log = "(1436509052.249713) can0 00000123#DEADBEEF\n\
+                   (1436509052.25) can0 123#R4\n\
+                   (1436509053.000001) can0 1FFFFFFF#\n";
+        let frames = parse_log(log).unwrap();
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].arb_id, frames[0].extended), (0x123, true));
+        assert_eq!(frames[0].timestamp, 1_436_509_052_249_713);
+        assert_eq!(
+            (frames[1].extended, frames[1].rtr, frames[1].dlc),
+            (false, true, 4)
+        );
+        assert_eq!(frames[1].timestamp, 1_436_509_052_250_000);
+        assert!(frames[1].data.is_empty());
+
+        let mut written = String::new();
+        for frame in &frames {
+            write_frame(&mut written, frame);
+        }
+        assert_eq!(
+            written,
+            "(1436509052.249713) can0 00000123#DEADBEEF\n\
+             (1436509052.250000) can0 123#R4\n\
+             (1436509053.000001) can0 1FFFFFFF#\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        use super::{parse_log, CandumpError};
+
+        for line in [
+            // Past u64 microseconds.
+            "(18446744073709551615.000000) can0 123#00",
+            "(1.0) can0 800#00",
+            "(1.0) can0 123#R9",
+            "(1.0) can0 123#001122334455667788",
+        ] {
+            assert_eq!(parse_log(line), Err(CandumpError::InvalidLine(1)));
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! CAN databases in the Vector DBC format, to decode frames into named physical values and
+//! encode them back.
+//!
+//! Messages (`BO_`), their signals (`SG_`) including simple multiplexing (`M` and `m<n>`), and
+//! value descriptions (`VAL_`) are read; everything else, like comments and attributes, is
+//! skipped. Bits are numbered as in DBC files: from the least significant bit of the first byte,
+//! with big endian (Motorola) signals starting at their most significant bit.
+
+use alloc::{string::String, vec, vec::Vec};
+use defmt::Format;
+
+/// Set in DBC identifiers of extended frames.
+const ID_EXTENDED: u32 = 1 << 31;
+/// Holds the signals which are not part of any message.
+const INDEPENDENT_SIGNALS: &str = "VECTOR__INDEPENDENT_SIG_MSG";
+
+#[derive(Debug, Format, Clone, PartialEq, Eq)]
+pub enum DbcError {
+    /// The given (1-based) line is not a valid statement.
+    InvalidLine(usize),
+    UnknownMessage,
+    UnknownSignal,
+    /// A value is outside of its signal's range, or does not fit in its bits.
+    OutOfRange,
+    /// A multiplexed signal was given without the multiplexor value selecting it.
+    InactiveSignal,
+}
+
+impl core::fmt::Display for DbcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl core::error::Error for DbcError {}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Intel, `@1`.
+    LittleEndian,
+    /// Motorola, `@0`.
+    BigEndian,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Mux {
+    /// Always present.
+    Plain,
+    /// Selects which multiplexed signals are present.
+    Multiplexor,
+    /// Present when the multiplexor has this value.
+    Multiplexed(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub start: u16,
+    pub len: u8,
+    pub byte_order: ByteOrder,
+    pub signed: bool,
+    pub factor: f64,
+    pub offset: f64,
+    /// Both are 0 when the range is unbounded.
+    pub min: f64,
+    pub max: f64,
+    pub unit: String,
+    pub mux: Mux,
+    /// Descriptions of raw values.
+    pub values: Vec<(i128, String)>,
+}
+
+impl Signal {
+    /// Bit positions, from the most significant for big endian signals and from the least
+    /// significant otherwise.
+    fn positions(&self) -> impl Iterator<Item = usize> {
+        let mut pos = self.start as usize;
+        let byte_order = self.byte_order;
+
+        (0..self.len).map(move |_| {
+            let current = pos;
+
+            pos = match byte_order {
+                ByteOrder::LittleEndian => pos + 1,
+                // Walk down the byte, then on to the top of the next one.
+                ByteOrder::BigEndian if pos % 8 == 0 => pos + 15,
+                ByteOrder::BigEndian => pos - 1,
+            };
+
+            current
+        })
+    }
+
+    /// The raw value in `data`, or `None` if the frame is too short for it. Raw values are wide
+    /// enough for both signed and unsigned 64 bit signals.
+    pub fn raw(&self, data: &[u8]) -> Option<i128> {
+        let mut bits = 0_u64;
+
+        for (i, pos) in self.positions().enumerate() {
+            let bit = (*data.get(pos / 8)? >> (pos % 8)) as u64 & 1;
+
+            match self.byte_order {
+                ByteOrder::LittleEndian => bits |= bit << i,
+                ByteOrder::BigEndian => bits = bits << 1 | bit,
+            }
+        }
+
+        if !self.signed {
+            return Some(bits as i128);
+        }
+
+        if self.len < 64 && bits >> (self.len - 1) & 1 != 0 {
+            // Sign extend.
+            bits |= u64::MAX << self.len;
+        }
+
+        Some(bits as i64 as i128)
+    }
+
+    pub fn physical(&self, raw: i128) -> f64 {
+        raw as f64 * self.factor + self.offset
+    }
+
+    /// The description of a raw value, if any.
+    pub fn label(&self, raw: i128) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(value, _)| *value == raw)
+            .map(|(_, label)| label.as_str())
+    }
+
+    /// The raw value of a description.
+    pub fn label_raw(&self, label: &str) -> Option<i128> {
+        self.values
+            .iter()
+            .find(|(_, value_label)| value_label == label)
+            .map(|(value, _)| *value)
+    }
+
+    /// The raw value closest to a physical one, checked against the range and the bits.
+    pub fn to_raw(&self, value: f64) -> Result<i128, DbcError> {
+        if self.min < self.max && !(self.min..=self.max).contains(&value) {
+            return Err(DbcError::OutOfRange);
+        }
+
+        let scaled = (value - self.offset) / self.factor;
+
+        if !scaled.is_finite() {
+            return Err(DbcError::OutOfRange);
+        }
+
+        // Rounded to the nearest, `as` truncates.
+        let raw = if scaled < 0.0 {
+            (scaled - 0.5) as i128
+        } else {
+            (scaled + 0.5) as i128
+        };
+
+        let fits = if self.signed {
+            (-(1 << (self.len - 1))..1 << (self.len - 1)).contains(&raw)
+        } else {
+            (0..1 << self.len).contains(&raw)
+        };
+
+        if fits {
+            Ok(raw)
+        } else {
+            Err(DbcError::OutOfRange)
+        }
+    }
+
+    /// Writes a raw value into `data`, which has to be long enough.
+    fn insert(&self, data: &mut [u8], raw: i128) {
+        let bits = raw as u64;
+
+        for (i, pos) in self.positions().enumerate() {
+            let bit = match self.byte_order {
+                ByteOrder::LittleEndian => bits >> i & 1,
+                ByteOrder::BigEndian => bits >> (self.len as usize - 1 - i) & 1,
+            };
+
+            data[pos / 8] &= !(1 << (pos % 8));
+            data[pos / 8] |= (bit as u8) << (pos % 8);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageDef {
+    pub arb_id: u32,
+    pub extended: bool,
+    pub name: String,
+    pub dlc: u8,
+    pub signals: Vec<Signal>,
+}
+
+impl MessageDef {
+    /// The raw values of the signals present in a frame: multiplexed ones only when the
+    /// multiplexor selects them, and none that the frame is too short for.
+    pub fn decode(&self, data: &[u8]) -> Vec<(&Signal, i128)> {
+        let mux = self
+            .signals
+            .iter()
+            .find(|signal| signal.mux == Mux::Multiplexor)
+            .and_then(|signal| signal.raw(data));
+
+        self.signals
+            .iter()
+            .filter(|signal| match signal.mux {
+                Mux::Plain | Mux::Multiplexor => true,
+                Mux::Multiplexed(value) => mux == Some(value as i128),
+            })
+            .filter_map(|signal| Some((signal, signal.raw(data)?)))
+            .collect()
+    }
+
+    /// Encodes a frame from physical values. Signals which are not given are zero.
+    pub fn encode(&self, values: &[(&str, f64)]) -> Result<Vec<u8>, DbcError> {
+        let mut data = vec![0; self.dlc as usize];
+        let mut mux = None;
+        let mut raws = Vec::with_capacity(values.len());
+
+        for &(name, value) in values {
+            let signal = self
+                .signals
+                .iter()
+                .find(|signal| signal.name == name)
+                .ok_or(DbcError::UnknownSignal)?;
+            let raw = signal.to_raw(value)?;
+
+            if signal.positions().any(|pos| pos / 8 >= data.len()) {
+                return Err(DbcError::OutOfRange);
+            }
+
+            if signal.mux == Mux::Multiplexor {
+                mux = Some(raw);
+            }
+
+            raws.push((signal, raw));
+        }
+
+        for (signal, raw) in raws {
+            if let Mux::Multiplexed(value) = signal.mux {
+                if mux != Some(value as i128) {
+                    return Err(DbcError::InactiveSignal);
+                }
+            }
+
+            signal.insert(&mut data, raw);
+        }
+
+        Ok(data)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Database {
+    pub messages: Vec<MessageDef>,
+}
+
+impl Database {
+    pub fn parse(text: &str) -> Result<Self, DbcError> {
+        let mut messages: Vec<MessageDef> = Vec::new();
+        // Signals of the pseudo message holding independent signals are skipped.
+        let mut skipping = false;
+        // Comments and attribute strings may span several lines.
+        let mut in_string = false;
+
+        for (i, line) in text.lines().enumerate() {
+            let quotes = quotes(line);
+
+            if in_string {
+                in_string = quotes % 2 == 0;
+                continue;
+            }
+
+            in_string = quotes % 2 == 1;
+
+            let invalid = DbcError::InvalidLine(i + 1);
+            let line = line.trim();
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+            match keyword {
+                "BO_" => {
+                    let message = parse_message(rest).ok_or(invalid)?;
+                    skipping = message.name == INDEPENDENT_SIGNALS;
+
+                    if !skipping {
+                        messages.push(message);
+                    }
+                }
+                "SG_" => {
+                    let signal = parse_signal(rest).ok_or(invalid.clone())?;
+
+                    if !skipping {
+                        messages.last_mut().ok_or(invalid)?.signals.push(signal);
+                    }
+                }
+                "VAL_" => {
+                    let tokens = tokenize(rest);
+
+                    // Descriptions of environment variables have no identifier.
+                    let Ok(id) = tokens.first().unwrap_or(&"").parse::<u32>() else {
+                        continue;
+                    };
+
+                    if tokens.len() < 3 || tokens.len() % 2 != 1 || tokens[tokens.len() - 1] != ";"
+                    {
+                        return Err(invalid);
+                    }
+
+                    let values = tokens[2..tokens.len() - 1]
+                        .chunks(2)
+                        .map(|pair| Some((pair[0].parse().ok()?, String::from(pair[1]))))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or(invalid.clone())?;
+
+                    let extended = id & ID_EXTENDED != 0;
+                    let signal = messages
+                        .iter_mut()
+                        .find(|message| {
+                            message.arb_id == id & !ID_EXTENDED && message.extended == extended
+                        })
+                        .and_then(|message| {
+                            message
+                                .signals
+                                .iter_mut()
+                                .find(|signal| signal.name == tokens[1])
+                        });
+
+                    // Descriptions of unknown signals are dropped, like their signals.
+                    if let Some(signal) = signal {
+                        signal.values = values;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { messages })
+    }
+
+    pub fn message(&self, arb_id: u32, extended: bool) -> Option<&MessageDef> {
+        self.messages
+            .iter()
+            .find(|message| message.arb_id == arb_id && message.extended == extended)
+    }
+
+    pub fn message_by_name(&self, name: &str) -> Option<&MessageDef> {
+        self.messages.iter().find(|message| message.name == name)
+    }
+}
+
+/// Parses `<id> <name>: <dlc> <transmitter>`.
+fn parse_message(rest: &str) -> Option<MessageDef> {
+    let (head, tail) = rest.split_once(':')?;
+    let mut head = head.split_whitespace();
+    let id = head.next()?.parse::<u32>().ok()?;
+    let name = head.next()?;
+    let dlc = tail.split_whitespace().next()?.parse().ok()?;
+
+    Some(MessageDef {
+        arb_id: id & !ID_EXTENDED,
+        extended: id & ID_EXTENDED != 0,
+        name: String::from(name),
+        dlc,
+        signals: Vec::new(),
+    })
+}
+
+/// Parses `<name> [M|m<n>] : <start>|<len>@<order><sign> (<factor>,<offset>) [<min>|<max>]
+/// "<unit>" <receivers>`.
+fn parse_signal(rest: &str) -> Option<Signal> {
+    let (head, tail) = rest.split_once(':')?;
+    let mut head = head.split_whitespace();
+    let name = head.next()?;
+    let mux = match head.next() {
+        None => Mux::Plain,
+        Some("M") => Mux::Multiplexor,
+        // Extended multiplexing (`m<n>M`) is not supported, those are taken as multiplexed.
+        Some(mux) => Mux::Multiplexed(mux.strip_prefix('m')?.trim_end_matches('M').parse().ok()?),
+    };
+
+    let (layout, tail) = tail.split_once('(')?;
+    let (scale, tail) = tail.split_once(')')?;
+    let (_, tail) = tail.split_once('[')?;
+    let (range, tail) = tail.split_once(']')?;
+    let (_, tail) = tail.split_once('"')?;
+    let (unit, _) = tail.split_once('"')?;
+
+    let (start, layout) = layout.trim().split_once('|')?;
+    let (len, layout) = layout.split_once('@')?;
+    let (byte_order, signed) = match layout.as_bytes() {
+        [order, sign] => (
+            match order {
+                b'0' => ByteOrder::BigEndian,
+                b'1' => ByteOrder::LittleEndian,
+                _ => return None,
+            },
+            match sign {
+                b'-' => true,
+                b'+' => false,
+                _ => return None,
+            },
+        ),
+        _ => return None,
+    };
+    let (factor, offset) = scale.split_once(',')?;
+    let (min, max) = range.split_once('|')?;
+    let len = len.parse().ok()?;
+
+    if !(1..=64).contains(&len) {
+        return None;
+    }
+
+    Some(Signal {
+        name: String::from(name),
+        start: start.parse().ok()?,
+        len,
+        byte_order,
+        signed,
+        factor: factor.trim().parse().ok()?,
+        offset: offset.trim().parse().ok()?,
+        min: min.trim().parse().ok()?,
+        max: max.trim().parse().ok()?,
+        unit: String::from(unit),
+        mux,
+        values: Vec::new(),
+    })
+}
+
+/// The number of quotes in a line, leaving out escaped ones (`\"`).
+fn quotes(line: &str) -> usize {
+    let mut escaped = false;
+
+    line.chars()
+        .filter(|&c| {
+            let quote = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            quote
+        })
+        .count()
+}
+
+/// Splits on whitespace, keeping quoted strings (without their quotes) whole and `;` apart.
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let (token, tail) = if let Some(quoted) = rest.strip_prefix('"') {
+            quoted.split_once('"').unwrap_or((quoted, ""))
+        } else if let Some(tail) = rest.strip_prefix(';') {
+            (";", tail)
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == ';' || c == '"')
+                .unwrap_or(rest.len());
+            rest.split_at(end)
+        };
+
+        tokens.push(token);
+        rest = tail.trim_start();
+    }
+
+    tokens
+}
+
+mod test {
+    /// In the style of a database exported by CANdb++, with a multi-line comment.
+    #[allow(unused)]
+    const VEHICLE: &str = r#"VERSION ""
+
+
+NS_ :
+	NS_DESC_
+	CM_
+	BA_DEF_
+	VAL_
+
+BS_:
+
+BU_: ECU Dash
+
+
+BO_ 256 EngineData: 8 ECU
+ SG_ EngineSpeed : 0|16@1+ (0.25,0) [0|16383.75] "rpm" Dash
+ SG_ CoolantTemp : 16|8@1+ (1,-40) [-40|215] "degC" Dash
+ SG_ Gear : 24|3@1+ (1,0) [0|0] "" Dash
+ SG_ Pressure : 39|12@0+ (0.5,0) [0|2047.5] "kPa" Dash
+
+BO_ 2566848766 Diagnostics: 8 ECU
+ SG_ Page M : 0|8@1+ (1,0) [0|255] "" Dash
+ SG_ Voltage m0 : 8|16@1+ (0.001,0) [0|65.535] "V" Dash
+ SG_ Current m1 : 8|16@1- (0.01,0) [-327.68|327.67] "A" Dash
+
+BO_ 3221225472 VECTOR__INDEPENDENT_SIG_MSG: 0 Vector__XXX
+ SG_ Orphan : 0|8@1+ (1,0) [0|0] "" Vector__XXX
+
+CM_ BO_ 256 "Dash cable is 12\" long";
+CM_ SG_ 256 Gear "Selected gear;
+BO_ 1 Bogus: 8 ECU";
+BA_DEF_ BO_  "GenMsgCycleTime" INT 0 65535;
+VAL_TABLE_ Gears 3 "Drive" 2 "Neutral" 1 "Reverse" 0 "Park" ;
+VAL_ 256 Gear 3 "Drive" 2 "Neutral" 1 "Reverse" 0 "Park" ;
+"#;
+
+    #[test]
+    fn test_parse() {
+        use super::{ByteOrder, Database, DbcError, Mux};
+
+        let db = Database::parse(VEHICLE).unwrap();
+        let names: alloc::vec::Vec<_> = db.messages.iter().map(|msg| msg.name.as_str()).collect();
+        assert_eq!(names, ["EngineData", "Diagnostics"]);
+
+        let engine = db.message(0x100, false).unwrap();
+        assert_eq!(engine.dlc, 8);
+        let temp = &engine.signals[1];
+        assert_eq!(
+            (temp.start, temp.len, temp.byte_order, temp.signed),
+            (16, 8, ByteOrder::LittleEndian, false)
+        );
+        assert_eq!(
+            (temp.factor, temp.offset, temp.min, temp.max),
+            (1.0, -40.0, -40.0, 215.0)
+        );
+        assert_eq!(temp.unit, "degC");
+        assert_eq!(engine.signals[3].byte_order, ByteOrder::BigEndian);
+        assert_eq!(engine.signals[2].label(3), Some("Drive"));
+
+        let diag = db.message_by_name("Diagnostics").unwrap();
+        assert_eq!((diag.arb_id, diag.extended), (0x18FF_00FE, true));
+        let muxes: alloc::vec::Vec<_> = diag.signals.iter().map(|signal| signal.mux).collect();
+        assert_eq!(
+            muxes,
+            [Mux::Multiplexor, Mux::Multiplexed(0), Mux::Multiplexed(1)]
+        );
+
+        assert_eq!(
+            Database::parse("BO_ 1 Broken: 8 ECU\n SG_ Nope : 0|8@2+ (1,0) [0|0] \"\" ECU"),
+            Err(DbcError::InvalidLine(2))
+        );
+        assert_eq!(
+            Database::parse(" SG_ Lost : 0|8@1+ (1,0) [0|0] \"\" ECU"),
+            Err(DbcError::InvalidLine(1))
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        use super::Database;
+
+        let db = Database::parse(VEHICLE).unwrap();
+        let engine = db.message(0x100, false).unwrap();
+        // 3000 rpm, 90 degC, third gear, 1000 kPa in big endian from bit 39.
+        let data = [0xE0, 0x2E, 0x82, 0x03, 0x7D, 0x00, 0, 0];
+        let values: alloc::vec::Vec<_> = engine
+            .decode(&data)
+            .iter()
+            .map(|(signal, raw)| (signal.name.as_str(), signal.physical(*raw)))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("EngineSpeed", 3000.0),
+                ("CoolantTemp", 90.0),
+                ("Gear", 3.0),
+                ("Pressure", 1000.0)
+            ]
+        );
+
+        // Signals past the end of short frames are left out.
+        assert_eq!(engine.decode(&data[..2]).len(), 1);
+
+        let diag = db.message(0x18FF_00FE, true).unwrap();
+        let values: alloc::vec::Vec<_> = diag
+            .decode(&[1, 0x18, 0xFC])
+            .iter()
+            .map(|(signal, raw)| (signal.name.as_str(), signal.physical(*raw)))
+            .collect();
+        assert_eq!(values, [("Page", 1.0), ("Current", -10.0)]);
+
+        // Unsigned 64 bit signals stay positive.
+        let db =
+            Database::parse("BO_ 3 Odometer: 8 ECU\n SG_ Total : 0|64@1+ (1,0) [0|0] \"\" ECU")
+                .unwrap();
+        let total = &db.messages[0].signals[0];
+        let raw = total.raw(&[0xFF; 8]).unwrap();
+        assert_eq!(raw, u64::MAX as i128);
+        assert_eq!(total.physical(raw), u64::MAX as f64);
+    }
+
+    #[test]
+    fn test_encode() {
+        use super::{Database, DbcError};
+
+        let db = Database::parse(VEHICLE).unwrap();
+        let engine = db.message_by_name("EngineData").unwrap();
+        assert_eq!(
+            engine.encode(&[
+                ("EngineSpeed", 3000.0),
+                ("CoolantTemp", 90.0),
+                ("Gear", 3.0),
+                ("Pressure", 1000.0)
+            ]),
+            Ok([0xE0, 0x2E, 0x82, 0x03, 0x7D, 0x00, 0, 0].into())
+        );
+        assert_eq!(
+            engine.encode(&[("CoolantTemp", 300.0)]),
+            Err(DbcError::OutOfRange)
+        );
+        // Unbounded, but only 3 bits.
+        assert_eq!(engine.encode(&[("Gear", 8.0)]), Err(DbcError::OutOfRange));
+        assert_eq!(
+            engine.encode(&[("Boost", 1.0)]),
+            Err(DbcError::UnknownSignal)
+        );
+
+        let diag = db.message_by_name("Diagnostics").unwrap();
+        assert_eq!(
+            diag.encode(&[("Page", 1.0), ("Current", -10.0)]),
+            Ok([1, 0x18, 0xFC, 0, 0, 0, 0, 0].into())
+        );
+        assert_eq!(
+            diag.encode(&[("Page", 1.0), ("Voltage", 12.0)]),
+            Err(DbcError::InactiveSignal)
+        );
+    }
+}
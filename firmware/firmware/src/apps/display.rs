@@ -1,24 +1,33 @@
 use crate::{
-    apps::{console::ConsoleDisplay, scrolling_console::ScrollingConsole},
+    apps::{
+        console::ConsoleDisplay,
+        dmx,
+        scrolling_console::ScrollingConsole,
+        sniffer::{self, View},
+        tx::waveform,
+    },
     platform::{
+        buttons::{Button, ButtonReceiver},
+        flushing_display::FlushingDisplay,
         mc3479::runner::{ShakeReceiver, ShakeSignal},
         repl::{
             console::{ConsoleReader, CONSOLE_MTU},
             display::{DisplayCommand, DisplayReceiver},
             rpc::{AppAck, AppAckSender, AppContextReceiver},
         },
+        vertical_scrolling::VerticalScrolling,
     },
 };
 use defmt::warn;
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDeviceWithConfig;
-use embassy_futures::select::{self, Either, Either3};
+use embassy_futures::select::{self, Either, Either3, Either4};
 use embassy_rp::{
     gpio::Output,
     peripherals::SPI0,
     spi::{self, Spi},
 };
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_time::{Instant, Timer};
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use embedded_graphics::{
     image::ImageDrawable, mono_font::ascii::FONT_7X13, pixelcolor::Rgb565, prelude::*,
     primitives::Rectangle,
@@ -63,6 +72,7 @@ pub async fn display_task(
     mut shake_rx: ShakeReceiver,
     shake_signal_ready: &'static ShakeSignal,
     shake_signal_done: &'static ShakeSignal,
+    button_rx: ButtonReceiver,
 ) -> ! {
     warn!("In the display app!!!");
     let puke = tinygif::Gif::<Rgb565>::from_slice(include_bytes!("../../assets/puke.gif")).unwrap();
@@ -88,25 +98,26 @@ pub async fn display_task(
 
     loop {
         'system: loop {
-            match select::select3(
+            match select::select4(
                 console_reader.read(&mut buf),
                 app_rx.changed(),
                 shake_rx.changed(),
+                button_rx.receive(),
             )
             .await
             {
-                Either3::First(count) => {
+                Either4::First(count) => {
                     let _ = console.write(&buf[..count]).await;
                     let _ = console.flush().await;
                 }
-                Either3::Second(user_control) => {
+                Either4::Second(user_control) => {
                     if user_control {
                         warn!("Exiting display system application context!");
                         app_ack_tx.send(AppAck::Display).await;
                         break 'system;
                     }
                 }
-                Either3::Third(shooketh) => {
+                Either4::Third(shooketh) => {
                     if shooketh {
                         warn!("Getting ready to blow chunks!");
                         shake_signal_ready.wait().await;
@@ -136,6 +147,25 @@ pub async fn display_task(
                             ScrollingConsole::new(display, FONT_7X13, cropped_rectangle).await;
                     }
                 }
+                Either4::Fourth(button @ (Button::A | Button::B)) => {
+                    let display = console.into_inner().await;
+                    let mut console_display = ConsoleDisplay::new(display, FONT_7X13).await;
+                    let user_control = match button {
+                        Button::A => sniff(&mut console_display, &mut app_rx, button_rx).await,
+                        _ => monitor_dmx(&mut console_display, &mut app_rx, button_rx).await,
+                    };
+                    let mut display = console_display.into_inner();
+                    let _ = display.clear(Rgb565::RED);
+                    let _ = display.flush().await;
+                    console = ScrollingConsole::new(display, FONT_7X13, cropped_rectangle).await;
+
+                    if user_control {
+                        warn!("Exiting display system application context!");
+                        app_ack_tx.send(AppAck::Display).await;
+                        break 'system;
+                    }
+                }
+                Either4::Fourth(_) => {}
             }
         }
 
@@ -176,6 +206,11 @@ pub async fn display_task(
                         // NOTE: Suppressing SPI errors for production.
                         console_display.flush().await;
                     }
+                    DisplayCommand::Waveform(wave) => {
+                        warn!("Waveform");
+                        let _ = waveform::draw(&wave, console_display.get_rotated());
+                        console_display.flush().await;
+                    }
                 },
                 Either::Second(user_control) => {
                     if !user_control {
@@ -183,8 +218,9 @@ pub async fn display_task(
                             "Returning to display system application context! {}",
                             user_control
                         );
-                        // Clear the console buffer before looping back.
+                        // Clear the console buffer and presses before looping back.
                         let _ = console_reader.try_read(&mut buf);
+                        while button_rx.try_receive().is_ok() {}
                         let mut display = console_display.into_inner();
                         let _ = display.clear(Rgb565::RED);
                         let _ = display.flush().await;
@@ -200,3 +236,74 @@ pub async fn display_task(
         }
     }
 }
+
+/// Runs the CAN sniffer until closed with A, or the user takes control, which is returned.
+async fn sniff<T>(
+    console_display: &mut ConsoleDisplay<T>,
+    app_rx: &mut AppContextReceiver,
+    button_rx: ButtonReceiver,
+) -> bool
+where
+    T: DrawTargetExt<Color = Rgb565> + FlushingDisplay + VerticalScrolling + OriginDimensions,
+{
+    warn!("Starting the CAN sniffer");
+    let mut view = View::new();
+    let mut ticker = Ticker::every(Duration::from_millis(250));
+    sniffer::start(view.bitrate());
+
+    let user_control = loop {
+        let _ = view.draw(console_display.get_rotated(), Instant::now());
+        console_display.flush().await;
+
+        match select::select3(button_rx.receive(), app_rx.changed(), ticker.next()).await {
+            Either3::First(Button::A) => break false,
+            Either3::First(button) => {
+                if view.press(button) {
+                    sniffer::start(view.bitrate());
+                }
+            }
+            // The user now owns the receiver.
+            Either3::Second(true) => break true,
+            Either3::Second(false) | Either3::Third(()) => {}
+        }
+    };
+
+    sniffer::stop(!user_control);
+    warn!("Stopped the CAN sniffer");
+
+    user_control
+}
+
+/// Runs the DMX512 universe monitor until closed with A, or the user takes control, which is
+/// returned.
+async fn monitor_dmx<T>(
+    console_display: &mut ConsoleDisplay<T>,
+    app_rx: &mut AppContextReceiver,
+    button_rx: ButtonReceiver,
+) -> bool
+where
+    T: DrawTargetExt<Color = Rgb565> + FlushingDisplay + VerticalScrolling + OriginDimensions,
+{
+    warn!("Starting the DMX monitor");
+    let mut view = dmx::View::new();
+    let mut ticker = Ticker::every(Duration::from_millis(250));
+    dmx::start();
+
+    let user_control = loop {
+        let _ = view.draw(console_display.get_rotated());
+        console_display.flush().await;
+
+        match select::select3(button_rx.receive(), app_rx.changed(), ticker.next()).await {
+            Either3::First(Button::A) => break false,
+            Either3::First(button) => view.press(button),
+            // The user now owns the receiver.
+            Either3::Second(true) => break true,
+            Either3::Second(false) | Either3::Third(()) => {}
+        }
+    };
+
+    dmx::stop(!user_control);
+    warn!("Stopped the DMX monitor");
+
+    user_control
+}
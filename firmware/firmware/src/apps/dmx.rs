@@ -0,0 +1,420 @@
+//! DMX512 over the RS-485 port, as used by stage and deck lighting.
+//!
+//! A packet is a break (the line held at space for at least 88 µs), a mark after break of at least
+//! 8 µs, then up to 513 slots sent as 250 kbaud 8N2 bytes: the start code, 0 for dimmer levels,
+//! and one level per channel. Packets follow each other back to back, 44 times a second for a full
+//! universe.
+//!
+//! The receiver sees a break as a UART break error. As a packet only ends with the break of the
+//! next one, it is handed over then. The last universe and the refresh stats are kept for
+//! `dmx::read` and for the monitor, a view of the 512 levels run from the system application
+//! context like the [`sniffer`](crate::apps::sniffer).
+
+use crate::{apps::rx::SerialParser, platform::buttons::Button};
+use alloc::{format, string::String, vec::Vec};
+use core::cell::RefCell;
+use defmt::Format;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::Instant;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+
+pub const BAUD: u32 = 250_000;
+
+/// Channels in a universe.
+pub const SLOTS: usize = 512;
+
+/// Start code of dimmer level packets. Others carry RDM, text and the like.
+pub const NULL_START_CODE: u8 = 0x00;
+
+/// Default break and mark after break sent, in µs.
+pub const BREAK_US: u32 = 176;
+pub const MAB_US: u32 = 12;
+
+/// µs per bit.
+pub const BIT_US: u32 = 4;
+
+/// Slots per row of the monitor, each a 10 pixel square under the header.
+const COLUMNS: usize = 32;
+const CELL: i32 = 10;
+/// A pixel apart.
+const CELL_SIZE: Size = Size::new(CELL as u32 - 1, CELL as u32 - 1);
+const HEADER_HEIGHT: i32 = 10;
+
+static UNIVERSE: Mutex<CriticalSectionRawMutex, RefCell<Universe>> =
+    Mutex::new(RefCell::new(Universe::new()));
+
+/// Asks the receiver for DMX (`true`) for the monitor, or to be set back up as it was before.
+pub static REQUEST: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Clears the universe and starts listening.
+pub fn start() {
+    clear();
+    REQUEST.signal(true);
+}
+
+/// Sets the receiver back up as it was, unless the user took it over. A start not yet carried out
+/// is dropped either way, so it cannot undo the user's setup.
+pub fn stop(release: bool) {
+    if release {
+        REQUEST.signal(false);
+    } else {
+        REQUEST.reset();
+    }
+}
+
+/// Records a packet (or a broken one) ended at `now`.
+pub fn record(result: &Result<Packet, Error>, now: Instant) {
+    UNIVERSE.lock(|universe| universe.borrow_mut().record(result, now));
+}
+
+pub fn clear() {
+    UNIVERSE.lock(|universe| universe.borrow_mut().clear());
+}
+
+/// The last universe and the stats so far.
+pub fn universe() -> Universe {
+    UNIVERSE.lock(|universe| universe.borrow().clone())
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum DmxWord {
+    Byte(u8),
+    Break,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub start_code: u8,
+    pub slots: Vec<u8>,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// More than 512 slots after the start code, the rest is dropped until the next break.
+    TooLong,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl core::error::Error for Error {}
+
+pub struct Parser {
+    /// The start code and slots since the last break.
+    buffer: Vec<u8>,
+    /// Whether a break came, bytes before the first one being part of an unknown packet.
+    in_packet: bool,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            buffer: Vec::new(),
+            in_packet: false,
+        }
+    }
+}
+
+impl SerialParser for Parser {
+    type Word = DmxWord;
+    type Message = Packet;
+    type Error = Error;
+
+    fn parse_word(&mut self, word: Self::Word) -> Option<Result<Self::Message, Self::Error>> {
+        match word {
+            DmxWord::Break => {
+                let packet = match self.buffer.split_first() {
+                    Some((&start_code, slots)) if self.in_packet => Some(Ok(Packet {
+                        start_code,
+                        slots: slots.to_vec(),
+                    })),
+                    _ => None,
+                };
+
+                self.buffer.clear();
+                self.in_packet = true;
+                packet
+            }
+            DmxWord::Byte(_) if !self.in_packet => None,
+            DmxWord::Byte(byte) => {
+                if self.buffer.len() == Self::mtu() {
+                    self.reset();
+                    return Some(Err(Error::TooLong));
+                }
+
+                self.buffer.push(byte);
+                None
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.in_packet = false;
+    }
+
+    fn mtu() -> usize {
+        SLOTS + 1
+    }
+
+    fn default_baud() -> u32 {
+        BAUD
+    }
+}
+
+/// The levels of the last dimmer packet, and how packets came in. Intervals are from break to
+/// break, in µs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Universe {
+    pub slots: Vec<u8>,
+    /// Packets of any start code.
+    pub packets: u32,
+    /// Packets with another start code than [`NULL_START_CODE`].
+    pub alternate: u32,
+    pub errors: u32,
+    pub min_interval: u64,
+    pub max_interval: u64,
+    total_interval: u64,
+    intervals: u32,
+    last: Option<Instant>,
+}
+
+impl Universe {
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            packets: 0,
+            alternate: 0,
+            errors: 0,
+            min_interval: 0,
+            max_interval: 0,
+            total_interval: 0,
+            intervals: 0,
+            last: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn record(&mut self, result: &Result<Packet, Error>, now: Instant) {
+        let packet = match result {
+            Ok(packet) => packet,
+            Err(_) => {
+                // The next packet is timed from its own break.
+                self.errors += 1;
+                self.last = None;
+                return;
+            }
+        };
+
+        if let Some(last) = self.last {
+            let interval = now.saturating_duration_since(last).as_micros();
+
+            if self.intervals == 0 {
+                self.min_interval = interval;
+            }
+
+            self.min_interval = self.min_interval.min(interval);
+            self.max_interval = self.max_interval.max(interval);
+            self.total_interval += interval;
+            self.intervals += 1;
+        }
+
+        self.last = Some(now);
+        self.packets += 1;
+
+        if packet.start_code == NULL_START_CODE {
+            self.slots.clone_from(&packet.slots);
+        } else {
+            self.alternate += 1;
+        }
+    }
+
+    /// Packets per second, from the mean interval.
+    pub fn refresh_rate(&self) -> f32 {
+        if self.total_interval == 0 {
+            return 0.0;
+        }
+
+        self.intervals as f32 * 1_000_000.0 / self.total_interval as f32
+    }
+}
+
+/// The line states of a packet, `true` for mark: the break and mark after break, in bits, then
+/// the start code and slots as 8N2 from the least significant bit.
+pub fn bits(
+    start_code: u8,
+    slots: &[u8],
+    break_bits: usize,
+    mab_bits: usize,
+) -> impl Iterator<Item = bool> + '_ {
+    let frame = |byte: u8| {
+        core::iter::once(false)
+            .chain((0..8).map(move |i| byte >> i & 1 != 0))
+            .chain([true, true])
+    };
+
+    core::iter::repeat_n(false, break_bits)
+        .chain(core::iter::repeat_n(true, mab_bits))
+        .chain(
+            core::iter::once(start_code)
+                .chain(slots.iter().copied())
+                .flat_map(frame),
+        )
+}
+
+/// The monitor: every level as a shade of grey, and a cursor reading out one channel.
+pub struct View {
+    cursor: usize,
+}
+
+impl View {
+    pub fn new() -> Self {
+        Self { cursor: 0 }
+    }
+
+    /// Moves the cursor, or clears the universe on B.
+    pub fn press(&mut self, button: Button) {
+        let cursor = match button {
+            Button::Up => self.cursor.checked_sub(COLUMNS),
+            Button::Down => Some(self.cursor + COLUMNS),
+            Button::Left => self.cursor.checked_sub(1),
+            Button::Right => Some(self.cursor + 1),
+            Button::B => {
+                clear();
+                None
+            }
+            Button::Center | Button::A => None,
+        };
+
+        if let Some(cursor) = cursor.filter(|cursor| *cursor < SLOTS) {
+            self.cursor = cursor;
+        }
+    }
+
+    /// Draws the universe, on a landscape target.
+    pub fn draw<D: DrawTarget<Color = Rgb565>>(&self, target: &mut D) -> Result<(), D::Error> {
+        let universe = universe();
+        let level = universe.slots.get(self.cursor);
+
+        target.clear(Rgb565::BLACK)?;
+
+        let level = match level {
+            Some(level) => format!("{:3}", level),
+            None => String::from("  -"),
+        };
+        let header = format!(
+            "ch {:3}: {}  {} slots  {:.1} Hz  {} err",
+            self.cursor + 1,
+            level,
+            universe.slots.len(),
+            universe.refresh_rate(),
+            universe.errors
+        );
+        let style = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
+        Text::with_baseline(&header, Point::zero(), style, Baseline::Top).draw(target)?;
+
+        for (i, &level) in universe.slots.iter().enumerate() {
+            let color = Rgb565::new(level >> 3, level >> 2, level >> 3);
+            Rectangle::new(cell(i), CELL_SIZE)
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(target)?;
+        }
+
+        Rectangle::new(cell(self.cursor), CELL_SIZE)
+            .into_styled(PrimitiveStyle::with_stroke(Rgb565::RED, 1))
+            .draw(target)?;
+
+        Ok(())
+    }
+}
+
+/// The top left corner of a slot's cell.
+fn cell(slot: usize) -> Point {
+    Point::new(
+        (slot % COLUMNS) as i32 * CELL,
+        HEADER_HEIGHT + (slot / COLUMNS) as i32 * CELL,
+    )
+}
+
+mod test {
+    #[test]
+    fn test_parser() {
+        use super::{DmxWord, Error, Packet, Parser, Universe};
+        use crate::apps::rx::SerialParser;
+        use alloc::vec::Vec;
+        use embassy_time::Instant;
+
+        let mut parser = Parser::new();
+        let mut packets = Vec::new();
+        let mut feed = |words: &[DmxWord]| {
+            for &word in words {
+                packets.extend(parser.parse_word(word));
+            }
+        };
+
+        // The tail of an unknown packet, a dimmer packet, an RDM one, then one too long.
+        feed(&[DmxWord::Byte(7), DmxWord::Byte(8), DmxWord::Break]);
+        feed(&[DmxWord::Byte(0), DmxWord::Byte(255), DmxWord::Byte(1)]);
+        feed(&[DmxWord::Break, DmxWord::Byte(0xCC), DmxWord::Break]);
+        feed(&[DmxWord::Byte(0); 515]);
+        feed(&[DmxWord::Break]);
+
+        let packet = |start_code, slots: &[u8]| {
+            Ok(Packet {
+                start_code,
+                slots: slots.to_vec(),
+            })
+        };
+        assert_eq!(
+            packets,
+            [packet(0, &[255, 1]), packet(0xCC, &[]), Err(Error::TooLong)]
+        );
+
+        let mut universe = Universe::new();
+        for (i, packet) in packets.iter().chain(packets[..1].iter()).enumerate() {
+            universe.record(packet, Instant::from_micros(i as u64 * 20_000));
+        }
+
+        assert_eq!(universe.slots, [255, 1]);
+        assert_eq!(
+            (universe.packets, universe.alternate, universe.errors),
+            (3, 1, 1)
+        );
+        // The packet after the error is not timed.
+        assert_eq!(
+            (universe.min_interval, universe.max_interval),
+            (20_000, 20_000)
+        );
+        assert_eq!(universe.refresh_rate(), 50.0);
+    }
+
+    #[test]
+    fn test_bits() {
+        use super::bits;
+        use alloc::vec::Vec;
+
+        // Break, mark after break, then start bit, data from bit 0 and stop bits.
+        let expected = "000 11 0_00000000_11 0_10000001_11"
+            .bytes()
+            .filter(|c| c.is_ascii_digit())
+            .map(|c| c == b'1')
+            .collect::<Vec<_>>();
+
+        assert_eq!(bits(0x00, &[0x81], 3, 2).collect::<Vec<_>>(), expected);
+    }
+}
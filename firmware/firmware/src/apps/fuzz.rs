@@ -0,0 +1,624 @@
+//! A mutational CAN fuzzer.
+//!
+//! Frames are mutated from a seed corpus with a small PRNG, so a run is reproduced exactly by its
+//! seed and configuration. Each frame gets one to three mutations: a new identifier from the
+//! configured ranges, a new DLC, a bit flip, a boundary value, a small arithmetic step or a
+//! dictionary token.
+//!
+//! A run is a [`Campaign`], stepped by the transmit task between commands like the scheduler, so
+//! it goes on in the background until done or stopped.
+//!
+//! While fuzzing, the receive task hands every message to [`observe`], which watches the
+//! target's heartbeat. The target is considered down when the heartbeat goes quiet for longer
+//! than its timeout, or when its masked payload differs from the first one seen, and the last
+//! frames sent are kept as a crash candidate.
+
+use crate::apps::{candump::Frame, rx::can::Message};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::cell::RefCell;
+use defmt::{warn, Format};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+/// Bytes that tend to hit edge cases: zero, one, and both sides of the sign and overflow
+/// boundaries.
+const BOUNDARY: [u8; 6] = [0x00, 0x01, 0x7F, 0x80, 0xFE, 0xFF];
+
+/// Largest step of the arithmetic mutation, either way.
+const MAX_STEP: u8 = 16;
+
+static MONITOR: Mutex<CriticalSectionRawMutex, RefCell<Option<Monitor>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Starts watching the heartbeat.
+pub fn arm(monitor: Monitor) {
+    MONITOR.lock(|current| current.replace(Some(monitor)));
+}
+
+pub fn disarm() {
+    MONITOR.lock(|current| current.replace(None));
+}
+
+/// Hands a received message to the heartbeat monitor, while fuzzing.
+pub fn observe(msg: &Message) {
+    MONITOR.lock(|monitor| {
+        if let Some(monitor) = monitor.borrow_mut().as_mut() {
+            monitor.observe(msg.arb_id(), msg.data(), Instant::now());
+        }
+    });
+}
+
+/// Whether the heartbeat has been seen since arming.
+pub fn is_alive() -> bool {
+    MONITOR.lock(|monitor| {
+        monitor
+            .borrow()
+            .as_ref()
+            .is_some_and(|monitor| monitor.baseline.is_some())
+    })
+}
+
+/// Checks the heartbeat at `now`.
+pub fn check(now: Instant) -> Option<Verdict> {
+    MONITOR.lock(|monitor| {
+        monitor
+            .borrow()
+            .as_ref()
+            .and_then(|monitor| monitor.check(now))
+    })
+}
+
+/// A splitmix64 generator, small and good enough to pick mutations with.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `0..n`, `n` must not be zero.
+    pub fn below(&mut self, n: u32) -> u32 {
+        (self.next_u64() % n as u64) as u32
+    }
+
+    /// A value in `lo..=hi`.
+    pub fn between(&mut self, lo: u32, hi: u32) -> u32 {
+        lo + (self.next_u64() % (hi as u64 - lo as u64 + 1)) as u32
+    }
+}
+
+/// What the target's heartbeat is expected to look like.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub arb_id: u32,
+    /// Longest gap between two heartbeats, in ms.
+    pub timeout: u64,
+    /// Payload bits compared against the first heartbeat, none when empty. A different length
+    /// also counts as a change then.
+    pub mask: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzConfig {
+    pub seed: u64,
+    /// Frames mutated from, as identifier and payload. Random frames are made up when empty.
+    pub corpus: Vec<(u32, Vec<u8>)>,
+    /// Inclusive ranges the identifier mutation picks from. Without any, it flips an identifier
+    /// bit instead.
+    pub ids: Vec<(u32, u32)>,
+    /// Byte strings written over the payload.
+    pub dictionary: Vec<Vec<u8>>,
+    /// Frames per second.
+    pub rate: u32,
+    /// Frames to send, unless the target goes down first.
+    pub count: usize,
+    pub heartbeat: Option<Heartbeat>,
+    /// Frames kept as the crash candidate.
+    pub history: usize,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// No heartbeat within the timeout.
+    Quiet,
+    /// The masked heartbeat payload changed.
+    Changed,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// Every frame was sent.
+    Done,
+    /// Stopped before every frame was sent.
+    Stopped,
+    /// The target went down, or never sent a first heartbeat (quiet, with nothing sent).
+    Down(Verdict),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzReport {
+    pub seed: u64,
+    pub sent: usize,
+    pub failed: usize,
+    pub stop: Stop,
+    /// The last frames sent, timestamped from the start of the run. Empty unless the target went
+    /// down.
+    pub candidate: Vec<Frame>,
+}
+
+/// Watches the heartbeat of the target.
+pub struct Monitor {
+    heartbeat: Heartbeat,
+    /// The first masked payload seen.
+    baseline: Option<Vec<u8>>,
+    last_seen: Instant,
+    changed: bool,
+}
+
+impl Monitor {
+    /// A monitor armed at `now`, which counts as the last heartbeat.
+    pub fn new(heartbeat: Heartbeat, now: Instant) -> Self {
+        Self {
+            heartbeat,
+            baseline: None,
+            last_seen: now,
+            changed: false,
+        }
+    }
+
+    fn masked(&self, data: &[u8]) -> Vec<u8> {
+        data.iter()
+            .zip(&self.heartbeat.mask)
+            .map(|(byte, mask)| byte & mask)
+            .collect()
+    }
+
+    pub fn observe(&mut self, arb_id: u32, data: &[u8], now: Instant) {
+        if arb_id != self.heartbeat.arb_id {
+            return;
+        }
+
+        self.last_seen = now;
+
+        if self.heartbeat.mask.is_empty() {
+            self.baseline.get_or_insert_with(Vec::new);
+            return;
+        }
+
+        // The length goes first, so that a different length is a change too.
+        let mut masked = Vec::with_capacity(data.len() + 1);
+        masked.push(data.len() as u8);
+        masked.extend(self.masked(data));
+
+        match &self.baseline {
+            Some(baseline) => self.changed |= *baseline != masked,
+            None => self.baseline = Some(masked),
+        }
+    }
+
+    /// Whether the target is down at `now`, a change is kept until the monitor is re-armed.
+    pub fn check(&self, now: Instant) -> Option<Verdict> {
+        if self.changed {
+            Some(Verdict::Changed)
+        } else if now.saturating_duration_since(self.last_seen)
+            > Duration::from_millis(self.heartbeat.timeout)
+        {
+            Some(Verdict::Quiet)
+        } else {
+            None
+        }
+    }
+}
+
+/// Makes the frames, and remembers the last ones.
+pub struct Fuzzer {
+    rng: Rng,
+    corpus: Vec<(u32, Vec<u8>)>,
+    ids: Vec<(u32, u32)>,
+    dictionary: Vec<Vec<u8>>,
+    history: VecDeque<Frame>,
+    depth: usize,
+}
+
+impl Fuzzer {
+    pub fn new(config: &FuzzConfig) -> Self {
+        Self {
+            rng: Rng::new(config.seed),
+            corpus: config.corpus.clone(),
+            ids: config.ids.clone(),
+            dictionary: config.dictionary.clone(),
+            history: VecDeque::with_capacity(config.history),
+            depth: config.history,
+        }
+    }
+
+    /// Mutates the next frame, sent `timestamp` µs from the start.
+    pub fn next_frame(&mut self, timestamp: u64) -> &Frame {
+        let (mut arb_id, mut data) = match self.corpus.len() {
+            0 => {
+                let len = self.rng.below(9) as usize;
+                let data = (0..len).map(|_| self.rng.next_u64() as u8).collect();
+                (self.rng.below(0x800), data)
+            }
+            len => self.corpus[self.rng.below(len as u32) as usize].clone(),
+        };
+
+        for _ in 0..self.rng.between(1, 3) {
+            self.mutate(&mut arb_id, &mut data);
+        }
+
+        if self.history.len() >= self.depth {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(Frame {
+            timestamp,
+            arb_id,
+            extended: arb_id > 0x7FF,
+            rtr: false,
+            dlc: data.len() as u8,
+            data,
+        });
+
+        self.history.back().unwrap()
+    }
+
+    fn mutate(&mut self, arb_id: &mut u32, data: &mut Vec<u8>) {
+        let rng = &mut self.rng;
+
+        match rng.below(6) {
+            0 if !self.ids.is_empty() => {
+                let (lo, hi) = self.ids[rng.below(self.ids.len() as u32) as usize];
+                *arb_id = rng.between(lo, hi);
+            }
+            0 => {
+                let bits = if *arb_id > 0x7FF { 29 } else { 11 };
+                *arb_id ^= 1 << rng.below(bits);
+            }
+            1 => {
+                let len = rng.below(9) as usize;
+                data.resize_with(len, || rng.next_u64() as u8);
+            }
+            2 if !data.is_empty() => {
+                let bit = rng.below(data.len() as u32 * 8) as usize;
+                data[bit / 8] ^= 1 << (bit % 8);
+            }
+            3 if !data.is_empty() => {
+                let i = rng.below(data.len() as u32) as usize;
+                data[i] = BOUNDARY[rng.below(BOUNDARY.len() as u32) as usize];
+            }
+            4 if !data.is_empty() => {
+                let i = rng.below(data.len() as u32) as usize;
+                let step = rng.between(1, MAX_STEP as u32) as u8;
+
+                data[i] = if rng.below(2) == 0 {
+                    data[i].wrapping_add(step)
+                } else {
+                    data[i].wrapping_sub(step)
+                };
+            }
+            5 if !self.dictionary.is_empty() => {
+                let token = &self.dictionary[rng.below(self.dictionary.len() as u32) as usize];
+                let token = &token[..token.len().min(8)];
+                let offset = rng.below(9 - token.len() as u32) as usize;
+
+                if data.len() < offset + token.len() {
+                    data.resize(offset + token.len(), 0);
+                }
+
+                data[offset..offset + token.len()].copy_from_slice(token);
+            }
+            // Nothing to mutate that way, flip a bit in what there is.
+            _ if !data.is_empty() => {
+                let bit = rng.below(data.len() as u32 * 8) as usize;
+                data[bit / 8] ^= 1 << (bit % 8);
+            }
+            _ => data.push(rng.next_u64() as u8),
+        }
+    }
+
+    /// The last frames made, oldest first.
+    pub fn history(&self) -> Vec<Frame> {
+        self.history.iter().cloned().collect()
+    }
+}
+
+enum Phase {
+    /// Polling for the first heartbeat, which later ones are compared against, up to a deadline.
+    Waiting(Instant),
+    /// Sending, the index of the next frame.
+    Sending(usize),
+    /// Giving the last frames a heartbeat timeout to take the target down too, up to a deadline.
+    Settling(Instant),
+    Finished(Stop),
+}
+
+/// A run, stepped by the transmit task.
+pub struct Campaign {
+    config: FuzzConfig,
+    fuzzer: Fuzzer,
+    phase: Phase,
+    /// When the frames started going out.
+    start: Instant,
+    /// When to step next.
+    due: Instant,
+    sent: usize,
+    failed: usize,
+}
+
+impl Campaign {
+    /// Starts a run at `now`, arming the heartbeat monitor if there is a heartbeat.
+    pub fn new(config: FuzzConfig, now: Instant) -> Self {
+        let phase = match &config.heartbeat {
+            Some(heartbeat) => {
+                arm(Monitor::new(heartbeat.clone(), now));
+                Phase::Waiting(now + Duration::from_millis(heartbeat.timeout))
+            }
+            None => Phase::Sending(0),
+        };
+
+        Self {
+            fuzzer: Fuzzer::new(&config),
+            config,
+            phase,
+            start: now,
+            due: now,
+            sent: 0,
+            failed: 0,
+        }
+    }
+
+    /// When to step next, none once finished.
+    pub fn next_due(&self) -> Option<Instant> {
+        (!self.is_finished()).then_some(self.due)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.phase, Phase::Finished(_))
+    }
+
+    /// When the frame at `index` is due.
+    fn frame_due(&self, index: usize) -> Instant {
+        self.start + Duration::from_micros(index as u64 * 1_000_000 / self.config.rate as u64)
+    }
+
+    fn finish(&mut self, stop: Stop) {
+        if let Stop::Down(verdict) = stop {
+            warn!(
+                "Fuzz target down after {} frames: {:?}",
+                self.sent + self.failed,
+                verdict
+            );
+        }
+
+        disarm();
+        self.phase = Phase::Finished(stop);
+    }
+
+    /// Moves the run on at `now`, returning the frame to send when one is due. Whether it went out
+    /// goes back through [`Campaign::done`].
+    pub fn step(&mut self, now: Instant) -> Option<Frame> {
+        match self.phase {
+            Phase::Waiting(deadline) => {
+                if is_alive() {
+                    self.start = now;
+                    self.phase = Phase::Sending(0);
+                    return self.step(now);
+                }
+
+                if now > deadline {
+                    self.finish(Stop::Down(Verdict::Quiet));
+                } else {
+                    self.due = now + Duration::from_millis(1);
+                }
+
+                None
+            }
+            Phase::Sending(index) => {
+                if let Some(verdict) = check(now) {
+                    self.finish(Stop::Down(verdict));
+                    return None;
+                }
+
+                if index == self.config.count {
+                    match &self.config.heartbeat {
+                        Some(heartbeat) => {
+                            self.due = now + Duration::from_millis(heartbeat.timeout + 1);
+                            self.phase = Phase::Settling(self.due);
+                        }
+                        None => self.finish(Stop::Done),
+                    }
+
+                    return None;
+                }
+
+                let due = self.frame_due(index);
+                if now < due {
+                    self.due = due;
+                    return None;
+                }
+
+                self.phase = Phase::Sending(index + 1);
+                self.due = self.frame_due(index + 1);
+
+                let timestamp = now.saturating_duration_since(self.start).as_micros();
+                Some(self.fuzzer.next_frame(timestamp).clone())
+            }
+            Phase::Settling(deadline) => {
+                if let Some(verdict) = check(now) {
+                    self.finish(Stop::Down(verdict));
+                } else if now >= deadline {
+                    self.finish(Stop::Done);
+                }
+
+                None
+            }
+            Phase::Finished(_) => None,
+        }
+    }
+
+    /// Records whether the frame from [`Campaign::step`] went out.
+    pub fn done(&mut self, sent: bool) {
+        if sent {
+            self.sent += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    /// Ends the run, unless it is already over.
+    pub fn stop(&mut self) {
+        if !self.is_finished() {
+            self.finish(Stop::Stopped);
+        }
+    }
+
+    /// How the run went, with the crash candidate if the target went down.
+    pub fn report(&self) -> FuzzReport {
+        let stop = match self.phase {
+            Phase::Finished(stop) => stop,
+            _ => Stop::Stopped,
+        };
+
+        let candidate = match stop {
+            Stop::Down(_) => self.fuzzer.history(),
+            Stop::Done | Stop::Stopped => Vec::new(),
+        };
+
+        FuzzReport {
+            seed: self.config.seed,
+            sent: self.sent,
+            failed: self.failed,
+            stop,
+            candidate,
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn test_fuzzer_reproducible() {
+        use super::{FuzzConfig, Fuzzer};
+        use alloc::vec;
+
+        let config = FuzzConfig {
+            seed: 0x1234_5678,
+            corpus: vec![(0x123, vec![0x11, 0x22, 0x33, 0x44])],
+            ids: vec![(0x100, 0x1FF)],
+            dictionary: vec![vec![0xDE, 0xAD, 0xBE, 0xEF]],
+            rate: 100,
+            count: 1000,
+            heartbeat: None,
+            history: 4,
+        };
+
+        let mut first = Fuzzer::new(&config);
+        let mut second = Fuzzer::new(&config);
+
+        for i in 0..1000 {
+            let frame = first.next_frame(i).clone();
+            assert_eq!(&frame, second.next_frame(i));
+            assert!(frame.data.len() <= 8);
+            assert!((0x100..=0x1FF).contains(&frame.arb_id));
+        }
+
+        let history = first.history();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[0].timestamp, 996);
+
+        // Another seed, other frames.
+        let mut first = Fuzzer::new(&config);
+        let mut other = Fuzzer::new(&FuzzConfig { seed: 1, ..config });
+        assert!((0..16).any(|i| first.next_frame(i) != other.next_frame(i)));
+    }
+
+    #[test]
+    fn test_campaign() {
+        use super::{Campaign, FuzzConfig, Stop};
+        use alloc::vec;
+        use embassy_time::{Duration, Instant};
+
+        let config = FuzzConfig {
+            seed: 1,
+            corpus: vec![],
+            ids: vec![],
+            dictionary: vec![],
+            rate: 100,
+            count: 3,
+            heartbeat: None,
+            history: 4,
+        };
+
+        let start = Instant::from_millis(0);
+        let mut campaign = Campaign::new(config.clone(), start);
+        let mut timestamps = vec![];
+
+        while let Some(due) = campaign.next_due() {
+            if let Some(frame) = campaign.step(due) {
+                timestamps.push(frame.timestamp);
+                campaign.done(true);
+            }
+        }
+
+        assert_eq!(timestamps, [0, 10_000, 20_000]);
+        let report = campaign.report();
+        assert_eq!((report.seed, report.sent, report.stop), (1, 3, Stop::Done));
+        assert!(report.candidate.is_empty());
+
+        // Stopped halfway, nothing is due any more.
+        let mut campaign = Campaign::new(config, start);
+        assert!(campaign.step(start).is_some());
+        campaign.done(false);
+        assert!(campaign.step(start).is_none());
+        assert_eq!(campaign.next_due(), Some(start + Duration::from_millis(10)));
+
+        campaign.stop();
+        assert_eq!(campaign.next_due(), None);
+        let report = campaign.report();
+        assert_eq!((report.failed, report.stop), (1, Stop::Stopped));
+    }
+
+    #[test]
+    fn test_monitor() {
+        use super::{Heartbeat, Monitor, Verdict};
+        use alloc::vec;
+        use embassy_time::{Duration, Instant};
+
+        let start = Instant::from_millis(0);
+        let at = |ms| start + Duration::from_millis(ms);
+        let heartbeat = Heartbeat {
+            arb_id: 0x700,
+            timeout: 100,
+            mask: vec![0xFF, 0x00],
+        };
+
+        let mut monitor = Monitor::new(heartbeat.clone(), start);
+        assert_eq!(monitor.check(at(100)), None);
+        assert_eq!(monitor.check(at(101)), Some(Verdict::Quiet));
+
+        // Only the masked bits and the length count.
+        monitor.observe(0x700, &[0x05, 0x01], at(50));
+        monitor.observe(0x700, &[0x05, 0x02], at(120));
+        monitor.observe(0x123, &[0x00], at(200));
+        assert_eq!(monitor.check(at(200)), None);
+        assert_eq!(monitor.check(at(221)), Some(Verdict::Quiet));
+
+        monitor.observe(0x700, &[0x7F, 0x02], at(230));
+        assert_eq!(monitor.check(at(230)), Some(Verdict::Changed));
+
+        let mut monitor = Monitor::new(heartbeat, start);
+        monitor.observe(0x700, &[0x05, 0x01], at(10));
+        monitor.observe(0x700, &[0x05], at(20));
+        assert_eq!(monitor.check(at(20)), Some(Verdict::Changed));
+    }
+}
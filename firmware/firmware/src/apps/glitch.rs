@@ -0,0 +1,227 @@
+//! Triggered voltage glitches and delay/width sweeps.
+//!
+//! A glitch drives the injector lines at a level pair for `width` system clock cycles, `delay`
+//! cycles after a trigger, and lets go of them again. It runs on the overwrite state machine
+//! (see [`crate::apps::tx::overwrite`]), which waits on the same IRQ6 whether the receiver's
+//! identifier matcher or the CPU raises it.
+//!
+//! A sweep tries every point of a delay/width grid, delay first, and classifies each attempt with
+//! a check from the user, building a table of outcomes.
+
+use alloc::string::String;
+use core::fmt::Write;
+use defmt::Format;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Raised by the CPU as soon as the glitch is armed.
+    Software,
+    /// An edge on SAO GPIO 1. It is seen through the I/O expander's interrupt, so with hundreds
+    /// of µs of latency and jitter.
+    Sao(Edge),
+    /// A pattern for the receiver's identifier matcher, see
+    /// [`crate::platform::repl::can::id_pattern`].
+    Can(u32),
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Glitch {
+    /// Injector code driven during the glitch.
+    pub code: u8,
+    /// System clock cycles from the trigger.
+    pub delay: u32,
+    /// System clock cycles held.
+    pub width: u32,
+    pub trigger: Trigger,
+    /// Time to wait for the trigger, in ms.
+    pub timeout: u64,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The trigger never came.
+    Missed,
+    /// The target carried on as if nothing happened.
+    Normal,
+    /// The target reset or hung.
+    Reset,
+    /// The target misbehaved the way we wanted.
+    Success,
+}
+
+impl Outcome {
+    pub fn parse(outcome: &str) -> Option<Self> {
+        match outcome {
+            "missed" => Some(Outcome::Missed),
+            "normal" => Some(Outcome::Normal),
+            "reset" => Some(Outcome::Reset),
+            "success" => Some(Outcome::Success),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Missed => "missed",
+            Outcome::Normal => "normal",
+            Outcome::Reset => "reset",
+            Outcome::Success => "success",
+        }
+    }
+}
+
+/// One axis of a sweep grid, `end` included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Axis {
+    pub start: u32,
+    pub end: u32,
+    pub step: u32,
+}
+
+impl Axis {
+    pub fn single(value: u32) -> Self {
+        Self {
+            start: value,
+            end: value,
+            step: 1,
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = u32> {
+        (self.start..=self.end).step_by(self.step.max(1) as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start > self.end
+    }
+}
+
+/// Every `(delay, width)` point of the grid, all widths of the first delay first.
+pub fn grid(delays: Axis, widths: Axis) -> impl Iterator<Item = (u32, u32)> {
+    delays
+        .values()
+        .flat_map(move |delay| widths.values().map(move |width| (delay, width)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempt {
+    pub delay: u32,
+    pub width: u32,
+    pub outcome: Outcome,
+}
+
+/// Attempts per outcome.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tally {
+    pub missed: usize,
+    pub normal: usize,
+    pub reset: usize,
+    pub success: usize,
+}
+
+impl Tally {
+    pub fn new(attempts: &[Attempt]) -> Self {
+        let mut tally = Self::default();
+
+        for attempt in attempts {
+            match attempt.outcome {
+                Outcome::Missed => tally.missed += 1,
+                Outcome::Normal => tally.normal += 1,
+                Outcome::Reset => tally.reset += 1,
+                Outcome::Success => tally.success += 1,
+            }
+        }
+
+        tally
+    }
+}
+
+/// Writes the attempts as CSV, with a header.
+pub fn write_table(out: &mut String, attempts: &[Attempt]) {
+    let _ = writeln!(out, "delay,width,outcome");
+
+    for attempt in attempts {
+        let _ = writeln!(
+            out,
+            "{},{},{}",
+            attempt.delay,
+            attempt.width,
+            attempt.outcome.as_str()
+        );
+    }
+}
+
+mod test {
+    #[test]
+    fn test_grid() {
+        use super::{grid, Axis};
+        use alloc::vec::Vec;
+
+        let delays = Axis {
+            start: 10,
+            end: 20,
+            step: 5,
+        };
+        let widths = Axis {
+            start: 3,
+            end: 4,
+            step: 1,
+        };
+
+        assert_eq!(delays.len(), 3);
+        assert_eq!(
+            grid(delays, widths).collect::<Vec<_>>(),
+            [(10, 3), (10, 4), (15, 3), (15, 4), (20, 3), (20, 4)]
+        );
+        assert_eq!(
+            grid(Axis::single(7), Axis::single(9)).collect::<Vec<_>>(),
+            [(7, 9)]
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        use super::{write_table, Attempt, Outcome, Tally};
+        use alloc::string::String;
+
+        let attempts = [
+            Attempt {
+                delay: 10,
+                width: 3,
+                outcome: Outcome::Normal,
+            },
+            Attempt {
+                delay: 10,
+                width: 4,
+                outcome: Outcome::Success,
+            },
+            Attempt {
+                delay: 15,
+                width: 3,
+                outcome: Outcome::Reset,
+            },
+        ];
+
+        let mut table = String::new();
+        write_table(&mut table, &attempts);
+        assert_eq!(
+            table,
+            "delay,width,outcome\n10,3,normal\n10,4,success\n15,3,reset\n"
+        );
+
+        let tally = Tally::new(&attempts);
+        assert_eq!((tally.normal, tally.reset, tally.success), (1, 1, 1));
+        assert_eq!(Outcome::parse("reset"), Some(Outcome::Reset));
+        assert_eq!(Outcome::parse("bogus"), None);
+    }
+}
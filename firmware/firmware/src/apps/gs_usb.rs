@@ -0,0 +1,585 @@
+//! The gs_usb (candleLight) vendor protocol, which the Linux `gs_usb` driver turns into a
+//! native SocketCAN interface.
+//!
+//! Frames go both ways as [`HostFrame`]s on a pair of bulk endpoints, and the channel is set up
+//! with vendor control requests. [`Device`] answers the requests and keeps the channel state;
+//! the ones with side effects come out as [`Command`]s, which `tasks::gs_usb` carries out.
+//!
+//! There is a single classic CAN channel: the MCP2518FD is not driven yet, so CAN FD is not
+//! advertised.
+
+use crate::apps::{
+    rx::{
+        can::Message,
+        pcapng::{CAN_EFF_FLAG, CAN_RTR_FLAG},
+    },
+    tx::can_pio::TxOutcome,
+};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use defmt::Format;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Instant;
+
+// Control requests.
+pub const BREQ_HOST_FORMAT: u8 = 0;
+pub const BREQ_BITTIMING: u8 = 1;
+pub const BREQ_MODE: u8 = 2;
+pub const BREQ_BT_CONST: u8 = 4;
+pub const BREQ_DEVICE_CONFIG: u8 = 5;
+pub const BREQ_TIMESTAMP: u8 = 6;
+pub const BREQ_SET_TERMINATION: u8 = 12;
+pub const BREQ_GET_TERMINATION: u8 = 13;
+
+// Device features, also the mode flags that enable them.
+pub const FEATURE_LISTEN_ONLY: u32 = 1 << 0;
+pub const FEATURE_HW_TIMESTAMP: u32 = 1 << 4;
+pub const FEATURE_TERMINATION: u32 = 1 << 11;
+
+const FEATURES: u32 = FEATURE_LISTEN_ONLY | FEATURE_HW_TIMESTAMP | FEATURE_TERMINATION;
+
+const MODE_RESET: u32 = 0;
+const MODE_START: u32 = 1;
+
+/// Host frame flag for frames received after some were dropped.
+const FLAG_OVERFLOW: u8 = 1 << 0;
+
+/// Echo ID of received frames, transmitted ones are echoed back with the host's.
+pub const RX_ECHO_ID: u32 = u32::MAX;
+
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+/// SocketCAN error classes and details, as in `linux/can/error.h`.
+const CAN_ERR_TX_TIMEOUT: u32 = 0x0000_0001;
+const CAN_ERR_LOSTARB: u32 = 0x0000_0002;
+const CAN_ERR_PROT: u32 = 0x0000_0008;
+const CAN_ERR_ACK: u32 = 0x0000_0020;
+const CAN_ERR_CNT: u32 = 0x0000_0200;
+const CAN_ERR_PROT_BIT: u8 = 0x01;
+const CAN_ERR_DLC: u8 = 8;
+
+/// Nominal CAN clock the bit timing is expressed in. The receiver picks its own timing, only the
+/// resulting bit rate, sample point and SJW matter.
+const FCLK_CAN: u32 = 48_000_000;
+
+/// Bit timing constraints, as for a typical bxCAN at [`FCLK_CAN`].
+const TSEG1: (u32, u32) = (1, 16);
+const TSEG2: (u32, u32) = (1, 8);
+const SJW_MAX: u32 = 4;
+const BRP: (u32, u32) = (1, 1024);
+
+const SW_VERSION: u32 = 2;
+const HW_VERSION: u32 = 1;
+
+/// Host frame length, without and with the timestamp.
+pub const FRAME_LEN: usize = 20;
+pub const FRAME_TS_LEN: usize = 24;
+
+const RX_QUEUE: usize = 32;
+
+/// Received frames, while the channel is started.
+pub static RX_FRAMES: Channel<CriticalSectionRawMutex, (Instant, Message), RX_QUEUE> =
+    Channel::new();
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+/// Set when a received frame is dropped, until taken with [`take_overflow`].
+static OVERFLOW: AtomicBool = AtomicBool::new(false);
+
+/// Starts or stops queueing received frames in [`RX_FRAMES`].
+pub fn set_started(started: bool) {
+    STARTED.store(started, Ordering::Relaxed);
+
+    if !started {
+        RX_FRAMES.clear();
+    }
+}
+
+/// Queues a received frame for the host, if the channel is started.
+pub fn forward(msg: &Message) {
+    if STARTED.load(Ordering::Relaxed) && RX_FRAMES.try_send((Instant::now(), *msg)).is_err() {
+        OVERFLOW.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether received frames were dropped since the last call.
+pub fn take_overflow() -> bool {
+    OVERFLOW.swap(false, Ordering::Relaxed)
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum GsUsbError {
+    /// Unknown request, or malformed data.
+    Invalid,
+    /// Bit timing outside of the advertised constraints.
+    InvalidBitTiming,
+    /// Only channel 0 exists.
+    InvalidChannel(u8),
+}
+
+/// Bit timing, in time quanta of the CAN clock.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceBitTiming {
+    pub prop_seg: u32,
+    pub phase_seg1: u32,
+    pub phase_seg2: u32,
+    pub sjw: u32,
+    pub brp: u32,
+}
+
+impl DeviceBitTiming {
+    fn from_bytes(data: &[u8]) -> Result<Self, GsUsbError> {
+        let [prop_seg, phase_seg1, phase_seg2, sjw, brp] = read_u32s(data)?;
+        let timing = Self {
+            prop_seg,
+            phase_seg1,
+            phase_seg2,
+            sjw,
+            brp,
+        };
+        let tseg1 = prop_seg + phase_seg1;
+
+        if !(TSEG1.0..=TSEG1.1).contains(&tseg1)
+            || !(TSEG2.0..=TSEG2.1).contains(&phase_seg2)
+            || !(1..=SJW_MAX).contains(&sjw)
+            || !(BRP.0..=BRP.1).contains(&brp)
+        {
+            return Err(GsUsbError::InvalidBitTiming);
+        }
+
+        Ok(timing)
+    }
+
+    /// Time quanta per bit.
+    fn quanta(&self) -> u32 {
+        1 + self.prop_seg + self.phase_seg1 + self.phase_seg2
+    }
+
+    pub fn baud(&self) -> u32 {
+        FCLK_CAN / (self.brp * self.quanta())
+    }
+
+    /// Sample point, in percent of the bit time.
+    pub fn sample_point(&self) -> u8 {
+        ((self.quanta() - self.phase_seg2) * 100 / self.quanta()) as u8
+    }
+
+    /// Synchronization jump width, in percent of the bit time.
+    pub fn sjw(&self) -> u8 {
+        (self.sjw * 100 / self.quanta()) as u8
+    }
+}
+
+/// Control requests with side effects, for the caller to carry out.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Starts the channel with the last bit timing set.
+    Start {
+        timing: DeviceBitTiming,
+        listen_only: bool,
+        timestamps: bool,
+    },
+    Stop,
+    /// Switches the 120 ohm termination on or off.
+    SetTermination(bool),
+}
+
+/// gs_usb channel state and control request handler.
+pub struct Device {
+    timing: DeviceBitTiming,
+    /// The 120 ohm termination is on from boot.
+    termination: bool,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Self {
+            // 500 kbit/s, sampled at 87.5%.
+            timing: DeviceBitTiming {
+                prop_seg: 6,
+                phase_seg1: 7,
+                phase_seg2: 2,
+                sjw: 1,
+                brp: 6,
+            },
+            termination: true,
+        }
+    }
+
+    /// Handles a host to device request on channel `value`.
+    pub fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> Result<Option<Command>, GsUsbError> {
+        if request != BREQ_HOST_FORMAT && value != 0 {
+            return Err(GsUsbError::InvalidChannel(value as u8));
+        }
+
+        match request {
+            // Always little-endian, this only dates back to older drivers.
+            BREQ_HOST_FORMAT => Ok(None),
+            BREQ_BITTIMING => {
+                self.timing = DeviceBitTiming::from_bytes(data)?;
+                Ok(None)
+            }
+            BREQ_MODE => {
+                let [mode, flags] = read_u32s(data)?;
+
+                if flags & !FEATURES != 0 {
+                    return Err(GsUsbError::Invalid);
+                }
+
+                match mode {
+                    MODE_RESET => Ok(Some(Command::Stop)),
+                    MODE_START => Ok(Some(Command::Start {
+                        timing: self.timing,
+                        listen_only: flags & FEATURE_LISTEN_ONLY != 0,
+                        timestamps: flags & FEATURE_HW_TIMESTAMP != 0,
+                    })),
+                    _ => Err(GsUsbError::Invalid),
+                }
+            }
+            BREQ_SET_TERMINATION => {
+                let [state] = read_u32s(data)?;
+                self.termination = state != 0;
+                Ok(Some(Command::SetTermination(self.termination)))
+            }
+            _ => Err(GsUsbError::Invalid),
+        }
+    }
+
+    /// Answers a device to host request on channel `value` in `buf`, returning the length.
+    pub fn control_in(
+        &self,
+        request: u8,
+        value: u16,
+        now: Instant,
+        buf: &mut [u8],
+    ) -> Result<usize, GsUsbError> {
+        // The device configuration is asked for before channels are known.
+        if request != BREQ_DEVICE_CONFIG && value != 0 {
+            return Err(GsUsbError::InvalidChannel(value as u8));
+        }
+
+        let words: &[u32] = match request {
+            BREQ_DEVICE_CONFIG => {
+                // Three reserved bytes, then the channel count minus one.
+                &[0, SW_VERSION, HW_VERSION]
+            }
+            BREQ_BT_CONST => &[
+                FEATURES, FCLK_CAN, TSEG1.0, TSEG1.1, TSEG2.0, TSEG2.1, SJW_MAX, BRP.0, BRP.1, 1,
+            ],
+            BREQ_TIMESTAMP => &[now.as_micros() as u32],
+            BREQ_GET_TERMINATION => &[self.termination as u32],
+            _ => return Err(GsUsbError::Invalid),
+        };
+
+        let len = words.len() * 4;
+        let buf = buf.get_mut(..len).ok_or(GsUsbError::Invalid)?;
+
+        for (chunk, word) in buf.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(len)
+    }
+}
+
+fn read_u32s<const N: usize>(data: &[u8]) -> Result<[u32; N], GsUsbError> {
+    if data.len() < N * 4 {
+        return Err(GsUsbError::Invalid);
+    }
+
+    let mut words = [0; N];
+
+    for (word, chunk) in words.iter_mut().zip(data.chunks_exact(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+
+    Ok(words)
+}
+
+/// A CAN frame, as exchanged with the host.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct HostFrame {
+    pub echo_id: u32,
+    /// Identifier, with the SocketCAN flags.
+    pub can_id: u32,
+    pub dlc: u8,
+    pub flags: u8,
+    pub data: [u8; 8],
+}
+
+impl HostFrame {
+    /// Reads a frame sent by the host.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, GsUsbError> {
+        let bytes = bytes.get(..FRAME_LEN).ok_or(GsUsbError::Invalid)?;
+        let [echo_id, can_id] = read_u32s(bytes)?;
+        let (dlc, channel, flags) = (bytes[8], bytes[9], bytes[10]);
+
+        if channel != 0 {
+            return Err(GsUsbError::InvalidChannel(channel));
+        }
+
+        if dlc > 8 || can_id & CAN_ERR_FLAG != 0 {
+            return Err(GsUsbError::Invalid);
+        }
+
+        let mut data = [0; 8];
+        data.copy_from_slice(&bytes[12..20]);
+
+        Ok(Self {
+            echo_id,
+            can_id,
+            dlc,
+            flags,
+            data,
+        })
+    }
+
+    /// A received frame.
+    pub fn from_message(msg: &Message) -> Self {
+        let mut can_id = msg.arb_id();
+
+        if msg.is_extended() {
+            can_id |= CAN_EFF_FLAG;
+        }
+
+        if msg.is_rtr() {
+            can_id |= CAN_RTR_FLAG;
+        }
+
+        let mut data = [0; 8];
+        data[..msg.data().len()].copy_from_slice(msg.data());
+
+        Self {
+            echo_id: RX_ECHO_ID,
+            can_id,
+            dlc: msg.dlc().min(8),
+            flags: 0,
+            data,
+        }
+    }
+
+    /// The error frame reporting a transmit that ended with `outcome`, or `None` if the frame
+    /// went out. `tx_errors` is the transmit error count, reported alongside.
+    pub fn from_tx_outcome(outcome: TxOutcome, tx_errors: u8) -> Option<Self> {
+        let mut data = [0; 8];
+        let class = match outcome {
+            TxOutcome::Sent => return None,
+            TxOutcome::ArbitrationLost => CAN_ERR_LOSTARB,
+            TxOutcome::NoAck => CAN_ERR_ACK,
+            TxOutcome::BitError => {
+                data[2] = CAN_ERR_PROT_BIT;
+                CAN_ERR_PROT
+            }
+            TxOutcome::BusBusy => CAN_ERR_TX_TIMEOUT,
+        };
+        data[6] = tx_errors;
+
+        Some(Self {
+            echo_id: RX_ECHO_ID,
+            can_id: CAN_ERR_FLAG | CAN_ERR_CNT | class,
+            dlc: CAN_ERR_DLC,
+            flags: 0,
+            data,
+        })
+    }
+
+    pub fn arb_id(&self) -> u32 {
+        if self.is_extended() {
+            self.can_id & 0x1FFF_FFFF
+        } else {
+            self.can_id & 0x7FF
+        }
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.can_id & CAN_EFF_FLAG != 0
+    }
+
+    pub fn is_rtr(&self) -> bool {
+        self.can_id & CAN_RTR_FLAG != 0
+    }
+
+    /// The payload bytes present, none for remote frames.
+    pub fn data(&self) -> &[u8] {
+        if self.is_rtr() {
+            &[]
+        } else {
+            &self.data[..self.dlc as usize]
+        }
+    }
+
+    /// Flags the frame as following dropped ones.
+    pub fn set_overflow(&mut self) {
+        self.flags |= FLAG_OVERFLOW;
+    }
+
+    /// Lays the frame out for the host, with a timestamp in microseconds if enabled.
+    pub fn to_bytes(&self, timestamp: Option<u32>) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FRAME_TS_LEN);
+
+        bytes.extend_from_slice(&self.echo_id.to_le_bytes());
+        bytes.extend_from_slice(&self.can_id.to_le_bytes());
+        // Channel 0.
+        bytes.extend_from_slice(&[self.dlc, 0, self.flags, 0]);
+        bytes.extend_from_slice(&self.data);
+
+        if let Some(timestamp) = timestamp {
+            bytes.extend_from_slice(&timestamp.to_le_bytes());
+        }
+
+        bytes
+    }
+}
+
+mod test {
+    #[test]
+    fn test_control() {
+        use super::{
+            Command, Device, DeviceBitTiming, GsUsbError, BREQ_BITTIMING, BREQ_BT_CONST,
+            BREQ_DEVICE_CONFIG, BREQ_GET_TERMINATION, BREQ_MODE, BREQ_SET_TERMINATION,
+            BREQ_TIMESTAMP,
+        };
+        use embassy_time::Instant;
+
+        let words = |words: &[u32]| -> alloc::vec::Vec<u8> {
+            words.iter().flat_map(|word| word.to_le_bytes()).collect()
+        };
+        let mut device = Device::new();
+        let mut buf = [0; 64];
+        let now = Instant::from_micros(0x1_0000_0001);
+
+        let len = device
+            .control_in(BREQ_DEVICE_CONFIG, 1, now, &mut buf)
+            .unwrap();
+        assert_eq!(buf[..len], words(&[0, 2, 1]));
+
+        let len = device.control_in(BREQ_BT_CONST, 0, now, &mut buf).unwrap();
+        assert_eq!(len, 40);
+        assert_eq!(buf[4..8], 48_000_000_u32.to_le_bytes());
+
+        // Wraps around.
+        let len = device.control_in(BREQ_TIMESTAMP, 0, now, &mut buf).unwrap();
+        assert_eq!(buf[..len], words(&[1]));
+
+        // 1 Mbit/s at 75%, as Linux would pick it.
+        let timing = words(&[5, 6, 4, 1, 3]);
+        assert_eq!(device.control_out(BREQ_BITTIMING, 0, &timing), Ok(None));
+
+        let command = device.control_out(BREQ_MODE, 0, &words(&[1, 1 << 4]));
+        let timing = DeviceBitTiming {
+            prop_seg: 5,
+            phase_seg1: 6,
+            phase_seg2: 4,
+            sjw: 1,
+            brp: 3,
+        };
+        assert_eq!(
+            command,
+            Ok(Some(Command::Start {
+                timing,
+                listen_only: false,
+                timestamps: true,
+            }))
+        );
+        assert_eq!(timing.baud(), 1_000_000);
+        assert_eq!(timing.sample_point(), 75);
+        assert_eq!(timing.sjw(), 6);
+
+        assert_eq!(
+            device.control_out(BREQ_MODE, 0, &words(&[0, 0])),
+            Ok(Some(Command::Stop))
+        );
+
+        assert_eq!(
+            device.control_out(BREQ_SET_TERMINATION, 0, &words(&[0])),
+            Ok(Some(Command::SetTermination(false)))
+        );
+        let len = device
+            .control_in(BREQ_GET_TERMINATION, 0, now, &mut buf)
+            .unwrap();
+        assert_eq!(buf[..len], words(&[0]));
+
+        // Loopback is not supported, nor is a zero prescaler or a second channel.
+        assert_eq!(
+            device.control_out(BREQ_MODE, 0, &words(&[1, 1 << 1])),
+            Err(GsUsbError::Invalid)
+        );
+        assert_eq!(
+            device.control_out(BREQ_BITTIMING, 0, &words(&[5, 6, 4, 1, 0])),
+            Err(GsUsbError::InvalidBitTiming)
+        );
+        assert_eq!(
+            device.control_out(BREQ_BITTIMING, 1, &words(&[5, 6, 4, 1, 3])),
+            Err(GsUsbError::InvalidChannel(1))
+        );
+    }
+
+    #[test]
+    fn test_host_frame() {
+        use super::{GsUsbError, HostFrame};
+
+        let mut bytes = [0_u8; 20];
+        bytes[0..4].copy_from_slice(&7_u32.to_le_bytes());
+        bytes[4..8].copy_from_slice(&0x8000_1234_u32.to_le_bytes());
+        bytes[8] = 2;
+        bytes[12..14].copy_from_slice(&[0xAA, 0xBB]);
+
+        let mut frame = HostFrame::from_bytes(&bytes).unwrap();
+        assert_eq!(frame.echo_id, 7);
+        assert_eq!(frame.arb_id(), 0x1234);
+        assert!(frame.is_extended());
+        assert!(!frame.is_rtr());
+        assert_eq!(frame.data(), [0xAA, 0xBB]);
+
+        // Echoed back as is.
+        assert_eq!(frame.to_bytes(None), bytes);
+
+        frame.set_overflow();
+        let echo = frame.to_bytes(Some(0x0102_0304));
+        assert_eq!(echo.len(), 24);
+        assert_eq!(echo[10], 1);
+        assert_eq!(echo[20..], [4, 3, 2, 1]);
+
+        // Another channel, too long, an error frame, and a short one.
+        bytes[9] = 1;
+        assert_eq!(
+            HostFrame::from_bytes(&bytes),
+            Err(GsUsbError::InvalidChannel(1))
+        );
+        bytes[9] = 0;
+        bytes[8] = 9;
+        assert_eq!(HostFrame::from_bytes(&bytes), Err(GsUsbError::Invalid));
+        bytes[8] = 0;
+        bytes[7] = 0x20;
+        assert_eq!(HostFrame::from_bytes(&bytes), Err(GsUsbError::Invalid));
+        assert_eq!(
+            HostFrame::from_bytes(&bytes[..19]),
+            Err(GsUsbError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_tx_error_frame() {
+        use super::{HostFrame, RX_ECHO_ID};
+        use crate::apps::tx::can_pio::TxOutcome;
+
+        assert_eq!(HostFrame::from_tx_outcome(TxOutcome::Sent, 0), None);
+
+        // An error frame with the ACK class and the error count, as a received frame.
+        let bytes = HostFrame::from_tx_outcome(TxOutcome::NoAck, 16)
+            .unwrap()
+            .to_bytes(None);
+        assert_eq!(bytes[0..4], RX_ECHO_ID.to_le_bytes());
+        assert_eq!(bytes[4..8], 0x2000_0220_u32.to_le_bytes());
+        assert_eq!(bytes[8], 8);
+        assert_eq!(bytes[18], 16);
+
+        let frame = HostFrame::from_tx_outcome(TxOutcome::BitError, 8).unwrap();
+        assert_eq!(frame.can_id, 0x2000_0208);
+        assert_eq!(frame.data[2], 1);
+    }
+}
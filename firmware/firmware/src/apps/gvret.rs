@@ -0,0 +1,345 @@
+//! The GVRET binary protocol, which SavvyCAN speaks to GVRET and ESP32RET based adapters.
+//!
+//! The host switches to binary mode with two `0xE7` bytes, then sends commands as `0xF1`, the
+//! command number and its arguments. Received frames go out with a timestamp in microseconds.
+//! [`Gvret`] only parses and keeps the bus state: bus setup and transmitted frames come out as
+//! the same [`Request`]s as SLCAN's, and are carried out the same way.
+//!
+//! There is a single bus, fed by the PIO receiver. There is no remote frame flag: received remote
+//! frames show up as empty data frames.
+
+use crate::apps::slcan::{Frame, Request, Step};
+use alloc::{vec, vec::Vec};
+
+/// Sent twice by the host to enter binary mode.
+pub const BINARY_MODE: u8 = 0xE7;
+const COMMAND: u8 = 0xF1;
+
+// Commands.
+const BUILD_CAN_FRAME: u8 = 0x00;
+const TIME_SYNC: u8 = 0x01;
+const GET_DIG_INPUTS: u8 = 0x02;
+const GET_ANALOG_INPUTS: u8 = 0x03;
+const SET_DIG_OUTPUTS: u8 = 0x04;
+const SETUP_CANBUS: u8 = 0x05;
+const GET_CANBUS_PARAMS: u8 = 0x06;
+const GET_DEVICE_INFO: u8 = 0x07;
+const SET_SINGLEWIRE_MODE: u8 = 0x08;
+const KEEPALIVE: u8 = 0x09;
+const SET_SYSTEM_TYPE: u8 = 0x0A;
+const GET_NUMBUSES: u8 = 0x0C;
+const SET_EXT_BUSES: u8 = 0x0E;
+
+/// Flags of the bus speeds in `SETUP_CANBUS`.
+const SPEED_HAS_FLAGS: u32 = 1 << 31;
+const SPEED_ENABLED: u32 = 1 << 30;
+const SPEED_LISTEN_ONLY: u32 = 1 << 29;
+const SPEED_MASK: u32 = 0xF_FFFF;
+
+/// Flags the identifier of extended frames.
+const ID_EXTENDED: u32 = 1 << 31;
+
+/// Firmware build number, answered to `GET_DEVICE_INFO`.
+const BUILD: u16 = 1;
+
+enum State {
+    /// Waiting for a command.
+    Idle,
+    /// Got `0xF1`, waiting for the command number.
+    Command,
+    /// Collecting the arguments of a command.
+    Args(u8),
+}
+
+/// GVRET bus state and command parser.
+pub struct Gvret {
+    state: State,
+    args: Vec<u8>,
+    open: bool,
+    listen_only: bool,
+    bitrate: u32,
+}
+
+impl Gvret {
+    pub fn new() -> Self {
+        Self {
+            state: State::Idle,
+            args: Vec::new(),
+            open: false,
+            listen_only: false,
+            bitrate: 500_000,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Feeds a byte from the host, and returns what to do once it ends a command. `micros` is
+    /// the time since boot, for time syncs.
+    pub fn push(&mut self, byte: u8, micros: u32) -> Option<Step> {
+        let command = match self.state {
+            // Anything but a command is skipped, including the binary mode switch.
+            State::Idle => {
+                if byte == COMMAND {
+                    self.state = State::Command;
+                }
+
+                return None;
+            }
+            State::Command => {
+                self.args.clear();
+                byte
+            }
+            State::Args(command) => {
+                self.args.push(byte);
+                command
+            }
+        };
+
+        let Some(len) = arg_len(command, &self.args) else {
+            // Unknown, its arguments are skipped as above.
+            self.state = State::Idle;
+            return None;
+        };
+
+        if self.args.len() < len {
+            self.state = State::Args(command);
+            return None;
+        }
+
+        self.state = State::Idle;
+        self.command(command, micros)
+    }
+
+    fn command(&mut self, command: u8, micros: u32) -> Option<Step> {
+        let args = &self.args;
+
+        let step = match command {
+            BUILD_CAN_FRAME => {
+                let id = u32::from_le_bytes([args[0], args[1], args[2], args[3]]);
+                let bus = args[4] & 0x3;
+                let dlc = (args[5] & 0xF).min(8);
+
+                if bus != 0 || !self.open || self.listen_only {
+                    return None;
+                }
+
+                let extended = id & ID_EXTENDED != 0;
+
+                Step::Request(Request::Transmit(Frame {
+                    arb_id: id & if extended { 0x1FFF_FFFF } else { 0x7FF },
+                    extended,
+                    rtr: false,
+                    dlc,
+                    data: args[6..6 + dlc as usize].to_vec(),
+                }))
+            }
+            TIME_SYNC => {
+                let mut reply = vec![COMMAND, TIME_SYNC];
+                reply.extend_from_slice(&micros.to_le_bytes());
+
+                Step::Reply(reply)
+            }
+            SETUP_CANBUS => {
+                // The second bus is ignored.
+                let speed = u32::from_le_bytes([args[0], args[1], args[2], args[3]]);
+                let bitrate = (speed & SPEED_MASK).min(1_000_000);
+                let (enabled, listen_only) = if speed & SPEED_HAS_FLAGS != 0 {
+                    (speed & SPEED_ENABLED != 0, speed & SPEED_LISTEN_ONLY != 0)
+                } else {
+                    (true, false)
+                };
+
+                if enabled && bitrate > 0 {
+                    Step::Request(Request::Open {
+                        bitrate,
+                        listen_only,
+                    })
+                } else if self.open {
+                    Step::Request(Request::Close)
+                } else {
+                    return None;
+                }
+            }
+            GET_CANBUS_PARAMS => {
+                let mut reply = vec![
+                    COMMAND,
+                    GET_CANBUS_PARAMS,
+                    self.open as u8 | (self.listen_only as u8) << 4,
+                ];
+                reply.extend_from_slice(&self.bitrate.to_le_bytes());
+                // The second bus, disabled.
+                reply.extend_from_slice(&[0; 5]);
+
+                Step::Reply(reply)
+            }
+            GET_DEVICE_INFO => {
+                let mut reply = vec![COMMAND, GET_DEVICE_INFO];
+                reply.extend_from_slice(&BUILD.to_le_bytes());
+                // EEPROM version, file output type, auto logging and single wire mode.
+                reply.extend_from_slice(&[0; 4]);
+
+                Step::Reply(reply)
+            }
+            KEEPALIVE => Step::Reply(vec![COMMAND, KEEPALIVE, 0xDE, 0xAD]),
+            GET_NUMBUSES => Step::Reply(vec![COMMAND, GET_NUMBUSES, 1]),
+            // No digital or analog I/O, single wire CAN or LIN.
+            _ => return None,
+        };
+
+        Some(step)
+    }
+
+    /// Reports how a request went. GVRET has no replies to them.
+    pub fn complete(&mut self, request: &Request, ok: bool) {
+        match request {
+            Request::Open {
+                bitrate,
+                listen_only,
+            } if ok => {
+                self.open = true;
+                self.bitrate = *bitrate;
+                self.listen_only = *listen_only;
+            }
+            Request::Close if ok => self.open = false,
+            _ => {}
+        }
+    }
+
+    /// Formats a received frame for the host, timestamped in microseconds since boot.
+    pub fn format_frame(&self, frame: &Frame, micros: u32) -> Vec<u8> {
+        let mut id = frame.arb_id;
+
+        if frame.extended {
+            id |= ID_EXTENDED;
+        }
+
+        let mut bytes = vec![COMMAND, BUILD_CAN_FRAME];
+        bytes.extend_from_slice(&micros.to_le_bytes());
+        bytes.extend_from_slice(&id.to_le_bytes());
+        // Bus 0 in the high nibble.
+        bytes.push(frame.data.len() as u8);
+        bytes.extend_from_slice(&frame.data);
+        // Unchecked checksum.
+        bytes.push(0);
+
+        bytes
+    }
+}
+
+/// Length of the arguments of a command, which for frames depends on the DLC. `None` for
+/// unknown commands.
+fn arg_len(command: u8, args: &[u8]) -> Option<usize> {
+    let len = match command {
+        // Identifier, bus, DLC, data and checksum.
+        BUILD_CAN_FRAME => match args.get(5) {
+            Some(dlc) => 6 + (dlc & 0xF).min(8) as usize + 1,
+            None => 6,
+        },
+        TIME_SYNC | GET_DIG_INPUTS | GET_ANALOG_INPUTS | GET_CANBUS_PARAMS | GET_DEVICE_INFO
+        | KEEPALIVE | GET_NUMBUSES => 0,
+        SET_DIG_OUTPUTS | SET_SINGLEWIRE_MODE | SET_SYSTEM_TYPE => 1,
+        // Both buses' speeds.
+        SETUP_CANBUS => 8,
+        SET_EXT_BUSES => 12,
+        _ => return None,
+    };
+
+    Some(len)
+}
+
+mod test {
+    #[test]
+    fn test_session() {
+        use super::Gvret;
+        use crate::apps::slcan::{Frame, Request, Step};
+        use alloc::vec::Vec;
+
+        let mut gvret = Gvret::new();
+        let mut steps = Vec::new();
+        let session: &[u8] = &[
+            0xE7, 0xE7, // Binary mode
+            0xF1, 0x0C, // Number of buses
+            0xF1, 0x01, // Time sync
+            0xF1, 0x06, // Bus parameters
+            0xF1, 0x05, 0x20, 0xA1, 0x07, 0xC0, 0, 0, 0, 0, // 500k, enabled
+            0xF1, 0x06, // Bus parameters
+            0xF1, 0x00, 0x34, 0x12, 0, 0x80, 0, 2, 0xAA, 0xBB, 0, // Extended frame
+            0xF1, 0x00, 0x23, 0x01, 0, 0, 1, 0, 0, // On the second bus
+            0xF1, 0x42, // Unknown
+            0xF1, 0x09, // Keepalive
+        ];
+
+        for &byte in session {
+            steps.extend(gvret.push(byte, 0x0102_0304));
+
+            // Requests succeed.
+            if let Some(Step::Request(request)) = steps.last() {
+                gvret.complete(request, true);
+            }
+        }
+
+        let reply = |bytes: &[u8]| Step::Reply(bytes.into());
+
+        assert_eq!(
+            steps,
+            [
+                reply(&[0xF1, 0x0C, 1]),
+                reply(&[0xF1, 0x01, 4, 3, 2, 1]),
+                reply(&[0xF1, 0x06, 0, 0x20, 0xA1, 0x07, 0, 0, 0, 0, 0, 0]),
+                Step::Request(Request::Open {
+                    bitrate: 500_000,
+                    listen_only: false,
+                }),
+                reply(&[0xF1, 0x06, 1, 0x20, 0xA1, 0x07, 0, 0, 0, 0, 0, 0]),
+                Step::Request(Request::Transmit(Frame {
+                    arb_id: 0x1234,
+                    extended: true,
+                    rtr: false,
+                    dlc: 2,
+                    data: [0xAA, 0xBB].into(),
+                })),
+                reply(&[0xF1, 0x09, 0xDE, 0xAD]),
+            ]
+        );
+
+        // Disabled, listen only.
+        let steps: Vec<_> = [0xF1, 0x05, 0x20, 0xA1, 0x07, 0x80, 0, 0, 0, 0]
+            .iter()
+            .filter_map(|&byte| gvret.push(byte, 0))
+            .collect();
+        assert_eq!(steps, [Step::Request(Request::Close)]);
+
+        let steps: Vec<_> = [0xF1, 0x05, 0x40, 0x42, 0x0F, 0xE0, 0, 0, 0, 0]
+            .iter()
+            .filter_map(|&byte| gvret.push(byte, 0))
+            .collect();
+        assert_eq!(
+            steps,
+            [Step::Request(Request::Open {
+                bitrate: 1_000_000,
+                listen_only: true,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_format_frame() {
+        use super::Gvret;
+        use crate::apps::slcan::Frame;
+
+        let frame = Frame {
+            arb_id: 0x7FF,
+            extended: false,
+            rtr: false,
+            dlc: 1,
+            data: [0x42].into(),
+        };
+
+        assert_eq!(
+            Gvret::new().format_frame(&frame, 0x0102_0304),
+            [0xF1, 0, 4, 3, 2, 1, 0xFF, 0x07, 0, 0, 1, 0x42, 0]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! MIL-STD-1553-style words over Manchester II bi-phase.
+//!
+//! A word is 20 bit times: a 3 bit sync, 16 data bits from the most significant one and an odd
+//! parity bit. Bits are split in two halves, a 1 being positive then negative and a 0 the other
+//! way around. The sync is 1.5 bits positive then 1.5 bits negative for command and status words,
+//! the other way around for data words, so no data can be mistaken for it.
+//!
+//! Halves are handled as `bool`s, `true` for positive. Through the receiver, a negative half
+//! reads the same as an idle bus, see [`crate::apps::rx::manchester`].
+
+use defmt::Format;
+
+/// Halves in a word.
+pub const WORD_HALVES: usize = 40;
+
+/// Words kept for `mil1553::read` by the receive task, those coming in past it are dropped.
+pub const MAX_RECEIVED: usize = 1024;
+
+const COMMAND_SYNC: u8 = 0b111_000;
+const DATA_SYNC: u8 = 0b000_111;
+const SYNC_MASK: u8 = 0b111_111;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Sync {
+    /// Command and status words.
+    Command,
+    Data,
+}
+
+impl Sync {
+    /// The sync ending with the last six halves, newest in bit 0.
+    fn from_halves(halves: u8) -> Option<Self> {
+        match halves & SYNC_MASK {
+            COMMAND_SYNC => Some(Sync::Command),
+            DATA_SYNC => Some(Sync::Data),
+            _ => None,
+        }
+    }
+
+    fn halves(&self) -> u8 {
+        match self {
+            Sync::Command => COMMAND_SYNC,
+            Sync::Data => DATA_SYNC,
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Word {
+    pub sync: Sync,
+    pub data: u16,
+}
+
+impl Word {
+    pub fn command(data: u16) -> Self {
+        Self {
+            sync: Sync::Command,
+            data,
+        }
+    }
+
+    pub fn data(data: u16) -> Self {
+        Self {
+            sync: Sync::Data,
+            data,
+        }
+    }
+
+    /// The halves of the word, sync first.
+    pub fn halves(&self) -> impl Iterator<Item = bool> {
+        let sync = self.sync.halves();
+        let bits = (self.data as u32) << 1 | parity(self.data) as u32;
+
+        (0..6)
+            .rev()
+            .map(move |i| sync >> i & 1 != 0)
+            .chain((0..17).rev().flat_map(move |i| {
+                let bit = bits >> i & 1 != 0;
+                [bit, !bit]
+            }))
+    }
+}
+
+/// The parity bit of the data, making the number of ones odd.
+pub fn parity(data: u16) -> bool {
+    data.count_ones() % 2 == 0
+}
+
+/// The fields of a command word.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    /// Remote terminal address, 31 for broadcast.
+    pub rt: u8,
+    /// Whether the terminal transmits, rather than receives.
+    pub transmit: bool,
+    /// Subaddress, 0 and 31 for mode codes.
+    pub subaddress: u8,
+    /// Data words, 0 for 32, or the mode code.
+    pub count: u8,
+}
+
+impl Command {
+    pub fn from_word(data: u16) -> Self {
+        Self {
+            rt: (data >> 11) as u8,
+            transmit: data & 1 << 10 != 0,
+            subaddress: (data >> 5 & 0x1F) as u8,
+            count: (data & 0x1F) as u8,
+        }
+    }
+
+    /// The command word. Fields are cut to their width.
+    pub fn to_word(&self) -> u16 {
+        (self.rt as u16 & 0x1F) << 11
+            | (self.transmit as u16) << 10
+            | (self.subaddress as u16 & 0x1F) << 5
+            | self.count as u16 & 0x1F
+    }
+
+    pub fn is_mode_code(&self) -> bool {
+        self.subaddress == 0 || self.subaddress == 31
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Two equal halves within a bit, at the given half from the end of the sync.
+    Manchester(u8),
+    /// A whole word, with the wrong parity.
+    Parity(Word),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Decodes words from a stream of halves, sampled in the middle of each.
+pub struct Decoder {
+    /// The last halves, newest in bit 0.
+    history: u8,
+    /// The sync of the word being read, with its halves and bits so far.
+    word: Option<(Sync, u8, u32)>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            history: 0,
+            word: None,
+        }
+    }
+
+    pub fn push(&mut self, half: bool) -> Option<Result<Word, Error>> {
+        self.history = self.history << 1 | half as u8;
+
+        let Some((sync, halves, bits)) = self.word.as_mut() else {
+            self.word = Sync::from_halves(self.history).map(|sync| (sync, 0, 0));
+            return None;
+        };
+
+        *halves += 1;
+
+        if *halves % 2 == 1 {
+            return None;
+        }
+
+        let bit = match self.history & 0b11 {
+            0b10 => true,
+            0b01 => false,
+            pair => {
+                // A command sync starts with three idle halves then three positive ones, which
+                // look like a data sync until the next bit.
+                let error = match (*sync, *halves, pair) {
+                    (Sync::Data, 2, 0b00) => None,
+                    _ => Some(Err(Error::Manchester(*halves))),
+                };
+
+                self.word = Sync::from_halves(self.history).map(|sync| (sync, 0, 0));
+                return error;
+            }
+        };
+
+        *bits = *bits << 1 | bit as u32;
+
+        if (*halves as usize) < WORD_HALVES - 6 {
+            return None;
+        }
+
+        let word = Word {
+            sync: *sync,
+            data: (*bits >> 1) as u16,
+        };
+        let valid = (*bits & 1 != 0) == parity(word.data);
+        self.word = None;
+
+        Some(if valid {
+            Ok(word)
+        } else {
+            Err(Error::Parity(word))
+        })
+    }
+}
+
+mod test {
+    #[test]
+    fn test_codec() {
+        use super::{parity, Command, Decoder, Error, Word};
+        use alloc::vec::Vec;
+
+        let command = Command {
+            rt: 5,
+            transmit: true,
+            subaddress: 1,
+            count: 2,
+        };
+        assert_eq!(command.to_word(), 0b00101_1_00001_00010);
+        assert_eq!(Command::from_word(command.to_word()), command);
+        assert!(parity(0x0000) && !parity(0x0001));
+
+        let halves = Word::data(0x8000).halves().collect::<Vec<_>>();
+        assert_eq!(halves.len(), super::WORD_HALVES);
+        assert_eq!(
+            halves[..10],
+            [false, false, false, true, true, true, true, false, false, true]
+        );
+        // One bit set, so a 0 parity bit.
+        assert_eq!(halves[38..], [false, true]);
+
+        // A message from idle, back to back, then a word with a bit flipped.
+        let words = [
+            Word::command(command.to_word()),
+            Word::data(0xBEEF),
+            Word::data(0x0000),
+        ];
+        let mut stream = [false; 8].to_vec();
+        stream.extend(words.iter().flat_map(|word| word.halves()));
+        stream.extend([false; 8]);
+
+        let mut flipped = Word::command(0x1234).halves().collect::<Vec<_>>();
+        flipped.swap(10, 11);
+        stream.extend(flipped);
+        stream.extend([false; 8]);
+
+        let mut decoder = Decoder::new();
+        let decoded = stream
+            .iter()
+            .filter_map(|&half| decoder.push(half))
+            .collect::<Vec<_>>();
+
+        assert_eq!(decoded[..3], words.map(Ok));
+        assert_eq!(
+            decoded[3],
+            Err(Error::Parity(Word::command(0x1234 ^ 0x2000)))
+        );
+        assert_eq!(decoded.len(), 4);
+    }
+
+    #[test]
+    fn test_errors() {
+        use super::{Decoder, Error, Word};
+        use alloc::vec::Vec;
+
+        // The line goes idle in the middle of a word.
+        let mut stream = [false; 8].to_vec();
+        stream.extend(Word::data(0xFFFF).halves().take(20));
+        stream.extend([false; 30]);
+        stream.extend(Word::data(0x00FF).halves());
+
+        let mut decoder = Decoder::new();
+        let decoded = stream
+            .iter()
+            .filter_map(|&half| decoder.push(half))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            decoded,
+            [Err(Error::Manchester(16)), Ok(Word::data(0x00FF))]
+        );
+    }
+}
@@ -1,10 +1,23 @@
+pub mod bridge;
+pub mod candump;
 pub mod console;
+pub mod dbc;
 pub mod display;
+pub mod dmx;
+pub mod fuzz;
+pub mod glitch;
+pub mod gs_usb;
+pub mod gvret;
 pub mod logging;
+pub mod mil1553;
 pub mod neopixel;
+pub mod react;
 pub mod rhai_repl;
 pub mod rx;
 pub mod scrolling_console;
+pub mod seatalk;
+pub mod slcan;
+pub mod sniffer;
 pub mod tx;
 pub mod usb_cli;
 #[cfg(feature = "wifi")]
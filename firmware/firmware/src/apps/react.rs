@@ -0,0 +1,252 @@
+//! Reactive transmit: a pre-armed payload sent as soon as the receiver sees a trigger.
+//!
+//! While armed, the receive task hands every CAN message to [`observe_can`] and every serial byte
+//! to [`observe_byte`]. A match signals the transmit task straight away, with the time it was
+//! seen, so the reaction never goes through the REPL. Serial triggers fire on their last byte,
+//! without waiting for the end of the sentence or frame.
+
+use crate::apps::rx::{can::Message, pcapng::MODBUS_GAP_BITS, RxMode};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use defmt::Format;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+
+static MATCHER: Mutex<CriticalSectionRawMutex, RefCell<Option<Matcher>>> =
+    Mutex::new(RefCell::new(None));
+
+/// When the last match was seen, for the transmit task.
+static FIRED: Signal<CriticalSectionRawMutex, Instant> = Signal::new();
+
+/// Starts matching the received traffic.
+pub fn arm(trigger: Trigger) {
+    FIRED.reset();
+    MATCHER.lock(|matcher| matcher.replace(Some(Matcher::new(trigger))));
+}
+
+/// Stops matching, and returns how many matches there were.
+pub fn disarm() -> u32 {
+    FIRED.reset();
+    MATCHER
+        .lock(|matcher| matcher.replace(None))
+        .map_or(0, |matcher| matcher.matches)
+}
+
+/// Hands a received CAN message to the matcher, while armed.
+pub fn observe_can(msg: &Message) {
+    observe(|matcher| matcher.can(msg.arb_id(), msg.data()));
+}
+
+/// Hands a received serial byte to the matcher, while armed.
+pub fn observe_byte(mode: RxMode, baud: u32, byte: u8) {
+    let gap = Duration::from_micros(MODBUS_GAP_BITS * 1_000_000 / baud.max(1) as u64);
+
+    observe(|matcher| matcher.byte(mode, byte, Instant::now(), gap));
+}
+
+fn observe(f: impl FnOnce(&mut Matcher) -> bool) {
+    MATCHER.lock(|matcher| {
+        if let Some(matcher) = matcher.borrow_mut().as_mut() {
+            if f(matcher) {
+                matcher.matches += 1;
+                FIRED.signal(Instant::now());
+            }
+        }
+    });
+}
+
+/// Waits for a match, and returns when it was seen. Matches coming in faster than they are
+/// waited for are merged.
+pub async fn fired() -> Instant {
+    FIRED.wait().await
+}
+
+#[derive(Debug, Format, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// CAN frames where `(id ^ arb_id) & mask == 0`.
+    Can { arb_id: u32, mask: u32 },
+    /// NMEA-0183 sentences starting with these characters after the `$` or `!`, e.g. `GPRMC`.
+    Nmea0183(Vec<u8>),
+    /// Modbus RTU frames for this unit and function code.
+    Modbus { unit: u8, function: u8 },
+    /// These bytes in a row on a serial bus, or in a CAN payload.
+    Bytes(Vec<u8>),
+}
+
+pub struct Matcher {
+    trigger: Trigger,
+    /// Serial bytes of the current sentence or frame, or the last ones for a byte pattern.
+    window: Vec<u8>,
+    /// Whether NMEA-0183 bytes are part of a sentence whose start is still being matched.
+    in_sentence: bool,
+    /// When the last serial byte came in, Modbus frames start after a silence.
+    last: Instant,
+    matches: u32,
+}
+
+impl Matcher {
+    pub fn new(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            window: Vec::new(),
+            in_sentence: false,
+            last: Instant::MIN,
+            matches: 0,
+        }
+    }
+
+    pub fn can(&mut self, arb_id: u32, data: &[u8]) -> bool {
+        match &self.trigger {
+            Trigger::Can { arb_id: id, mask } => (arb_id ^ id) & mask == 0,
+            Trigger::Bytes(pattern) => data.windows(pattern.len()).any(|bytes| bytes == pattern),
+            Trigger::Nmea0183(_) | Trigger::Modbus { .. } => false,
+        }
+    }
+
+    /// Matches a serial byte received at `now`. `gap` is the silence between Modbus frames.
+    pub fn byte(&mut self, mode: RxMode, byte: u8, now: Instant, gap: Duration) -> bool {
+        let silence = now.saturating_duration_since(self.last);
+        self.last = now;
+
+        match &self.trigger {
+            Trigger::Nmea0183(prefix) if mode == RxMode::Nmea0183 => {
+                if byte == b'$' || byte == b'!' {
+                    self.window.clear();
+                    self.in_sentence = true;
+                    return false;
+                }
+
+                if !self.in_sentence {
+                    return false;
+                }
+
+                self.window.push(byte);
+
+                if self.window.len() < prefix.len() {
+                    return false;
+                }
+
+                self.in_sentence = false;
+                self.window == *prefix
+            }
+            Trigger::Modbus { unit, function } if mode == RxMode::Modbus => {
+                if silence > gap {
+                    self.window.clear();
+                }
+
+                if self.window.len() >= 2 {
+                    return false;
+                }
+
+                self.window.push(byte);
+                self.window == [*unit, *function]
+            }
+            Trigger::Bytes(pattern) => {
+                if self.window.len() == pattern.len() {
+                    self.window.remove(0);
+                }
+
+                self.window.push(byte);
+                self.window == *pattern
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Trigger-to-transmit latencies of the reactions, in µs.
+#[derive(Debug, Format, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReactReport {
+    /// Matches seen by the receiver, including those merged into a single reaction.
+    pub matched: u32,
+    pub sent: u32,
+    pub failed: u32,
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+}
+
+impl ReactReport {
+    /// Records a reaction sent `latency` µs after its match, counted to the end of the frame, or
+    /// one the transmitter failed to send.
+    pub fn record(&mut self, latency: Option<u64>) {
+        let Some(latency) = latency else {
+            self.failed += 1;
+            return;
+        };
+
+        if self.sent == 0 {
+            self.min = latency;
+        }
+
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+        self.total += latency;
+        self.sent += 1;
+    }
+
+    /// Mean latency of the reactions sent.
+    pub fn mean(&self) -> u64 {
+        self.total / self.sent.max(1) as u64
+    }
+}
+
+mod test {
+    #[test]
+    fn test_serial_triggers() {
+        use super::{Matcher, Trigger};
+        use crate::apps::rx::RxMode;
+        use alloc::vec::Vec;
+        use embassy_time::{Duration, Instant};
+
+        let gap = Duration::from_micros(4000);
+        let feed = |matcher: &mut Matcher, mode, bytes: &[u8], start: u64| {
+            bytes
+                .iter()
+                .enumerate()
+                .filter(|(i, byte)| {
+                    let now = Instant::from_micros(start + *i as u64 * 1000);
+                    matcher.byte(mode, **byte, now, gap)
+                })
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+
+        let mut nmea = Matcher::new(Trigger::Nmea0183(b"GPRMC".to_vec()));
+        let sentences = b"$GPGGA,1*00\r\n$GPRMC,2*00\r\n!GPRMC";
+        assert_eq!(feed(&mut nmea, RxMode::Nmea0183, sentences, 0), [18, 31]);
+        assert!(feed(&mut nmea, RxMode::Modbus, b"$GPRMC", 0).is_empty());
+
+        // Unit 1, read holding registers, then unit 2 with the same bytes further in the frame.
+        let mut modbus = Matcher::new(Trigger::Modbus {
+            unit: 1,
+            function: 3,
+        });
+        assert_eq!(feed(&mut modbus, RxMode::Modbus, &[1, 3, 0, 0], 0), [1]);
+        assert!(feed(&mut modbus, RxMode::Modbus, &[2, 1, 3], 10_000).is_empty());
+        assert_eq!(feed(&mut modbus, RxMode::Modbus, &[1, 3], 20_000), [1]);
+
+        let mut bytes = Matcher::new(Trigger::Bytes(b"AB".to_vec()));
+        assert_eq!(feed(&mut bytes, RxMode::Modbus, b"AAB_AB", 0), [2, 5]);
+        assert!(bytes.can(0x123, b"_AB_"));
+        assert!(!bytes.can(0x123, b"A_B"));
+    }
+
+    #[test]
+    fn test_report() {
+        use super::ReactReport;
+
+        let mut report = ReactReport::default();
+        report.record(None);
+        report.record(Some(40));
+        report.record(Some(20));
+        report.record(Some(60));
+
+        // Failures have no latency.
+        assert_eq!((report.sent, report.failed), (3, 1));
+        assert_eq!((report.min, report.max, report.mean()), (20, 60, 40));
+    }
+}
@@ -0,0 +1,357 @@
+//! Edge capture and encoding inference for unknown signals on the receive pin.
+//!
+//! A PIO program times the pulses between edges, so a capture costs one word per edge instead of
+//! one bit per sample. The widths are then clustered into a histogram, the shortest recurring one
+//! taken as the time unit, and the run lengths in units matched against the patterns of the
+//! common line encodings.
+
+use alloc::{vec, vec::Vec};
+use defmt::Format;
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    dma::{AnyChannel, Channel},
+    pio::{
+        Common, Config, Direction as PioDirection, FifoJoin, Instance, LoadedProgram, PioPin,
+        StateMachine,
+    },
+    Peri,
+};
+use embassy_time::{with_timeout, Duration};
+use fixed::traits::ToFixed;
+
+/// Largest capture, in edges. The buffer lives on the heap, which is much larger in PSRAM.
+#[cfg(feature = "heap-in-psram")]
+pub const MAX_EDGES: usize = 1024 * 1024;
+#[cfg(not(feature = "heap-in-psram"))]
+pub const MAX_EDGES: usize = 8 * 1024;
+
+/// Pulses needed for an inference.
+pub const MIN_PULSES: usize = 16;
+
+/// Histogram bins reported, the most populated ones.
+pub const HISTOGRAM_BINS: usize = 8;
+
+/// Pulses agreeing within 1/8th are counted as the same width.
+const CLUSTER_SLACK: u32 = 8;
+
+/// Pulses needed for a width to be trusted as the unit.
+const CLUSTER_MIN: u32 = 3;
+
+/// Pulses this many units long (or longer) are taken as the bus idling between messages.
+const IDLE_UNITS: u32 = 11;
+
+/// Longest run of equal bits in a stuffed CAN frame.
+const CAN_MAX_RUN: u32 = 5;
+
+/// Share of pulses (in percent) that has to fit an encoding's pattern.
+const MATCH_PERCENT: usize = 95;
+
+/// This struct represents the edge capture program loaded into pio instruction memory.
+pub struct PioEdgeCaptureProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioEdgeCaptureProgram<'d, PIO> {
+    /// Load the edge capture program into the given pio
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        // NOTE: Runs at clk_sys, counting two cycles per loop. The count is the inverse of what is
+        //       left of x. Pushes alternate between high and low pulses, starting with high.
+        let prg = pio::pio_asm!(
+            r#"
+                wait 1 pin 0                ; start on a known level
+            .wrap_target
+                mov x, ~null
+            high:
+                jmp pin still_high
+                mov isr, ~x                 ; falling edge
+                push noblock
+                mov x, ~null
+            low:
+                jmp pin rose
+                jmp x-- low
+            rose:
+                mov isr, ~x                 ; rising edge
+                push noblock
+            .wrap
+            still_high:
+                jmp x-- high
+            "#
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed edge capture
+pub struct PioEdgeCapture<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    dma: Peri<'d, AnyChannel>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioEdgeCapture<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded edge capture program.
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        dma: Peri<'d, impl Channel>,
+        rx_pin: Peri<'d, impl PioPin>,
+        program: &PioEdgeCaptureProgram<'d, PIO>,
+    ) -> Self {
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+
+        let rx_pin = common.make_pio_pin(rx_pin);
+        cfg.set_in_pins(&[&rx_pin]);
+        cfg.set_jmp_pin(&rx_pin);
+        sm.set_pin_dirs(PioDirection::In, &[&rx_pin]);
+        cfg.fifo_join = FifoJoin::RxOnly;
+        cfg.clock_divider = 1_u32.to_fixed();
+        sm.set_config(&cfg);
+
+        Self {
+            sm,
+            dma: dma.into(),
+        }
+    }
+
+    /// Time the pulses on the pin for `duration`, or until [`MAX_EDGES`] are in.
+    pub async fn capture(&mut self, duration: Duration) -> Edges {
+        // No count reaches this, so it marks the words the DMA has not written.
+        let mut widths = vec![u32::MAX; MAX_EDGES];
+
+        self.sm.clear_fifos();
+        self.sm.restart();
+        self.sm.set_enable(true);
+
+        let transfer = self
+            .sm
+            .rx()
+            .dma_pull(self.dma.reborrow(), &mut widths, false);
+        let _ = with_timeout(duration, transfer).await;
+
+        self.sm.set_enable(false);
+
+        let len = widths
+            .iter()
+            .position(|&count| count == u32::MAX)
+            .unwrap_or(widths.len());
+        widths.truncate(len);
+
+        let clk = clk_sys_freq() as u64;
+
+        for width in widths.iter_mut() {
+            // Two cycles per count, plus the edge detection.
+            *width = ((2 * *width as u64 + 3) * 1_000_000_000 / clk).min(u32::MAX as u64) as u32;
+        }
+
+        // The first pulse started before the capture did.
+        if !widths.is_empty() {
+            widths.remove(0);
+        }
+
+        Edges { widths }
+    }
+}
+
+/// Captured pulse widths in nanoseconds, alternating between low and high, starting with low.
+pub struct Edges {
+    widths: Vec<u32>,
+}
+
+impl Edges {
+    pub fn len(&self) -> usize {
+        self.widths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.widths.is_empty()
+    }
+
+    /// Edge timestamps in nanoseconds, from the first edge.
+    pub fn timestamps(&self) -> impl Iterator<Item = u64> + '_ {
+        self.widths.iter().scan(0_u64, |t, &width| {
+            let edge = *t;
+            *t += width as u64;
+            Some(edge)
+        })
+    }
+
+    /// Whether the `i`th pulse is high.
+    fn is_high(i: usize) -> bool {
+        i % 2 == 1
+    }
+
+    /// Infers the encoding and bit rate of the captured signal.
+    pub fn analyze(&self) -> Report {
+        let mut histogram = histogram(&self.widths);
+        let unit = histogram
+            .iter()
+            .find(|bin| bin.count >= CLUSTER_MIN)
+            .map_or(0, |bin| bin.width);
+
+        let mut report = Report {
+            edges: self.widths.len(),
+            encoding: Encoding::Unknown,
+            bit_rate: 0,
+            unit,
+            idle_high: true,
+            histogram: Vec::new(),
+        };
+
+        if unit > 0 && self.widths.len() >= MIN_PULSES {
+            self.infer(&mut report);
+        }
+
+        histogram.sort_unstable_by_key(|bin| core::cmp::Reverse(bin.count));
+        histogram.truncate(HISTOGRAM_BINS);
+        histogram.sort_unstable_by_key(|bin| bin.width);
+        report.histogram = histogram;
+
+        report
+    }
+
+    fn infer(&self, report: &mut Report) {
+        let unit = report.unit;
+        let units = |width: u32| (width + unit / 2) / unit;
+
+        // Pulses within messages, with their level, and the level the bus idles at.
+        let mut pulses: Vec<(bool, u32)> = Vec::with_capacity(self.widths.len());
+        let mut idle = [0_usize; 2];
+
+        for (i, &width) in self.widths.iter().enumerate() {
+            if units(width) >= IDLE_UNITS {
+                idle[Self::is_high(i) as usize] += 1;
+            } else {
+                pulses.push((Self::is_high(i), width));
+            }
+        }
+
+        report.idle_high = idle[1] >= idle[0];
+
+        if pulses.len() < MIN_PULSES {
+            return;
+        }
+
+        let share = |n: usize| n * 100 / pulses.len();
+        let count = |f: &dyn Fn(u32) -> bool| pulses.iter().filter(|&&(_, w)| f(w)).count();
+
+        // PWM: every bit is a period of constant length, the duty cycle carries the value.
+        let periods: Vec<u32> = pulses
+            .chunks_exact(2)
+            .map(|pair| pair[0].1 + pair[1].1)
+            .collect();
+        let mut sorted = periods.clone();
+        sorted.sort_unstable();
+        let period = sorted[sorted.len() / 2];
+        let steady = periods
+            .iter()
+            .filter(|&&p| p.abs_diff(period) <= period / CLUSTER_SLACK)
+            .count();
+        let active: Vec<u32> = pulses
+            .iter()
+            .filter(|&&(high, _)| high != report.idle_high)
+            .map(|&(_, w)| w)
+            .collect();
+        // Duty cycles under a third and over two thirds, a square wave has neither.
+        let short = active.iter().filter(|&&w| 3 * w < period).count();
+        let long = active.iter().filter(|&&w| 3 * w > 2 * period).count();
+
+        if steady * 100 / periods.len() >= MATCH_PERCENT
+            && (short + long) * 100 / active.len().max(1) >= MATCH_PERCENT
+            && 10 * short >= active.len()
+            && 10 * long >= active.len()
+        {
+            report.encoding = Encoding::Pwm;
+            report.bit_rate = 1_000_000_000 / period.max(1);
+            return;
+        }
+
+        // Manchester: a transition mid-bit, so only half and whole bit pulses.
+        let halves = count(&|w| units(w) == 1);
+        let wholes = count(&|w| units(w) == 2);
+
+        if share(halves + wholes) >= MATCH_PERCENT && wholes > 0 {
+            report.encoding = Encoding::Manchester;
+            report.bit_rate = 1_000_000_000 / (2 * unit);
+            return;
+        }
+
+        // CAN: NRZ with a stuff bit after five equal bits, so no runs longer than five bits.
+        let stuffed = count(&|w| (1..=CAN_MAX_RUN).contains(&units(w)));
+        let longest = pulses.iter().map(|&(_, w)| units(w)).max().unwrap_or(0);
+
+        if share(stuffed) >= MATCH_PERCENT && longest == CAN_MAX_RUN && report.idle_high {
+            report.encoding = Encoding::Can;
+            report.bit_rate = 1_000_000_000 / unit;
+            return;
+        }
+
+        // UART: NRZ framed by start and stop bits, so runs of up to ten bits between idle periods.
+        if share(count(&|w| units(w) >= 1)) >= MATCH_PERCENT {
+            report.encoding = Encoding::Uart;
+            report.bit_rate = 1_000_000_000 / unit;
+        }
+    }
+}
+
+/// Line encodings told apart by [`Edges::analyze`].
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Unknown,
+    /// NRZ framed by start and stop bits.
+    Uart,
+    /// NRZ with bit stuffing.
+    Can,
+    Manchester,
+    /// Pulse width modulation, one bit per period.
+    Pwm,
+}
+
+/// A histogram bin of pulse widths.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct Bin {
+    /// Mean width in nanoseconds.
+    pub width: u32,
+    pub count: u32,
+}
+
+/// Outcome of [`Edges::analyze`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Edges captured.
+    pub edges: usize,
+    pub encoding: Encoding,
+    /// Likely bit rate, 0 when unknown.
+    pub bit_rate: u32,
+    /// Shortest recurring pulse width in nanoseconds, 0 when there is none.
+    pub unit: u32,
+    /// Whether the bus idles high.
+    pub idle_high: bool,
+    /// The most populated bins, by width.
+    pub histogram: Vec<Bin>,
+}
+
+/// Groups the widths into bins spanning 1/[`CLUSTER_SLACK`] of their narrowest width.
+fn histogram(widths: &[u32]) -> Vec<Bin> {
+    let mut sorted = widths.to_vec();
+    sorted.sort_unstable();
+
+    let mut bins = Vec::new();
+    let mut rest = &sorted[..];
+
+    while let Some(&first) = rest.first() {
+        let limit = first + first / CLUSTER_SLACK;
+        let len = rest.iter().take_while(|&&w| w <= limit).count();
+        let mean = rest[..len].iter().map(|&w| w as u64).sum::<u64>() / len as u64;
+
+        bins.push(Bin {
+            width: mean as u32,
+            count: len as u32,
+        });
+        rest = &rest[len..];
+    }
+
+    bins
+}
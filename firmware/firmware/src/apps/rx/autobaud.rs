@@ -0,0 +1,187 @@
+//! Bit-rate detection for the receive port.
+//!
+//! A PIO program times the dominant (low) pulses on the receive pin. The shortest recurring pulse
+//! is taken as the bit time, and the standard rates closest to it are proposed for trial decoding.
+
+use crate::apps::rx::RxMode;
+use alloc::vec::Vec;
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    pio::{
+        Common, Config, Direction as PioDirection, Instance, LoadedProgram, PioPin, StateMachine,
+    },
+    Peri,
+};
+use embassy_time::{with_timeout, Duration, Instant};
+use fixed::traits::ToFixed;
+
+/// Standard rates tried, by the mode that confirms them.
+pub const STANDARD_RATES: [(RxMode, u32); 8] = [
+    (RxMode::Can, 125_000),
+    (RxMode::Can, 250_000),
+    (RxMode::Can, 500_000),
+    (RxMode::Can, 1_000_000),
+    (RxMode::Nmea0183, 4_800),
+    (RxMode::Nmea0183, 9_600),
+    (RxMode::Nmea0183, 38_400),
+    (RxMode::Nmea0183, 115_200),
+];
+
+/// Largest deviation between the measured rate and a proposed standard rate, in percent.
+pub const RATE_TOLERANCE: u32 = 20;
+
+/// Number of pulses timed before estimating.
+pub const PULSE_COUNT: usize = 128;
+
+/// Time spent collecting pulses, at most.
+pub const PULSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time spent trial-decoding a CAN rate, busy buses send many frames a second.
+pub const CAN_TRIAL: Duration = Duration::from_millis(500);
+
+/// Time spent trial-decoding a UART rate, NMEA talkers typically send once a second.
+pub const UART_TRIAL: Duration = Duration::from_millis(2500);
+
+/// Pulses shorter than this are glitches (well under a bit at 1 Mbit/s).
+const MIN_PULSE_NS: u64 = 400;
+
+/// Pulses agreeing within 1/8th are counted as the same width.
+const CLUSTER_SLACK: u32 = 8;
+
+/// Pulses needed for a width to be trusted.
+const CLUSTER_MIN: usize = 3;
+
+/// This struct represents the edge timer program loaded into pio instruction memory.
+pub struct PioEdgeTimerProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioEdgeTimerProgram<'d, PIO> {
+    /// Load the edge timer program into the given pio
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        // NOTE: Runs at clk_sys, counting two cycles per loop. The count is the inverse of what is
+        //       left of x.
+        let prg = pio::pio_asm!(
+            r#"
+            .wrap_target
+                wait 0 pin 0                ; falling edge
+                mov x, ~null
+            low:
+                jmp pin high                ; rising edge
+                jmp x-- low
+            high:
+                mov isr, ~x
+                push noblock
+            .wrap
+            "#
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed low pulse timer
+pub struct PioEdgeTimer<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioEdgeTimer<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded edge timer program.
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        rx_pin: Peri<'d, impl PioPin>,
+        program: &PioEdgeTimerProgram<'d, PIO>,
+    ) -> Self {
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+
+        let rx_pin = common.make_pio_pin(rx_pin);
+        cfg.set_in_pins(&[&rx_pin]);
+        cfg.set_jmp_pin(&rx_pin);
+        sm.set_pin_dirs(PioDirection::In, &[&rx_pin]);
+        cfg.clock_divider = 1_u32.to_fixed();
+        sm.set_config(&cfg);
+
+        Self { sm }
+    }
+
+    /// Time up to [`PULSE_COUNT`] low pulses, giving up after [`PULSE_TIMEOUT`]. Widths are in
+    /// nanoseconds.
+    pub async fn collect(&mut self) -> Vec<u32> {
+        let mut widths = Vec::with_capacity(PULSE_COUNT);
+        let clk = clk_sys_freq() as u64;
+        let deadline = Instant::now() + PULSE_TIMEOUT;
+
+        self.sm.clear_fifos();
+        self.sm.restart();
+        self.sm.set_enable(true);
+
+        while widths.len() < PULSE_COUNT {
+            let left = deadline.saturating_duration_since(Instant::now());
+
+            match with_timeout(left, self.sm.rx().wait_pull()).await {
+                Ok(count) => {
+                    // Two cycles per count, plus the edge detection.
+                    let ns = (2 * count as u64 + 3) * 1_000_000_000 / clk;
+
+                    if ns >= MIN_PULSE_NS {
+                        widths.push(ns.min(u32::MAX as u64) as u32);
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        self.sm.set_enable(false);
+
+        widths
+    }
+}
+
+/// The bit rate implied by the shortest pulse width seen at least [`CLUSTER_MIN`] times.
+pub fn estimate_rate(widths: &mut [u32]) -> Option<u32> {
+    widths.sort_unstable();
+
+    for (i, &width) in widths.iter().enumerate() {
+        let limit = width + width / CLUSTER_SLACK;
+        let cluster = &widths[i..];
+        let len = cluster.iter().take_while(|&&w| w <= limit).count();
+
+        if len >= CLUSTER_MIN {
+            let mean = cluster[..len].iter().map(|&w| w as u64).sum::<u64>() / len as u64;
+
+            return Some((1_000_000_000 / mean.max(1)) as u32);
+        }
+    }
+
+    None
+}
+
+/// Standard rates within [`RATE_TOLERANCE`] of `rate`, closest first.
+pub fn candidates(rate: u32) -> Vec<(RxMode, u32)> {
+    let deviation = |standard: u32| standard.abs_diff(rate) as u64 * 100 / standard as u64;
+    let mut candidates: Vec<(RxMode, u32)> = STANDARD_RATES
+        .iter()
+        .copied()
+        .filter(|&(_, standard)| deviation(standard) <= RATE_TOLERANCE as u64)
+        .collect();
+    candidates.sort_unstable_by_key(|&(_, standard)| deviation(standard));
+
+    candidates
+}
+
+/// Time spent trial-decoding a rate for the given mode.
+pub fn trial_duration(mode: RxMode) -> Duration {
+    match mode {
+        RxMode::Can => CAN_TRIAL,
+        RxMode::Nmea0183
+        | RxMode::Modbus
+        | RxMode::Raw
+        | RxMode::Mil1553
+        | RxMode::Dmx
+        | RxMode::Seatalk => UART_TRIAL,
+    }
+}
@@ -1,17 +1,19 @@
-use crate::apps::rx::SerialParser;
+use crate::apps::rx::{RxError, SerialParser};
 use core::convert::Infallible;
 use defmt::{debug, error, warn, Format};
-use embassy_futures::{select, select::Either3};
+use embassy_futures::{select, select::Either3, yield_now};
 use embassy_rp::{
     clocks::clk_sys_freq,
+    dma::{AnyChannel, Channel},
     pio::{
-        Common, Config, Direction as PioDirection, Instance, Irq, LoadedProgram, PioPin,
-        ShiftDirection, StateMachine,
+        Common, Config, Direction as PioDirection, FifoJoin, Instance, Irq, IrqFlags,
+        LoadedProgram, PioPin, ShiftDirection, StateMachine,
     },
     Peri,
 };
+use embassy_time::{with_timeout, Duration, Timer};
 use embedded_io_async::ErrorType;
-use fixed::traits::ToFixed;
+use fixed::{types::extra::U8, FixedU32};
 
 // MTU as bytes
 pub const CAN_2B_MTU: usize = 22;
@@ -21,6 +23,179 @@ pub enum CanWord {
     Sof,
     Word(u32),
     Ifs,
+    /// A whole frame, decoded bit by bit (ACK mode).
+    Frame(Result<Message, Error>),
+}
+
+/// Time quanta per bit the receiver may run at, preferred first.
+const QUANTA_PER_BIT: [u32; 9] = [16, 20, 24, 12, 10, 32, 28, 18, 14];
+
+/// Largest deviation from the requested baud, in parts per million.
+pub const BAUD_TOLERANCE_PPM: u32 = 1_000;
+
+/// Shortest resynchronization window the receiver can poll for, in time quanta.
+pub const MIN_SJW_QUANTA: u32 = 2;
+
+/// Bit timing of the PIO CAN receiver.
+///
+/// The receiver runs at `quanta` PIO cycles per bit. It hard syncs on SOF, then resyncs on every
+/// recessive to dominant edge seen between its sample point and `sjw` past the nominal bit start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitTiming {
+    baud: u32,
+    /// Sample point, in percent of the bit time.
+    sample_point: u8,
+    /// Synchronization jump width, in percent of the bit time.
+    sjw: u8,
+    quanta: u32,
+    clock_divider: FixedU32<U8>,
+    /// Program delays and counts, see [`PioCanRxProgram`].
+    sof_delay: u8,
+    bit_delay: u8,
+    dominant_delay: u8,
+    edge_delay: u8,
+    polls: u8,
+}
+
+impl BitTiming {
+    pub const DEFAULT_SAMPLE_POINT: u8 = 75;
+    pub const DEFAULT_SJW: u8 = 15;
+
+    /// Pick the receiver timing closest to the request. Prefers quanta counts for which the PIO
+    /// clock divider is an integer, as fractional dividers jitter.
+    pub fn new(baud: u32, sample_point: u8, sjw: u8) -> Result<Self, RxError> {
+        if !(50..=95).contains(&sample_point) {
+            return Err(RxError::InvalidSamplePoint(sample_point));
+        }
+
+        if sjw == 0 || sjw > 50 {
+            return Err(RxError::InvalidSjw(sjw));
+        }
+
+        if baud == 0 {
+            return Err(RxError::BaudOutOfTolerance(baud));
+        }
+
+        let clk = clk_sys_freq() as u64;
+        let mut best: Option<(u32, Self)> = None;
+        let mut sjw_ok = false;
+
+        for quanta in QUANTA_PER_BIT {
+            let Some(timing) = Self::with_quanta(baud, sample_point, sjw, quanta) else {
+                continue;
+            };
+            sjw_ok = true;
+
+            // Divider in 16.8 fixed point, rounded to nearest.
+            let divisor = quanta as u64 * baud as u64;
+            let bits = (clk * 256 + divisor / 2) / divisor;
+
+            if bits < 256 || bits > 0xFFFF_FF00 {
+                continue;
+            }
+
+            let actual = clk * 256 / (bits * quanta as u64);
+            let error = (actual.abs_diff(baud as u64) * 1_000_000 / baud as u64) as u32;
+            let timing = Self {
+                clock_divider: FixedU32::<U8>::from_bits(bits as u32),
+                ..timing
+            };
+
+            if clk % divisor == 0 {
+                return Ok(timing);
+            }
+
+            if best.map_or(true, |(best_error, _)| error < best_error) {
+                best = Some((error, timing));
+            }
+        }
+
+        if !sjw_ok {
+            return Err(RxError::InvalidSjw(sjw));
+        }
+
+        match best {
+            Some((error, timing)) if error <= BAUD_TOLERANCE_PPM => Ok(timing),
+            _ => Err(RxError::BaudOutOfTolerance(baud)),
+        }
+    }
+
+    /// Program delays for the given quanta per bit, if they fit.
+    fn with_quanta(baud: u32, sample_point: u8, sjw: u8, quanta: u32) -> Option<Self> {
+        // Sample point and window end, in quanta from the (nominal) bit start.
+        let sample = (quanta * sample_point as u32 + 50) / 100;
+        let window = ((quanta * sjw as u32 + 50) / 100).max(MIN_SJW_QUANTA);
+
+        // See the cycle counts in `PioCanRxProgram`.
+        let polls = (window + quanta).checked_sub(sample + 4)? / 2;
+        let bit_delay = quanta.checked_sub(7 + 2 * polls)?;
+        let edge_delay = sample.checked_sub(4 + bit_delay)?;
+        let dominant_delay = quanta.checked_sub(5 + bit_delay)?;
+
+        if [polls, bit_delay, edge_delay, dominant_delay]
+            .iter()
+            .any(|&v| v > 31)
+        {
+            return None;
+        }
+
+        Some(Self {
+            baud,
+            sample_point,
+            sjw,
+            quanta,
+            clock_divider: FixedU32::<U8>::from_num(1),
+            sof_delay: edge_delay as u8,
+            bit_delay: bit_delay as u8,
+            dominant_delay: dominant_delay as u8,
+            edge_delay: edge_delay as u8,
+            polls: polls as u8,
+        })
+    }
+
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    pub fn sample_point(&self) -> u8 {
+        self.sample_point
+    }
+
+    pub fn sjw(&self) -> u8 {
+        self.sjw
+    }
+
+    pub fn clock_divider(&self) -> FixedU32<U8> {
+        self.clock_divider
+    }
+
+    /// Quanta between the start of a bit and the receiver raising [`IRQ_BIT`] for it.
+    fn bit_irq_offset(&self) -> u32 {
+        self.edge_delay as u32 + 3
+    }
+
+    /// Quanta between the start of a bit and the receiver sampling it.
+    fn sample_offset(&self) -> u32 {
+        self.edge_delay as u32 + self.bit_delay as u32 + 4
+    }
+
+    /// Delay of the identifier matcher after SOF, see [`PioCanMatchProgram`].
+    fn match_sof_delay(&self) -> u8 {
+        self.sample_offset().saturating_sub(4) as u8
+    }
+
+    /// Delay of the identifier matcher per bit, see [`PioCanMatchProgram`].
+    fn match_bit_delay(&self) -> u8 {
+        (self.quanta - 4) as u8
+    }
+}
+
+fn set_delay(instr: &mut u16, delay: u8) {
+    *instr = (*instr & !(0x1F << 8)) | ((delay as u16) << 8);
+}
+
+fn set_data(instr: &mut u16, data: u8) {
+    *instr = (*instr & !0x1F) | data as u16;
 }
 
 /// This struct represents a Uart Rx program loaded into pio instruction memory.
@@ -29,35 +204,55 @@ pub struct PioCanRxProgram<'d, PIO: Instance> {
 }
 
 impl<'d, PIO: Instance> PioCanRxProgram<'d, PIO> {
-    /// Load the uart rx program into the given pio
-    pub fn new(common: &mut Common<'d, PIO>) -> Self {
-        let prg = pio::pio_asm!(
+    /// Load the CAN rx program, patched for the given timing, into the given pio
+    pub fn new(common: &mut Common<'d, PIO>, timing: &BitTiming) -> Self {
+        // NOTE: With the `in` of a bit at cycle 0 and Q quanta per bit, the next `in` lands on
+        //       cycle Q through all three paths:
+        //         dominant: no edge can follow, wait out the bit (5 + DOMINANT + BIT).
+        //         recessive, no edge: poll until the window closes (7 + 2 * POLLS + BIT).
+        //         recessive, edge at cycle T: restart the bit from the edge (T + 3 + EDGE + BIT,
+        //         which puts the sample point at EDGE + BIT + 4 after the edge).
+        //       The delays and the poll count are patched in by `BitTiming`.
+        let mut prg = pio::pio_asm!(
             r#"
-                wait_ifs:
-                    set x, 0b11                 ; we need 3 recessive bits before we start
-                    mov isr, x                  ; 3 bits * 16 (oversample) / 2 (instructions) = 24 = 0x18 = 0b11<<3
-                    in null, 3                  ; shift left 3
-                ifs_loop:
-                    jmp x-- idle                ; got enough ifs, jump to idle
-                    jmp pin ifs_loop            ; recessive just keep counting
-                    jmp wait_ifs                ; got dominant, reset counter
                 idle:
-                    mov isr, null               ; clear ISR
                     wait 0 pin 0                ; wait for dominant bit
-                    irq 0 [3]                   ; IRQ0: SOF
-                receive_bit:
+                    irq nowait 4                ; IRQ4: SOF (consumed by the identifier matcher)
+                public sof:
+                    irq 0                       ; IRQ0: SOF [EDGE]
+                .wrap_target
+                public bit:
+                    irq nowait 5                ; IRQ5: bit (consumed by the ACK trigger) [BIT]
                     in pins, 1                  ; shift bit into ISR
-                    jmp pin recessive [4]       ; got recessive bit
-                    set x, 6                    ; dominant, reset counter
-                    jmp receive_bit
+                    jmp pin recessive           ; got recessive bit
+                public dominant:
+                    set x, 6                    ; dominant, reset counter [DOMINANT]
+                    jmp bit
                 recessive:
-                    jmp x-- receive_bit [1]     ; did not get EOF
+                    jmp x-- resync              ; did not get EOF
                     irq 1                       ; Got EOF (irq1)
                     push                        ; flush pending data
-                    jmp wait_ifs
+                    jmp idle                    ; back to waiting for SOF
+                public resync:
+                    set y, 0                    ; [POLLS]
+                poll:
+                    jmp pin still_recessive     ; look for an edge
+                public edge:
+                    jmp bit                     ; got one, resync [EDGE]
+                still_recessive:
+                    jmp y-- poll
+                .wrap
             "#
         );
 
+        let defines = &prg.public_defines;
+        let code = &mut prg.program.code;
+        set_delay(&mut code[defines.sof as usize], timing.sof_delay);
+        set_delay(&mut code[defines.bit as usize], timing.bit_delay);
+        set_delay(&mut code[defines.dominant as usize], timing.dominant_delay);
+        set_delay(&mut code[defines.edge as usize], timing.edge_delay);
+        set_data(&mut code[defines.resync as usize], timing.polls);
+
         let prg = common.load_program(&prg.program);
 
         Self { prg }
@@ -74,7 +269,7 @@ pub struct PioCanRx<'d, PIO: Instance, const SM: usize> {
 impl<'d, PIO: Instance, const SM: usize> PioCanRx<'d, PIO, SM> {
     /// Configure a pio state machine to use the loaded rx program.
     pub fn new(
-        baud: u32,
+        timing: &BitTiming,
         common: &mut Common<'d, PIO>,
         mut sm_rx: StateMachine<'d, PIO, SM>,
         rx_pin: Peri<'d, impl PioPin>,
@@ -82,6 +277,7 @@ impl<'d, PIO: Instance, const SM: usize> PioCanRx<'d, PIO, SM> {
         program: &PioCanRxProgram<'d, PIO>,
         irq_sof: Irq<'d, PIO, 0>,
         irq_ifs: Irq<'d, PIO, 1>,
+        bitwise: bool,
     ) -> Self {
         let mut cfg = Config::default();
         // let debug_pin = common.make_pio_pin(debug_pin);
@@ -95,17 +291,24 @@ impl<'d, PIO: Instance, const SM: usize> PioCanRx<'d, PIO, SM> {
         sm_rx.set_pin_dirs(PioDirection::In, &[&rx_pin]);
         // sm_rx.set_pin_dirs(PioDirection::Out, &[&debug_pin]);
 
-        // TODO: Check if the clock divider is an integer.
-        cfg.clock_divider = (clk_sys_freq() / (8 * baud)).to_fixed();
-        warn!(
-            "SYS CLOCK: {:?} CLOCK DIV: {:?}",
+        cfg.clock_divider = timing.clock_divider();
+        debug!(
+            "SYS CLOCK: {:?} TIMING: {:?}",
             defmt::Debug2Format(&clk_sys_freq()),
-            defmt::Debug2Format(&cfg.clock_divider)
+            defmt::Debug2Format(timing)
         );
         cfg.shift_in.auto_fill = true;
         cfg.shift_in.direction = ShiftDirection::Left;
-        cfg.shift_in.threshold = 32;
-        // cfg.fifo_join = FifoJoin::RxOnly;
+
+        if bitwise {
+            // Push every bit as it is sampled, see `PioCan::read_frame`.
+            cfg.shift_in.threshold = 1;
+            cfg.fifo_join = FifoJoin::RxOnly;
+        } else {
+            cfg.shift_in.threshold = 32;
+            // cfg.fifo_join = FifoJoin::RxOnly;
+        }
+
         sm_rx.set_config(&cfg);
 
         // flush
@@ -151,6 +354,344 @@ impl<'d, PIO: Instance, const SM: usize> PioCanRx<'d, PIO, SM> {
     }
 }
 
+/// PIO IRQ flag raised by the receiver program on every SOF.
+pub const IRQ_MATCH_SOF: usize = 4;
+
+/// Maximum number of stuffed bits the identifier matcher can compare.
+pub const MATCH_PATTERN_MAX_LEN: usize = 27;
+
+/// This struct represents the identifier matcher program loaded into pio instruction memory.
+pub struct PioCanMatchProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioCanMatchProgram<'d, PIO> {
+    /// Load the identifier matcher program, timed for the receiver, into the given pio
+    pub fn new(common: &mut Common<'d, PIO>, timing: &BitTiming) -> Self {
+        // NOTE: Runs at the receiver's quanta per bit. The pattern word holds the number of bits to
+        //       compare (minus one) in the top 5 bits, followed by the stuffed SOF and identifier
+        //       bits, MSB first. On a full match, IRQ6 is raised on the previous PIO block (PIO1),
+        //       which hosts the transmitter.
+        // NOTE: With the SOF seen at cycle 0, the first `jmp pin` lands on cycle 4 + SOF, and each
+        //       bit takes 4 + BIT cycles. Both delays are patched in from `BitTiming`, putting the
+        //       reads on the receiver's sample points (without its resyncs).
+        let mut prg = pio::pio_asm!(
+            r#"
+                pull block                  ; get the pattern word
+                mov isr, osr                ; keep a copy of it in the ISR
+            .wrap_target
+            idle:
+                mov osr, isr                ; restore the pattern
+                out x, 5                    ; x = pattern length - 1
+            public sof:
+                wait 1 irq 4                ; IRQ4: SOF, align to the receiver's sample point [SOF]
+            compare:
+                out y, 1                    ; y = expected bit
+                jmp pin bus_recessive
+            bus_dominant:
+                jmp !y next_bit             ; expected dominant, got dominant
+                jmp idle                    ; mismatch
+            bus_recessive:
+                jmp !y idle                 ; expected dominant, got recessive
+            public next_bit:
+                jmp x-- compare             ; [BIT]
+                irq 6 prev                  ; IRQ6 (PIO1): identifier matched
+            .wrap
+            "#
+        );
+
+        let defines = &prg.public_defines;
+        let code = &mut prg.program.code;
+        set_delay(&mut code[defines.sof as usize], timing.match_sof_delay());
+        set_delay(
+            &mut code[defines.next_bit as usize],
+            timing.match_bit_delay(),
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed CAN identifier matcher, cooperating with [`PioCanRx`] on the same PIO block.
+pub struct PioCanMatch<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    origin: u8,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioCanMatch<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded matcher program.
+    pub fn new(
+        timing: &BitTiming,
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        rx_pin: Peri<'d, impl PioPin>,
+        program: &PioCanMatchProgram<'d, PIO>,
+    ) -> Self {
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+
+        let rx_pin = common.make_pio_pin(rx_pin);
+        cfg.set_jmp_pin(&rx_pin);
+        sm.set_pin_dirs(PioDirection::In, &[&rx_pin]);
+
+        // Must track the receiver's clock exactly.
+        cfg.clock_divider = timing.clock_divider();
+        cfg.shift_out.auto_fill = false;
+        cfg.shift_out.direction = ShiftDirection::Left;
+        sm.set_config(&cfg);
+
+        Self {
+            sm,
+            origin: program.prg.origin,
+        }
+    }
+
+    /// Load a new pattern (see [`crate::platform::repl::can::id_pattern`]) and start matching.
+    pub fn arm(&mut self, irq_flags: &IrqFlags<'d, PIO>, pattern: u32) {
+        self.disarm();
+        self.sm.clear_fifos();
+        self.sm.restart();
+        unsafe { self.sm.exec_jmp(self.origin) };
+        // Drop any SOF seen while we were not listening.
+        irq_flags.clear(IRQ_MATCH_SOF);
+        let _ = self.sm.tx().try_push(pattern);
+        self.sm.set_enable(true);
+    }
+
+    pub fn disarm(&mut self) {
+        if self.sm.is_enabled() {
+            self.sm.set_enable(false);
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.sm.is_enabled()
+    }
+}
+
+/// PIO IRQ flag raised by the receiver program on every bit, just ahead of sampling it.
+pub const IRQ_BIT: usize = 5;
+
+/// Upper bound of bits read for a single frame in ACK mode, error frames included.
+pub const MAX_FRAME_BITS: usize = 256;
+
+/// PIO IRQ flag raised by the receiver program after 7 recessive bits, the end of a frame.
+const IRQ_EOF: usize = 1;
+
+/// Highest baud of ACK mode. The trigger is loaded between sampling the last CRC bit and the
+/// start of the next, a window the CPU does not make past this.
+pub const MAX_ACK_BAUD: u32 = 250_000;
+
+/// Bits ahead of the ACK slot [`PioCan::read_frame`] stops sleeping and polls the DMA.
+const ACK_GUARD_BITS: usize = 8;
+
+/// This struct represents the ACK trigger program loaded into pio instruction memory.
+pub struct PioCanAckProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioCanAckProgram<'d, PIO> {
+    /// Load the ACK trigger program, timed for the receiver, into the given pio
+    pub fn new(common: &mut Common<'d, PIO>, timing: &BitTiming) -> Self {
+        // NOTE: One shot per word pushed. Raises IRQ6 on the previous PIO block (PIO1) in step with
+        //       the receiver's next bit, which fires the transmitter's overwrite. The delay lines the
+        //       overwrite up with the start of the following bit.
+        let mut prg = pio::pio_asm!(
+            r#"
+                pull block                  ; armed by the CPU
+            public bit:
+                wait 1 irq 5                ; IRQ5: next bit [DELAY]
+                irq 6 prev                  ; IRQ6 (PIO1): drive the ACK slot
+            "#
+        );
+
+        // The overwrite needs ~3.5 of its 8 cycles per bit to go dominant.
+        let lead = 7 * timing.quanta / 16 + 2 + timing.bit_irq_offset();
+        let delay = timing.quanta.saturating_sub(lead).min(31) as u8;
+        set_delay(
+            &mut prg.program.code[prg.public_defines.bit as usize],
+            delay,
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed ACK trigger, cooperating with [`PioCanRx`] on the same PIO block.
+pub struct PioCanAck<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioCanAck<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded ACK trigger program.
+    pub fn new(
+        timing: &BitTiming,
+        mut sm: StateMachine<'d, PIO, SM>,
+        program: &PioCanAckProgram<'d, PIO>,
+    ) -> Self {
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+        // Must track the receiver's clock exactly.
+        cfg.clock_divider = timing.clock_divider();
+        sm.set_config(&cfg);
+
+        Self { sm }
+    }
+
+    pub fn enable(&mut self) {
+        self.sm.clear_fifos();
+        self.sm.restart();
+        self.sm.set_enable(true);
+    }
+
+    pub fn disable(&mut self) {
+        if self.sm.is_enabled() {
+            self.sm.set_enable(false);
+        }
+    }
+
+    /// Fire the overwrite on the receiver's next bit.
+    pub fn trigger(&mut self, irq_flags: &IrqFlags<'d, PIO>) {
+        // The receiver raises IRQ5 on every bit, drop the stale one.
+        irq_flags.clear(IRQ_BIT);
+        let _ = self.sm.tx().try_push(0);
+    }
+}
+
+/// The CAN receiver and its helpers sharing a PIO block.
+pub struct PioCan<'d, PIO: Instance> {
+    pub rx: PioCanRx<'d, PIO, 0>,
+    pub matcher: PioCanMatch<'d, PIO, 1>,
+    pub ack: PioCanAck<'d, PIO, 2>,
+    pub irq_flags: IrqFlags<'d, PIO>,
+    /// Moves the bits into memory in ACK mode, see [`PioCan::read_frame`].
+    dma: Peri<'d, AnyChannel>,
+    bit: Duration,
+}
+
+impl<'d, PIO: Instance> PioCan<'d, PIO> {
+    /// Bundle the receiver and its helpers, `dma` being spare for the receiver in ACK mode.
+    pub fn new(
+        timing: &BitTiming,
+        rx: PioCanRx<'d, PIO, 0>,
+        matcher: PioCanMatch<'d, PIO, 1>,
+        ack: PioCanAck<'d, PIO, 2>,
+        irq_flags: IrqFlags<'d, PIO>,
+        dma: Peri<'d, impl Channel>,
+    ) -> Self {
+        Self {
+            rx,
+            matcher,
+            ack,
+            irq_flags,
+            dma: dma.into(),
+            bit: Duration::from_hz(timing.baud() as u64),
+        }
+    }
+
+    /// Receive a whole frame bit by bit, acknowledging it if it passes CRC and `filter` (an
+    /// identifier and a mask). The receiver must have been set up bitwise.
+    ///
+    /// The ACK is driven by the transmitter's overwrite, which must be armed for it.
+    pub async fn read_frame(&mut self, filter: Option<(u32, u32)>) -> CanWord {
+        // The receiver flushes an empty word after 7 recessive bits, drop the last frame's.
+        while self.rx.sm_rx.rx().try_pull().is_some() {}
+        self.irq_flags.clear(IRQ_EOF);
+        self.rx.irq_sof.wait().await;
+
+        let mut bits = [0u32; MAX_FRAME_BITS];
+        let base = bits.as_mut_ptr();
+        let regs = self.dma.regs();
+        // SAFETY: `bits` outlives the transfer, and is only read behind the DMA's write address.
+        let transfer = self.rx.sm_rx.rx().dma_pull(
+            self.dma.reborrow(),
+            unsafe { core::slice::from_raw_parts_mut(base, MAX_FRAME_BITS) },
+            false,
+        );
+        let written = || (regs.write_addr().read() as usize - base as usize) / 4;
+        let bit_at = |i: usize| unsafe { base.add(i).read_volatile() } & 0b1 == 1;
+
+        let mut decoder = BitDecoder::new();
+        let mut result = None;
+        let mut read = 0;
+
+        // Up to the ACK slot, sleeping while the DMA fills `bits` and polling the last few.
+        while result.is_none() {
+            let ahead = decoder.bits_to_ack_slot();
+
+            if ahead == 0 {
+                break;
+            }
+
+            if ahead == 1 {
+                // NOTE: The trigger has to be loaded between sampling the last CRC bit and the
+                //       start of the next, so only that bit is decoded with interrupts off.
+                cortex_m::interrupt::free(|_| {
+                    while written() == read {}
+
+                    let step = decoder.push(bit_at(read));
+                    read += 1;
+
+                    match step {
+                        Step::Pending => {}
+                        Step::AckSlot { arb_id, crc_ok } => {
+                            // With bits behind it the slot is too close, acknowledge nothing.
+                            let on_time = written() == read;
+
+                            match filter {
+                                Some((id, mask))
+                                    if on_time && crc_ok && (arb_id ^ id) & mask == 0 =>
+                                {
+                                    self.ack.trigger(&self.irq_flags);
+                                }
+                                _ => {}
+                            }
+                        }
+                        Step::Done(done) => result = Some(done),
+                    }
+                });
+            } else if written() > read {
+                if let Step::Done(done) = decoder.push(bit_at(read)) {
+                    result = Some(done);
+                }
+                read += 1;
+            } else if ahead > ACK_GUARD_BITS {
+                Timer::after(self.bit * (ahead - ACK_GUARD_BITS) as u32).await;
+            } else {
+                yield_now().await;
+            }
+        }
+
+        // The rest once the receiver saw the end of the frame, or of the error frame after it.
+        let _ = with_timeout(self.bit * MAX_FRAME_BITS as u32, self.rx.irq_ifs.wait()).await;
+        let mut ones = (0..read).rev().take_while(|&i| bit_at(i)).count();
+
+        while result.is_none() && read < written() {
+            let bit = bit_at(read);
+
+            // The receiver flushes an empty word after 7 recessive bits.
+            if ones == 7 {
+                break;
+            }
+
+            ones = if bit { ones + 1 } else { 0 };
+            read += 1;
+
+            if let Step::Done(done) = decoder.push(bit) {
+                result = Some(done);
+            }
+        }
+
+        drop(transfer);
+
+        CanWord::Frame(result.unwrap_or_else(|| decoder.finish()))
+    }
+}
+
 impl<PIO: Instance, const SM: usize> ErrorType for PioCanRx<'_, PIO, SM> {
     type Error = Infallible;
 }
@@ -205,6 +746,8 @@ pub enum State {
 pub struct Message {
     /// Arbitration ID
     arb_id: u32,
+    /// Extended (29-bit) identifier
+    extended: bool,
     /// Remote transmit request
     rtr: bool,
     /// Number of payload bytes present
@@ -218,8 +761,29 @@ pub struct Message {
 }
 
 impl Message {
+    pub fn arb_id(&self) -> u32 {
+        self.arb_id
+    }
+
     pub fn is_extended(&self) -> bool {
-        (self.arb_id >> 12) > 0
+        self.extended
+    }
+
+    pub fn is_rtr(&self) -> bool {
+        self.rtr
+    }
+
+    pub fn dlc(&self) -> u8 {
+        self.dlc
+    }
+
+    /// The payload bytes present, none for remote frames.
+    pub fn data(&self) -> &[u8] {
+        if self.rtr {
+            &[]
+        } else {
+            &self.payload[..self.dlc.min(8) as usize]
+        }
     }
 }
 
@@ -236,6 +800,7 @@ pub enum Error {
     InvalidChecksum(u16, u16),
     InvalidEof,
     InvalidIfs,
+    InvalidStuffBit,
 }
 
 impl core::fmt::Display for Error {
@@ -246,6 +811,273 @@ impl core::fmt::Display for Error {
 
 impl core::error::Error for Error {}
 
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Field {
+    Sof,
+    IdA,
+    SrrRtr,
+    Ide,
+    IdB,
+    Rtr,
+    R1,
+    R0,
+    Dlc,
+    Data,
+    Crc,
+    CrcDelim,
+    Ack,
+    AckDelim,
+    Eof,
+}
+
+/// Result of feeding a bit to the [`BitDecoder`].
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Pending,
+    /// The next bit is the CRC delimiter, the ACK slot follows it.
+    AckSlot {
+        arb_id: u32,
+        crc_ok: bool,
+    },
+    Done(Result<Message, Error>),
+}
+
+/// Bit level CAN 2.0B decoder, fed with the bits on the wire (stuff bits included) from the SOF.
+pub struct BitDecoder {
+    field: Field,
+    stuffing: bool,
+    prev: bool,
+    run: u8,
+    left: u8,
+    acc: u32,
+    crc: u16,
+    msg: Message,
+}
+
+impl BitDecoder {
+    pub fn new() -> Self {
+        BitDecoder {
+            field: Field::Sof,
+            stuffing: true,
+            // The bus idles recessive.
+            prev: true,
+            run: 0,
+            left: 0,
+            acc: 0,
+            crc: 0,
+            msg: Message {
+                arb_id: 0,
+                extended: false,
+                rtr: false,
+                dlc: 0,
+                payload: [0; 8],
+                crc: 0,
+                ack: false,
+            },
+        }
+    }
+
+    fn field(&mut self, field: Field, len: u8) {
+        self.field = field;
+        self.left = len;
+        self.acc = 0;
+    }
+
+    fn crc_ok(&self) -> bool {
+        self.msg.crc == self.crc
+    }
+
+    /// Lower bound of the bits still to push up to [`Step::AckSlot`], leaving out stuff bits and
+    /// fields not known yet. Zero once past it.
+    pub fn bits_to_ack_slot(&self) -> usize {
+        let left = self.left as usize;
+
+        match self.field {
+            Field::Sof => 34,
+            Field::IdA | Field::IdB => left + 22,
+            Field::SrrRtr | Field::Rtr => 22,
+            Field::Ide | Field::R1 => 21,
+            Field::R0 => 20,
+            Field::Dlc | Field::Data => left + 15,
+            Field::Crc => left,
+            // The trailing stuff bit after the CRC sequence is still to come.
+            Field::CrcDelim => self.stuffing as usize,
+            Field::Ack | Field::AckDelim | Field::Eof => 0,
+        }
+    }
+
+    pub fn push(&mut self, bit: bool) -> Step {
+        if self.stuffing && self.run == 5 {
+            if bit == self.prev {
+                return Step::Done(Err(Error::InvalidStuffBit));
+            }
+
+            self.prev = bit;
+            self.run = 1;
+
+            if self.field == Field::CrcDelim {
+                // Trailing stuff bit after the CRC sequence.
+                self.stuffing = false;
+
+                return Step::AckSlot {
+                    arb_id: self.msg.arb_id,
+                    crc_ok: self.crc_ok(),
+                };
+            }
+
+            return Step::Pending;
+        }
+
+        if self.stuffing {
+            self.run = if bit == self.prev { self.run + 1 } else { 1 };
+            self.prev = bit;
+        }
+
+        if self.field < Field::Crc {
+            self.update_crc(bit);
+        }
+
+        self.acc = (self.acc << 1) | bit as u32;
+        self.left = self.left.saturating_sub(1);
+
+        match self.field {
+            Field::Sof => {
+                if bit {
+                    return Step::Done(Err(Error::InvalidSof));
+                }
+
+                self.field(Field::IdA, 11);
+            }
+            Field::IdA => {
+                if self.left == 0 {
+                    self.msg.arb_id = self.acc;
+                    self.field(Field::SrrRtr, 1);
+                }
+            }
+            Field::SrrRtr => {
+                self.msg.rtr = bit;
+                self.field(Field::Ide, 1);
+            }
+            Field::Ide => {
+                if !bit {
+                    self.field(Field::R0, 1);
+                } else if !self.msg.rtr {
+                    return Step::Done(Err(Error::ExpectedSrr));
+                } else {
+                    self.msg.extended = true;
+                    self.field(Field::IdB, 18);
+                }
+            }
+            Field::IdB => {
+                if self.left == 0 {
+                    self.msg.arb_id = (self.msg.arb_id << 18) | self.acc;
+                    self.field(Field::Rtr, 1);
+                }
+            }
+            Field::Rtr => {
+                self.msg.rtr = bit;
+                self.field(Field::R1, 1);
+            }
+            Field::R1 => {
+                self.field(Field::R0, 1);
+            }
+            Field::R0 => {
+                self.field(Field::Dlc, 4);
+            }
+            Field::Dlc => {
+                if self.left == 0 {
+                    self.msg.dlc = self.acc as u8;
+                    let len = if self.msg.rtr { 0 } else { self.msg.dlc.min(8) };
+
+                    if len == 0 {
+                        self.field(Field::Crc, 15);
+                    } else {
+                        self.field(Field::Data, 8 * len);
+                    }
+                }
+            }
+            Field::Data => {
+                if self.left % 8 == 0 {
+                    let len = self.msg.dlc.min(8);
+                    self.msg.payload[(len - 1 - self.left / 8) as usize] = self.acc as u8;
+                    self.acc = 0;
+                }
+
+                if self.left == 0 {
+                    self.field(Field::Crc, 15);
+                }
+            }
+            Field::Crc => {
+                if self.left == 0 {
+                    self.msg.crc = self.acc as u16;
+                    self.field(Field::CrcDelim, 1);
+
+                    if self.run != 5 {
+                        self.stuffing = false;
+
+                        return Step::AckSlot {
+                            arb_id: self.msg.arb_id,
+                            crc_ok: self.crc_ok(),
+                        };
+                    }
+                }
+            }
+            Field::CrcDelim => {
+                if !bit {
+                    return Step::Done(Err(Error::InvalidCrcDelim));
+                }
+
+                self.field(Field::Ack, 1);
+            }
+            Field::Ack => {
+                self.msg.ack = !bit;
+                self.field(Field::AckDelim, 1);
+            }
+            Field::AckDelim => {
+                if !bit {
+                    return Step::Done(Err(Error::InvalidAckDelim));
+                }
+
+                self.field(Field::Eof, 7);
+            }
+            Field::Eof => {
+                if !bit {
+                    return Step::Done(Err(Error::InvalidEof));
+                }
+
+                if self.left == 0 {
+                    return Step::Done(self.finish());
+                }
+            }
+        }
+
+        Step::Pending
+    }
+
+    /// Wrap up once the receiver has seen the end of the frame.
+    pub fn finish(&self) -> Result<Message, Error> {
+        if self.field != Field::Eof {
+            Err(Error::InvalidEof)
+        } else if !self.crc_ok() {
+            Err(Error::InvalidChecksum(self.msg.crc, self.crc))
+        } else {
+            Ok(self.msg)
+        }
+    }
+
+    fn update_crc(&mut self, bit: bool) {
+        const POLY: u16 = 0x4599;
+        let msb = (self.crc >> 14) & 0x1;
+        self.crc <<= 1;
+
+        if msb ^ (bit as u16) != 0 {
+            self.crc ^= POLY;
+        }
+
+        self.crc &= 0x7FFF;
+    }
+}
+
 pub struct Parser {
     state: State,
 }
@@ -357,7 +1189,7 @@ fn remove_stuff_bits(
 
 impl SerialParser for Parser {
     type Word = CanWord;
-    type Message = ();
+    type Message = Message;
     type Error = Error;
 
     fn parse_word(&mut self, word: Self::Word) -> Option<Result<Self::Message, Self::Error>> {
@@ -372,6 +1204,10 @@ impl SerialParser for Parser {
 
                 self.state = State::Start0;
             }
+            CanWord::Frame(result) => {
+                self.state = State::Sof;
+                return Some(result);
+            }
             CanWord::Word(word) => {
                 let has_eof = contains_eof(word);
                 warn!("GOT WORD {:08X} EOF {}", word, has_eof);
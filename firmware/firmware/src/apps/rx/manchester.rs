@@ -0,0 +1,156 @@
+//! PIO sampler for Manchester (bi-phase) signals, see [`crate::apps::mil1553`].
+//!
+//! The program samples the middle of every half bit, restarting its timing on every edge, so
+//! only the runs of equal halves between edges (at most two in a bit, four around a sync) are
+//! timed from the clock. Samples are pushed 32 at a time. Once the line has been idle for a few
+//! halves, the rest of the word is filled with idle halves and pushed, and the program waits for
+//! the line to go positive again.
+
+use crate::apps::rx::RxError;
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    pio::{
+        Common, Config, Direction as PioDirection, FifoJoin, Instance, LoadedProgram, PioPin,
+        ShiftDirection, StateMachine,
+    },
+    Peri,
+};
+use fixed::{types::extra::U8, FixedU32};
+
+/// PIO cycles per half bit.
+pub const HALF_CYCLES: u32 = 16;
+
+/// 1553 runs at 1 Mbit/s.
+pub const DEFAULT_BAUD: u32 = 1_000_000;
+
+/// This struct represents the Manchester sampler program loaded into pio instruction memory.
+pub struct PioManchesterRxProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioManchesterRxProgram<'d, PIO> {
+    /// Load the Manchester sampler program into the given pio
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        // NOTE: The receive pin is low on a positive half, and high on a negative or idle one.
+        //       With an `in` at cycle 0, the next one lands on cycle 16 (HALF_CYCLES) when no edge
+        //       comes, the polls covering cycles 3 to 14. An edge restarts the timing, putting the
+        //       next `in` 7 or 8 cycles after it.
+        let prg = pio::pio_asm!(
+            r#"
+                    mov osr, ~null              ; all ones, shifted in as idle halves
+                .wrap_target
+                idle:
+                    wait 0 pin 0                ; the line goes positive
+                fell:
+                    nop [4]                     ; to the middle of the half
+                positive:
+                    in pins, 1                  ; sample a positive half
+                    set y, 5 [1]
+                positive_poll:
+                    jmp pin rose                ; the line goes negative, resync
+                    jmp y-- positive_poll
+                    jmp positive                ; still positive a half later
+                rose:
+                    set x, 3 [5]                ; idle after four negative halves
+                negative:
+                    in pins, 1                  ; sample a negative half
+                    set y, 5 [1]
+                negative_poll:
+                    jmp pin still_negative
+                    jmp fell                    ; the line goes positive, resync
+                still_negative:
+                    jmp y-- negative_poll
+                    jmp x-- negative            ; still negative a half later
+                    set y, 31                   ; idle, push the samples
+                pad:
+                    in osr, 1
+                    jmp pin still_idle
+                    jmp fell                    ; the line goes positive again
+                still_idle:
+                    jmp y-- pad
+                .wrap
+            "#
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed Manchester sampler
+pub struct PioManchesterRx<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioManchesterRx<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded sampler program.
+    pub fn new(
+        clock_divider: FixedU32<U8>,
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        rx_pin: Peri<'d, impl PioPin>,
+        program: &PioManchesterRxProgram<'d, PIO>,
+    ) -> Self {
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+
+        let rx_pin = common.make_pio_pin(rx_pin);
+        cfg.set_in_pins(&[&rx_pin]);
+        cfg.set_jmp_pin(&rx_pin);
+        sm.set_pin_dirs(PioDirection::In, &[&rx_pin]);
+
+        cfg.clock_divider = clock_divider;
+        cfg.shift_in.auto_fill = true;
+        cfg.shift_in.threshold = 32;
+        cfg.shift_in.direction = ShiftDirection::Left;
+        cfg.fifo_join = FifoJoin::RxOnly;
+        sm.set_config(&cfg);
+
+        let rx = sm.rx();
+        while let Some(_) = rx.try_pull() {}
+        sm.restart();
+
+        Self { sm }
+    }
+
+    pub fn enable(&mut self) {
+        if !self.sm.is_enabled() {
+            self.sm.set_enable(true);
+        }
+    }
+
+    pub fn disable(&mut self) {
+        if self.sm.is_enabled() {
+            self.sm.set_enable(false);
+        }
+    }
+
+    /// The next 32 halves, oldest in the most significant bit, set when not positive.
+    pub async fn read_word(&mut self) -> u32 {
+        self.sm.rx().wait_pull().await
+    }
+}
+
+/// The PIO clock divider for the given bit rate, if the PIO can run that fast or slow.
+pub fn clock_divider(baud: u32) -> Result<FixedU32<U8>, RxError> {
+    if baud == 0 {
+        return Err(RxError::BaudOutOfTolerance(baud));
+    }
+
+    // Divider in 16.8 fixed point, rounded to nearest.
+    let clk = clk_sys_freq() as u64;
+    let divisor = 2 * HALF_CYCLES as u64 * baud as u64;
+    let bits = (clk * 256 + divisor / 2) / divisor;
+
+    if bits < 256 || bits > 0xFFFF_FF00 {
+        return Err(RxError::BaudOutOfTolerance(baud));
+    }
+
+    Ok(FixedU32::<U8>::from_bits(bits as u32))
+}
+
+/// The halves of a sampled word, oldest first, `true` when positive.
+pub fn halves(word: u32) -> impl Iterator<Item = bool> {
+    (0..32).rev().map(move |i| word >> i & 1 == 0)
+}
@@ -1,21 +1,44 @@
+pub mod analyze;
+pub mod autobaud;
 pub mod can;
+pub mod manchester;
 pub mod modbus;
 pub mod nmea0183;
+pub mod pcapng;
+pub mod raw;
+pub mod stream;
+pub mod uart9;
 
 use crate::{
-    apps::rx::can::{PioCanRx, PioCanRxProgram},
+    apps::{
+        dmx::{self, DmxWord},
+        rx::{
+            analyze::{PioEdgeCapture, PioEdgeCaptureProgram, Report},
+            autobaud::{PioEdgeTimer, PioEdgeTimerProgram},
+            can::{
+                BitTiming, PioCan, PioCanAck, PioCanAckProgram, PioCanMatch, PioCanMatchProgram,
+                PioCanRx, PioCanRxProgram,
+            },
+            manchester::{PioManchesterRx, PioManchesterRxProgram},
+            raw::{Capture, CaptureConfig, ExportFormat, PioRaw, PioRawProgram},
+            uart9::{PioUart9Rx, PioUart9RxProgram},
+        },
+        seatalk,
+    },
     platform::{i2c_io_expander, i2c_io_expander::models::pca9536::PCA9536, irqs::Irqs},
 };
-use defmt::{error, Format};
+use alloc::vec::Vec;
+use defmt::{debug, error, Format};
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_rp::{
     i2c,
     peripherals::{DMA_CH4, I2C0, PIN_9, PIO2, UART1},
     pio::Pio,
-    uart::{Async, Config, UartRx},
+    uart::{self, Async, Config, UartRx},
     Peri,
 };
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{with_timeout, Duration, Instant};
 
 pub trait SerialParser {
     type Word;
@@ -34,11 +57,50 @@ pub trait SerialParser {
     fn default_baud() -> u32;
 }
 
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum RxError {
+    InvalidMode(RxMode),
+    /// The sample point (in percent of the bit time) is out of range.
+    InvalidSamplePoint(u8),
+    /// The SJW (in percent of the bit time) is out of range, or too short for the receiver.
+    InvalidSjw(u8),
+    /// The baud cannot be hit within [`can::BAUD_TOLERANCE_PPM`].
+    BaudOutOfTolerance(u32),
+    /// Autobaud saw no usable pulses on the receive pin.
+    NoSignal,
+    /// Autobaud measured the given rate, but no candidate decoded.
+    NoMatch(u32),
+    /// The capture rate or depth is out of range, see [`raw::CaptureConfig`].
+    InvalidCapture,
+    /// The capture trigger did not fire in time.
+    CaptureTimeout,
+    /// Samples were lost within the capture, the DMA not keeping up with the sample rate.
+    CaptureOverrun,
+    /// There is no capture to export.
+    NoCapture,
+    /// The export needs the given bytes, more than the heap has free. Export it in chunks.
+    ExportTooLarge(usize),
+    /// ACK mode does not keep up with the given baud, see [`can::MAX_ACK_BAUD`].
+    AckBaudTooHigh(u32),
+}
+
+impl core::fmt::Display for RxError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl core::error::Error for RxError {}
+
 #[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
 pub enum RxMode {
     Nmea0183,
     Modbus,
     Can,
+    Raw,
+    Mil1553,
+    Dmx,
+    Seatalk,
 }
 
 impl From<RxWord> for RxMode {
@@ -47,13 +109,19 @@ impl From<RxWord> for RxMode {
             RxWord::Nmea0183(_) => RxMode::Nmea0183,
             RxWord::Modbus(_) => RxMode::Modbus,
             RxWord::Can(_) => RxMode::Can,
+            RxWord::Mil1553(_) => RxMode::Mil1553,
+            RxWord::Dmx(_) => RxMode::Dmx,
+            RxWord::Seatalk(_) => RxMode::Seatalk,
         }
     }
 }
 
 pub enum RxState {
     Uart(UartRx<'static, Async>),
-    Pio(PioCanRx<'static, PIO2, 0>),
+    Pio(PioCan<'static, PIO2>),
+    Raw(PioRaw<'static, PIO2, 0>),
+    Manchester(PioManchesterRx<'static, PIO2, 0>),
+    Seatalk(PioUart9Rx<'static, PIO2, 0>),
 }
 
 #[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
@@ -61,6 +129,22 @@ pub enum RxWord {
     Nmea0183(<nmea0183::Parser as SerialParser>::Word),
     Modbus(u8),
     Can(<can::Parser as SerialParser>::Word),
+    /// 32 Manchester halves, see [`manchester::halves`].
+    Mil1553(u32),
+    Dmx(<dmx::Parser as SerialParser>::Word),
+    /// A 9 bit word, see [`seatalk::COMMAND_BIT`].
+    Seatalk(<seatalk::Parser as SerialParser>::Word),
+}
+
+/// What the receiver was set up for, to put it back after borrowing it, see
+/// [`RxController::restore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RxSetup {
+    mode: RxMode,
+    enabled: bool,
+    ack: Option<(u32, u32)>,
+    timing: BitTiming,
+    uart_baud: u32,
 }
 
 pub struct RxController {
@@ -76,6 +160,16 @@ pub struct RxController {
     mode: RxMode,
     state: RxState,
     enabled: bool,
+    /// Identifier and mask of the frames acknowledged in ACK mode, `None` when listening only.
+    ack: Option<(u32, u32)>,
+    /// Bit timing of the PIO receiver (CAN mode).
+    timing: BitTiming,
+    /// Baud of the UART receiver (NMEA-0183, Modbus and DMX512 modes).
+    uart_baud: u32,
+    /// Bit rate of the Manchester sampler (MIL-STD-1553 mode).
+    manchester_baud: u32,
+    /// Last capture (raw mode), kept for exporting.
+    capture: Option<Capture>,
 }
 
 impl RxController {
@@ -99,38 +193,25 @@ impl RxController {
         let pio_cpy = pio.clone_unchecked();
         let dma_cpy = dma.clone_unchecked();
         let rx_pin_cpy = rx_pin.clone_unchecked();
+        let timing = BitTiming::new(
+            can::Parser::default_baud(),
+            BitTiming::DEFAULT_SAMPLE_POINT,
+            BitTiming::DEFAULT_SJW,
+        )
+        .expect("default CAN timing is valid");
+
+        let uart_baud = Self::default_uart_baud(mode);
 
         let state = match mode {
-            RxMode::Nmea0183 => {
-                let mut cfg = Config::default();
-                cfg.baudrate = nmea0183::Parser::default_baud();
-                RxState::Uart(UartRx::new(uart_cpy, rx_pin_cpy, Irqs, dma_cpy, cfg))
-            }
-            RxMode::Modbus => {
-                let mut cfg = Config::default();
-                cfg.baudrate = 9600; // TODO modbus::Parser::default_baud();
-                RxState::Uart(UartRx::new(uart_cpy, rx_pin_cpy, Irqs, dma_cpy, cfg))
+            RxMode::Nmea0183 | RxMode::Modbus | RxMode::Dmx => {
+                Self::new_uart_state(uart_cpy, rx_pin_cpy, dma_cpy, uart_baud)
             }
-            RxMode::Can => {
-                let Pio {
-                    mut common,
-                    sm0,
-                    irq0,
-                    irq1,
-                    ..
-                } = Pio::new(pio_cpy, Irqs);
-                let can_rx_prog = PioCanRxProgram::new(&mut common);
-
-                RxState::Pio(PioCanRx::new(
-                    can::Parser::default_baud(),
-                    &mut common,
-                    sm0,
-                    rx_pin_cpy,
-                    &can_rx_prog,
-                    irq0,
-                    irq1,
-                ))
+            RxMode::Can => Self::new_can_state(pio_cpy, rx_pin_cpy, dma_cpy, false, &timing),
+            RxMode::Raw => Self::new_raw_state(pio_cpy, rx_pin_cpy, dma_cpy),
+            RxMode::Mil1553 => {
+                Self::new_manchester_state(pio_cpy, rx_pin_cpy, manchester::DEFAULT_BAUD)
             }
+            RxMode::Seatalk => Self::new_seatalk_state(pio_cpy, rx_pin_cpy),
         };
 
         RxController {
@@ -141,16 +222,171 @@ impl RxController {
             mode,
             state: state,
             enabled: false,
+            ack: None,
+            timing,
+            uart_baud,
+            manchester_baud: manchester::DEFAULT_BAUD,
+            capture: None,
             pwr_receiver,
         }
     }
 
+    fn default_uart_baud(mode: RxMode) -> u32 {
+        match mode {
+            RxMode::Nmea0183 => nmea0183::Parser::default_baud(),
+            RxMode::Modbus => 9600, // TODO modbus::Parser::default_baud();
+            RxMode::Dmx => dmx::Parser::default_baud(),
+            RxMode::Can | RxMode::Raw | RxMode::Mil1553 | RxMode::Seatalk => {
+                nmea0183::Parser::default_baud()
+            }
+        }
+    }
+
+    unsafe fn new_uart_state(
+        uart: Peri<'static, UART1>,
+        rx_pin: Peri<'static, PIN_9>,
+        dma: Peri<'static, DMA_CH4>,
+        baud: u32,
+    ) -> RxState {
+        let mut cfg = Config::default();
+        cfg.baudrate = baud;
+        RxState::Uart(UartRx::new(uart, rx_pin, Irqs, dma, cfg))
+    }
+
+    unsafe fn new_can_state(
+        pio: Peri<'static, PIO2>,
+        rx_pin: Peri<'static, PIN_9>,
+        dma: Peri<'static, DMA_CH4>,
+        bitwise: bool,
+        timing: &BitTiming,
+    ) -> RxState {
+        let Pio {
+            mut common,
+            irq_flags,
+            sm0,
+            sm1,
+            sm2,
+            irq0,
+            irq1,
+            ..
+        } = Pio::new(pio, Irqs);
+        let can_rx_prog = PioCanRxProgram::new(&mut common, timing);
+        let can_match_prog = PioCanMatchProgram::new(&mut common, timing);
+        let can_ack_prog = PioCanAckProgram::new(&mut common, timing);
+
+        let can_rx = PioCanRx::new(
+            timing,
+            &mut common,
+            sm0,
+            rx_pin.clone_unchecked(),
+            &can_rx_prog,
+            irq0,
+            irq1,
+            bitwise,
+        );
+        let can_match = PioCanMatch::new(timing, &mut common, sm1, rx_pin, &can_match_prog);
+        let can_ack = PioCanAck::new(timing, sm2, &can_ack_prog);
+
+        RxState::Pio(PioCan::new(
+            timing, can_rx, can_match, can_ack, irq_flags, dma,
+        ))
+    }
+
+    unsafe fn new_raw_state(
+        pio: Peri<'static, PIO2>,
+        rx_pin: Peri<'static, PIN_9>,
+        dma: Peri<'static, DMA_CH4>,
+    ) -> RxState {
+        let Pio {
+            mut common, sm0, ..
+        } = Pio::new(pio, Irqs);
+        let raw_prog = PioRawProgram::new(&mut common);
+
+        RxState::Raw(PioRaw::new(&mut common, sm0, dma, rx_pin, &raw_prog))
+    }
+
+    unsafe fn new_manchester_state(
+        pio: Peri<'static, PIO2>,
+        rx_pin: Peri<'static, PIN_9>,
+        baud: u32,
+    ) -> RxState {
+        let Pio {
+            mut common, sm0, ..
+        } = Pio::new(pio, Irqs);
+        let prog = PioManchesterRxProgram::new(&mut common);
+        let clock_divider =
+            manchester::clock_divider(baud).expect("Manchester bit rate is checked when set");
+
+        RxState::Manchester(PioManchesterRx::new(
+            clock_divider,
+            &mut common,
+            sm0,
+            rx_pin,
+            &prog,
+        ))
+    }
+
+    unsafe fn new_seatalk_state(pio: Peri<'static, PIO2>, rx_pin: Peri<'static, PIN_9>) -> RxState {
+        let Pio {
+            mut common, sm0, ..
+        } = Pio::new(pio, Irqs);
+        let prog = PioUart9RxProgram::new(&mut common);
+        let clock_divider =
+            uart9::clock_divider(seatalk::BAUD).expect("SeaTalk baud is within the PIO's range");
+
+        RxState::Seatalk(PioUart9Rx::new(
+            clock_divider,
+            &mut common,
+            sm0,
+            rx_pin,
+            &prog,
+        ))
+    }
+
+    /// Builds the receiver state of the given mode, from the current configuration.
+    unsafe fn new_state(&self, mode: RxMode) -> RxState {
+        match mode {
+            RxMode::Nmea0183 | RxMode::Modbus | RxMode::Dmx => Self::new_uart_state(
+                self.uart.clone_unchecked(),
+                self.rx_pin.clone_unchecked(),
+                self.dma.clone_unchecked(),
+                self.uart_baud,
+            ),
+            RxMode::Can => Self::new_can_state(
+                self.pio.clone_unchecked(),
+                self.rx_pin.clone_unchecked(),
+                self.dma.clone_unchecked(),
+                self.ack.is_some(),
+                &self.timing,
+            ),
+            RxMode::Raw => Self::new_raw_state(
+                self.pio.clone_unchecked(),
+                self.rx_pin.clone_unchecked(),
+                self.dma.clone_unchecked(),
+            ),
+            RxMode::Mil1553 => Self::new_manchester_state(
+                self.pio.clone_unchecked(),
+                self.rx_pin.clone_unchecked(),
+                self.manchester_baud,
+            ),
+            RxMode::Seatalk => {
+                Self::new_seatalk_state(self.pio.clone_unchecked(), self.rx_pin.clone_unchecked())
+            }
+        }
+    }
+
     pub async fn enable(&mut self) {
         match &mut self.state {
-            RxState::Uart(_) => {}
-            RxState::Pio(pio_rx) => {
-                pio_rx.enable();
+            RxState::Uart(_) | RxState::Raw(_) => {}
+            RxState::Pio(pio) => {
+                pio.rx.enable();
+
+                if self.ack.is_some() {
+                    pio.ack.enable();
+                }
             }
+            RxState::Manchester(rx) => rx.enable(),
+            RxState::Seatalk(rx) => rx.enable(),
         }
 
         self.pwr_receiver.set_output(false).await;
@@ -159,10 +395,14 @@ impl RxController {
 
     pub async fn disable(&mut self) {
         match &mut self.state {
-            RxState::Uart(_) => {}
-            RxState::Pio(pio_rx) => {
-                pio_rx.disable();
+            RxState::Uart(_) | RxState::Raw(_) => {}
+            RxState::Pio(pio) => {
+                pio.rx.disable();
+                pio.matcher.disarm();
+                pio.ack.disable();
             }
+            RxState::Manchester(rx) => rx.disable(),
+            RxState::Seatalk(rx) => rx.disable(),
         }
 
         self.pwr_receiver.set_output(true).await;
@@ -184,66 +424,16 @@ impl RxController {
             self.disable().await;
         }
 
-        match self.mode {
-            RxMode::Nmea0183 | RxMode::Modbus => {
-                match mode {
-                    RxMode::Nmea0183 | RxMode::Modbus => {
-                        // Don't need to swap controllers.
-                    }
-                    RxMode::Can => {
-                        // Swap from UART to PIO.
-                        let Pio {
-                            mut common,
-                            sm0,
-                            irq0,
-                            irq1,
-                            ..
-                        } = Pio::new(self.pio.clone_unchecked(), Irqs);
-                        let can_rx_prog = PioCanRxProgram::new(&mut common);
-
-                        // TODO: Need to make sure the old state gets dropped.
-                        self.state = RxState::Pio(PioCanRx::new(
-                            can::Parser::default_baud(),
-                            &mut common,
-                            sm0,
-                            self.rx_pin.clone_unchecked(),
-                            &can_rx_prog,
-                            irq0,
-                            irq1,
-                        ))
-                    }
-                }
+        self.ack = None;
+        self.uart_baud = Self::default_uart_baud(mode);
+
+        match (self.mode, mode) {
+            (RxMode::Nmea0183 | RxMode::Modbus, RxMode::Nmea0183 | RxMode::Modbus) => {
+                // Don't need to swap controllers.
             }
-            RxMode::Can => {
-                match mode {
-                    RxMode::Nmea0183 => {
-                        // Swap from PIO to UART.
-                        let mut cfg = Config::default();
-                        cfg.baudrate = nmea0183::Parser::default_baud();
-                        self.state = RxState::Uart(UartRx::new(
-                            self.uart.clone_unchecked(),
-                            self.rx_pin.clone_unchecked(),
-                            Irqs,
-                            self.dma.clone_unchecked(),
-                            cfg,
-                        ));
-                    }
-                    RxMode::Modbus => {
-                        // Swap from PIO to UART.
-                        let mut cfg = Config::default();
-                        cfg.baudrate = 9600; // TODO modbus::Parser::default_baud();
-                        self.state = RxState::Uart(UartRx::new(
-                            self.uart.clone_unchecked(),
-                            self.rx_pin.clone_unchecked(),
-                            Irqs,
-                            self.dma.clone_unchecked(),
-                            cfg,
-                        ));
-                    }
-                    RxMode::Can => {
-                        // Don't need to swap controllers.
-                    }
-                }
+            _ => {
+                // TODO: Need to make sure the old state gets dropped.
+                self.state = self.new_state(mode);
             }
         }
 
@@ -258,6 +448,36 @@ impl RxController {
         self.mode
     }
 
+    pub fn setup(&self) -> RxSetup {
+        RxSetup {
+            mode: self.mode,
+            enabled: self.enabled,
+            ack: self.ack,
+            timing: self.timing,
+            uart_baud: self.uart_baud,
+        }
+    }
+
+    /// Sets the receiver up as it was when `setup` was taken.
+    pub async unsafe fn restore(&mut self, setup: RxSetup) {
+        if self.setup() == setup {
+            return;
+        }
+
+        self.disable().await;
+
+        self.mode = setup.mode;
+        self.ack = setup.ack;
+        self.timing = setup.timing;
+        self.uart_baud = setup.uart_baud;
+        // TODO: Need to make sure the old state gets dropped.
+        self.state = self.new_state(self.mode);
+
+        if setup.enabled {
+            self.enable().await;
+        }
+    }
+
     // TODO: Custom error type.
     pub async fn read_word(&mut self) -> Option<RxWord> {
         match &mut self.state {
@@ -268,18 +488,407 @@ impl RxController {
                     Ok(_) => match self.mode {
                         RxMode::Nmea0183 => return Some(RxWord::Nmea0183(buf[0])),
                         RxMode::Modbus => return Some(RxWord::Modbus(buf[0])),
-                        RxMode::Can => {
+                        RxMode::Dmx => return Some(RxWord::Dmx(DmxWord::Byte(buf[0]))),
+                        RxMode::Can | RxMode::Raw | RxMode::Mil1553 | RxMode::Seatalk => {
                             unreachable!()
                         }
                     },
+                    // DMX512 packets start with a break.
+                    Err(uart::Error::Break) if self.mode == RxMode::Dmx => {
+                        return Some(RxWord::Dmx(DmxWord::Break));
+                    }
                     Err(err) => {
                         error!("UART error: {:?}", err);
                         return None;
                     }
                 }
             }
-            RxState::Pio(pio_rx) => {
-                return Some(RxWord::Can(pio_rx.read_word().await));
+            RxState::Pio(pio) => match self.ack {
+                Some(filter) => return Some(RxWord::Can(pio.read_frame(Some(filter)).await)),
+                None => return Some(RxWord::Can(pio.rx.read_word().await)),
+            },
+            // Samples are only taken on capture.
+            RxState::Raw(_) => core::future::pending().await,
+            RxState::Manchester(rx) => Some(RxWord::Mil1553(rx.read_word().await)),
+            RxState::Seatalk(rx) => Some(RxWord::Seatalk(rx.read_word().await)),
+        }
+    }
+
+    /// Arms the CAN identifier matcher with the given pattern, or disarms it on `None`.
+    pub fn set_match(&mut self, pattern: Option<u32>) -> Result<(), RxError> {
+        match &mut self.state {
+            RxState::Uart(_) | RxState::Raw(_) | RxState::Manchester(_) | RxState::Seatalk(_) => {
+                Err(RxError::InvalidMode(self.mode))
+            }
+            RxState::Pio(pio) => {
+                match pattern {
+                    Some(pattern) => pio.matcher.arm(&pio.irq_flags, pattern),
+                    None => pio.matcher.disarm(),
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// The current baud, of the PIO receiver in CAN mode, the last capture's sample rate in raw
+    /// mode, the bit rate in MIL-STD-1553 mode, the fixed SeaTalk baud, or of the UART otherwise.
+    pub fn baud(&self) -> u32 {
+        match self.mode {
+            RxMode::Can => self.timing.baud(),
+            RxMode::Nmea0183 | RxMode::Modbus | RxMode::Dmx => self.uart_baud,
+            RxMode::Raw => self.capture.as_ref().map_or(0, Capture::rate),
+            RxMode::Mil1553 => self.manchester_baud,
+            RxMode::Seatalk => seatalk::BAUD,
+        }
+    }
+
+    /// Sets the bit rate of the Manchester sampler. Takes effect immediately in MIL-STD-1553 mode.
+    pub async unsafe fn set_manchester_baud(&mut self, baud: u32) -> Result<(), RxError> {
+        manchester::clock_divider(baud)?;
+        self.manchester_baud = baud;
+
+        if self.mode != RxMode::Mil1553 {
+            return Ok(());
+        }
+
+        let enabled = self.enabled;
+
+        if enabled {
+            self.disable().await;
+        }
+
+        // TODO: Need to make sure the old state gets dropped.
+        self.state = self.new_state(self.mode);
+
+        if enabled {
+            self.enable().await;
+        }
+
+        Ok(())
+    }
+
+    pub fn can_timing(&self) -> BitTiming {
+        self.timing
+    }
+
+    /// Sets the CAN bit timing, see [`BitTiming::new`]. Takes effect immediately in CAN mode.
+    pub async unsafe fn set_can_timing(
+        &mut self,
+        baud: u32,
+        sample_point: u8,
+        sjw: u8,
+    ) -> Result<(), RxError> {
+        if self.ack.is_some() && baud > can::MAX_ACK_BAUD {
+            return Err(RxError::AckBaudTooHigh(baud));
+        }
+
+        self.timing = BitTiming::new(baud, sample_point, sjw)?;
+
+        if self.mode != RxMode::Can {
+            return Ok(());
+        }
+
+        let enabled = self.enabled;
+
+        if enabled {
+            self.disable().await;
+        }
+
+        // The delays are patched into the programs, so they have to be reloaded.
+        // TODO: Need to make sure the old state gets dropped.
+        self.state = Self::new_can_state(
+            self.pio.clone_unchecked(),
+            self.rx_pin.clone_unchecked(),
+            self.dma.clone_unchecked(),
+            self.ack.is_some(),
+            &self.timing,
+        );
+
+        if enabled {
+            self.enable().await;
+        }
+
+        Ok(())
+    }
+
+    /// Enables ACK mode, acknowledging the frames whose identifier matches the given identifier
+    /// and mask, or goes back to listening only on `None`. The ACK itself is driven by the
+    /// transmitter, which has to be armed for it. Refused above [`can::MAX_ACK_BAUD`].
+    pub async unsafe fn set_ack(&mut self, filter: Option<(u32, u32)>) -> Result<(), RxError> {
+        if self.mode != RxMode::Can {
+            return Err(RxError::InvalidMode(self.mode));
+        }
+
+        if filter.is_some() && self.timing.baud() > can::MAX_ACK_BAUD {
+            return Err(RxError::AckBaudTooHigh(self.timing.baud()));
+        }
+
+        if self.ack.is_some() == filter.is_some() {
+            self.ack = filter;
+            return Ok(());
+        }
+
+        let enabled = self.enabled;
+
+        if enabled {
+            self.disable().await;
+        }
+
+        // The receiver pushes bit by bit in ACK mode, so it has to be rebuilt.
+        // TODO: Need to make sure the old state gets dropped.
+        self.state = Self::new_can_state(
+            self.pio.clone_unchecked(),
+            self.rx_pin.clone_unchecked(),
+            self.dma.clone_unchecked(),
+            filter.is_some(),
+            &self.timing,
+        );
+        self.ack = filter;
+
+        if enabled {
+            self.enable().await;
+        }
+
+        Ok(())
+    }
+
+    /// Captures the receive pin in raw mode, see [`raw`]. The capture replaces the previous one
+    /// and returns its length in samples.
+    pub async fn capture(
+        &mut self,
+        config: &CaptureConfig,
+        timeout: Duration,
+    ) -> Result<usize, RxError> {
+        let RxState::Raw(raw) = &mut self.state else {
+            return Err(RxError::InvalidMode(self.mode));
+        };
+
+        // Free the previous capture first, the two may not fit at once.
+        self.capture = None;
+        let capture = raw.capture(config, timeout).await?;
+        let len = capture.len();
+        debug!("Captured {} samples, trigger at {}", len, capture.trigger());
+        self.capture = Some(capture);
+
+        Ok(len)
+    }
+
+    /// Exports the samples `from..from + count` of the last capture in the given format, empty
+    /// past its end. Fails rather than running the heap out, larger captures export in chunks.
+    pub fn export(
+        &self,
+        format: ExportFormat,
+        from: usize,
+        count: usize,
+    ) -> Result<Vec<u8>, RxError> {
+        let capture = self.capture.as_ref().ok_or(RxError::NoCapture)?;
+
+        let size = capture.export_size(format, from, count);
+        if size > crate::HEAP.free() {
+            return Err(RxError::ExportTooLarge(size));
+        }
+
+        Ok(match format {
+            ExportFormat::Vcd => capture.to_vcd(from, count).into_bytes(),
+            ExportFormat::Sigrok => capture.to_sigrok(from, count),
+        })
+    }
+
+    /// Times the edges on the receive pin for `duration` and infers the encoding and bit rate of
+    /// the signal (see [`analyze`]). Works in any mode, the configuration is left as it was.
+    pub async unsafe fn analyze(&mut self, duration: Duration) -> Result<Report, RxError> {
+        let enabled = self.enabled;
+
+        if enabled {
+            self.disable().await;
+        }
+
+        // Release the PIO block (if it is in use) for the edge capture.
+        self.state = Self::new_uart_state(
+            self.uart.clone_unchecked(),
+            self.rx_pin.clone_unchecked(),
+            self.dma.clone_unchecked(),
+            self.uart_baud,
+        );
+        self.pwr_receiver.set_output(false).await;
+
+        let edges = {
+            let Pio {
+                mut common, sm0, ..
+            } = Pio::new(self.pio.clone_unchecked(), Irqs);
+            let prog = PioEdgeCaptureProgram::new(&mut common);
+            let mut capture = PioEdgeCapture::new(
+                &mut common,
+                sm0,
+                self.dma.clone_unchecked(),
+                self.rx_pin.clone_unchecked(),
+                &prog,
+            );
+
+            capture.capture(duration).await
+        };
+
+        let report = if edges.is_empty() {
+            Err(RxError::NoSignal)
+        } else {
+            let report = edges.analyze();
+            debug!(
+                "Analyze: {} edges, {:?} at ~{} baud",
+                report.edges, report.encoding, report.bit_rate
+            );
+            Ok(report)
+        };
+
+        // TODO: Need to make sure the old state gets dropped.
+        self.state = self.new_state(self.mode);
+        self.disable().await;
+
+        if enabled {
+            self.enable().await;
+        }
+
+        report
+    }
+
+    /// Detects the mode and baud of the connected bus: times the pulses on the receive pin, then
+    /// trial-decodes the closest standard rates (see [`autobaud`]). Switches to the detected mode
+    /// and baud on success, and leaves the configuration as it was otherwise. ACK mode is left.
+    pub async unsafe fn autobaud(&mut self) -> Result<(RxMode, u32), RxError> {
+        let enabled = self.enabled;
+
+        if enabled {
+            self.disable().await;
+        }
+
+        self.ack = None;
+
+        // Release the PIO block (if it is in use) for the edge timer.
+        self.state = Self::new_uart_state(
+            self.uart.clone_unchecked(),
+            self.rx_pin.clone_unchecked(),
+            self.dma.clone_unchecked(),
+            self.uart_baud,
+        );
+        self.pwr_receiver.set_output(false).await;
+
+        let mut widths = {
+            let Pio {
+                mut common, sm0, ..
+            } = Pio::new(self.pio.clone_unchecked(), Irqs);
+            let prog = PioEdgeTimerProgram::new(&mut common);
+            let mut timer =
+                PioEdgeTimer::new(&mut common, sm0, self.rx_pin.clone_unchecked(), &prog);
+
+            timer.collect().await
+        };
+
+        let detected = match autobaud::estimate_rate(&mut widths) {
+            Some(rate) => {
+                debug!("Autobaud: {} pulses, ~{} baud", widths.len(), rate);
+                let mut detected = Err(RxError::NoMatch(rate));
+
+                for (mode, baud) in autobaud::candidates(rate) {
+                    if self.trial(mode, baud).await {
+                        detected = Ok((mode, baud));
+                        break;
+                    }
+                }
+
+                detected
+            }
+            None => Err(RxError::NoSignal),
+        };
+
+        if let Ok((mode, _)) = detected {
+            self.mode = mode;
+        }
+
+        // Trials leave their own state behind, settle on the (possibly new) configuration.
+        self.state = self.new_state(self.mode);
+        self.disable().await;
+
+        if enabled {
+            self.enable().await;
+        }
+
+        detected
+    }
+
+    /// Decode at the given mode and baud until a valid message comes in. Keeps the baud on
+    /// success.
+    async unsafe fn trial(&mut self, mode: RxMode, baud: u32) -> bool {
+        let deadline = Instant::now() + autobaud::trial_duration(mode);
+
+        match mode {
+            RxMode::Can => {
+                let Ok(timing) =
+                    BitTiming::new(baud, self.timing.sample_point(), self.timing.sjw())
+                else {
+                    return false;
+                };
+
+                // Word by word, as when listening.
+                let mut state = Self::new_can_state(
+                    self.pio.clone_unchecked(),
+                    self.rx_pin.clone_unchecked(),
+                    self.dma.clone_unchecked(),
+                    false,
+                    &timing,
+                );
+                let RxState::Pio(pio) = &mut state else {
+                    unreachable!()
+                };
+                let mut parser = can::Parser::new();
+                pio.rx.enable();
+
+                loop {
+                    let left = deadline.saturating_duration_since(Instant::now());
+
+                    match with_timeout(left, pio.rx.read_word()).await {
+                        Ok(word) => {
+                            if let Some(Ok(_)) = parser.parse_word(word) {
+                                self.timing = timing;
+                                return true;
+                            }
+                        }
+                        Err(_) => return false,
+                    }
+                }
+            }
+            RxMode::Nmea0183
+            | RxMode::Modbus
+            | RxMode::Raw
+            | RxMode::Mil1553
+            | RxMode::Dmx
+            | RxMode::Seatalk => {
+                let mut state = Self::new_uart_state(
+                    self.uart.clone_unchecked(),
+                    self.rx_pin.clone_unchecked(),
+                    self.dma.clone_unchecked(),
+                    baud,
+                );
+                let RxState::Uart(uart_rx) = &mut state else {
+                    unreachable!()
+                };
+                let mut parser = nmea0183::Parser::new();
+                let mut buf = [0_u8; 1];
+
+                loop {
+                    let left = deadline.saturating_duration_since(Instant::now());
+
+                    match with_timeout(left, uart_rx.read(&mut buf)).await {
+                        Ok(Ok(_)) => {
+                            if let Some(Ok(_)) = parser.parse_word(buf[0]) {
+                                self.uart_baud = baud;
+                                return true;
+                            }
+                        }
+                        // Framing errors are expected at the wrong baud.
+                        Ok(Err(_)) => {}
+                        Err(_) => return false,
+                    }
+                }
             }
         }
     }
@@ -24,6 +24,11 @@ fn from_ascii(bytes: &[u8]) -> Option<&str> {
     }
 }
 
+/// The checksum of a sentence, between the `$` or `!` and the `*`.
+pub fn checksum(sentence: &str) -> u8 {
+    sentence.bytes().fold(0, |acc, byte| acc ^ byte)
+}
+
 #[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
 pub enum State {
     WaitStart,
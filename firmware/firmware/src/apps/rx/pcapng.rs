@@ -0,0 +1,493 @@
+//! PCAPNG capture of the received traffic, for opening in Wireshark.
+//!
+//! [`PcapngWriter`] writes the section header, then one interface description per receiver
+//! configuration and an enhanced packet per frame, to any [`Write`] sink. [`Recorder`] drives it
+//! from the receive task, keeping the file in memory until it is fetched over the REPL.
+//!
+//! CAN frames use the SocketCAN link type. NMEA-0183 sentences and Modbus RTU frames have no link
+//! type of their own, so they go out as `DLT_USER0` and `DLT_USER1`: map those to the `nmea0183`
+//! and `mbrtu` dissectors in Wireshark's "DLT_USER" preferences.
+
+use crate::apps::rx::{can::Message, RxMode};
+use alloc::{format, vec::Vec};
+use core::convert::Infallible;
+use defmt::{warn, Format};
+use embassy_time::{Duration, Instant};
+use embedded_io_async::{ErrorType, Write};
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_DESCRIPTION: u16 = 3;
+const OPT_IF_TSRESOL: u16 = 9;
+
+/// Timestamps are in nanoseconds (10^-9 s).
+const TSRESOL_NS: u8 = 9;
+
+/// SocketCAN identifier flags.
+pub const CAN_EFF_FLAG: u32 = 0x8000_0000;
+pub const CAN_RTR_FLAG: u32 = 0x4000_0000;
+
+/// Length of a SocketCAN classic frame.
+pub const SOCKETCAN_LEN: usize = 16;
+
+/// Largest recording, the file lives on the heap, which is much larger in PSRAM.
+#[cfg(feature = "heap-in-psram")]
+pub const MAX_RECORDING: usize = 4 * 1024 * 1024;
+#[cfg(not(feature = "heap-in-psram"))]
+pub const MAX_RECORDING: usize = 64 * 1024;
+
+/// Modbus RTU frames end after 3.5 character times of silence (of 11 bits each).
+pub const MODBUS_GAP_BITS: u64 = 35 * 11 / 10;
+
+/// Link types of the interfaces.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    /// `LINKTYPE_CAN_SOCKETCAN`
+    SocketCan,
+    /// `LINKTYPE_USER0`, carrying NMEA-0183 sentences.
+    Nmea0183,
+    /// `LINKTYPE_USER1`, carrying Modbus RTU frames.
+    ModbusRtu,
+}
+
+impl LinkType {
+    pub fn code(&self) -> u16 {
+        match self {
+            LinkType::SocketCan => 227,
+            LinkType::Nmea0183 => 147,
+            LinkType::ModbusRtu => 148,
+        }
+    }
+
+    /// Largest packet of the link type.
+    pub fn snaplen(&self) -> u32 {
+        match self {
+            LinkType::SocketCan => SOCKETCAN_LEN as u32,
+            LinkType::Nmea0183 | LinkType::ModbusRtu => 256,
+        }
+    }
+}
+
+impl From<RxMode> for LinkType {
+    fn from(mode: RxMode) -> LinkType {
+        match mode {
+            RxMode::Can | RxMode::Raw | RxMode::Mil1553 | RxMode::Dmx => LinkType::SocketCan,
+            // Converted to NMEA 0183.
+            RxMode::Nmea0183 | RxMode::Seatalk => LinkType::Nmea0183,
+            RxMode::Modbus => LinkType::ModbusRtu,
+        }
+    }
+}
+
+/// Appends an option, padded to 32 bits.
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+/// Wraps the (padded) body into a block of the given type.
+fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+    let len = (12 + body.len()) as u32;
+    let mut buf = Vec::with_capacity(len as usize);
+
+    buf.extend_from_slice(&kind.to_le_bytes());
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(body);
+    buf.extend_from_slice(&len.to_le_bytes());
+
+    buf
+}
+
+/// The section header block, naming the application.
+pub fn section_header_block() -> Vec<u8> {
+    let mut body = Vec::new();
+
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    // Version 1.0.
+    body.extend_from_slice(&1_u16.to_le_bytes());
+    body.extend_from_slice(&0_u16.to_le_bytes());
+    // Section length unknown.
+    body.extend_from_slice(&(-1_i64).to_le_bytes());
+    push_option(&mut body, OPT_SHB_USERAPPL, b"mhv-dc33");
+    push_option(&mut body, OPT_END, &[]);
+
+    block(BLOCK_SECTION_HEADER, &body)
+}
+
+/// An interface description block with nanosecond timestamps.
+pub fn interface_description_block(link: LinkType, name: &str, description: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    body.extend_from_slice(&link.code().to_le_bytes());
+    // Reserved.
+    body.extend_from_slice(&0_u16.to_le_bytes());
+    body.extend_from_slice(&link.snaplen().to_le_bytes());
+    push_option(&mut body, OPT_IF_NAME, name.as_bytes());
+    push_option(&mut body, OPT_IF_DESCRIPTION, description.as_bytes());
+    push_option(&mut body, OPT_IF_TSRESOL, &[TSRESOL_NS]);
+    push_option(&mut body, OPT_END, &[]);
+
+    block(BLOCK_INTERFACE_DESCRIPTION, &body)
+}
+
+/// An enhanced packet block, `timestamp` in nanoseconds.
+pub fn enhanced_packet_block(interface: u32, timestamp: u64, data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(20 + data.len() + 3);
+
+    body.extend_from_slice(&interface.to_le_bytes());
+    body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+    // Captured and original length.
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(data);
+    pad(&mut body);
+
+    block(BLOCK_ENHANCED_PACKET, &body)
+}
+
+/// A classic CAN frame in the SocketCAN layout, identifier in network byte order.
+pub fn socketcan_frame(msg: &Message) -> [u8; SOCKETCAN_LEN] {
+    let mut frame = [0_u8; SOCKETCAN_LEN];
+    let mut id = msg.arb_id();
+
+    if msg.is_extended() {
+        id |= CAN_EFF_FLAG;
+    }
+
+    if msg.is_rtr() {
+        id |= CAN_RTR_FLAG;
+    }
+
+    let data = msg.data();
+    frame[0..4].copy_from_slice(&id.to_be_bytes());
+    frame[4] = msg.dlc().min(8);
+    // Raw DLC, when it is over 8.
+    frame[7] = if msg.dlc() > 8 { msg.dlc() } else { 0 };
+    frame[8..8 + data.len()].copy_from_slice(data);
+
+    frame
+}
+
+/// Nanoseconds since boot.
+fn timestamp(instant: Instant) -> u64 {
+    instant.as_micros() * 1_000
+}
+
+/// Writes a PCAPNG section to a byte sink.
+pub struct PcapngWriter<W: Write> {
+    sink: W,
+    interfaces: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Starts the section, writing its header.
+    pub async fn new(mut sink: W) -> Result<Self, W::Error> {
+        sink.write_all(&section_header_block()).await?;
+
+        Ok(Self {
+            sink,
+            interfaces: 0,
+        })
+    }
+
+    /// Describes a new interface, and returns its identifier for [`PcapngWriter::write_packet`].
+    pub async fn add_interface(
+        &mut self,
+        link: LinkType,
+        name: &str,
+        description: &str,
+    ) -> Result<u32, W::Error> {
+        self.sink
+            .write_all(&interface_description_block(link, name, description))
+            .await?;
+        self.interfaces += 1;
+
+        Ok(self.interfaces - 1)
+    }
+
+    pub async fn write_packet(
+        &mut self,
+        interface: u32,
+        time: Instant,
+        data: &[u8],
+    ) -> Result<(), W::Error> {
+        self.sink
+            .write_all(&enhanced_packet_block(interface, timestamp(time), data))
+            .await
+    }
+
+    pub async fn flush(&mut self) -> Result<(), W::Error> {
+        self.sink.flush().await
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.sink
+    }
+
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+}
+
+/// In-memory byte sink, dropping the writes that would grow it past its limit.
+pub struct MemorySink {
+    buf: Vec<u8>,
+    limit: usize,
+    full: bool,
+}
+
+impl MemorySink {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            limit,
+            full: false,
+        }
+    }
+
+    /// Whether writes were dropped.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl ErrorType for MemorySink {
+    type Error = Infallible;
+}
+
+impl Write for MemorySink {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        // Blocks are written whole, and nothing after the first one that does not fit, so the
+        // file never ends on a partial block or refers to a dropped interface.
+        self.full |= self.buf.len() + buf.len() > self.limit;
+
+        if !self.full {
+            self.buf.extend_from_slice(buf);
+        }
+
+        Ok(buf.len())
+    }
+}
+
+/// Splits received Modbus RTU bytes into frames, on the silence between them.
+pub struct ModbusFramer {
+    /// Frame being received, and when its last byte came in.
+    frame: Vec<u8>,
+    last: Instant,
+}
+
+impl ModbusFramer {
+    pub fn new() -> Self {
+        Self {
+            frame: Vec::new(),
+            last: Instant::MIN,
+        }
+    }
+
+    /// Adds a byte, and returns the previous frame with the time of its last byte if this one
+    /// starts a new frame.
+    pub fn push(&mut self, baud: u32, byte: u8) -> Option<(Instant, Vec<u8>)> {
+        let now = Instant::now();
+        let gap = Duration::from_micros(MODBUS_GAP_BITS * 1_000_000 / baud.max(1) as u64);
+
+        let done = if now.saturating_duration_since(self.last) > gap {
+            self.take()
+        } else {
+            None
+        };
+
+        if self.frame.len() < LinkType::ModbusRtu.snaplen() as usize {
+            self.frame.push(byte);
+        } else {
+            warn!("Modbus frame too long, truncating");
+        }
+
+        self.last = now;
+
+        done
+    }
+
+    /// Takes the frame being received, if any.
+    pub fn take(&mut self) -> Option<(Instant, Vec<u8>)> {
+        if self.frame.is_empty() {
+            return None;
+        }
+
+        Some((self.last, core::mem::take(&mut self.frame)))
+    }
+}
+
+/// Records the received traffic into an in-memory PCAPNG file.
+pub struct Recorder {
+    writer: PcapngWriter<MemorySink>,
+    /// Mode and baud of the current interface, with its identifier.
+    interface: Option<(RxMode, u32, u32)>,
+    modbus: ModbusFramer,
+}
+
+impl Recorder {
+    pub async fn new() -> Self {
+        let writer = match PcapngWriter::new(MemorySink::new(MAX_RECORDING)).await {
+            Ok(writer) => writer,
+            Err(never) => match never {},
+        };
+
+        Self {
+            writer,
+            interface: None,
+            modbus: ModbusFramer::new(),
+        }
+    }
+
+    /// The interface for the given receiver configuration, described on first use.
+    async fn interface(&mut self, mode: RxMode, baud: u32) -> u32 {
+        match self.interface {
+            Some((m, b, id)) if m == mode && b == baud => id,
+            _ => {
+                let name = match mode {
+                    RxMode::Nmea0183 => "nmea0183",
+                    RxMode::Modbus => "modbus",
+                    RxMode::Can => "can",
+                    RxMode::Raw => "raw",
+                    RxMode::Mil1553 => "mil1553",
+                    RxMode::Dmx => "dmx",
+                    RxMode::Seatalk => "seatalk",
+                };
+                let description = format!("mhv-dc33 rx, {} at {} baud", name, baud);
+                let id = match self
+                    .writer
+                    .add_interface(mode.into(), "rx", &description)
+                    .await
+                {
+                    Ok(id) => id,
+                    Err(never) => match never {},
+                };
+                self.interface = Some((mode, baud, id));
+
+                id
+            }
+        }
+    }
+
+    async fn packet(&mut self, mode: RxMode, baud: u32, time: Instant, data: &[u8]) {
+        let interface = self.interface(mode, baud).await;
+        let _ = self.writer.write_packet(interface, time, data).await;
+    }
+
+    pub async fn record_can(&mut self, baud: u32, msg: &Message) {
+        self.packet(RxMode::Can, baud, Instant::now(), &socketcan_frame(msg))
+            .await;
+    }
+
+    pub async fn record_nmea0183(&mut self, baud: u32, sof: u8, sentence: &str, chksum: u8) {
+        let line = format!("{}{}*{:02X}\r\n", sof as char, sentence, chksum);
+        self.packet(RxMode::Nmea0183, baud, Instant::now(), line.as_bytes())
+            .await;
+    }
+
+    /// Adds a Modbus byte, framing on the silence between frames.
+    pub async fn record_modbus(&mut self, baud: u32, byte: u8) {
+        if let Some((time, frame)) = self.modbus.push(baud, byte) {
+            self.packet(RxMode::Modbus, baud, time, &frame).await;
+        }
+    }
+
+    /// Ends the recording, and returns the file.
+    pub async fn finish(mut self, baud: u32) -> Vec<u8> {
+        if let Some((time, frame)) = self.modbus.take() {
+            self.packet(RxMode::Modbus, baud, time, &frame).await;
+        }
+
+        if self.writer.get_ref().is_full() {
+            warn!("PCAPNG recording is full, the latest packets were dropped");
+        }
+
+        self.writer.into_inner().into_inner()
+    }
+}
+
+mod test {
+    #[test]
+    fn test_section_header_block() {
+        use super::section_header_block;
+
+        let shb = section_header_block();
+        assert_eq!(shb.len() % 4, 0);
+        assert_eq!(shb[0..4], [0x0A, 0x0D, 0x0D, 0x0A]);
+        assert_eq!(shb[4..8], (shb.len() as u32).to_le_bytes());
+        assert_eq!(shb[shb.len() - 4..], (shb.len() as u32).to_le_bytes());
+        assert_eq!(shb[8..12], [0x4D, 0x3C, 0x2B, 0x1A]);
+        // Version 1.0, unknown section length.
+        assert_eq!(shb[12..16], [1, 0, 0, 0]);
+        assert_eq!(shb[16..24], [0xFF; 8]);
+        // shb_userappl, then the end of options.
+        assert_eq!(shb[24..28], [4, 0, 8, 0]);
+        assert_eq!(&shb[28..36], b"mhv-dc33");
+        assert_eq!(shb[36..40], [0, 0, 0, 0]);
+        assert_eq!(shb.len(), 44);
+    }
+
+    #[test]
+    fn test_interface_description_block() {
+        use super::{interface_description_block, LinkType};
+
+        let idb = interface_description_block(LinkType::SocketCan, "rx", "can 250000");
+        assert_eq!(idb.len() % 4, 0);
+        assert_eq!(idb[0..4], [1, 0, 0, 0]);
+        assert_eq!(idb[4..8], (idb.len() as u32).to_le_bytes());
+        assert_eq!(idb[idb.len() - 4..], (idb.len() as u32).to_le_bytes());
+        // LINKTYPE_CAN_SOCKETCAN, reserved, snaplen.
+        assert_eq!(idb[8..12], [227, 0, 0, 0]);
+        assert_eq!(idb[12..16], [16, 0, 0, 0]);
+        // if_name "rx", padded.
+        assert_eq!(idb[16..20], [2, 0, 2, 0]);
+        assert_eq!(idb[20..24], [b'r', b'x', 0, 0]);
+        // if_description "can 250000", padded.
+        assert_eq!(idb[24..28], [3, 0, 10, 0]);
+        assert_eq!(&idb[28..38], b"can 250000");
+        assert_eq!(idb[38..40], [0, 0]);
+        // if_tsresol nanoseconds, then the end of options.
+        assert_eq!(idb[40..48], [9, 0, 1, 0, 9, 0, 0, 0]);
+        assert_eq!(idb[48..52], [0, 0, 0, 0]);
+        assert_eq!(idb.len(), 56);
+    }
+
+    #[test]
+    fn test_enhanced_packet_block() {
+        use super::enhanced_packet_block;
+
+        let epb = enhanced_packet_block(1, 0x0000_0001_2345_6789, b"abcde");
+        assert_eq!(epb.len() % 4, 0);
+        assert_eq!(epb[0..4], [6, 0, 0, 0]);
+        assert_eq!(epb[4..8], (epb.len() as u32).to_le_bytes());
+        assert_eq!(epb[epb.len() - 4..], (epb.len() as u32).to_le_bytes());
+        assert_eq!(epb[8..12], [1, 0, 0, 0]);
+        // Timestamp, high word first.
+        assert_eq!(epb[12..16], [1, 0, 0, 0]);
+        assert_eq!(epb[16..20], [0x89, 0x67, 0x45, 0x23]);
+        // Captured and original length, then the padded data.
+        assert_eq!(epb[20..24], [5, 0, 0, 0]);
+        assert_eq!(epb[24..28], [5, 0, 0, 0]);
+        assert_eq!(&epb[28..33], b"abcde");
+        assert_eq!(epb[33..36], [0, 0, 0]);
+        assert_eq!(epb.len(), 40);
+    }
+}
@@ -0,0 +1,446 @@
+//! Logic analyzer capture of the receive pin.
+//!
+//! A PIO program samples the pin at a configurable rate, 32 samples per word, first sample in the
+//! MSB. DMA fills a ring of blocks while the previous block is scanned for the trigger. Once it
+//! fires, capturing carries on until the samples after the trigger are in, and the ring is rotated
+//! so the capture starts `pre_trigger` samples ahead of the trigger.
+//!
+//! The DMA is rearmed for every block. Should the sampler fill the FIFO in between, it stalls and
+//! samples are lost, which fails the capture if it happened within the captured samples.
+
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+use defmt::Format;
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    dma::{AnyChannel, Channel},
+    pio::{
+        Common, Config, Direction as PioDirection, FifoJoin, Instance, LoadedProgram, PioPin,
+        ShiftDirection, StateMachine,
+    },
+    Peri,
+};
+use embassy_time::{with_timeout, Duration};
+use fixed::{types::extra::U8, FixedU32};
+
+use crate::apps::rx::RxError;
+
+/// Samples per DMA block.
+pub const BLOCK_SAMPLES: usize = 32 * BLOCK_WORDS;
+const BLOCK_WORDS: usize = 256;
+
+/// Largest capture, in samples. The buffer lives on the heap, which is much larger in PSRAM.
+#[cfg(feature = "heap-in-psram")]
+pub const MAX_DEPTH: usize = 8 * 1024 * 1024;
+#[cfg(not(feature = "heap-in-psram"))]
+pub const MAX_DEPTH: usize = 256 * 1024;
+
+/// Longest VCD header, the comment carrying the trigger sample.
+const VCD_HEADER_MAX: usize = 192;
+/// Longest VCD value change, a timestamp of up to 20 characters and the value.
+const VCD_CHANGE_MAX: usize = 25;
+
+/// Highest sample rate, the trigger scan of a block has to keep up with the DMA filling the next.
+pub const MAX_RATE: u32 = 10_000_000;
+
+/// Condition starting a capture.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Capture right away.
+    Immediate,
+    Rising,
+    Falling,
+    /// Either edge.
+    Edge,
+    /// The last samples, oldest in the MSB, equal `pattern` where `mask` is set.
+    Pattern {
+        pattern: u32,
+        mask: u32,
+    },
+}
+
+impl Trigger {
+    /// Index of the first sample in `word` that fires the trigger. `prev` is the word sampled
+    /// before `word`.
+    fn scan(&self, prev: u32, word: u32) -> Option<usize> {
+        // Each sample's predecessor, lined up with the sample.
+        let before = (word >> 1) | (prev << 31);
+        let hits = match *self {
+            Trigger::Immediate => 1 << 31,
+            Trigger::Rising => word & !before,
+            Trigger::Falling => !word & before,
+            Trigger::Edge => word ^ before,
+            Trigger::Pattern { pattern, mask } => {
+                let window = ((prev as u64) << 32) | word as u64;
+
+                (0..32)
+                    .find(|i| ((window >> (31 - i)) as u32 & mask) == pattern & mask)
+                    .map_or(0, |i| 1 << (31 - i))
+            }
+        };
+
+        (hits != 0).then(|| hits.leading_zeros() as usize)
+    }
+}
+
+/// File formats a capture exports to.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Value change dump, see [`Capture::to_vcd`].
+    Vcd,
+    /// Raw sigrok binary input, see [`Capture::to_sigrok`].
+    Sigrok,
+}
+
+/// Capture settings.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureConfig {
+    /// Samples per second.
+    pub rate: u32,
+    /// Samples captured.
+    pub depth: usize,
+    /// Samples kept ahead of the trigger.
+    pub pre_trigger: usize,
+    pub trigger: Trigger,
+}
+
+impl CaptureConfig {
+    pub fn validate(&self) -> Result<(), RxError> {
+        if self.rate == 0 || self.rate > MAX_RATE || clk_sys_freq() / self.rate == 0 {
+            return Err(RxError::InvalidCapture);
+        }
+
+        if self.depth == 0 || self.depth > MAX_DEPTH || self.pre_trigger > self.depth {
+            return Err(RxError::InvalidCapture);
+        }
+
+        Ok(())
+    }
+}
+
+/// A finished capture.
+pub struct Capture {
+    rate: u32,
+    words: Vec<u32>,
+    /// Sample index of the first captured sample in `words`.
+    start: usize,
+    len: usize,
+    /// Sample index of the trigger, from the start of the capture.
+    trigger: usize,
+}
+
+impl Capture {
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn trigger(&self) -> usize {
+        self.trigger
+    }
+
+    pub fn sample(&self, i: usize) -> bool {
+        let i = self.start + i;
+
+        (self.words[i / 32] >> (31 - i % 32)) & 0b1 == 1
+    }
+
+    /// The samples `from..from + count` of the capture, clamped to its length.
+    fn range(&self, from: usize, count: usize) -> core::ops::Range<usize> {
+        from.min(self.len)..from.saturating_add(count).min(self.len)
+    }
+
+    /// Upper bound of the bytes [`Capture::to_vcd`] or [`Capture::to_sigrok`] produce for the
+    /// same samples, to check an export fits before building it.
+    pub fn export_size(&self, format: ExportFormat, from: usize, count: usize) -> usize {
+        let range = self.range(from, count);
+
+        match format {
+            ExportFormat::Vcd => {
+                // A change at every sample the value differs from the one before, and at the
+                // start of the chunk.
+                let changes = range
+                    .clone()
+                    .filter(|&i| i == range.start || self.sample(i) != self.sample(i - 1))
+                    .count();
+
+                VCD_HEADER_MAX + changes * VCD_CHANGE_MAX + VCD_CHANGE_MAX
+            }
+            ExportFormat::Sigrok => range.len(),
+        }
+    }
+
+    /// Value change dump of the samples `from..from + count`, the trigger at time 0. The header
+    /// is only in the chunk starting at sample 0 and the end timestamp only in the chunk reaching
+    /// the last sample, so consecutive chunks concatenate to the dump of the whole capture.
+    pub fn to_vcd(&self, from: usize, count: usize) -> String {
+        let range = self.range(from, count);
+        let mut vcd = String::with_capacity(self.export_size(ExportFormat::Vcd, from, count));
+        // Picoseconds keep the timestamps integral for any rate we can sample at.
+        let ps_per_sample = 1_000_000_000_000 / self.rate as u64;
+
+        if range.is_empty() {
+            return vcd;
+        }
+
+        if range.start == 0 {
+            let _ = write!(
+                vcd,
+                "$comment mhv-dc33 capture, trigger at sample {} $end\n\
+                 $timescale 1 ps $end\n\
+                 $scope module rx $end\n\
+                 $var wire 1 ! rx $end\n\
+                 $upscope $end\n\
+                 $enddefinitions $end\n",
+                self.trigger
+            );
+        }
+
+        let mut last = (range.start > 0).then(|| self.sample(range.start - 1));
+
+        for i in range.clone() {
+            let sample = self.sample(i);
+
+            if last != Some(sample) {
+                let t = (i as i64 - self.trigger as i64) * ps_per_sample as i64;
+                let _ = write!(vcd, "#{}\n{}!\n", t, sample as u8);
+                last = Some(sample);
+            }
+        }
+
+        if range.end == self.len {
+            let end = (self.len as i64 - self.trigger as i64) * ps_per_sample as i64;
+            let _ = write!(vcd, "#{}\n", end);
+        }
+
+        vcd
+    }
+
+    /// One byte per sample of the samples `from..from + count`, channel 0 in bit 0. Loads with
+    /// `sigrok-cli -I binary:numchannels=1:samplerate=<rate> -i <file>`.
+    pub fn to_sigrok(&self, from: usize, count: usize) -> Vec<u8> {
+        self.range(from, count)
+            .map(|i| self.sample(i) as u8)
+            .collect()
+    }
+
+    /// The `sigrok-cli` input options matching [`Capture::to_sigrok`].
+    pub fn sigrok_options(&self) -> String {
+        format!("binary:numchannels=1:samplerate={}", self.rate)
+    }
+}
+
+/// This struct represents the sampling program loaded into pio instruction memory.
+pub struct PioRawProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioRawProgram<'d, PIO> {
+    /// Load the sampling program into the given pio
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        let prg = pio::pio_asm!(
+            r#"
+            .wrap_target
+                in pins, 1                  ; one sample per cycle, autopushed every 32
+            .wrap
+            "#
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed sampler of the receive pin
+pub struct PioRaw<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    dma: Peri<'d, AnyChannel>,
+    cfg: Config<'d, PIO>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioRaw<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded sampling program.
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        dma: Peri<'d, impl Channel>,
+        rx_pin: Peri<'d, impl PioPin>,
+        program: &PioRawProgram<'d, PIO>,
+    ) -> Self {
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+
+        let rx_pin = common.make_pio_pin(rx_pin);
+        cfg.set_in_pins(&[&rx_pin]);
+        sm.set_pin_dirs(PioDirection::In, &[&rx_pin]);
+        cfg.shift_in.auto_fill = true;
+        cfg.shift_in.threshold = 32;
+        cfg.shift_in.direction = ShiftDirection::Left;
+        cfg.fifo_join = FifoJoin::RxOnly;
+
+        Self {
+            sm,
+            dma: dma.into(),
+            cfg,
+        }
+    }
+
+    /// Run a capture, giving up if the trigger does not fire within `timeout`.
+    pub async fn capture(
+        &mut self,
+        config: &CaptureConfig,
+        timeout: Duration,
+    ) -> Result<Capture, RxError> {
+        config.validate()?;
+
+        // Room for the capture, its misalignment to the blocks, and the block being filled when it
+        // completes.
+        let blocks = config.depth.div_ceil(BLOCK_SAMPLES) + 2;
+        let mut ring: Vec<u32> = vec![0; blocks * BLOCK_WORDS];
+
+        let div = clk_sys_freq() as u64 * 256 / config.rate as u64;
+        self.cfg.clock_divider = FixedU32::<U8>::from_bits(div.min(u32::MAX as u64) as u32);
+        self.sm.set_config(&self.cfg);
+        self.sm.clear_fifos();
+        self.sm.restart();
+
+        let found = with_timeout(timeout, self.fill(&mut ring, config)).await;
+        self.sm.set_enable(false);
+
+        let (trigger, end, gap) = found.map_err(|_| RxError::CaptureTimeout)?;
+
+        // Oldest sample kept, as an absolute sample index.
+        let start = trigger.saturating_sub(config.pre_trigger);
+
+        if gap.is_some_and(|gap| gap > start) {
+            return Err(RxError::CaptureOverrun);
+        }
+        let len = config.depth.min(end - start);
+        let ring_samples = ring.len() * 32;
+        let offset = start % ring_samples;
+        ring.rotate_left(offset / 32);
+
+        Ok(Capture {
+            rate: config.rate,
+            words: ring,
+            start: offset % 32,
+            len,
+            trigger: trigger - start,
+        })
+    }
+
+    /// Keep the ring filled until the trigger fired and the post-trigger samples are in. Returns
+    /// the trigger and end sample indices, counted from the start of the capture, and the index of
+    /// the last block boundary where samples were lost, if any.
+    async fn fill(
+        &mut self,
+        ring: &mut [u32],
+        config: &CaptureConfig,
+    ) -> (usize, usize, Option<usize>) {
+        let blocks = ring.len() / BLOCK_WORDS;
+        let ptr = ring.as_mut_ptr();
+        // SAFETY: The DMA only ever writes the block after the one being scanned.
+        let block = |i: usize| unsafe {
+            core::slice::from_raw_parts_mut(ptr.add((i % blocks) * BLOCK_WORDS), BLOCK_WORDS)
+        };
+
+        let mut trigger: Option<usize> = None;
+        let mut prev: Option<u32> = None;
+        let mut gap: Option<usize> = None;
+        let mut filled = 0;
+
+        // Clear a stall left over from a previous capture.
+        self.sm.rx().stalled();
+        self.sm.set_enable(true);
+        let mut transfer = self.sm.rx().dma_pull(self.dma.reborrow(), block(0), false);
+
+        loop {
+            transfer.await;
+            let done = block(filled);
+            filled += 1;
+
+            // The sampler stalls on a full FIFO until the DMA is rearmed. A stall just after the
+            // previous check only shows up now, so it is put at the later boundary.
+            if self.sm.rx().stalled() {
+                gap = Some(filled * BLOCK_SAMPLES);
+            }
+
+            // Scan the block we have while the next one fills.
+            transfer = self
+                .sm
+                .rx()
+                .dma_pull(self.dma.reborrow(), block(filled), false);
+
+            if trigger.is_none() {
+                for (i, &word) in done.iter().enumerate() {
+                    // Don't make an edge out of the very first sample.
+                    let before = prev.unwrap_or(0_u32.wrapping_sub(word >> 31));
+
+                    if let Some(bit) = config.trigger.scan(before, word) {
+                        trigger = Some(((filled - 1) * BLOCK_WORDS + i) * 32 + bit);
+                        break;
+                    }
+
+                    prev = Some(word);
+                }
+            }
+
+            if let Some(trigger) = trigger {
+                let start = trigger.saturating_sub(config.pre_trigger);
+                let end = filled * BLOCK_SAMPLES;
+
+                if end >= start + config.depth {
+                    drop(transfer);
+                    return (trigger, end, gap);
+                }
+            }
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn test_export_chunks() {
+        use super::{Capture, ExportFormat};
+        use alloc::{string::String, vec, vec::Vec};
+
+        let capture = Capture {
+            rate: 1_000_000,
+            words: vec![0x0F0F_00FF, 0x8000_0001],
+            start: 4,
+            len: 56,
+            trigger: 10,
+        };
+
+        let vcd = capture.to_vcd(0, usize::MAX);
+        assert!(vcd.starts_with("$comment"));
+        assert!(vcd.ends_with("#46000000\n"));
+        assert!(vcd.len() <= capture.export_size(ExportFormat::Vcd, 0, usize::MAX));
+
+        for chunk in [1, 7, 32, 100] {
+            let mut chunked = String::new();
+            let mut sigrok = Vec::new();
+
+            for from in (0..capture.len()).step_by(chunk) {
+                let part = capture.to_vcd(from, chunk);
+                assert!(part.len() <= capture.export_size(ExportFormat::Vcd, from, chunk));
+                chunked.push_str(&part);
+                sigrok.extend(capture.to_sigrok(from, chunk));
+            }
+
+            assert_eq!(chunked, vcd);
+            assert_eq!(sigrok, capture.to_sigrok(0, usize::MAX));
+        }
+
+        assert!(capture.to_vcd(56, 10).is_empty());
+        assert!(capture.to_sigrok(56, 10).is_empty());
+    }
+}
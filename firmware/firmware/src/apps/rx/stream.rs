@@ -0,0 +1,140 @@
+//! Live streaming of the received traffic over the stream USB serial port.
+//!
+//! Every frame goes out as a small header followed by the packet, in the same encapsulation as
+//! the PCAPNG recordings:
+//!
+//! | Offset | Size | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | 2    | Magic, `0xDC 0x33`                                     |
+//! | 2      | 2    | Link type (see [`LinkType::code`]), little-endian      |
+//! | 4      | 2    | Packet length, little-endian                           |
+//! | 6      | 8    | Timestamp in microseconds since boot, little-endian    |
+//! | 14     | len  | Packet                                                 |
+//!
+//! Frames are dropped rather than delayed when the host does not keep up, the receive task never
+//! waits on USB. The host side lives in `software/extcap`.
+//!
+//! The port turns into an SLCAN or GVRET adapter instead once the host sends it a command, see
+//! `tasks::stream`.
+
+use crate::apps::rx::{
+    can::Message,
+    pcapng::{socketcan_frame, LinkType, ModbusFramer},
+};
+use alloc::{format, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use defmt::debug;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pipe};
+use embassy_time::Instant;
+
+pub const MAGIC: [u8; 2] = [0xDC, 0x33];
+
+pub const HEADER_LEN: usize = 14;
+
+/// Bytes buffered between the receive task and USB.
+pub const STREAM_BUF: usize = 2048;
+
+pub type StreamPipe = pipe::Pipe<CriticalSectionRawMutex, STREAM_BUF>;
+
+pub static STREAM_PIPE: StreamPipe = StreamPipe::new();
+
+/// Set when a frame is dropped, until taken with [`take_overrun`].
+static OVERRUN: AtomicBool = AtomicBool::new(false);
+
+/// Whether frames were dropped since the last call.
+pub fn take_overrun() -> bool {
+    OVERRUN.swap(false, Ordering::Relaxed)
+}
+
+/// Frames a packet for the stream.
+pub fn encode(link: LinkType, time: Instant, packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + packet.len());
+
+    frame.extend_from_slice(&MAGIC);
+    frame.extend_from_slice(&link.code().to_le_bytes());
+    frame.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+    frame.extend_from_slice(&time.as_micros().to_le_bytes());
+    frame.extend_from_slice(packet);
+
+    frame
+}
+
+/// Splits a frame into its link type code, timestamp and packet.
+pub fn decode(frame: &[u8]) -> (u16, u64, &[u8]) {
+    let link = u16::from_le_bytes([frame[2], frame[3]]);
+    let mut time = [0; 8];
+    time.copy_from_slice(&frame[6..HEADER_LEN]);
+
+    (link, u64::from_le_bytes(time), &frame[HEADER_LEN..])
+}
+
+/// Waits for the next frame in [`STREAM_PIPE`].
+///
+/// Frames go in whole and without yielding, so once the first byte is in, so is the rest, and
+/// this only ever waits before reading anything: dropping it does not desynchronize the stream.
+pub async fn read_frame() -> Vec<u8> {
+    let mut frame = vec![0; HEADER_LEN];
+    read_exact(&mut frame).await;
+
+    let len = u16::from_le_bytes([frame[4], frame[5]]) as usize;
+    frame.resize(HEADER_LEN + len, 0);
+    read_exact(&mut frame[HEADER_LEN..]).await;
+
+    frame
+}
+
+async fn read_exact(mut buf: &mut [u8]) {
+    while !buf.is_empty() {
+        let n = STREAM_PIPE.read(buf).await;
+        buf = &mut buf[n..];
+    }
+}
+
+/// Feeds the received traffic to [`STREAM_PIPE`].
+pub struct Streamer {
+    modbus: ModbusFramer,
+}
+
+impl Streamer {
+    pub fn new() -> Self {
+        Self {
+            modbus: ModbusFramer::new(),
+        }
+    }
+
+    fn send(&mut self, link: LinkType, time: Instant, packet: &[u8]) {
+        let frame = encode(link, time, packet);
+
+        // All or nothing, a partial frame would desynchronize the host.
+        if STREAM_PIPE.free_capacity() < frame.len() {
+            debug!("Stream is full, dropping a frame");
+            OVERRUN.store(true, Ordering::Relaxed);
+            return;
+        }
+
+        let mut rest = &frame[..];
+
+        while !rest.is_empty() {
+            match STREAM_PIPE.try_write(rest) {
+                Ok(n) => rest = &rest[n..],
+                Err(_) => break,
+            }
+        }
+    }
+
+    pub fn send_can(&mut self, msg: &Message) {
+        self.send(LinkType::SocketCan, Instant::now(), &socketcan_frame(msg));
+    }
+
+    pub fn send_nmea0183(&mut self, sof: u8, sentence: &str, chksum: u8) {
+        let line = format!("{}{}*{:02X}\r\n", sof as char, sentence, chksum);
+        self.send(LinkType::Nmea0183, Instant::now(), line.as_bytes());
+    }
+
+    /// Adds a Modbus byte, a frame goes out once the next one starts.
+    pub fn send_modbus(&mut self, baud: u32, byte: u8) {
+        if let Some((time, frame)) = self.modbus.push(baud, byte) {
+            self.send(LinkType::ModbusRtu, time, &frame);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! PIO 9 bit UART receiver, for SeaTalk1, see [`crate::apps::seatalk`].
+//!
+//! The UART can't do 9 bit frames, so this follows the usual PIO UART receiver: wait for the
+//! start bit, sample the middle of each of the 9 data bits and push them if the stop bit is idle.
+//! Frames with a bad stop bit are dropped, and the program waits for the line to go idle again.
+
+use crate::apps::rx::RxError;
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    pio::{
+        Common, Config, Direction as PioDirection, FifoJoin, Instance, LoadedProgram, PioPin,
+        ShiftDirection, StateMachine,
+    },
+    Peri,
+};
+use fixed::{types::extra::U8, FixedU32};
+
+/// PIO cycles per bit.
+pub const BIT_CYCLES: u32 = 8;
+
+/// This struct represents the 9 bit receiver program loaded into pio instruction memory.
+pub struct PioUart9RxProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioUart9RxProgram<'d, PIO> {
+    /// Load the 9 bit receiver program into the given pio
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        // NOTE: Through the transceiver, the receive pin is low while the bus idles at 12 V and
+        //       high while a talker pulls it down, so the start bit is a rising edge and the bits
+        //       are read inverted. The first sample is 12 cycles (1.5 bits) after the edge.
+        let prg = pio::pio_asm!(
+            r#"
+                .wrap_target
+                start:
+                    wait 1 pin 0                ; start bit
+                    set x, 8 [10]               ; to the middle of the first data bit
+                bitloop:
+                    in pins, 1                  ; least significant bit first
+                    jmp x-- bitloop [6]
+                    jmp pin framing             ; the stop bit should be idle
+                    push
+                .wrap
+                framing:
+                    wait 0 pin 0                ; wait for the bus to idle again
+                    jmp start
+            "#
+        );
+
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed 9 bit UART receiver
+pub struct PioUart9Rx<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioUart9Rx<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded receiver program.
+    pub fn new(
+        clock_divider: FixedU32<U8>,
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        rx_pin: Peri<'d, impl PioPin>,
+        program: &PioUart9RxProgram<'d, PIO>,
+    ) -> Self {
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[]);
+
+        let rx_pin = common.make_pio_pin(rx_pin);
+        cfg.set_in_pins(&[&rx_pin]);
+        cfg.set_jmp_pin(&rx_pin);
+        sm.set_pin_dirs(PioDirection::In, &[&rx_pin]);
+
+        cfg.clock_divider = clock_divider;
+        cfg.shift_in.auto_fill = false;
+        cfg.shift_in.direction = ShiftDirection::Right;
+        cfg.fifo_join = FifoJoin::RxOnly;
+        sm.set_config(&cfg);
+
+        let rx = sm.rx();
+        while let Some(_) = rx.try_pull() {}
+        sm.restart();
+
+        Self { sm }
+    }
+
+    pub fn enable(&mut self) {
+        if !self.sm.is_enabled() {
+            self.sm.set_enable(true);
+        }
+    }
+
+    pub fn disable(&mut self) {
+        if self.sm.is_enabled() {
+            self.sm.set_enable(false);
+        }
+    }
+
+    /// The next 9 bit word, the command bit being [`crate::apps::seatalk::COMMAND_BIT`].
+    pub async fn read_word(&mut self) -> u16 {
+        word(self.sm.rx().wait_pull().await)
+    }
+}
+
+/// The word in the top 9 bits of the shift register, read inverted.
+pub fn word(isr: u32) -> u16 {
+    (!isr >> 23) as u16 & 0x1FF
+}
+
+/// The PIO clock divider for the given baud, if the PIO can run that fast or slow.
+pub fn clock_divider(baud: u32) -> Result<FixedU32<U8>, RxError> {
+    if baud == 0 {
+        return Err(RxError::BaudOutOfTolerance(baud));
+    }
+
+    // Divider in 16.8 fixed point, rounded to nearest.
+    let clk = clk_sys_freq() as u64;
+    let divisor = BIT_CYCLES as u64 * baud as u64;
+    let bits = (clk * 256 + divisor / 2) / divisor;
+
+    if bits < 256 || bits > 0xFFFF_FF00 {
+        return Err(RxError::BaudOutOfTolerance(baud));
+    }
+
+    Ok(FixedU32::<U8>::from_bits(bits as u32))
+}
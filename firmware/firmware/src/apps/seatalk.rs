@@ -0,0 +1,351 @@
+//! SeaTalk1, the instrument bus of older Raymarine gear.
+//!
+//! Talkers share a single wire idling at 12 V, sending 4800 baud words of 9 data bits, least
+//! significant first: a byte, and a command bit set on the first byte of a datagram only. A
+//! datagram is that command byte, an attribute byte whose low nibble is the count of data bytes
+//! past the first one, and the data bytes. Talkers read back what they send and give up on a
+//! collision, so a datagram can be cut short by the next command byte.
+//!
+//! The words come from a PIO receiver, see [`crate::apps::rx::uart9`]. Depth, speed, wind,
+//! heading and autopilot datagrams are decoded and converted to NMEA 0183 sentences, which go to
+//! the PCAPNG recording and the stream like received ones. Layouts are after Thomas Knauf's
+//! SeaTalk reference.
+
+use crate::apps::rx::{nmea0183, SerialParser};
+use alloc::{format, string::String, vec, vec::Vec};
+use defmt::Format;
+
+pub const BAUD: u32 = 4800;
+
+/// The 9th bit of a word, set on the first byte of a datagram.
+pub const COMMAND_BIT: u16 = 0x100;
+
+/// Datagrams kept for `seatalk::read` by the receive task, those coming in past it are dropped.
+pub const MAX_RECEIVED: usize = 256;
+
+/// Talker of the converted sentences, integrated instrumentation.
+const TALKER: &str = "II";
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The next datagram started after the given bytes, its talker gave up on a collision.
+    Truncated(usize),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl core::error::Error for Error {}
+
+pub struct Parser {
+    /// The bytes since the last command byte, empty out of a datagram.
+    buffer: Vec<u8>,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser { buffer: Vec::new() }
+    }
+}
+
+impl SerialParser for Parser {
+    type Word = u16;
+    type Message = Vec<u8>;
+    type Error = Error;
+
+    fn parse_word(&mut self, word: Self::Word) -> Option<Result<Self::Message, Self::Error>> {
+        let byte = word as u8;
+
+        if word & COMMAND_BIT != 0 {
+            let truncated = match self.buffer.len() {
+                0 => None,
+                len => Some(Err(Error::Truncated(len))),
+            };
+
+            self.buffer.clear();
+            self.buffer.push(byte);
+            return truncated;
+        }
+
+        // Data bytes before the first command byte are from a datagram joined halfway.
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        self.buffer.push(byte);
+
+        match self.buffer.get(1) {
+            Some(&attribute) if self.buffer.len() == 3 + (attribute & 0x0F) as usize => {
+                Some(Ok(core::mem::take(&mut self.buffer)))
+            }
+            _ => None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    fn mtu() -> usize {
+        3 + 0x0F
+    }
+
+    fn default_baud() -> u32 {
+        BAUD
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum AutopilotMode {
+    Standby,
+    Auto,
+    /// Steering to the apparent wind angle.
+    Vane,
+    Track,
+}
+
+/// The datagrams converted to NMEA 0183.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Datagram {
+    /// Depth below the transducer (0x00), in 0.1 ft.
+    Depth(u16),
+    /// Speed through the water (0x20 and 0x26), in 0.01 kn.
+    Speed(u32),
+    /// Apparent wind angle (0x10), right of the bow in 0.5°.
+    WindAngle(u16),
+    /// Apparent wind speed (0x11), in 0.1 kn.
+    WindSpeed(u16),
+    /// Magnetic compass heading in degrees (0x89 and 0x9C), with the rudder angle in degrees to
+    /// starboard from 0x9C.
+    Heading { heading: u16, rudder: Option<i8> },
+    /// Compass heading and autopilot state (0x84), the course being in 0.5°.
+    Autopilot {
+        heading: u16,
+        course: u16,
+        mode: AutopilotMode,
+        rudder: i8,
+    },
+}
+
+/// The heading packed in the high nibble of the attribute byte and the next one.
+fn heading(attribute: u8, byte: u8) -> u16 {
+    let u = attribute >> 4;
+    (u & 0x03) as u16 * 90 + (byte & 0x3F) as u16 * 2 + ((u >> 2) & 0x03).count_ones() as u16
+}
+
+impl Datagram {
+    /// Decodes a whole datagram, `None` for others.
+    pub fn decode(data: &[u8]) -> Option<Datagram> {
+        let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+
+        let datagram = match (data.first()?, data.len()) {
+            (0x00, 5) => Datagram::Depth(word(3)),
+            (0x10, 4) => Datagram::WindAngle(u16::from_be_bytes([data[2], data[3]])),
+            (0x11, 4) => {
+                Datagram::WindSpeed((data[2] & 0x7F) as u16 * 10 + (data[3] & 0x0F) as u16)
+            }
+            (0x20, 4) => Datagram::Speed(word(2) as u32 * 10),
+            (0x26, 7) => Datagram::Speed(word(2) as u32),
+            (0x84, 9) => {
+                let z = data[4] & 0x0F;
+                let mode = if z & 0x08 != 0 {
+                    AutopilotMode::Track
+                } else if z & 0x04 != 0 {
+                    AutopilotMode::Vane
+                } else if z & 0x02 != 0 {
+                    AutopilotMode::Auto
+                } else {
+                    AutopilotMode::Standby
+                };
+
+                Datagram::Autopilot {
+                    heading: heading(data[1], data[2]),
+                    course: (data[2] >> 6) as u16 * 180 + data[3] as u16,
+                    mode,
+                    rudder: data[6] as i8,
+                }
+            }
+            (0x89, 5) => Datagram::Heading {
+                heading: heading(data[1], data[2]),
+                rudder: None,
+            },
+            (0x9C, 4) => Datagram::Heading {
+                heading: heading(data[1], data[2]),
+                rudder: Some(data[3] as i8),
+            },
+            _ => return None,
+        };
+
+        Some(datagram)
+    }
+}
+
+/// Converts datagrams to NMEA 0183 sentences.
+pub struct Converter {
+    /// The last apparent wind angle, sent along with the next wind speed.
+    wind_angle: Option<u16>,
+}
+
+impl Converter {
+    pub fn new() -> Converter {
+        Converter { wind_angle: None }
+    }
+
+    pub fn reset(&mut self) {
+        self.wind_angle = None;
+    }
+
+    /// The sentences for the given datagram, without the `$`, with their checksum.
+    pub fn convert(&mut self, datagram: &Datagram) -> Vec<(String, u8)> {
+        let sentences = match *datagram {
+            Datagram::Depth(depth) => {
+                let feet = depth as f32 / 10.0;
+                vec![format!(
+                    "DBT,{:.1},f,{:.1},M,{:.1},F",
+                    feet,
+                    feet * 0.3048,
+                    feet / 6.0
+                )]
+            }
+            Datagram::Speed(speed) => {
+                let knots = speed as f32 / 100.0;
+                vec![format!("VHW,,T,,M,{:.2},N,{:.2},K", knots, knots * 1.852)]
+            }
+            Datagram::WindAngle(angle) => {
+                self.wind_angle = Some(angle);
+                vec![]
+            }
+            Datagram::WindSpeed(speed) => match self.wind_angle {
+                Some(angle) => vec![format!(
+                    "MWV,{:.1},R,{:.1},N,A",
+                    angle as f32 / 2.0,
+                    speed as f32 / 10.0
+                )],
+                None => vec![],
+            },
+            Datagram::Heading { heading, rudder } => {
+                let mut sentences = vec![format!("HDM,{:.1},M", heading as f32)];
+                sentences.extend(rudder.map(|rudder| format!("RSA,{:.1},A,,V", rudder as f32)));
+                sentences
+            }
+            Datagram::Autopilot {
+                heading, rudder, ..
+            } => vec![
+                format!("HDM,{:.1},M", heading as f32),
+                format!("RSA,{:.1},A,,V", rudder as f32),
+            ],
+        };
+
+        sentences
+            .into_iter()
+            .map(|sentence| {
+                let sentence = format!("{}{}", TALKER, sentence);
+                let chksum = nmea0183::checksum(&sentence);
+                (sentence, chksum)
+            })
+            .collect()
+    }
+}
+
+/// A datagram as received, with the sentences it was converted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Received {
+    pub data: Vec<u8>,
+    pub sentences: Vec<(String, u8)>,
+}
+
+mod test {
+    #[test]
+    fn test_parser() {
+        use super::{Error, Parser, COMMAND_BIT};
+        use crate::apps::rx::SerialParser;
+        use alloc::vec::Vec;
+
+        let command = |byte: u8| byte as u16 | COMMAND_BIT;
+        let words = [
+            // Joined halfway through a datagram.
+            0x12,
+            0x34,
+            // Depth, cut short by a collision, then sent again.
+            command(0x00),
+            0x02,
+            command(0x00),
+            0x02,
+            0x00,
+            0x65,
+            0x00,
+            // Speed.
+            command(0x20),
+            0x01,
+            0x3C,
+            0x00,
+        ];
+
+        let mut parser = Parser::new();
+        let parsed = words
+            .iter()
+            .filter_map(|&word| parser.parse_word(word))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            parsed,
+            [
+                Err(Error::Truncated(2)),
+                Ok([0x00, 0x02, 0x00, 0x65, 0x00].to_vec()),
+                Ok([0x20, 0x01, 0x3C, 0x00].to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        use super::{AutopilotMode, Converter, Datagram};
+        use alloc::{format, string::String, vec::Vec};
+
+        // 10.1 ft.
+        let depth = Datagram::decode(&[0x00, 0x02, 0x00, 0x65, 0x00]).unwrap();
+        assert_eq!(depth, Datagram::Depth(101));
+
+        // A heading of 3 * 90 + 2 * 0x05 + 1, a course of 90 + 0x15 / 2 and the rudder 5° to port.
+        let autopilot =
+            Datagram::decode(&[0x84, 0x76, 0x45, 0x15, 0x02, 0x00, 0xFB, 0x00, 0x00]).unwrap();
+        assert_eq!(
+            autopilot,
+            Datagram::Autopilot {
+                heading: 281,
+                course: 180 + 0x15,
+                mode: AutopilotMode::Auto,
+                rudder: -5,
+            }
+        );
+        assert_eq!(Datagram::decode(&[0x99, 0x00, 0x00]), None);
+
+        let mut converter = Converter::new();
+        let sentences = |converter: &mut Converter, data: &[u8]| {
+            converter
+                .convert(&Datagram::decode(data).unwrap())
+                .into_iter()
+                .map(|(sentence, chksum)| format!("${}*{:02X}", sentence, chksum))
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            sentences(&mut converter, &[0x00, 0x02, 0x00, 0x65, 0x00]),
+            ["$IIDBT,10.1,f,3.1,M,1.7,F*25"]
+        );
+        // No wind angle yet.
+        assert!(sentences(&mut converter, &[0x11, 0x01, 0x0C, 0x05]).is_empty());
+        assert!(sentences(&mut converter, &[0x10, 0x01, 0x00, 0x5B]).is_empty());
+        assert_eq!(
+            sentences(&mut converter, &[0x11, 0x01, 0x0C, 0x05]),
+            ["$IIMWV,45.5,R,12.5,N,A*3F"]
+        );
+        assert_eq!(
+            sentences(&mut converter, &[0x9C, 0x01, 0x2D, 0x03]),
+            ["$IIHDM,90.0,M*1B", "$IIRSA,3.0,A,,V*7A"]
+        );
+    }
+}
@@ -0,0 +1,521 @@
+//! The SLCAN (Lawicel) ASCII protocol, which `slcand` turns into a SocketCAN interface.
+//!
+//! Commands are lines ended by `\r`, answered by `\r` when they succeed and by a BEL (`\x07`)
+//! when they do not. [`Slcan`] only parses and keeps the channel state: the commands with side
+//! effects come out as [`Request`]s, which the caller carries out before reporting back with
+//! [`Slcan::complete`].
+
+use crate::apps::rx::pcapng::{CAN_EFF_FLAG, CAN_RTR_FLAG};
+use alloc::{format, vec::Vec};
+use defmt::Format;
+
+/// Bit rates of the `S0` to `S8` commands.
+pub const BITRATES: [u32; 9] = [
+    10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 800_000, 1_000_000,
+];
+
+/// Longest command, an extended frame with 8 bytes of data.
+const MAX_LINE: usize = 1 + 8 + 1 + 16;
+
+const OK: &[u8] = b"\r";
+const ERROR: &[u8] = b"\x07";
+
+/// Hardware and software versions, answered to `V`.
+const VERSION: &[u8] = b"V0101\r";
+const SERIAL_NUMBER: &[u8] = b"NDC33\r";
+
+/// Status flags (`F`), as in the SJA1000 based adapters.
+pub const STATUS_DATA_OVERRUN: u8 = 1 << 3;
+pub const STATUS_BUS_ERROR: u8 = 1 << 7;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum SlcanError {
+    /// Unknown command, or malformed arguments.
+    Invalid,
+    /// The command is not allowed with the channel open (or closed).
+    WrongState,
+    LineTooLong,
+}
+
+/// A CAN frame, as sent by the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub arb_id: u32,
+    pub extended: bool,
+    pub rtr: bool,
+    /// Data length code, which remote frames carry without data.
+    pub dlc: u8,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// Reads a frame in the SocketCAN layout, as the receiver streams them.
+    pub fn from_socketcan(packet: &[u8]) -> Option<Frame> {
+        let id = u32::from_be_bytes(packet.get(0..4)?.try_into().ok()?);
+        let dlc = (*packet.get(4)?).min(8);
+        let rtr = id & CAN_RTR_FLAG != 0;
+
+        let data = if rtr {
+            Vec::new()
+        } else {
+            packet.get(8..8 + dlc as usize)?.to_vec()
+        };
+
+        Some(Frame {
+            arb_id: id & 0x1FFF_FFFF,
+            extended: id & CAN_EFF_FLAG != 0,
+            rtr,
+            dlc,
+            data,
+        })
+    }
+}
+
+/// Commands with side effects, for the caller to carry out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Opens the channel at the given bit rate, without transmitting in listen-only mode.
+    Open {
+        bitrate: u32,
+        listen_only: bool,
+    },
+    Close,
+    Transmit(Frame),
+}
+
+/// Outcome of a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Send this back to the host.
+    Reply(Vec<u8>),
+    /// Carry this out, then report back with [`Slcan::complete`] (or `Gvret::complete`).
+    Request(Request),
+}
+
+/// SLCAN channel state and command parser.
+pub struct Slcan {
+    line: Vec<u8>,
+    overflow: bool,
+    open: bool,
+    listen_only: bool,
+    bitrate: u32,
+    timestamps: bool,
+    /// Status flags since the last `F`.
+    status: u8,
+}
+
+impl Slcan {
+    pub fn new() -> Self {
+        Self {
+            line: Vec::with_capacity(MAX_LINE),
+            overflow: false,
+            open: false,
+            listen_only: false,
+            bitrate: BITRATES[6],
+            timestamps: false,
+            status: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Raises status flags, reported (and cleared) by the next `F`.
+    pub fn flag(&mut self, status: u8) {
+        self.status |= status;
+    }
+
+    /// Feeds a byte from the host, and returns what to do once it ends a command.
+    pub fn push(&mut self, byte: u8) -> Option<Step> {
+        match byte {
+            b'\r' => {
+                let line = core::mem::take(&mut self.line);
+                let overflow = core::mem::replace(&mut self.overflow, false);
+
+                let outcome = if overflow {
+                    Err(SlcanError::LineTooLong)
+                } else {
+                    self.command(&line)
+                };
+
+                Some(outcome.unwrap_or_else(|_| Step::Reply(ERROR.into())))
+            }
+            // Some hosts end lines with `\r\n`.
+            b'\n' => None,
+            _ => {
+                if self.line.len() < MAX_LINE {
+                    self.line.push(byte);
+                } else {
+                    self.overflow = true;
+                }
+
+                None
+            }
+        }
+    }
+
+    fn command(&mut self, line: &[u8]) -> Result<Step, SlcanError> {
+        let Some((&command, args)) = line.split_first() else {
+            // An empty line, which `slcand` sends to flush the adapter's buffer.
+            return Ok(Step::Reply(OK.into()));
+        };
+
+        let step = match command {
+            b'S' => {
+                if self.open {
+                    return Err(SlcanError::WrongState);
+                }
+
+                let [index] = args else {
+                    return Err(SlcanError::Invalid);
+                };
+                let index = (*index as char).to_digit(10).ok_or(SlcanError::Invalid)?;
+                self.bitrate = *BITRATES.get(index as usize).ok_or(SlcanError::Invalid)?;
+
+                Step::Reply(OK.into())
+            }
+            b'O' | b'L' if args.is_empty() => {
+                if self.open {
+                    return Err(SlcanError::WrongState);
+                }
+
+                Step::Request(Request::Open {
+                    bitrate: self.bitrate,
+                    listen_only: command == b'L',
+                })
+            }
+            b'C' if args.is_empty() => {
+                if !self.open {
+                    return Err(SlcanError::WrongState);
+                }
+
+                Step::Request(Request::Close)
+            }
+            b't' | b'T' | b'r' | b'R' => {
+                if !self.open || self.listen_only {
+                    return Err(SlcanError::WrongState);
+                }
+
+                Step::Request(Request::Transmit(parse_frame(command, args)?))
+            }
+            b'F' if args.is_empty() => {
+                let status = core::mem::take(&mut self.status);
+
+                Step::Reply(format!("F{:02X}\r", status).into_bytes())
+            }
+            b'Z' => {
+                self.timestamps = match args {
+                    b"0" => false,
+                    b"1" => true,
+                    _ => return Err(SlcanError::Invalid),
+                };
+
+                Step::Reply(OK.into())
+            }
+            b'V' if args.is_empty() => Step::Reply(VERSION.into()),
+            b'N' if args.is_empty() => Step::Reply(SERIAL_NUMBER.into()),
+            _ => return Err(SlcanError::Invalid),
+        };
+
+        Ok(step)
+    }
+
+    /// Reports how a request went, and returns the reply to the host.
+    pub fn complete(&mut self, request: &Request, ok: bool) -> Vec<u8> {
+        if !ok {
+            if let Request::Transmit(_) = request {
+                self.status |= STATUS_BUS_ERROR;
+            }
+
+            return ERROR.into();
+        }
+
+        match request {
+            Request::Open { listen_only, .. } => {
+                self.open = true;
+                self.listen_only = *listen_only;
+                OK.into()
+            }
+            Request::Close => {
+                self.open = false;
+                OK.into()
+            }
+            Request::Transmit(frame) if frame.extended => b"Z\r".into(),
+            Request::Transmit(_) => b"z\r".into(),
+        }
+    }
+
+    /// Formats a received frame for the host, with the time in milliseconds when timestamps are
+    /// on.
+    pub fn format_frame(&self, frame: &Frame, millis: u64) -> Vec<u8> {
+        let command = match (frame.extended, frame.rtr) {
+            (false, false) => 't',
+            (true, false) => 'T',
+            (false, true) => 'r',
+            (true, true) => 'R',
+        };
+
+        let mut line = if frame.extended {
+            format!("{}{:08X}{:X}", command, frame.arb_id, frame.dlc)
+        } else {
+            format!("{}{:03X}{:X}", command, frame.arb_id, frame.dlc)
+        };
+
+        if !frame.rtr {
+            for byte in &frame.data {
+                line.push_str(&format!("{:02X}", byte));
+            }
+        }
+
+        if self.timestamps {
+            // Wraps every minute.
+            line.push_str(&format!("{:04X}", millis % 60_000));
+        }
+
+        line.push('\r');
+        line.into_bytes()
+    }
+}
+
+/// Parses the arguments of `t`, `T`, `r` and `R`: the identifier, the DLC, then the data.
+fn parse_frame(command: u8, args: &[u8]) -> Result<Frame, SlcanError> {
+    let extended = command.is_ascii_uppercase();
+    let rtr = command.eq_ignore_ascii_case(&b'r');
+    let id_len = if extended { 8 } else { 3 };
+
+    let id = args.get(..id_len).ok_or(SlcanError::Invalid)?;
+    let arb_id = parse_hex(id).ok_or(SlcanError::Invalid)?;
+
+    if arb_id > if extended { 0x1FFF_FFFF } else { 0x7FF } {
+        return Err(SlcanError::Invalid);
+    }
+
+    let dlc = args
+        .get(id_len..id_len + 1)
+        .and_then(parse_hex)
+        .filter(|&dlc| dlc <= 8)
+        .ok_or(SlcanError::Invalid)? as u8;
+    let data = &args[id_len + 1..];
+
+    let data = if rtr {
+        if !data.is_empty() {
+            return Err(SlcanError::Invalid);
+        }
+
+        Vec::new()
+    } else {
+        if data.len() != 2 * dlc as usize {
+            return Err(SlcanError::Invalid);
+        }
+
+        data.chunks(2)
+            .map(|pair| parse_hex(pair).map(|byte| byte as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(SlcanError::Invalid)?
+    };
+
+    Ok(Frame {
+        arb_id,
+        extended,
+        rtr,
+        dlc,
+        data,
+    })
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u32> {
+    digits.iter().try_fold(0_u32, |value, &digit| {
+        Some(value << 4 | (digit as char).to_digit(16)?)
+    })
+}
+
+mod test {
+    #[test]
+    fn test_session() {
+        use super::{Frame, Request, Slcan, Step};
+        use alloc::vec::Vec;
+
+        let mut slcan = Slcan::new();
+        let mut steps = Vec::new();
+
+        for &byte in b"S8\rO\rt1232AABB\rR1ABCDEF00\r\rr7FF1\rF\rZ1\rC\r".iter() {
+            steps.extend(slcan.push(byte));
+
+            // Requests succeed.
+            if let Some(Step::Request(request)) = steps.last() {
+                let reply = slcan.complete(request, true);
+                steps.push(Step::Reply(reply));
+            }
+        }
+
+        let reply = |bytes: &[u8]| Step::Reply(bytes.into());
+
+        assert_eq!(
+            steps,
+            [
+                reply(b"\r"),
+                Step::Request(Request::Open {
+                    bitrate: 1_000_000,
+                    listen_only: false,
+                }),
+                reply(b"\r"),
+                Step::Request(Request::Transmit(Frame {
+                    arb_id: 0x123,
+                    extended: false,
+                    rtr: false,
+                    dlc: 2,
+                    data: [0xAA, 0xBB].into(),
+                })),
+                reply(b"z\r"),
+                Step::Request(Request::Transmit(Frame {
+                    arb_id: 0x1ABC_DEF0,
+                    extended: true,
+                    rtr: true,
+                    dlc: 0,
+                    data: Vec::new(),
+                })),
+                reply(b"Z\r"),
+                // A blank line.
+                reply(b"\r"),
+                Step::Request(Request::Transmit(Frame {
+                    arb_id: 0x7FF,
+                    extended: false,
+                    rtr: true,
+                    dlc: 1,
+                    data: Vec::new(),
+                })),
+                reply(b"z\r"),
+                reply(b"F00\r"),
+                reply(b"\r"),
+                Step::Request(Request::Close),
+                reply(b"\r"),
+            ]
+        );
+        assert!(!slcan.is_open());
+    }
+
+    #[test]
+    fn test_errors() {
+        use super::{Request, Slcan, Step, STATUS_BUS_ERROR};
+        use alloc::vec::Vec;
+
+        let mut slcan = Slcan::new();
+        let push = |slcan: &mut Slcan, line: &[u8]| {
+            let steps: Vec<Step> = line.iter().filter_map(|&byte| slcan.push(byte)).collect();
+            assert_eq!(steps.len(), 1);
+            steps.into_iter().next().unwrap()
+        };
+        let bell = Step::Reply(b"\x07".into());
+
+        // Closed: no transmit, no close.
+        assert_eq!(push(&mut slcan, b"t1230\r"), bell);
+        assert_eq!(push(&mut slcan, b"C\r"), bell);
+        // Bad bit rate, unknown command, identifier out of range, DLC and data mismatch.
+        assert_eq!(push(&mut slcan, b"S9\r"), bell);
+        assert_eq!(push(&mut slcan, b"X\r"), bell);
+
+        let open = Request::Open {
+            bitrate: 500_000,
+            listen_only: true,
+        };
+        assert_eq!(push(&mut slcan, b"L\r"), Step::Request(open.clone()));
+        slcan.complete(&open, true);
+
+        // Listen-only: no transmit, and no bit rate change while open.
+        assert_eq!(push(&mut slcan, b"t1230\r"), bell);
+        assert_eq!(push(&mut slcan, b"S4\r"), bell);
+
+        let mut slcan = Slcan::new();
+        let open = Request::Open {
+            bitrate: 500_000,
+            listen_only: false,
+        };
+        assert_eq!(push(&mut slcan, b"O\r"), Step::Request(open.clone()));
+        slcan.complete(&open, true);
+
+        assert_eq!(push(&mut slcan, b"t8000\r"), bell);
+        assert_eq!(push(&mut slcan, b"t1232AA\r"), bell);
+        assert_eq!(push(&mut slcan, b"t1239\r"), bell);
+        assert_eq!(push(&mut slcan, b"r1231AA\r"), bell);
+        assert_eq!(push(&mut slcan, b"t12380011223344556677889900\r"), bell);
+
+        // A failed transmit is reported by `F`.
+        let Step::Request(request) = push(&mut slcan, b"t1230\r") else {
+            panic!();
+        };
+        assert_eq!(slcan.complete(&request, false), b"\x07");
+        assert_eq!(
+            push(&mut slcan, b"F\r"),
+            Step::Reply(alloc::format!("F{:02X}\r", STATUS_BUS_ERROR).into_bytes())
+        );
+        assert_eq!(push(&mut slcan, b"F\r"), Step::Reply(b"F00\r".into()));
+    }
+
+    #[test]
+    fn test_format_frame() {
+        use super::{Frame, Slcan};
+        use alloc::vec::Vec;
+
+        let mut slcan = Slcan::new();
+        let frame = Frame {
+            arb_id: 0x18FE_F100,
+            extended: true,
+            rtr: false,
+            dlc: 2,
+            data: [0x01, 0xFF].into(),
+        };
+        assert_eq!(slcan.format_frame(&frame, 0), b"T18FEF100201FF\r");
+
+        let frame = Frame {
+            arb_id: 0x7,
+            extended: false,
+            rtr: true,
+            dlc: 8,
+            data: Vec::new(),
+        };
+        assert_eq!(slcan.format_frame(&frame, 0), b"r0078\r");
+
+        // Timestamps wrap every minute.
+        for &byte in b"Z1\r" {
+            slcan.push(byte);
+        }
+        assert_eq!(slcan.format_frame(&frame, 60_000 + 0x1234), b"r00781234\r");
+    }
+
+    #[test]
+    fn test_from_socketcan() {
+        use super::Frame;
+        use alloc::vec::Vec;
+
+        let packet = [
+            0x98, 0xFE, 0xF1, 0x00, 2, 0, 0, 0, 0x01, 0xFF, 0, 0, 0, 0, 0, 0,
+        ];
+        let frame = Frame::from_socketcan(&packet).unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                arb_id: 0x18FE_F100,
+                extended: true,
+                rtr: false,
+                dlc: 2,
+                data: [0x01, 0xFF].into(),
+            }
+        );
+
+        let packet = [0x40, 0x00, 0x01, 0x23, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let frame = Frame::from_socketcan(&packet).unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                arb_id: 0x123,
+                extended: false,
+                rtr: true,
+                dlc: 4,
+                data: Vec::new(),
+            }
+        );
+
+        assert_eq!(Frame::from_socketcan(&packet[..4]), None);
+    }
+}
@@ -0,0 +1,361 @@
+//! A cansniffer style view of the bus on the display, run from the system application context.
+//!
+//! Every identifier gets a row with its DLC, latest payload and rate, and the bytes that changed
+//! within the last second are drawn in red. The joystick scrolls up and down, picks the bitrate
+//! left and right, and switches between sorting by identifier and by rate in the center. B clears
+//! the table, A closes the view.
+//!
+//! The sniffer sets the receiver up through [`REQUEST`] rather than the RPC runtime, as it belongs
+//! to the system rather than the user.
+
+use crate::{
+    apps::{rx::can::Message, slcan::BITRATES},
+    platform::buttons::Button,
+};
+use alloc::{format, vec::Vec};
+use core::{
+    cell::RefCell,
+    cmp::Reverse,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use defmt::Format;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    text::{Baseline, Text},
+};
+
+/// Identifiers seen past this are ignored, until the table is cleared.
+pub const MAX_IDS: usize = 256;
+
+/// How long changed bytes stay highlighted.
+const HIGHLIGHT: Duration = Duration::from_secs(1);
+/// How often rates are updated.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+const CHAR_WIDTH: i32 = 6;
+const LINE_HEIGHT: i32 = 10;
+/// Rows below the header, on the 170 pixel high landscape display.
+const ROWS: usize = 16;
+
+static SNIFFER: Mutex<CriticalSectionRawMutex, RefCell<Sniffer>> =
+    Mutex::new(RefCell::new(Sniffer::new()));
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Asks the receiver for CAN at a bitrate, or to be set back up as it was before the sniffer.
+pub static REQUEST: Signal<CriticalSectionRawMutex, Option<u32>> = Signal::new();
+
+/// Clears the table and starts listening at `bitrate`.
+pub fn start(bitrate: u32) {
+    SNIFFER.lock(|sniffer| sniffer.borrow_mut().clear(Instant::now()));
+    ACTIVE.store(true, Ordering::Relaxed);
+    REQUEST.signal(Some(bitrate));
+}
+
+/// Stops recording, and sets the receiver back up as it was unless the user took it over. A
+/// bitrate change not yet carried out is dropped either way, so it cannot undo the user's setup.
+pub fn stop(release: bool) {
+    ACTIVE.store(false, Ordering::Relaxed);
+
+    if release {
+        REQUEST.signal(None);
+    } else {
+        REQUEST.reset();
+    }
+}
+
+/// Records a received message, while the sniffer is running.
+pub fn record(msg: &Message) {
+    if ACTIVE.load(Ordering::Relaxed) {
+        SNIFFER.lock(|sniffer| {
+            sniffer.borrow_mut().record(
+                msg.arb_id(),
+                msg.is_extended(),
+                msg.dlc(),
+                msg.data(),
+                Instant::now(),
+            )
+        });
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    Id,
+    /// Busiest first.
+    Rate,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    arb_id: u32,
+    extended: bool,
+    dlc: u8,
+    len: usize,
+    data: [u8; 8],
+    /// When each byte last changed.
+    changed: [Instant; 8],
+    /// Messages in the current rate window.
+    count: u32,
+    /// Messages per second in the last window.
+    rate: u32,
+}
+
+/// The latest message of every identifier, kept ordered by identifier.
+pub struct Sniffer {
+    entries: Vec<Entry>,
+    window_start: Instant,
+}
+
+impl Sniffer {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            window_start: Instant::from_ticks(0),
+        }
+    }
+
+    pub fn clear(&mut self, now: Instant) {
+        self.entries.clear();
+        self.window_start = now;
+    }
+
+    /// Records a message. Every byte of a new identifier counts as changed, as well as bytes past
+    /// the previous payload.
+    pub fn record(&mut self, arb_id: u32, extended: bool, dlc: u8, data: &[u8], now: Instant) {
+        let index = match self
+            .entries
+            .binary_search_by_key(&(extended, arb_id), |entry| (entry.extended, entry.arb_id))
+        {
+            Ok(index) => index,
+            Err(index) => {
+                if self.entries.len() == MAX_IDS {
+                    return;
+                }
+
+                self.entries.insert(
+                    index,
+                    Entry {
+                        arb_id,
+                        extended,
+                        dlc,
+                        len: 0,
+                        data: [0; 8],
+                        changed: [now; 8],
+                        count: 0,
+                        rate: 0,
+                    },
+                );
+
+                index
+            }
+        };
+
+        let entry = &mut self.entries[index];
+        let len = data.len().min(8);
+
+        for (i, &byte) in data[..len].iter().enumerate() {
+            if i >= entry.len || entry.data[i] != byte {
+                entry.changed[i] = now;
+            }
+        }
+
+        entry.dlc = dlc;
+        entry.len = len;
+        entry.data[..len].copy_from_slice(&data[..len]);
+        entry.count += 1;
+    }
+
+    /// Updates the rates once a window has passed.
+    pub fn tick(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+
+        for entry in &mut self.entries {
+            entry.rate = (entry.count as u64 * 1000 / elapsed.as_millis()) as u32;
+            entry.count = 0;
+        }
+
+        self.window_start = now;
+    }
+
+    fn rows(&self, sort: Sort) -> Vec<Entry> {
+        let mut rows = self.entries.clone();
+
+        if sort == Sort::Rate {
+            rows.sort_by_key(|entry| Reverse(entry.rate));
+        }
+
+        rows
+    }
+}
+
+/// What is shown, and how the receiver is set up.
+pub struct View {
+    sort: Sort,
+    scroll: usize,
+    bitrate: usize,
+}
+
+impl View {
+    pub fn new() -> Self {
+        Self {
+            sort: Sort::Id,
+            scroll: 0,
+            // 500 kbit/s.
+            bitrate: 6,
+        }
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        BITRATES[self.bitrate]
+    }
+
+    /// Handles a button, returning whether the bitrate changed.
+    pub fn press(&mut self, button: Button) -> bool {
+        match button {
+            Button::Up => self.scroll = self.scroll.saturating_sub(1),
+            // Bounded when drawing.
+            Button::Down => self.scroll += 1,
+            Button::Left if self.bitrate > 0 => {
+                self.bitrate -= 1;
+                return true;
+            }
+            Button::Right if self.bitrate < BITRATES.len() - 1 => {
+                self.bitrate += 1;
+                return true;
+            }
+            Button::Center => {
+                self.sort = match self.sort {
+                    Sort::Id => Sort::Rate,
+                    Sort::Rate => Sort::Id,
+                };
+                self.scroll = 0;
+            }
+            Button::B => SNIFFER.lock(|sniffer| sniffer.borrow_mut().clear(Instant::now())),
+            Button::Left | Button::Right | Button::A => {}
+        }
+
+        false
+    }
+
+    /// Draws the table at `now`, on a landscape target.
+    pub fn draw<D: DrawTarget<Color = Rgb565>>(
+        &mut self,
+        target: &mut D,
+        now: Instant,
+    ) -> Result<(), D::Error> {
+        let rows = SNIFFER.lock(|sniffer| {
+            let mut sniffer = sniffer.borrow_mut();
+            sniffer.tick(now);
+            sniffer.rows(self.sort)
+        });
+
+        self.scroll = self.scroll.min(rows.len().saturating_sub(ROWS));
+
+        target.clear(Rgb565::BLACK)?;
+
+        let sort = match self.sort {
+            Sort::Id => "id",
+            Sort::Rate => "rate",
+        };
+        let header = format!(
+            "{} kbit/s  {} IDs  by {}",
+            self.bitrate() / 1000,
+            rows.len(),
+            sort
+        );
+        draw_text(target, &header, 0, 0, Rgb565::YELLOW)?;
+
+        for (row, entry) in rows.iter().skip(self.scroll).take(ROWS).enumerate() {
+            let y = (row as i32 + 1) * LINE_HEIGHT;
+
+            let id = if entry.extended {
+                format!("{:08X}", entry.arb_id)
+            } else {
+                format!("     {:03X}", entry.arb_id)
+            };
+            draw_text(target, &id, 0, y, Rgb565::CYAN)?;
+            draw_text(target, &format!("{}", entry.dlc), 9, y, Rgb565::WHITE)?;
+
+            for (i, byte) in entry.data[..entry.len].iter().enumerate() {
+                let color = if now.saturating_duration_since(entry.changed[i]) < HIGHLIGHT {
+                    Rgb565::RED
+                } else {
+                    Rgb565::WHITE
+                };
+
+                draw_text(
+                    target,
+                    &format!("{:02X}", byte),
+                    11 + 3 * i as i32,
+                    y,
+                    color,
+                )?;
+            }
+
+            draw_text(target, &format!("{:5}/s", entry.rate), 36, y, Rgb565::GREEN)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Draws text from column `x` and pixel row `y`.
+fn draw_text<D: DrawTarget<Color = Rgb565>>(
+    target: &mut D,
+    text: &str,
+    x: i32,
+    y: i32,
+    color: Rgb565,
+) -> Result<(), D::Error> {
+    let style = MonoTextStyle::new(&FONT_6X10, color);
+    Text::with_baseline(text, Point::new(x * CHAR_WIDTH, y), style, Baseline::Top).draw(target)?;
+
+    Ok(())
+}
+
+mod test {
+    #[test]
+    fn test_sniffer() {
+        use super::{Sniffer, Sort};
+        use embassy_time::{Duration, Instant};
+
+        let start = Instant::from_millis(0);
+        let later = start + Duration::from_millis(500);
+        let mut sniffer = Sniffer::new();
+        sniffer.clear(start);
+
+        sniffer.record(0x200, false, 2, &[1, 2], start);
+        sniffer.record(0x100, false, 1, &[1], start);
+        sniffer.record(0x100, true, 0, &[], start);
+        sniffer.record(0x200, false, 3, &[1, 3, 4], later);
+        sniffer.record(0x200, false, 3, &[1, 3, 4], later);
+
+        let rows = sniffer.rows(Sort::Id);
+        let ids: [_; 3] = core::array::from_fn(|i| (rows[i].arb_id, rows[i].extended));
+        assert_eq!(ids, [(0x100, false), (0x200, false), (0x100, true)]);
+
+        // Only the second and new third bytes changed.
+        assert_eq!(rows[1].data[..rows[1].len], [1, 3, 4]);
+        assert_eq!(rows[1].changed[..3], [start, later, later]);
+
+        // No rates until a window has passed.
+        sniffer.tick(later);
+        assert_eq!(sniffer.rows(Sort::Rate)[0].rate, 0);
+
+        sniffer.tick(start + Duration::from_secs(2));
+        let rows = sniffer.rows(Sort::Rate);
+        assert_eq!((rows[0].arb_id, rows[0].rate), (0x200, 1));
+        assert_eq!((rows[1].arb_id, rows[1].rate), (0x100, 0));
+    }
+}
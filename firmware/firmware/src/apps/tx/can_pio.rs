@@ -1,87 +1,237 @@
-//! Pio backed uart drivers
+//! Pio backed CAN transmitter
+//!
+//! Frames are clocked out only once the bus is idle, and every bit is read back from the receiver's
+//! pin at the sample point. The transmitter stops at the first mismatch, which covers losing
+//! arbitration, bit errors and the ACK slot being driven dominant by another node.
 
+use defmt::Format;
+use embassy_futures::select::{select, Either};
 use embassy_rp::{
     clocks::clk_sys_freq,
+    gpio::Level,
     pio::{
-        Common, Config, Direction, Instance, LoadedProgram, PioPin, ShiftDirection, StateMachine,
+        Common, Config, Direction, Instance, LoadedProgram, Pin, PioPin, ShiftDirection,
+        StateMachine,
     },
     Peri,
 };
+use embassy_time::{with_timeout, Duration};
 use fixed::{traits::ToFixed, types::extra::U8, FixedU32};
 
 use crate::apps::tx::TxError;
 
-/// This struct represents a uart tx program loaded into pio instruction memory.
+/// GPIO of the receiver's output. It's owned by the receiver, the transmitter only reads it back.
+pub const CAN_RX_GPIO: u8 = 9;
+
+/// PIO clock cycles per CAN bit.
+pub const CYCLES_PER_BIT: u32 = 12;
+
+/// Give up on a frame if the bus did not go idle (or the frame did not finish) in time.
+pub const BUS_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Status pushed by the program when the ACK slot was read back recessive.
+const STATUS_COMPLETE: u32 = u32::MAX;
+
+/// Outcome of a single transmission attempt.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum TxOutcome {
+    /// The frame went out and was acknowledged.
+    Sent,
+    /// Another node won arbitration; we backed off.
+    ArbitrationLost,
+    /// Nobody drove the ACK slot dominant.
+    NoAck,
+    /// The bus level did not match what we drove outside of arbitration.
+    BitError,
+    /// The bus never went idle.
+    BusBusy,
+}
+
+/// Positions of interest in a stuffed frame, as bit indices on the wire counted from the SOF.
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// Last bit of the arbitration field (RTR).
+    pub arbitration_end: u32,
+    /// The ACK slot.
+    pub ack_slot: u32,
+}
+
+/// Walks the wire bits of a frame, skipping stuff bits.
+struct Destuffer<'a> {
+    words: &'a [u32],
+    index: u32,
+    prev: bool,
+    run: u32,
+}
+
+impl<'a> Destuffer<'a> {
+    fn new(words: &'a [u32]) -> Self {
+        // The bus idles recessive.
+        Self {
+            words,
+            index: 0,
+            prev: true,
+            run: 0,
+        }
+    }
+
+    fn raw(&self, index: u32) -> Option<bool> {
+        let word = self.words.get((index / 32) as usize)?;
+        Some((word >> (31 - index % 32)) & 0b1 == 1)
+    }
+
+    /// Skips a pending stuff bit, if any.
+    fn skip_stuff(&mut self) -> Option<()> {
+        if self.run == 5 {
+            let stuff = self.raw(self.index)?;
+
+            if stuff == self.prev {
+                // Stuff error
+                return None;
+            }
+
+            self.index += 1;
+            self.prev = stuff;
+            self.run = 1;
+        }
+
+        Some(())
+    }
+
+    fn next(&mut self) -> Option<bool> {
+        self.skip_stuff()?;
+
+        let bit = self.raw(self.index)?;
+        self.index += 1;
+        self.run = if bit == self.prev { self.run + 1 } else { 1 };
+        self.prev = bit;
+
+        Some(bit)
+    }
+
+    fn take(&mut self, len: u32) -> Option<u32> {
+        (0..len).try_fold(0, |acc, _| Some((acc << 1) | self.next()? as u32))
+    }
+}
+
+impl FrameLayout {
+    /// Parse an encoded (stuffed) CAN 2.0B frame, as produced by `can::encode`. `None` if it is
+    /// malformed, or ends before the ACK slot.
+    pub fn parse(words: &[u32]) -> Option<Self> {
+        let mut bits = Destuffer::new(words);
+
+        // SOF must be dominant.
+        if bits.next()? {
+            return None;
+        }
+
+        bits.take(11)?;
+        let mut rtr = bits.next()?;
+        let mut arbitration_end = bits.index - 1;
+        let ide = bits.next()?;
+
+        if ide {
+            // The bit we read as RTR was the SRR.
+            bits.take(18)?;
+            rtr = bits.next()?;
+            arbitration_end = bits.index - 1;
+            // r1
+            bits.next()?;
+        }
+
+        // r0
+        bits.next()?;
+
+        let dlc = bits.take(4)?;
+        let len = if rtr { 0 } else { dlc.min(8) };
+
+        for _ in 0..len {
+            bits.take(8)?;
+        }
+
+        bits.take(15)?;
+        // Stuffing covers the CRC sequence, up to the CRC delimiter.
+        bits.skip_stuff()?;
+
+        let ack_slot = bits.index + 1;
+
+        // The words clocked out must reach the ACK slot.
+        if words.len() <= (ack_slot / 32) as usize {
+            return None;
+        }
+
+        Some(Self {
+            arbitration_end,
+            ack_slot,
+        })
+    }
+
+    /// Interpret the status word pushed by the transmitter program.
+    pub fn outcome(&self, words: &[u32], status: u32) -> TxOutcome {
+        if status == STATUS_COMPLETE {
+            return TxOutcome::NoAck;
+        }
+
+        let index = self.ack_slot - status;
+        let driven = Destuffer::new(words).raw(index).unwrap_or(true);
+
+        if index == self.ack_slot {
+            TxOutcome::Sent
+        } else if !driven {
+            // Drove dominant, read back recessive.
+            TxOutcome::BitError
+        } else if index <= self.arbitration_end {
+            TxOutcome::ArbitrationLost
+        } else {
+            TxOutcome::BitError
+        }
+    }
+}
+
+/// This struct represents the CAN transmitter program loaded into pio instruction memory.
 pub struct PioCanTrxProgram<'d, PIO: Instance> {
     prg: LoadedProgram<'d, PIO>,
 }
 
 impl<'d, PIO: Instance> PioCanTrxProgram<'d, PIO> {
-    /// Load the uart tx program into the given pio
+    /// Load the CAN transmitter program into the given pio
     pub fn new(common: &mut Common<'d, PIO>) -> Self {
-        // let prg = pio::pio_asm!(
-        //     r#"
-        //     .define DOMINANT 0b010
-        //     .define RECESSIVE 0b101
-
-        //     wait_ifs:
-        //         set pins, RECESSIVE         ; OUTPUT recessive
-        //     ifs_loop_reset:
-        //         set x, 0b11                 ; we need 3 recessive bits before we start
-        //         mov isr, x                  ; 7 bits * 16 (oversample) / 2 (instructions) = 24 = 0x18
-        //         in null, 3                  ; shift left 3
-        //     ifs_loop:
-        //         jmp x-- idle                ; got ifs, jump to idle
-        //         jmp pin ifs_loop            ; recessive just keep counting
-        //         jmp ifs_loop_reset          ; got dominant, reset counter
-        //     idle:
-        //         jmp !osre tx_bit
-        //     eof:
-        //         irq 0 [1]                   ; IRQ0 = EOF
-        //         set pins, RECESSIVE         ; OUTPUT recessive
-        //         set x, 7                    ; eof is recessive for 7 bits
-        //     eof_loop:
-        //         jmp x-- eof_loop [15]
-        //         jmp wait_ifs
-        //     tx_bit:
-        //         out x, 1                    ; get next bit
-        //         jmp !x dominant
-        //     recessive:
-        //         set pins, RECESSIVE [7]     ; OUTPUT recessive
-        //         jmp pin idle [3]            ; check for recessive
-        //         irq 1                       ; IRQ1 = arbitration error
-        //         jmp wait_ifs                ; reset
-        //     dominant:
-        //         set pins, DOMINANT [7]      ; OUTPUT dominant
-        //         jmp idle [3]
-        // "#
-        // );
+        // NOTE: Instruction memory is shared with the injector and the overwrite, every instruction
+        //       counts. Only H_V1 differs between the recessive and dominant states of the high
+        //       side, so it is the only side-set pin (leaving room for delays).
+        // NOTE: The header word is the number of bits through the ACK slot, minus one. When the
+        //       read back level does not match, the program stops and pushes the number of bits
+        //       remaining (0xFFFFFFFF when the ACK slot was read back recessive).
         let prg = pio::pio_asm!(
             r#"
-            .side_set 3 opt
+            .side_set 1 opt
             .define DOMINANT         0b00100
             .define RECESSIVE        0b10011
-            .define STRONG_RECESSIVE 0b00010
-            .define SIDE_RECESSIVE   0b001
-            .define SIDE_DOMINANT    0b011
-
-            ; initialize x to be 0xffffffff
-            mov x, ! null
 
-            loop:
-                jmp !osre prepare
-                pull noblock
-            inner:
+                out x, 32                       ; bits through the ACK slot, minus one
+            idle_reset:
+                set y, 21
+            idle:
+                jmp pin idle_recessive
+                jmp idle_reset
+            idle_recessive:
+                jmp y-- idle [4]                ; 22 * 6 cycles: 11 recessive bits
+            bit:
                 out y, 1
                 jmp !y dominant
-            recessive:
-                set pins RECESSIVE side SIDE_RECESSIVE
-                jmp loop
+                set pins RECESSIVE side 0 [7]
+                jmp pin match                   ; read back recessive
+                jmp done                        ; lost arbitration, ACK or bit error
             dominant:
-                set pins DOMINANT side SIDE_DOMINANT
-                jmp loop
-            prepare:
-                jmp inner
+                set pins DOMINANT side 1 [7]
+                jmp pin done                    ; read back recessive: bit error
+            match:
+                jmp x-- bit
+            done:
+                in x, 32                        ; status: bits remaining
+            .wrap_target
+                set pins RECESSIVE side 0       ; halt until restarted
+            .wrap
         "#
         );
         let prg = common.load_program(&prg.program);
@@ -90,9 +240,12 @@ impl<'d, PIO: Instance> PioCanTrxProgram<'d, PIO> {
     }
 }
 
-/// PIO backed Uart transmitter
+/// PIO backed CAN transmitter
 pub struct PioCanTrx<'d, PIO: Instance, const SM: usize> {
     sm: StateMachine<'d, PIO, SM>,
+    origin: u8,
+    recessive_high: [Pin<'d, PIO>; 4],
+    recessive_low: [Pin<'d, PIO>; 4],
 }
 
 impl<'d, PIO: Instance, const SM: usize> PioCanTrx<'d, PIO, SM> {
@@ -126,12 +279,27 @@ impl<'d, PIO: Instance, const SM: usize> PioCanTrx<'d, PIO, SM> {
 
         let mut cfg = Config::default();
         cfg.set_set_pins(&[&l_z0, &l_v2, &l_v1, &l_v0, &h_z0]);
-        cfg.use_program(&program.prg, &[&h_v2, &h_v1, &h_v0]);
+        cfg.use_program(&program.prg, &[&h_v1]);
+
+        // NOTE: The receiver's pin is only read, so it is not handed over to this PIO block.
+        let mut exec = cfg.get_exec();
+        exec.jmp_pin = CAN_RX_GPIO;
+        unsafe { cfg.set_exec(exec) };
+
         cfg.shift_out.direction = ShiftDirection::Left;
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = 32;
+        cfg.shift_in.auto_fill = true;
+        cfg.shift_in.threshold = 32;
         cfg.clock_divider = Self::clk_div(baud)?;
         sm.set_config(&cfg);
 
-        Ok(Self { sm })
+        Ok(Self {
+            sm,
+            origin: program.prg.origin,
+            recessive_high: [l_z0, l_v2, h_z0, h_v2],
+            recessive_low: [l_v1, l_v0, h_v1, h_v0],
+        })
     }
 
     pub fn clk_div(baud: u32) -> Result<FixedU32<U8>, TxError> {
@@ -145,7 +313,7 @@ impl<'d, PIO: Instance, const SM: usize> PioCanTrx<'d, PIO, SM> {
             }
         }
 
-        let divisor = 6_u32
+        let divisor = CYCLES_PER_BIT
             .checked_mul(baud)
             .ok_or(TxError::ClockDividerTooSmall)?;
 
@@ -171,7 +339,6 @@ impl<'d, PIO: Instance, const SM: usize> PioCanTrx<'d, PIO, SM> {
         }
 
         self.sm.clkdiv_restart();
-        self.restart();
 
         Ok(())
     }
@@ -180,8 +347,9 @@ impl<'d, PIO: Instance, const SM: usize> PioCanTrx<'d, PIO, SM> {
         self.sm.is_enabled()
     }
 
-    /// Enable's the PIO program, continuing the wave generation from the PIO program.
+    /// Enable's the PIO program, driving the bus recessive until a frame is written.
     pub fn enable(&mut self) {
+        self.restart();
         self.sm.set_enable(true);
     }
 
@@ -190,25 +358,46 @@ impl<'d, PIO: Instance, const SM: usize> PioCanTrx<'d, PIO, SM> {
         self.sm.set_enable(false);
     }
 
+    /// Stops the PIO program, drops any queued words and releases the bus.
     pub fn restart(&mut self) {
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
         self.sm.restart();
+        unsafe { self.sm.exec_jmp(self.origin) };
+
+        let [l_z0, l_v2, h_z0, h_v2] = &self.recessive_high;
+        let [l_v1, l_v0, h_v1, h_v0] = &self.recessive_low;
+        self.sm.set_pins(Level::High, &[l_z0, l_v2, h_z0, h_v2]);
+        self.sm.set_pins(Level::Low, &[l_v1, l_v0, h_v1, h_v0]);
     }
 
-    /// Write a single u8
-    pub async fn write_word(&mut self, word: u32) {
-        self.sm.tx().wait_push(word).await;
+    /// Make a single attempt at sending an encoded frame (see `can::encode`).
+    pub async fn write_frame(&mut self, words: &[u32]) -> Result<TxOutcome, TxError> {
+        let layout = FrameLayout::parse(words).ok_or(TxError::InvalidFrame)?;
+        // Only the words up to the ACK slot are clocked out, the rest of the frame is recessive.
+        let words = &words[..(layout.ack_slot / 32 + 1) as usize];
+
+        self.enable();
+        let status = with_timeout(BUS_TIMEOUT, self.clock_out(layout.ack_slot, words)).await;
+        self.enable();
+
+        Ok(match status {
+            Ok(status) => layout.outcome(words, status),
+            Err(_) => TxOutcome::BusBusy,
+        })
     }
-}
 
-// impl<PIO: Instance, const SM: usize> ErrorType for PioCanTrx<'_, PIO, SM> {
-//     type Error = Infallible;
-// }
-
-// impl<PIO: Instance, const SM: usize> Write for PioCanTrx<'_, PIO, SM> {
-//     async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
-//         for byte in buf {
-//             self.write_u8(*byte).await;
-//         }
-//         Ok(buf.len())
-//     }
-// }
+    async fn clock_out(&mut self, header: u32, words: &[u32]) -> u32 {
+        let (rx, tx) = self.sm.rx_tx();
+
+        // NOTE: The program stops consuming words as soon as it bails out, so keep an eye on the
+        //       status while feeding it.
+        for word in core::iter::once(header).chain(words.iter().copied()) {
+            if let Either::Second(status) = select(tx.wait_push(word), rx.wait_pull()).await {
+                return status;
+            }
+        }
+
+        rx.wait_pull().await
+    }
+}
@@ -1,18 +1,124 @@
-use core::convert::Infallible;
+use alloc::vec::Vec;
+use core::{
+    convert::Infallible,
+    future::pending,
+    ptr::addr_of_mut,
+    sync::atomic::{compiler_fence, AtomicU32, Ordering},
+};
+use defmt::{debug, warn};
 use embassy_rp::{
     clocks::clk_sys_freq,
     dma::{AnyChannel, Channel},
     gpio::{Level, Output},
-    peripherals::PIN_25,
+    pac::{
+        self,
+        dma::vals::{DataSize, TreqSel},
+    },
+    peripherals::{PIN_25, PIO1},
     pio::{
         Common, Config, Direction, Instance, LoadedProgram, PioPin, ShiftDirection, StateMachine,
     },
     Peri,
 };
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pipe::Pipe};
+use embassy_time::{with_timeout, Duration, Timer};
 use embedded_io_async::{ErrorType, Write};
 use fixed::{traits::ToFixed, types::extra::U8, FixedU32};
 
-use crate::apps::tx::TxError;
+use crate::{
+    apps::tx::TxError,
+    platform::repl::tx::{H_Z0, L_Z0},
+};
+
+/// Both lines at high impedance, as the program drives them with an empty FIFO.
+const IDLE: u8 = H_Z0 | L_Z0;
+
+/// DREQ of the first PIO1 state machine's TX FIFO.
+const TREQ_PIO1_TX0: u8 = 8;
+
+/// Streamed samples are played from a ring the DMA wraps around by itself.
+const RING_BITS: u8 = 14;
+const RING_SIZE: usize = 1 << RING_BITS;
+const RING_MASK: usize = RING_SIZE - 1;
+
+/// Samples kept ahead of the DMA when a stream (re)starts.
+const STREAM_LEAD: usize = RING_SIZE / 4;
+
+/// How often the ring is topped up while no samples come in. A full ring lasts over 3 ms at
+/// 5 MHz.
+const FEED_PERIOD: Duration = Duration::from_millis(1);
+
+pub const STREAM_PIPE_SIZE: usize = 4096;
+
+/// Samples written by the host to the USB stream interface, see `platform::sample_stream`.
+pub static STREAM: Pipe<CriticalSectionRawMutex, STREAM_PIPE_SIZE> = Pipe::new();
+
+// The DMA ring wrap needs the ring aligned to its size.
+#[repr(C, align(16384))]
+struct Ring([u8; RING_SIZE]);
+
+static mut RING: Ring = Ring([IDLE; RING_SIZE]);
+
+/// Where the control channel restarts a loop from.
+static LOOP_START: AtomicU32 = AtomicU32::new(0);
+
+fn ring() -> *mut u8 {
+    unsafe { addr_of_mut!(RING.0) as *mut u8 }
+}
+
+/// Fills `len` ring samples from `start` with the idle code, wrapping around.
+fn clear_ring(start: usize, len: usize) {
+    for i in 0..len {
+        unsafe { ring().add((start + i) & RING_MASK).write_volatile(IDLE) };
+    }
+}
+
+/// Position of a stream in the ring. Counters are free running and wrap.
+struct Stream {
+    /// Last DMA offset into the ring.
+    offset: usize,
+    read: usize,
+    written: usize,
+    samples: u64,
+    underruns: u32,
+    /// Whether the ring ran dry with no samples since.
+    starved: bool,
+}
+
+impl Stream {
+    /// Catches up with the DMA.
+    fn account(&mut self, dma: pac::dma::Channel) {
+        let offset = (dma.read_addr().read() as usize).wrapping_sub(ring() as usize) & RING_MASK;
+        let consumed = offset.wrapping_sub(self.offset) & RING_MASK;
+
+        // Played samples go back to idle, so an underrun lets the bus go rather than replaying
+        // old samples.
+        clear_ring(self.offset, consumed);
+        self.offset = offset;
+        self.read = self.read.wrapping_add(consumed);
+
+        if self.read.wrapping_sub(self.written) as isize >= 0 {
+            if self.samples > 0 && !self.starved {
+                warn!("Injector stream underrun after {} samples", self.samples);
+                self.underruns += 1;
+            }
+
+            self.written = self.read.wrapping_add(STREAM_LEAD);
+            self.starved = true;
+        }
+    }
+
+    fn free(&self) -> usize {
+        RING_SIZE - self.written.wrapping_sub(self.read)
+    }
+}
+
+enum Playback {
+    Idle,
+    /// The samples are kept alive for the DMA.
+    Looping(Vec<u8>),
+    Streaming(Stream),
+}
 
 pub struct PioInjectorProgram<'d, PIO: Instance> {
     prg: LoadedProgram<'d, PIO>,
@@ -22,20 +128,18 @@ impl<'d, PIO: Instance> PioInjectorProgram<'d, PIO> {
     /// Load the uart tx program into the given pio
     pub fn new(common: &mut Common<'d, PIO>) -> Self {
         // TODO: Is the behavior correct for the default?
+        // NOTE: X holds the default (0V, Hi-Z), set from `PioInjector::new`. Instruction memory is
+        //       shared with the CAN transmitter and the overwrite, with no room to spare.
 
         let prg = pio::pio_asm!(
             r#"
-            set x, 0b10001      ; default: 0V, Hi-Z
-
             .wrap_target
                 pull noblock        ; get data into the OSR
-                in osr, 5           ; shift 5 bits from OSR into ISR
+                in osr, 8           ; shift 8 bits from OSR into ISR, the top 3 land on unused pins
                 out null, 5         ; flush 5 bits from OSR
-                in null, 3          ; shift 0b000 bits into ISR
                 in osr, 3           ; shift next 3 bits from OSR into ISR
                 in null, 21         ; fill ISR with 21 zeros
-                mov osr, isr        ; move ISR into OSR
-                out pins, 11        ; write OSR to pins
+                mov pins, isr [2]   ; write ISR to pins, 8 cycles per sample
             .wrap
         "#
         );
@@ -48,7 +152,10 @@ impl<'d, PIO: Instance> PioInjectorProgram<'d, PIO> {
 pub struct PioInjector<'d, PIO: Instance, const SM: usize> {
     sm: StateMachine<'d, PIO, SM>,
     dma: Peri<'d, AnyChannel>,
+    /// Restarts `dma` for loops.
+    ctrl_dma: Peri<'d, AnyChannel>,
     led: Output<'static>,
+    playback: Playback,
 }
 
 impl<'d, PIO: Instance, const SM: usize> PioInjector<'d, PIO, SM> {
@@ -58,6 +165,7 @@ impl<'d, PIO: Instance, const SM: usize> PioInjector<'d, PIO, SM> {
         common: &mut Common<'d, PIO>,
         mut sm: StateMachine<'d, PIO, SM>,
         dma: Peri<'d, impl Channel>,
+        ctrl_dma: Peri<'d, impl Channel>,
         l_z0: Peri<'d, impl PioPin>,
         l_v2: Peri<'d, impl PioPin>,
         l_v1: Peri<'d, impl PioPin>,
@@ -99,10 +207,23 @@ impl<'d, PIO: Instance, const SM: usize> PioInjector<'d, PIO, SM> {
         cfg.clock_divider = Self::clk_div(baud)?;
         sm.set_config(&cfg);
 
+        // Default: 0V, Hi-Z
+        unsafe {
+            sm.exec_instr(
+                pio::InstructionOperands::SET {
+                    destination: pio::SetDestination::X,
+                    data: IDLE,
+                }
+                .encode(),
+            )
+        };
+
         Ok(Self {
             sm,
             dma: dma.into(),
+            ctrl_dma: ctrl_dma.into(),
             led: Output::new(led, Level::Low),
+            playback: Playback::Idle,
         })
     }
 
@@ -175,6 +296,170 @@ impl<'d, PIO: Instance, const SM: usize> PioInjector<'d, PIO, SM> {
             .await;
         self.led.set_low();
     }
+
+    /// Whether a loop or a stream is playing.
+    pub fn is_playing(&self) -> bool {
+        !matches!(self.playback, Playback::Idle)
+    }
+
+    /// Stops a loop or a stream, returning the samples streamed and the underruns. The program
+    /// lets go of the bus once the FIFO runs dry.
+    pub fn stop(&mut self) -> (u64, u32) {
+        if !self.is_playing() {
+            return (0, 0);
+        }
+
+        let (data, ctrl) = (self.dma.number(), self.ctrl_dma.number());
+
+        // NOTE: Chained channels are disabled first, or the abort may not stick.
+        for ch in [data, ctrl] {
+            pac::DMA.ch(ch as _).ctrl_trig().modify(|w| w.set_en(false));
+        }
+        pac::DMA
+            .chan_abort()
+            .modify(|m| m.set_chan_abort((1 << data) | (1 << ctrl)));
+        for ch in [data, ctrl] {
+            while pac::DMA.ch(ch as _).ctrl_trig().read().busy() {}
+        }
+        compiler_fence(Ordering::SeqCst);
+
+        self.led.set_low();
+
+        match core::mem::replace(&mut self.playback, Playback::Idle) {
+            Playback::Streaming(stream) => {
+                STREAM.clear();
+                (stream.samples, stream.underruns)
+            }
+            Playback::Looping(samples) => {
+                debug!("Stopped a {} sample loop", samples.len());
+                (0, 0)
+            }
+            Playback::Idle => (0, 0),
+        }
+    }
+
+    /// Moves streamed samples into the ring, returning after a while so commands get through.
+    /// Never returns unless streaming.
+    pub async fn feed(&mut self) {
+        let dma = self.dma.regs();
+        let Playback::Streaming(stream) = &mut self.playback else {
+            return pending().await;
+        };
+
+        stream.account(dma);
+        let start = stream.written & RING_MASK;
+        let len = stream.free().min(RING_SIZE - start);
+
+        if len == 0 {
+            Timer::after(FEED_PERIOD).await;
+            return;
+        }
+
+        // SAFETY: The DMA is behind `read`, and only ever reads the ring.
+        let buf = unsafe { core::slice::from_raw_parts_mut(ring().add(start), len) };
+
+        if let Ok(len) = with_timeout(FEED_PERIOD, STREAM.read(buf)).await {
+            stream.written = stream.written.wrapping_add(len);
+            stream.samples += len as u64;
+            stream.starved = false;
+        }
+    }
+}
+
+impl<'d, const SM: usize> PioInjector<'d, PIO1, SM> {
+    fn txf() -> u32 {
+        pac::PIO1.txf(SM).as_ptr() as u32
+    }
+
+    /// Plays `samples` over and over, until stopped. One channel plays the samples and chains to
+    /// another, which restarts it from the start.
+    pub fn start_loop(&mut self, samples: Vec<u8>) {
+        self.stop();
+
+        let data = self.dma.regs();
+        let ctrl = self.ctrl_dma.regs();
+
+        LOOP_START.store(samples.as_ptr() as u32, Ordering::Relaxed);
+
+        // Runs once straight away, pointing the disabled data channel at the samples.
+        ctrl.read_addr().write_value(LOOP_START.as_ptr() as u32);
+        ctrl.write_addr()
+            .write_value(data.al3_read_addr_trig().as_ptr() as u32);
+        ctrl.trans_count().write(|w| {
+            w.set_mode(0.into());
+            w.set_count(1);
+        });
+        compiler_fence(Ordering::SeqCst);
+        ctrl.ctrl_trig().write(|w| {
+            w.set_treq_sel(TreqSel::PERMANENT);
+            w.set_data_size(DataSize::SIZE_WORD);
+            w.set_incr_read(false);
+            w.set_incr_write(false);
+            w.set_chain_to(self.ctrl_dma.number());
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+        while ctrl.ctrl_trig().read().busy() {}
+
+        data.write_addr().write_value(Self::txf());
+        data.trans_count().write(|w| {
+            w.set_mode(0.into());
+            w.set_count(samples.len() as u32);
+        });
+        compiler_fence(Ordering::SeqCst);
+        data.ctrl_trig().write(|w| {
+            w.set_treq_sel(TreqSel::from(TREQ_PIO1_TX0 + SM as u8));
+            w.set_data_size(DataSize::SIZE_BYTE);
+            w.set_incr_read(true);
+            w.set_incr_write(false);
+            w.set_chain_to(self.ctrl_dma.number());
+            // NOTE: Short loops would otherwise swamp the DMA interrupt.
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+
+        self.led.set_high();
+        self.playback = Playback::Looping(samples);
+    }
+
+    /// Plays samples from `STREAM` as `feed` moves them into the ring, until stopped. The bus is
+    /// let go while no samples come in.
+    pub fn start_stream(&mut self) {
+        self.stop();
+
+        clear_ring(0, RING_SIZE);
+
+        let data = self.dma.regs();
+        data.read_addr().write_value(ring() as u32);
+        data.write_addr().write_value(Self::txf());
+        data.trans_count().write(|w| {
+            // Endless
+            w.set_mode(0xf.into());
+            w.set_count(0);
+        });
+        compiler_fence(Ordering::SeqCst);
+        data.ctrl_trig().write(|w| {
+            w.set_treq_sel(TreqSel::from(TREQ_PIO1_TX0 + SM as u8));
+            w.set_data_size(DataSize::SIZE_BYTE);
+            w.set_incr_read(true);
+            w.set_incr_write(false);
+            w.set_ring_sel(false);
+            w.set_ring_size(RING_BITS);
+            w.set_chain_to(self.dma.number());
+            w.set_irq_quiet(true);
+            w.set_en(true);
+        });
+
+        self.led.set_high();
+        self.playback = Playback::Streaming(Stream {
+            offset: 0,
+            read: 0,
+            written: 0,
+            samples: 0,
+            underruns: 0,
+            starved: false,
+        });
+    }
 }
 
 impl<PIO: Instance, const SM: usize> ErrorType for PioInjector<'_, PIO, SM> {
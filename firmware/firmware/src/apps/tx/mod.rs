@@ -1,11 +1,16 @@
 pub mod can_pio;
 pub mod can_spi;
 pub mod inject;
+pub mod overwrite;
+pub mod replay;
+pub mod schedule;
+pub mod waveform;
 
 use crate::{
     apps::tx::{
-        can_pio::{PioCanTrx, PioCanTrxProgram},
+        can_pio::{PioCanTrx, PioCanTrxProgram, TxOutcome},
         inject::{PioInjector, PioInjectorProgram},
+        overwrite::{PioCanOverwrite, PioCanOverwriteProgram},
     },
     platform::{
         i2c_io_expander::{
@@ -24,7 +29,7 @@ use embassy_rp::{
     interrupt::typelevel::Binding,
     peripherals::{
         I2C0, PIN_18, PIN_19, PIN_20, PIN_21, PIN_22, PIN_23, PIN_24, PIN_25, PIN_26, PIN_27,
-        PIN_28,
+        PIN_28, PIO1,
     },
     pio::{Instance, Pio},
     Peri,
@@ -35,6 +40,9 @@ use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 pub enum TxError {
     ClockDividerTooLarge,
     ClockDividerTooSmall,
+    OffsetTooSmall,
+    InvalidWidth,
+    InvalidFrame,
 }
 
 impl core::fmt::Display for TxError {
@@ -80,8 +88,10 @@ impl TxWords {
 pub struct TxController<'a, P: Instance> {
     mode: TxMode,
     enabled: bool,
+    retries: u8,
     pio_inj: PioInjector<'a, P, 0>,
     pio_can: PioCanTrx<'a, P, 1>,
+    pio_overwrite: PioCanOverwrite<'a, P, 2>,
     tx_connect: i2c_io_expander::pin::Pin<
         CriticalSectionRawMutex,
         I2cDevice<'static, CriticalSectionRawMutex, i2c::I2c<'static, I2C0, i2c::Async>>,
@@ -106,6 +116,7 @@ where
     pub async unsafe fn new(
         mode: TxMode,
         dma: Peri<'a, impl Channel>,
+        ctrl_dma: Peri<'a, impl Channel>,
         pio: Peri<'a, P>,
         l_z0: Peri<'static, PIN_18>,
         l_v2: Peri<'static, PIN_19>,
@@ -136,16 +147,41 @@ where
     ) -> Result<Self, TxError> {
         let Pio {
             mut common,
+            irq_flags,
+            irq3,
             sm0,
             sm1,
+            sm2,
             ..
         } = Pio::new(pio, Irqs);
+        // NOTE: The overwrite goes first, as the injector takes the LED pin back from the PIO.
+        let prg_overwrite = PioCanOverwriteProgram::new(&mut common);
+        let pio_overwrite = PioCanOverwrite::new(
+            Self::default_baud(),
+            &mut common,
+            sm2,
+            l_z0.clone_unchecked(),
+            l_v2.clone_unchecked(),
+            l_v1.clone_unchecked(),
+            l_v0.clone_unchecked(),
+            h_z0.clone_unchecked(),
+            nil_0.clone_unchecked(),
+            nil_1.clone_unchecked(),
+            led.clone_unchecked(),
+            h_v2.clone_unchecked(),
+            h_v1.clone_unchecked(),
+            h_v0.clone_unchecked(),
+            &prg_overwrite,
+            irq_flags,
+            irq3,
+        )?;
         let prg_inj = PioInjectorProgram::new(&mut common);
         let pio_inj = PioInjector::new(
             Self::default_baud(),
             &mut common,
             sm0,
             dma,
+            ctrl_dma,
             l_z0.clone_unchecked(),
             l_v2.clone_unchecked(),
             l_v1.clone_unchecked(),
@@ -186,8 +222,10 @@ where
         Ok(Self {
             mode,
             enabled: false,
+            retries: Self::default_retries(),
             pio_inj,
             pio_can,
+            pio_overwrite,
             tx_connect,
             tx_enable,
             pwr_injector,
@@ -198,6 +236,16 @@ where
         250_000
     }
 
+    pub fn default_retries() -> u8 {
+        3
+    }
+
+    /// Number of times a CAN frame is sent again after losing arbitration, a missing ACK or a bit
+    /// error.
+    pub fn set_retries(&mut self, retries: u8) {
+        self.retries = retries;
+    }
+
     pub fn mode(&self) -> TxMode {
         self.mode
     }
@@ -223,6 +271,7 @@ where
     pub fn set_baud(&mut self, baud: u32) -> Result<(), TxError> {
         self.pio_inj.set_baud(baud)?;
         self.pio_can.set_baud(baud)?;
+        self.pio_overwrite.set_baud(baud)?;
 
         Ok(())
     }
@@ -232,11 +281,9 @@ where
     }
 
     pub async fn enable(&mut self) {
-        match self.mode {
-            TxMode::Inject => self.pio_inj.enable(),
-            TxMode::Can => {
-                self.pio_can.enable();
-            }
+        // The overwrite owns the bus pins while armed.
+        if !self.pio_overwrite.is_armed() {
+            self.enable_mode();
         }
 
         self.tx_connect.set_output(true).await;
@@ -245,22 +292,47 @@ where
         self.enabled = true;
     }
 
+    fn enable_mode(&mut self) {
+        match self.mode {
+            TxMode::Inject => self.pio_inj.enable(),
+            TxMode::Can => self.pio_can.enable(),
+        }
+    }
+
     pub async fn disable(&mut self) {
+        self.pio_inj.stop();
         self.pio_inj.disable();
         self.pio_can.disable();
+        self.pio_overwrite.disarm();
         self.tx_connect.set_output(false).await;
         self.tx_enable.set_output(false).await;
         self.pwr_injector.set_output(false).await;
         self.enabled = false;
     }
 
-    pub async fn send(&mut self, words: TxWords) {
+    /// Whether the injector is playing a loop or a stream.
+    pub fn is_playing(&self) -> bool {
+        self.pio_inj.is_playing()
+    }
+
+    /// Stops a loop or a stream, returning the samples streamed and the underruns.
+    pub fn stop(&mut self) -> (u64, u32) {
+        self.pio_inj.stop()
+    }
+
+    /// Keeps a stream going, see `PioInjector::feed`.
+    pub async fn feed(&mut self) {
+        self.pio_inj.feed().await
+    }
+
+    pub async fn send(&mut self, words: TxWords) -> Result<TxOutcome, TxError> {
         assert_eq!(words.mode(), self.mode);
 
         match self.mode {
             TxMode::Inject => match words {
                 TxWords::Inject(words) => {
                     self.pio_inj.write_bytes(&words).await;
+                    Ok(TxOutcome::Sent)
                 }
                 TxWords::Can(_) => {
                     unreachable!()
@@ -271,11 +343,76 @@ where
                     unreachable!()
                 }
                 TxWords::Can(words) => {
-                    for word in words {
-                        self.pio_can.write_word(word).await;
+                    let mut outcome = self.pio_can.write_frame(&words).await?;
+
+                    for _ in 0..self.retries {
+                        match outcome {
+                            TxOutcome::Sent | TxOutcome::BusBusy => break,
+                            _ => outcome = self.pio_can.write_frame(&words).await?,
+                        }
                     }
+
+                    Ok(outcome)
                 }
             },
         }
     }
+
+    /// Arms the targeted overwrite: `delay` bits after the receiver's identifier match, drive the
+    /// bus dominant for `width` bits. Only valid in CAN mode, while enabled.
+    pub fn arm_overwrite(&mut self, delay: u32, width: u32) -> Result<(), TxError> {
+        self.pio_can.disable();
+        self.pio_overwrite.arm(delay, width)
+    }
+
+    /// Arms the overwrite to drive the ACK slot on the receiver's ACK trigger. Only valid in CAN
+    /// mode, while enabled.
+    pub fn arm_ack(&mut self) {
+        self.pio_can.disable();
+        self.pio_overwrite.arm_ack();
+    }
+
+    /// Arms a glitch driving the injector `code` for `width` system clock cycles, `delay` cycles
+    /// after the trigger (see [`crate::apps::glitch`]). Valid in either mode, while enabled.
+    pub fn arm_glitch(&mut self, code: u8, delay: u32, width: u32) -> Result<(), TxError> {
+        self.pio_inj.disable();
+        self.pio_can.disable();
+        self.pio_overwrite.arm_glitch(code, delay, width)
+    }
+
+    /// Triggers an armed glitch from the CPU.
+    pub fn fire_glitch(&mut self) {
+        self.pio_overwrite.fire();
+    }
+
+    /// Waits for an armed glitch to be triggered and over.
+    pub async fn wait_glitch(&mut self) {
+        self.pio_overwrite.wait_done().await;
+    }
+
+    pub fn disarm_overwrite(&mut self) {
+        self.pio_overwrite.disarm();
+
+        if self.enabled {
+            self.enable_mode();
+        }
+    }
+
+    pub fn is_overwrite_armed(&self) -> bool {
+        self.pio_overwrite.is_armed()
+    }
+}
+
+impl<'a> TxController<'a, PIO1> {
+    /// Plays injector samples over and over until stopped. Only valid in inject mode, while
+    /// enabled.
+    pub fn send_loop(&mut self, samples: Vec<u8>) {
+        self.pio_inj.start_loop(samples);
+    }
+
+    /// Plays injector samples from the USB stream interface until stopped. Only valid in inject
+    /// mode, while enabled.
+    pub fn stream(&mut self) {
+        self.pio_inj.start_stream();
+    }
 }
@@ -0,0 +1,262 @@
+//! PIO backed targeted bit overwrite ("CANflict")
+//!
+//! Cooperates with [`crate::apps::rx::can::PioCanMatch`] on the receive PIO block: once the
+//! matcher sees the configured identifier, it raises IRQ6 on this PIO block and this program drives
+//! the bus dominant at a configured bit offset for a configured number of bits.
+//!
+//! The same program drives the ACK slot for [`crate::apps::rx::can::PioCanAck`], which raises
+//! IRQ6 on the bit following a frame's CRC, and voltage glitches (see [`crate::apps::glitch`]),
+//! where IRQ6 may also come from the CPU.
+
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    pio::{
+        Common, Config, Direction, Instance, Irq, IrqFlags, LoadedProgram, PioPin, StateMachine,
+    },
+    Peri,
+};
+use fixed::{traits::ToFixed, types::extra::U8, FixedU32};
+
+use crate::{
+    apps::tx::TxError,
+    platform::repl::tx::{H_3V5, L_1V5},
+};
+
+/// PIO IRQ flag raised (from the receive PIO block) when the identifier matched.
+pub const IRQ_MATCH: usize = 6;
+
+/// PIO IRQ flag raised once the pins are back at rest.
+pub const IRQ_DONE: usize = 3;
+
+/// Cycles from the trigger to the pins changing, at the least.
+pub const MIN_GLITCH_DELAY: u32 = 3;
+
+/// Cycles the pins are held, at the least.
+pub const MIN_GLITCH_WIDTH: u32 = 3;
+
+/// The out pin word driving an injector code, skipping the three pins between L and H.
+pub fn pins(code: u8) -> u32 {
+    (code as u32 & 0b1_1111) | ((code as u32 >> 5) << 8)
+}
+
+/// Bits elapsed between the end of the matched pattern and the earliest bit we can overwrite.
+pub const MIN_OFFSET_AFTER_MATCH: u32 = 2;
+
+/// This struct represents the overwrite program loaded into pio instruction memory.
+pub struct PioCanOverwriteProgram<'d, PIO: Instance> {
+    prg: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioCanOverwriteProgram<'d, PIO> {
+    /// Load the overwrite program into the given pio
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        // NOTE: Instruction memory is shared with the injector and the CAN transmitter, so the
+        //       setup relies on autopull to stay small.
+        let prg = pio::pio_asm!(
+            r#"
+            .define REST             0b10001
+
+                out isr, 32                     ; cycles to wait after the trigger
+                out y, 32                       ; pins to drive
+                pull block                      ; cycles to hold them
+            .wrap_target
+                set pins REST                   ; both lines at high impedance
+                mov x, isr
+                wait 1 irq 6                    ; IRQ6: from the receive PIO block or the CPU
+            delay:
+                jmp x-- delay
+                mov x, osr
+                mov pins, y
+            hold:
+                jmp x-- hold
+                irq nowait 3                    ; IRQ3: done
+            .wrap
+        "#
+        );
+        let prg = common.load_program(&prg.program);
+
+        Self { prg }
+    }
+}
+
+/// PIO backed targeted dominant bit injector
+pub struct PioCanOverwrite<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    irq_flags: IrqFlags<'d, PIO>,
+    irq_done: Irq<'d, PIO, IRQ_DONE>,
+    origin: u8,
+    /// Divider for the bit rate, glitches run at the system clock.
+    clk_div: FixedU32<U8>,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioCanOverwrite<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded overwrite program.
+    pub fn new(
+        baud: u32,
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        l_z0: Peri<'d, impl PioPin>,
+        l_v2: Peri<'d, impl PioPin>,
+        l_v1: Peri<'d, impl PioPin>,
+        l_v0: Peri<'d, impl PioPin>,
+        h_z0: Peri<'d, impl PioPin>,
+        nil_0: Peri<'d, impl PioPin>,
+        nil_1: Peri<'d, impl PioPin>,
+        nil_2: Peri<'d, impl PioPin>,
+        h_v2: Peri<'d, impl PioPin>,
+        h_v1: Peri<'d, impl PioPin>,
+        h_v0: Peri<'d, impl PioPin>,
+        program: &PioCanOverwriteProgram<'d, PIO>,
+        irq_flags: IrqFlags<'d, PIO>,
+        irq_done: Irq<'d, PIO, IRQ_DONE>,
+    ) -> Result<Self, TxError> {
+        let l_z0 = common.make_pio_pin(l_z0);
+        let l_v2 = common.make_pio_pin(l_v2);
+        let l_v1 = common.make_pio_pin(l_v1);
+        let l_v0 = common.make_pio_pin(l_v0);
+        let h_z0 = common.make_pio_pin(h_z0);
+        let nil_0 = common.make_pio_pin(nil_0);
+        let nil_1 = common.make_pio_pin(nil_1);
+        let nil_2 = common.make_pio_pin(nil_2);
+        let h_v2 = common.make_pio_pin(h_v2);
+        let h_v1 = common.make_pio_pin(h_v1);
+        let h_v0 = common.make_pio_pin(h_v0);
+        sm.set_pin_dirs(
+            Direction::Out,
+            &[&l_z0, &l_v2, &l_v1, &l_v0, &h_z0, &h_v2, &h_v1, &h_v0],
+        );
+
+        let clk_div = Self::clk_div(baud)?;
+
+        let mut cfg = Config::default();
+        cfg.set_out_pins(&[
+            &l_z0, &l_v2, &l_v1, &l_v0, &h_z0, &nil_0, &nil_1, &nil_2, &h_v2, &h_v1, &h_v0,
+        ]);
+        cfg.set_set_pins(&[&l_z0, &l_v2, &l_v1, &l_v0, &h_z0]);
+        cfg.use_program(&program.prg, &[]);
+        cfg.shift_out.auto_fill = true;
+        cfg.shift_out.threshold = 32;
+        cfg.clock_divider = clk_div;
+        sm.set_config(&cfg);
+
+        Ok(Self {
+            sm,
+            irq_flags,
+            irq_done,
+            origin: program.prg.origin,
+            clk_div,
+        })
+    }
+
+    pub fn clk_div(baud: u32) -> Result<FixedU32<U8>, TxError> {
+        fn _check_clock_div(div: FixedU32<U8>) -> Result<(), TxError> {
+            if div < FixedU32::<U8>::from_num(1.0) {
+                Err(TxError::ClockDividerTooSmall)
+            } else if div > FixedU32::<U8>::from_bits(0xFFFF_FF00) {
+                Err(TxError::ClockDividerTooLarge)
+            } else {
+                Ok(())
+            }
+        }
+
+        // 8 cycles per bit, counted from the matcher's trigger.
+        let divisor = 8_u32
+            .checked_mul(baud)
+            .ok_or(TxError::ClockDividerTooSmall)?;
+
+        let clk_div = (clk_sys_freq() / divisor).to_fixed();
+        _check_clock_div(clk_div)?;
+
+        Ok(clk_div)
+    }
+
+    /// Modify the PIO baud.
+    pub fn set_baud(&mut self, baud: u32) -> Result<(), TxError> {
+        self.clk_div = Self::clk_div(baud)?;
+        self.sm.set_clock_divider(self.clk_div);
+        self.sm.clkdiv_restart();
+
+        Ok(())
+    }
+
+    /// Arm the overwrite. `delay` is the number of bits between the end of the matched pattern and
+    /// the first overwritten bit, `width` is the number of bits held dominant.
+    pub fn arm(&mut self, delay: u32, width: u32) -> Result<(), TxError> {
+        if delay < MIN_OFFSET_AFTER_MATCH {
+            return Err(TxError::OffsetTooSmall);
+        }
+
+        if width == 0 {
+            return Err(TxError::InvalidWidth);
+        }
+
+        // The program counts cycles, 8 per bit.
+        self.load(self.clk_div, 8 * delay - 9, H_3V5 | L_1V5, 8 * width - 3);
+
+        Ok(())
+    }
+
+    /// Arm the overwrite to drive the ACK slot, for a trigger raised ~3.5 cycles ahead of it (see
+    /// [`crate::apps::rx::can::PioCanAck`]).
+    pub fn arm_ack(&mut self) {
+        // Dominant for ~7/8 of the ACK slot.
+        self.load(self.clk_div, 0, H_3V5 | L_1V5, 4);
+    }
+
+    /// Arm a glitch: `delay` system clock cycles after the trigger, drive the injector `code` for
+    /// `width` cycles, then let go of both lines again.
+    pub fn arm_glitch(&mut self, code: u8, delay: u32, width: u32) -> Result<(), TxError> {
+        if delay < MIN_GLITCH_DELAY {
+            return Err(TxError::OffsetTooSmall);
+        }
+
+        if width < MIN_GLITCH_WIDTH {
+            return Err(TxError::InvalidWidth);
+        }
+
+        self.load(
+            FixedU32::<U8>::from_num(1),
+            delay - MIN_GLITCH_DELAY,
+            code,
+            width - MIN_GLITCH_WIDTH,
+        );
+
+        Ok(())
+    }
+
+    /// Triggers an armed glitch (or overwrite) from the CPU.
+    pub fn fire(&mut self) {
+        self.irq_flags.set(IRQ_MATCH);
+    }
+
+    /// Waits for the pins to be back at rest after a trigger.
+    pub async fn wait_done(&mut self) {
+        self.irq_done.wait().await;
+    }
+
+    fn load(&mut self, clk_div: FixedU32<U8>, delay: u32, code: u8, hold: u32) {
+        self.disarm();
+        self.sm.set_clock_divider(clk_div);
+        self.sm.clkdiv_restart();
+        self.sm.clear_fifos();
+        self.sm.restart();
+        unsafe { self.sm.exec_jmp(self.origin) };
+        // Drop any trigger raised while we were not listening.
+        self.irq_flags.clear(IRQ_MATCH);
+        self.irq_flags.clear(IRQ_DONE);
+        let _ = self.sm.tx().try_push(delay);
+        let _ = self.sm.tx().try_push(pins(code));
+        let _ = self.sm.tx().try_push(hold);
+        self.sm.set_enable(true);
+    }
+
+    pub fn disarm(&mut self) {
+        if self.sm.is_enabled() {
+            self.sm.set_enable(false);
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.sm.is_enabled()
+    }
+}
@@ -0,0 +1,111 @@
+//! Timed replay of recorded CAN frames, for playing back a candump log.
+//!
+//! Frames go out at their offsets from the start, stepped by the transmit task between commands
+//! like the scheduler, so a replay goes on in the background until done or stopped.
+
+use alloc::collections::VecDeque;
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+
+use crate::apps::tx::TxWords;
+
+/// How a replay went.
+#[derive(Debug, Format, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub sent: usize,
+    pub failed: usize,
+    /// Frames left out by stopping early.
+    pub skipped: usize,
+}
+
+pub struct Replay {
+    /// Frames still to send, each with its offset in µs from the start.
+    frames: VecDeque<(u64, TxWords)>,
+    start: Instant,
+    report: ReplayReport,
+}
+
+impl Replay {
+    /// Starts a replay at `now`.
+    pub fn new(frames: impl IntoIterator<Item = (u64, TxWords)>, now: Instant) -> Self {
+        Self {
+            frames: frames.into_iter().collect(),
+            start: now,
+            report: ReplayReport::default(),
+        }
+    }
+
+    /// When the next frame is due, none once all are out.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.frames
+            .front()
+            .map(|(offset, _)| self.start + Duration::from_micros(*offset))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The next frame if it is due at `now`. Whether it went out goes back through
+    /// [`Replay::done`].
+    pub fn due(&mut self, now: Instant) -> Option<TxWords> {
+        match self.next_due() {
+            Some(due) if due <= now => self.frames.pop_front().map(|(_, words)| words),
+            _ => None,
+        }
+    }
+
+    /// Records whether the frame from [`Replay::due`] went out.
+    pub fn done(&mut self, sent: bool) {
+        if sent {
+            self.report.sent += 1;
+        } else {
+            self.report.failed += 1;
+        }
+    }
+
+    /// Drops the frames not sent yet.
+    pub fn stop(&mut self) {
+        self.report.skipped += self.frames.len();
+        self.frames.clear();
+    }
+
+    pub fn report(&self) -> ReplayReport {
+        self.report
+    }
+}
+
+mod test {
+    #[test]
+    fn test_replay() {
+        use super::{Replay, ReplayReport};
+        use crate::apps::tx::TxWords;
+        use alloc::vec;
+        use embassy_time::Instant;
+
+        let frame = |word| TxWords::Can(vec![word]);
+        let start = Instant::from_millis(0);
+        let mut replay = Replay::new([(0, frame(1)), (1_000, frame(2)), (5_000, frame(3))], start);
+
+        assert_eq!(replay.due(start), Some(frame(1)));
+        replay.done(true);
+        assert_eq!(replay.due(start), None);
+        assert_eq!(replay.next_due(), Some(Instant::from_millis(1)));
+
+        // Late, the frame still goes out.
+        assert_eq!(replay.due(Instant::from_millis(2)), Some(frame(2)));
+        replay.done(false);
+
+        replay.stop();
+        assert!(replay.is_finished());
+        assert_eq!(replay.next_due(), None);
+        assert_eq!(
+            replay.report(),
+            ReplayReport {
+                sent: 1,
+                failed: 1,
+                skipped: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Cyclic transmit scheduler, for simulating a node's periodic frames.
+//!
+//! Every entry sends a CAN frame at a fixed period. Its payload may cycle through several
+//! versions, carry a rolling counter and end with a checksum, all updated every cycle on the
+//! transmit task itself. When several entries are due at once, the highest priority goes first,
+//! then the lowest identifier, as arbitration would have it.
+
+use alloc::vec::Vec;
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// XOR of the other bytes.
+    Xor,
+    /// Sum of the other bytes, modulo 256.
+    Sum,
+    /// CRC-8 SAE J1850 of the other bytes.
+    Crc8,
+}
+
+impl Checksum {
+    pub fn compute<'a>(&self, bytes: impl Iterator<Item = &'a u8>) -> u8 {
+        match self {
+            Checksum::Xor => bytes.fold(0, |acc, byte| acc ^ byte),
+            Checksum::Sum => bytes.fold(0, |acc, byte| acc.wrapping_add(*byte)),
+            Checksum::Crc8 => {
+                let crc = bytes.fold(0xFF, |mut crc: u8, byte| {
+                    crc ^= byte;
+
+                    for _ in 0..8 {
+                        crc = if crc & 0x80 != 0 {
+                            (crc << 1) ^ 0x1D
+                        } else {
+                            crc << 1
+                        };
+                    }
+
+                    crc
+                });
+
+                crc ^ 0xFF
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryConfig {
+    pub arb_id: u32,
+    /// Whether the identifier is extended.
+    pub extended: bool,
+    /// In ms.
+    pub period: u64,
+    /// Higher goes first when several entries are due at once.
+    pub priority: i32,
+    /// Payloads sent in turn, one per cycle.
+    pub payloads: Vec<Vec<u8>>,
+    /// Byte and bit mask of a counter incremented every cycle, wrapping within the mask.
+    pub counter: Option<(usize, u8)>,
+    /// Byte the checksum of the other bytes goes into, after the counter.
+    pub checksum: Option<(usize, Checksum)>,
+}
+
+/// How an entry went so far. Jitter is how late a frame was handed to the transmitter, in µs.
+#[derive(Debug, Format, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EntryStats {
+    pub arb_id: u32,
+    pub period: u64,
+    pub enabled: bool,
+    pub sent: u32,
+    pub failed: u32,
+    /// Cycles skipped because the previous frame went out more than a period late.
+    pub missed: u32,
+    pub jitter_max: u64,
+    pub jitter_total: u64,
+}
+
+impl EntryStats {
+    pub fn jitter_mean(&self) -> u64 {
+        self.jitter_total / (self.sent + self.failed).max(1) as u64
+    }
+}
+
+struct Entry {
+    config: EntryConfig,
+    cycle: u32,
+    next: Instant,
+    stats: EntryStats,
+}
+
+impl Entry {
+    fn period(&self) -> Duration {
+        Duration::from_millis(self.config.period)
+    }
+
+    /// The payload of the current cycle.
+    fn payload(&self) -> Vec<u8> {
+        let payloads = &self.config.payloads;
+        let mut payload = payloads[self.cycle as usize % payloads.len()].clone();
+
+        if let Some((byte, mask)) = self.config.counter {
+            if let Some(value) = payload.get_mut(byte) {
+                let shift = mask.trailing_zeros();
+                let counter = (self.cycle << shift) as u8 & mask;
+                *value = (*value & !mask) | counter;
+            }
+        }
+
+        if let Some((byte, checksum)) = self.config.checksum {
+            if byte < payload.len() {
+                let others = payload
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != byte)
+                    .map(|(_, value)| value);
+                payload[byte] = checksum.compute(others);
+            }
+        }
+
+        payload
+    }
+}
+
+pub struct Schedule {
+    entries: Vec<Entry>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds an entry, or replaces the one with the same identifier. It is first due at `now`.
+    pub fn add(&mut self, config: EntryConfig, now: Instant) {
+        self.remove(config.arb_id);
+
+        let stats = EntryStats {
+            arb_id: config.arb_id,
+            period: config.period,
+            enabled: true,
+            ..EntryStats::default()
+        };
+
+        self.entries.push(Entry {
+            config,
+            cycle: 0,
+            next: now,
+            stats,
+        });
+    }
+
+    /// Removes the entry for the identifier, if any.
+    pub fn remove(&mut self, arb_id: u32) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.config.arb_id != arb_id);
+
+        self.entries.len() != len
+    }
+
+    /// Enables or disables the entry for the identifier, if any. Enabled again, it is due at
+    /// `now`.
+    pub fn set_enabled(&mut self, arb_id: u32, enabled: bool, now: Instant) -> bool {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.config.arb_id == arb_id)
+        {
+            Some(entry) => {
+                if enabled && !entry.stats.enabled {
+                    entry.next = now;
+                }
+
+                entry.stats.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// When the next enabled entry is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.stats.enabled)
+            .map(|entry| entry.next)
+            .min()
+    }
+
+    /// The first of the entries due at `now`, with its identifier, whether that is extended, and
+    /// the payload of its current cycle.
+    pub fn due(&self, now: Instant) -> Option<(usize, u32, bool, Vec<u8>)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.stats.enabled && entry.next <= now)
+            .min_by_key(|(_, entry)| (-entry.config.priority, entry.config.arb_id))
+            .map(|(index, entry)| {
+                let config = &entry.config;
+                (index, config.arb_id, config.extended, entry.payload())
+            })
+    }
+
+    /// Records the frame of a due entry handed to the transmitter at `start`, and moves it to its
+    /// next cycle.
+    pub fn done(&mut self, index: usize, start: Instant, sent: bool) {
+        let entry = &mut self.entries[index];
+        let period = entry.period();
+        let jitter = start.saturating_duration_since(entry.next).as_micros();
+
+        entry.stats.jitter_max = entry.stats.jitter_max.max(jitter);
+        entry.stats.jitter_total += jitter;
+
+        if sent {
+            entry.stats.sent += 1;
+        } else {
+            entry.stats.failed += 1;
+        }
+
+        entry.cycle = entry.cycle.wrapping_add(1);
+        entry.next += period;
+
+        // Keep the phase, skipping the cycles there is no time left for.
+        while entry.next <= start {
+            entry.next += period;
+            entry.stats.missed += 1;
+        }
+    }
+
+    /// Stats of every entry, by identifier.
+    pub fn stats(&self) -> Vec<EntryStats> {
+        let mut stats = self
+            .entries
+            .iter()
+            .map(|entry| entry.stats)
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|stats| stats.arb_id);
+
+        stats
+    }
+}
+
+mod test {
+    #[test]
+    fn test_payload() {
+        use super::{Checksum, EntryConfig, Schedule};
+        use alloc::vec;
+        use embassy_time::Instant;
+
+        assert_eq!(Checksum::Crc8.compute(b"123456789".iter()), 0x4B);
+
+        let mut schedule = Schedule::new();
+        let now = Instant::from_millis(0);
+        schedule.add(
+            EntryConfig {
+                arb_id: 0x100,
+                extended: false,
+                period: 10,
+                priority: 0,
+                payloads: vec![vec![0xA0, 0x01, 0x00], vec![0xA0, 0x02, 0x00]],
+                counter: Some((0, 0x0F)),
+                checksum: Some((2, Checksum::Xor)),
+            },
+            now,
+        );
+
+        let mut payloads = vec![];
+        for cycle in 0..17 {
+            let now = Instant::from_millis(cycle * 10);
+            let (index, _, _, payload) = schedule.due(now).unwrap();
+            payloads.push(payload);
+            schedule.done(index, now, true);
+        }
+
+        assert_eq!(payloads[0], [0xA0, 0x01, 0xA1]);
+        assert_eq!(payloads[1], [0xA1, 0x02, 0xA3]);
+        assert_eq!(payloads[15], [0xAF, 0x02, 0xAD]);
+        assert_eq!(payloads[16], [0xA0, 0x01, 0xA1]);
+    }
+
+    #[test]
+    fn test_order() {
+        use super::{EntryConfig, Schedule};
+        use alloc::vec;
+        use embassy_time::Instant;
+
+        let entry = |arb_id, period, priority| EntryConfig {
+            arb_id,
+            extended: false,
+            period,
+            priority,
+            payloads: vec![vec![]],
+            counter: None,
+            checksum: None,
+        };
+
+        let mut schedule = Schedule::new();
+        let start = Instant::from_millis(0);
+        schedule.add(entry(0x300, 100, 0), start);
+        schedule.add(entry(0x200, 10, 0), start);
+        schedule.add(entry(0x400, 1000, 1), start);
+
+        let mut order = vec![];
+        while let Some((index, arb_id, _, _)) = schedule.due(start) {
+            order.push(arb_id);
+            schedule.done(index, start, true);
+        }
+        assert_eq!(order, [0x400, 0x200, 0x300]);
+        assert_eq!(schedule.next_due(), Some(Instant::from_millis(10)));
+
+        // 25 ms late: two cycles of the 10 ms entry are skipped.
+        let late = Instant::from_millis(35);
+        let (index, arb_id, _, _) = schedule.due(late).unwrap();
+        assert_eq!(arb_id, 0x200);
+        schedule.done(index, late, true);
+
+        let stats = schedule.stats();
+        assert_eq!(stats[0].arb_id, 0x200);
+        assert_eq!((stats[0].missed, stats[0].jitter_max), (2, 25_000));
+
+        assert!(schedule.set_enabled(0x200, false, late));
+        assert_eq!(schedule.next_due(), Some(Instant::from_millis(100)));
+        assert!(!schedule.remove(0x500));
+    }
+}
@@ -0,0 +1,374 @@
+//! Sample buffers for the differential injector, built from segments.
+//!
+//! Every sample is an injector code, `H_V0 H_V1 H_V2 H_Z0 L_V0 L_V1 L_V2 L_Z0` from the most
+//! significant bit. A line is either driven to one of eight levels, from 0 V to 4 V without 0.5 V,
+//! or left at high impedance, in which case its voltage bits must be clear.
+
+use crate::platform::repl::tx::{H_Z0, L_Z0};
+use alloc::{format, vec::Vec};
+use defmt::Format;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle},
+    text::{Baseline, Text},
+};
+
+/// The voltage bits of each level, by half volts.
+const LEVEL_BITS: [(u8, u8); 8] = [
+    (0, 0b000),
+    (2, 0b100),
+    (3, 0b010),
+    (4, 0b110),
+    (5, 0b001),
+    (6, 0b101),
+    (7, 0b011),
+    (8, 0b111),
+];
+
+const H_SHIFT: u8 = 5;
+const L_SHIFT: u8 = 1;
+
+/// CAN bus states.
+pub const RECESSIVE: (Level, Level) = (Level::Drive(5), Level::Drive(5));
+pub const DOMINANT: (Level, Level) = (Level::Drive(7), Level::Drive(3));
+
+/// Bipolar states, for buses driven both ways such as MIL-STD-1553.
+pub const POSITIVE: (Level, Level) = (Level::Drive(7), Level::Drive(3));
+pub const NEGATIVE: (Level, Level) = (Level::Drive(3), Level::Drive(7));
+
+/// Equal bits after which a stuff bit goes in, as on CAN.
+const STUFF_AFTER: usize = 5;
+
+const WIDTH: i32 = 320;
+const HEIGHT: i32 = 170;
+/// Pixels per volt, under the header.
+const VOLT_HEIGHT: i32 = 36;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum WaveformError {
+    /// Not one of the injector levels.
+    InvalidLevel,
+    /// High impedance has no voltage to ramp from or to.
+    InvalidRamp,
+    /// Bit patterns are made of `0` and `1`, `_` and spaces being ignored.
+    InvalidPattern,
+    /// The given sample drives a line at high impedance.
+    IllegalCode(usize),
+}
+
+impl core::fmt::Display for WaveformError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl core::error::Error for WaveformError {}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    HiZ,
+    /// In half volts.
+    Drive(u8),
+}
+
+impl Level {
+    /// The level at exactly `volts`.
+    pub fn from_volts(volts: f64) -> Result<Self, WaveformError> {
+        LEVEL_BITS
+            .iter()
+            .find(|(half, _)| *half as f64 == volts * 2.0)
+            .map(|(half, _)| Level::Drive(*half))
+            .ok_or(WaveformError::InvalidLevel)
+    }
+
+    /// The voltage bits, and the high impedance one.
+    fn bits(self) -> Result<(u8, bool), WaveformError> {
+        match self {
+            Level::HiZ => Ok((0, true)),
+            Level::Drive(half) => LEVEL_BITS
+                .iter()
+                .find(|(level, _)| *level == half)
+                .map(|(_, bits)| (*bits, false))
+                .ok_or(WaveformError::InvalidLevel),
+        }
+    }
+
+    fn from_bits(bits: u8, z: bool) -> Option<Self> {
+        match (bits, z) {
+            (0, true) => Some(Level::HiZ),
+            (_, true) => None,
+            (bits, false) => LEVEL_BITS
+                .iter()
+                .find(|(_, level)| *level == bits)
+                .map(|(half, _)| Level::Drive(*half)),
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// A sample per bit, 0 dominant and 1 recessive.
+    Nrz,
+    /// NRZ with a complement bit after every five equal ones, as on CAN.
+    Stuffed,
+    /// Two halves per bit, IEEE 802.3 style: 0 is dominant then recessive, 1 the other way around.
+    Manchester,
+    /// Manchester II bi-phase, bipolar: 1 is positive then negative, 0 the other way around.
+    Biphase,
+}
+
+/// The code driving CAN H and CAN L at these levels.
+pub fn code(h: Level, l: Level) -> Result<u8, WaveformError> {
+    let (h_bits, h_z) = h.bits()?;
+    let (l_bits, l_z) = l.bits()?;
+    let mut code = h_bits << H_SHIFT | l_bits << L_SHIFT;
+
+    if h_z {
+        code |= H_Z0;
+    }
+
+    if l_z {
+        code |= L_Z0;
+    }
+
+    Ok(code)
+}
+
+/// The levels of a code, unless it drives a line at high impedance.
+pub fn levels(code: u8) -> Option<(Level, Level)> {
+    let h = Level::from_bits(code >> H_SHIFT, code & H_Z0 != 0)?;
+    let l = Level::from_bits(code >> L_SHIFT & 0b111, code & L_Z0 != 0)?;
+
+    Some((h, l))
+}
+
+/// Checks every sample of a buffer, including hand-made ones.
+pub fn check(wave: &[u8]) -> Result<(), WaveformError> {
+    match wave.iter().position(|&code| levels(code).is_none()) {
+        Some(i) => Err(WaveformError::IllegalCode(i)),
+        None => Ok(()),
+    }
+}
+
+pub fn hold(h: Level, l: Level, samples: usize) -> Result<Vec<u8>, WaveformError> {
+    Ok(alloc::vec![code(h, l)?; samples])
+}
+
+/// Moves both lines from one state to the other in even steps, each sample at the nearest level.
+/// The first and last samples are at the given states.
+pub fn ramp(
+    from: (Level, Level),
+    to: (Level, Level),
+    samples: usize,
+) -> Result<Vec<u8>, WaveformError> {
+    let half = |level| match level {
+        Level::Drive(half) => Ok(half as i64),
+        Level::HiZ => Err(WaveformError::InvalidRamp),
+    };
+    let (h0, l0, h1, l1) = (half(from.0)?, half(from.1)?, half(to.0)?, half(to.1)?);
+
+    // Levels are compared scaled by the steps, to stay in integers.
+    let steps = samples.saturating_sub(1).max(1) as i64;
+    let nearest = |scaled: i64| {
+        let (half, _) = LEVEL_BITS
+            .iter()
+            .min_by_key(|(half, _)| (*half as i64 * steps - scaled).abs())
+            .unwrap();
+        Level::Drive(*half)
+    };
+
+    (0..samples as i64)
+        .map(|i| {
+            let h = nearest(h0 * steps + (h1 - h0) * i);
+            let l = nearest(l0 * steps + (l1 - l0) * i);
+            code(h, l)
+        })
+        .collect()
+}
+
+/// A dominant pulse.
+pub fn pulse(width: usize) -> Vec<u8> {
+    hold(DOMINANT.0, DOMINANT.1, width).unwrap()
+}
+
+/// Encodes a pattern of `0` and `1` as CAN states, `width` samples for each of them.
+pub fn bits(pattern: &str, encoding: Encoding, width: usize) -> Result<Vec<u8>, WaveformError> {
+    let mut bits = Vec::new();
+
+    for c in pattern.chars() {
+        match c {
+            '0' => bits.push(false),
+            '1' => bits.push(true),
+            '_' | ' ' => {}
+            _ => return Err(WaveformError::InvalidPattern),
+        }
+    }
+
+    let states: Vec<bool> = match encoding {
+        Encoding::Nrz => bits,
+        Encoding::Stuffed => {
+            let mut stuffed = Vec::with_capacity(bits.len() + bits.len() / STUFF_AFTER);
+            let mut run = 0;
+
+            for bit in bits {
+                run = match stuffed.last() {
+                    Some(&last) if last == bit => run + 1,
+                    _ => 1,
+                };
+                stuffed.push(bit);
+
+                if run == STUFF_AFTER {
+                    stuffed.push(!bit);
+                    run = 1;
+                }
+            }
+
+            stuffed
+        }
+        Encoding::Manchester => bits.iter().flat_map(|&bit| [bit, !bit]).collect(),
+        Encoding::Biphase => return halves(bits.iter().flat_map(|&bit| [bit, !bit]), width),
+    };
+
+    let recessive = code(RECESSIVE.0, RECESSIVE.1)?;
+    let dominant = code(DOMINANT.0, DOMINANT.1)?;
+
+    Ok(states
+        .iter()
+        .flat_map(|&state| {
+            let code = if state { recessive } else { dominant };
+            core::iter::repeat_n(code, width)
+        })
+        .collect())
+}
+
+/// Encodes bipolar halves, `true` for positive, `width` samples for each of them.
+pub fn halves(halves: impl Iterator<Item = bool>, width: usize) -> Result<Vec<u8>, WaveformError> {
+    let positive = code(POSITIVE.0, POSITIVE.1)?;
+    let negative = code(NEGATIVE.0, NEGATIVE.1)?;
+
+    Ok(halves
+        .flat_map(|half| {
+            let code = if half { positive } else { negative };
+            core::iter::repeat_n(code, width)
+        })
+        .collect())
+}
+
+pub fn repeat(wave: &[u8], times: usize) -> Vec<u8> {
+    wave.repeat(times)
+}
+
+/// Plots a checked buffer on a landscape target, CAN H in yellow and CAN L in cyan. Lines at high
+/// impedance are left out.
+pub fn draw<D: DrawTarget<Color = Rgb565>>(wave: &[u8], target: &mut D) -> Result<(), D::Error> {
+    let text = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
+    let grid = PrimitiveStyle::with_stroke(Rgb565::new(6, 12, 6), 1);
+    let y = |half: u8| HEIGHT - 8 - half as i32 * VOLT_HEIGHT / 2;
+
+    target.clear(Rgb565::BLACK)?;
+
+    let header = format!("{} samples, 0 V to 4 V", wave.len());
+    Text::with_baseline(&header, Point::zero(), text, Baseline::Top).draw(target)?;
+
+    for half in (0..=8).step_by(2) {
+        Line::new(Point::new(0, y(half)), Point::new(WIDTH - 1, y(half)))
+            .into_styled(grid)
+            .draw(target)?;
+    }
+
+    let len = wave.len().max(1) as i32;
+    let traces = [(H_SHIFT, Rgb565::YELLOW), (L_SHIFT, Rgb565::CYAN)];
+
+    for (shift, color) in traces {
+        let style = PrimitiveStyle::with_stroke(color, 1);
+        let mut previous = None;
+
+        for (i, &code) in wave.iter().enumerate() {
+            let (h, l) = levels(code).unwrap_or((Level::HiZ, Level::HiZ));
+            let level = if shift == H_SHIFT { h } else { l };
+
+            let Level::Drive(half) = level else {
+                previous = None;
+                continue;
+            };
+
+            let start = Point::new(i as i32 * WIDTH / len, y(half));
+            let end = Point::new(((i as i32 + 1) * WIDTH / len - 1).max(start.x), y(half));
+
+            if let Some(previous) = previous {
+                Line::new(previous, start).into_styled(style).draw(target)?;
+            }
+
+            Line::new(start, end).into_styled(style).draw(target)?;
+            previous = Some(end);
+        }
+    }
+
+    Ok(())
+}
+
+mod test {
+    #[test]
+    fn test_codes() {
+        use super::{check, code, levels, Level, WaveformError};
+
+        // The constants of `platform::repl::tx`.
+        assert_eq!(code(Level::Drive(8), Level::Drive(2)), Ok(0b111_0_100_0));
+        assert_eq!(code(Level::Drive(5), Level::Drive(3)), Ok(0b001_0_010_0));
+        assert_eq!(code(Level::HiZ, Level::Drive(0)), Ok(0b000_1_000_0));
+        assert_eq!(code(Level::HiZ, Level::HiZ), Ok(0b000_1_000_1));
+        assert_eq!(Level::from_volts(2.5), Ok(Level::Drive(5)));
+        assert_eq!(Level::from_volts(0.5), Err(WaveformError::InvalidLevel));
+
+        for code in 0..=u8::MAX {
+            if let Some((h, l)) = levels(code) {
+                assert_eq!(super::code(h, l), Ok(code));
+            }
+        }
+
+        // Voltage bits on a line at high impedance.
+        assert_eq!(
+            check(&[0b111_0_100_0, 0b001_1_000_0]),
+            Err(WaveformError::IllegalCode(1))
+        );
+    }
+
+    #[test]
+    fn test_segments() {
+        use super::{bits, code, ramp, Encoding, Level, DOMINANT, NEGATIVE, POSITIVE, RECESSIVE};
+        use alloc::vec::Vec;
+
+        let decode = |wave: Vec<u8>| -> Vec<(Level, Level)> {
+            wave.iter()
+                .map(|&code| super::levels(code).unwrap())
+                .collect()
+        };
+
+        // Steps between two levels go to the lower one.
+        let wave = ramp(
+            (Level::Drive(8), Level::Drive(4)),
+            (Level::Drive(4), Level::Drive(2)),
+            5,
+        );
+        let levels = decode(wave.unwrap());
+        let h: Vec<_> = levels.iter().map(|(h, _)| *h).collect();
+        let l: Vec<_> = levels.iter().map(|(_, l)| *l).collect();
+        assert_eq!(h, [8, 7, 6, 5, 4].map(Level::Drive));
+        assert_eq!(l, [4, 3, 3, 2, 2].map(Level::Drive));
+
+        let d = code(DOMINANT.0, DOMINANT.1).unwrap();
+        let r = code(RECESSIVE.0, RECESSIVE.1).unwrap();
+        assert_eq!(bits("01", Encoding::Nrz, 2), Ok([d, d, r, r].into()));
+        assert_eq!(bits("01", Encoding::Manchester, 1), Ok([d, r, r, d].into()));
+        let p = code(POSITIVE.0, POSITIVE.1).unwrap();
+        let n = code(NEGATIVE.0, NEGATIVE.1).unwrap();
+        assert_eq!(bits("10", Encoding::Biphase, 1), Ok([p, n, n, p].into()));
+        assert_eq!(
+            bits("0000_0111 1110", Encoding::Stuffed, 1),
+            Ok([d, d, d, d, d, r, r, r, r, r, d, r, r, d].into())
+        );
+        assert!(bits("012", Encoding::Nrz, 1).is_err());
+    }
+}
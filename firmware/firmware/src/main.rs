@@ -90,6 +90,8 @@ async fn main(spawner: Spawner) -> () {
     // RPC channels
     let (app_watch, app_ack_channel) = make_app_channels!();
     let (call_channel, result_channel) = make_rpc_channels!();
+    let (usb_call_channel, usb_result_channel) = make_rpc_channels!();
+    let (gs_usb_call_channel, gs_usb_result_channel) = make_rpc_channels!();
     let (repl_in_channel, repl_out_channel) = make_repl_channels!();
 
     #[allow(static_mut_refs)]
@@ -108,7 +110,7 @@ async fn main(spawner: Spawner) -> () {
     });
 
     // USB
-    let (usb, logger, cli, _storage) = usb::initialize(p.USB);
+    let (usb, logger, cli, stream, gs_usb, sample_stream, _storage) = usb::initialize(p.USB);
 
     // SD Card and Display
     const DISPLAY_FREQ: u32 = 62_500_000;
@@ -220,6 +222,7 @@ async fn main(spawner: Spawner) -> () {
     }
 
     let display_channel = make_display_channel!();
+    let button_channel = make_button_channel!();
     let console_pipe = CONSOLE_PIPE.init(ConsolePipe::new());
     let (console_reader, console_writer) = console_pipe.split();
 
@@ -231,7 +234,8 @@ async fn main(spawner: Spawner) -> () {
         display,
         unwrap!(shake_watch.receiver()),
         shake_signal_ready,
-        shake_signal_done
+        shake_signal_done,
+        button_channel.receiver()
     )));
 
     // NeoPixel
@@ -247,6 +251,23 @@ async fn main(spawner: Spawner) -> () {
     let logger = Logger::new(multi_writer);
     unwrap!(spawner.spawn(tasks::log::log_task(logger)));
 
+    // Live capture stream
+    unwrap!(spawner.spawn(tasks::stream::stream_task(
+        stream,
+        usb_call_channel.sender(),
+        usb_result_channel.receiver(),
+    )));
+
+    // Native SocketCAN through the Linux gs_usb driver
+    unwrap!(spawner.spawn(tasks::gs_usb::gs_usb_task(
+        gs_usb,
+        gs_usb_call_channel.sender(),
+        gs_usb_result_channel.receiver(),
+    )));
+
+    // Injector samples for tx::stream
+    unwrap!(spawner.spawn(tasks::sample_stream::sample_stream_task(sample_stream)));
+
     // Wifi
     #[cfg(feature = "wifi")]
     {
@@ -314,6 +335,10 @@ async fn main(spawner: Spawner) -> () {
     joy_left.set_direction(true).await;
     button_a.set_direction(true).await;
     button_b.set_direction(true).await;
+    unwrap!(spawner.spawn(tasks::buttons::buttons_task(
+        [joy_up, joy_right, joy_down, joy_center, joy_left, button_a, button_b],
+        button_channel.sender()
+    )));
 
     // Connect sd card to storage
     // storage.set_storage(sd_card);
@@ -323,6 +348,9 @@ async fn main(spawner: Spawner) -> () {
 
     let (ctrl_channel, ctrl_ack) = make_ctrl_channel!();
 
+    // The glitch trigger listens on SAO GPIO 1 while the control task owns it.
+    let sao_trigger = sao_gpio_1.get_inner_pin();
+
     // Backlight test
     unwrap!(spawner.spawn(tasks::ctrl::ctrl_task(
         ctrl_channel.receiver(),
@@ -418,6 +446,7 @@ async fn main(spawner: Spawner) -> () {
         tx_ack,
         p.PIO1,
         p.DMA_CH5,
+        p.DMA_CH6,
         p.PIN_18,
         p.PIN_19,
         p.PIN_20,
@@ -432,6 +461,7 @@ async fn main(spawner: Spawner) -> () {
         tx_connect,
         tx_enable,
         pwr_injector,
+        sao_trigger,
     )));
 
     // RPC runtime
@@ -443,6 +473,10 @@ async fn main(spawner: Spawner) -> () {
         app_ack_channel.receiver(),
         call_channel.receiver(),
         result_channel.sender(),
+        usb_call_channel.receiver(),
+        usb_result_channel.sender(),
+        gs_usb_call_channel.receiver(),
+        gs_usb_result_channel.sender(),
         display_channel.sender(),
         led_channel.sender(),
         tx_channel.sender(),
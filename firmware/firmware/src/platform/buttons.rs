@@ -0,0 +1,44 @@
+//! The joystick and the A and B buttons, on the first GPIO expander.
+
+use defmt::Format;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Up,
+    Right,
+    Down,
+    Center,
+    Left,
+    A,
+    B,
+}
+
+/// In the order of the expander pins.
+pub const BUTTONS: [Button; 7] = [
+    Button::Up,
+    Button::Right,
+    Button::Down,
+    Button::Center,
+    Button::Left,
+    Button::A,
+    Button::B,
+];
+
+pub const BUTTON_MTU: usize = 4;
+
+pub type ButtonChannel = channel::Channel<CriticalSectionRawMutex, Button, BUTTON_MTU>;
+pub type ButtonSender = channel::Sender<'static, CriticalSectionRawMutex, Button, BUTTON_MTU>;
+pub type ButtonReceiver = channel::Receiver<'static, CriticalSectionRawMutex, Button, BUTTON_MTU>;
+
+#[macro_export]
+macro_rules! make_button_channel {
+    () => {{
+        use crate::platform::buttons::ButtonChannel;
+        use embassy_sync::lazy_lock::LazyLock;
+
+        static CHANNEL: LazyLock<ButtonChannel> = LazyLock::new(|| ButtonChannel::new());
+
+        CHANNEL.get()
+    }};
+}
@@ -0,0 +1,183 @@
+//! gs_usb vendor class, see `apps::gs_usb` for the protocol.
+
+use crate::apps::gs_usb::{Command, Device, HostFrame};
+use core::mem::MaybeUninit;
+use defmt::{debug, warn};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
+use embassy_time::Instant;
+use embassy_usb::{
+    control::{InResponse, OutResponse, Recipient, Request, RequestType},
+    driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut},
+    types::InterfaceNumber,
+    Builder, Handler,
+};
+
+const VENDOR_CLASS: u8 = 0xFF;
+
+const MAX_PACKET_SIZE: u16 = 64;
+
+const COMMAND_MTU: usize = 4;
+
+pub type CommandChannel = channel::Channel<CriticalSectionRawMutex, Command, COMMAND_MTU>;
+pub type CommandReceiver<'d> = channel::Receiver<'d, CriticalSectionRawMutex, Command, COMMAND_MTU>;
+
+pub struct State<'d> {
+    commands: CommandChannel,
+    control: MaybeUninit<Control<'d>>,
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> State<'d> {
+    pub const fn new() -> Self {
+        Self {
+            commands: CommandChannel::new(),
+            control: MaybeUninit::uninit(),
+        }
+    }
+}
+
+/// Answers the vendor requests to our interface, and queues the resulting commands for the task.
+struct Control<'d> {
+    ifnum: InterfaceNumber,
+    device: Device,
+    commands: &'d CommandChannel,
+}
+
+impl<'d> Control<'d> {
+    fn accepts(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Vendor
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.ifnum) as u16
+    }
+}
+
+impl<'d> Handler for Control<'d> {
+    fn control_out(&mut self, req: Request, buf: &[u8]) -> Option<OutResponse> {
+        if !self.accepts(&req) {
+            return None;
+        }
+
+        match self.device.control_out(req.request, req.value, buf) {
+            Ok(Some(command)) => match self.commands.try_send(command) {
+                Ok(()) => Some(OutResponse::Accepted),
+                Err(_) => {
+                    warn!("gs_usb command queue is full, rejecting {:?}", command);
+                    Some(OutResponse::Rejected)
+                }
+            },
+            Ok(None) => Some(OutResponse::Accepted),
+            Err(err) => {
+                warn!("Rejected gs_usb request {}: {}", req.request, err);
+                Some(OutResponse::Rejected)
+            }
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.accepts(&req) {
+            return None;
+        }
+
+        match self
+            .device
+            .control_in(req.request, req.value, Instant::now(), buf)
+        {
+            Ok(len) => Some(InResponse::Accepted(&buf[..len.min(req.length as usize)])),
+            Err(err) => {
+                warn!("Rejected gs_usb request {}: {}", req.request, err);
+                Some(InResponse::Rejected)
+            }
+        }
+    }
+}
+
+pub struct GsUsbClass<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    commands: &'d CommandChannel,
+}
+
+impl<'d, D: Driver<'d>> GsUsbClass<'d, D> {
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>) -> Self {
+        let (ifnum, read_ep, write_ep) = {
+            let mut func = builder.function(VENDOR_CLASS, VENDOR_CLASS, VENDOR_CLASS);
+            let mut interface = func.interface();
+            let ifnum = interface.interface_number();
+            let mut alt = interface.alt_setting(VENDOR_CLASS, VENDOR_CLASS, VENDOR_CLASS, None);
+            let write_ep = alt.endpoint_bulk_in(None, MAX_PACKET_SIZE);
+            let read_ep = alt.endpoint_bulk_out(None, MAX_PACKET_SIZE);
+            (ifnum, read_ep, write_ep)
+        };
+
+        let commands = &state.commands;
+        let control = state.control.write(Control {
+            ifnum,
+            device: Device::new(),
+            commands,
+        });
+        builder.handler(control);
+
+        Self {
+            read_ep,
+            write_ep,
+            commands,
+        }
+    }
+
+    /// Splits the class into the frame sender and receiver, and the command receiver.
+    pub fn split(self) -> (Sender<'d, D>, Receiver<'d, D>, CommandReceiver<'d>) {
+        (
+            Sender {
+                write_ep: self.write_ep,
+            },
+            Receiver {
+                read_ep: self.read_ep,
+            },
+            self.commands.receiver(),
+        )
+    }
+}
+
+pub struct Sender<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> Sender<'d, D> {
+    /// Sends a frame to the host, with a timestamp in microseconds if enabled.
+    pub async fn write_frame(
+        &mut self,
+        frame: &HostFrame,
+        timestamp: Option<u32>,
+    ) -> Result<(), EndpointError> {
+        self.write_ep.write(&frame.to_bytes(timestamp)).await
+    }
+}
+
+pub struct Receiver<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+}
+
+impl<'d, D: Driver<'d>> Receiver<'d, D> {
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await;
+    }
+
+    /// Waits for a frame from the host, skipping malformed ones.
+    pub async fn read_frame(&mut self) -> Result<HostFrame, EndpointError> {
+        let mut buf = [0_u8; MAX_PACKET_SIZE as usize];
+
+        loop {
+            let len = self.read_ep.read(&mut buf).await?;
+
+            match HostFrame::from_bytes(&buf[..len]) {
+                Ok(frame) => return Ok(frame),
+                Err(err) => debug!("Dropping a {} byte gs_usb frame: {}", len, err),
+            }
+        }
+    }
+}
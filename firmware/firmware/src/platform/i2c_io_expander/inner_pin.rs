@@ -21,6 +21,11 @@ impl<M: RawMutex> InnerPin<M> {
     pub async fn wait(&self) -> bool {
         self.signal.wait().await
     }
+
+    /// Forgets an edge that was signalled but not waited for yet.
+    pub fn clear(&self) {
+        self.signal.reset();
+    }
 }
 
 impl<M: RawMutex> InnerPin<M> {
@@ -1,8 +1,10 @@
 pub mod async_io_on_sync_io;
 pub mod bq25895;
+pub mod buttons;
 pub mod cropped_wrapped_converted_draw_target;
 pub mod cropped_wrapped_draw_target;
 pub mod flushing_display;
+pub mod gs_usb;
 pub mod i2c_io_expander;
 pub mod interrupt_i2c;
 pub mod irqs;
@@ -18,6 +20,7 @@ pub mod picotool;
 #[cfg(feature = "heap-in-psram")]
 pub mod psram;
 pub mod repl;
+pub mod sample_stream;
 pub mod sdcard;
 pub mod sdmmc;
 pub mod set_frequency;
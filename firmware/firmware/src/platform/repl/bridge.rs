@@ -0,0 +1,426 @@
+//! Man-in-the-middle bridge calls, see `apps::bridge`.
+
+use crate::{
+    apps::{
+        bridge::{self, BridgeMessage, BridgeReport, Held, MAX_HELD},
+        rx::RxMode,
+        tx::{can_pio::TxOutcome, waveform, TxMode, TxWords},
+    },
+    platform::repl::{
+        can::encode_frame,
+        rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
+        rpc_call,
+        tx::{bytes_to_u32, int_option},
+        wave::waveform_error,
+    },
+    register_repl_fn,
+};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec::Vec};
+use embassy_time::{Duration, Instant};
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, Map, Module, NativeCallContext, INT};
+
+/// Default time the bridge runs for, in ms.
+const DEFAULT_DURATION_MS: INT = 10_000;
+
+/// Longest delay the filter can ask for, in ms.
+const MAX_DELAY_MS: INT = 10_000;
+
+/// Longest time the bridge runs for, a day in ms.
+const MAX_DURATION_MS: INT = 86_400_000;
+
+/// A message as it goes out on the Tx port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outgoing {
+    Can {
+        arb_id: u32,
+        extended: bool,
+        rtr: bool,
+        dlc: u8,
+        data: Vec<u8>,
+    },
+    Nmea0183(u8, String),
+}
+
+impl From<&BridgeMessage> for Outgoing {
+    fn from(message: &BridgeMessage) -> Outgoing {
+        match message {
+            BridgeMessage::Can(msg) => Outgoing::Can {
+                arb_id: msg.arb_id(),
+                extended: msg.is_extended(),
+                rtr: msg.is_rtr(),
+                dlc: msg.dlc(),
+                data: msg.data().to_vec(),
+            },
+            BridgeMessage::Nmea0183(sof, sentence) => Outgoing::Nmea0183(*sof, sentence.clone()),
+        }
+    }
+}
+
+fn message_error(ctx: &NativeCallContext, field: &str, expected: &str) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorMismatchDataType(
+        expected.to_owned(),
+        format!("message.{}", field),
+        ctx.call_position(),
+    ))
+}
+
+/// The message handed to the filter, `#{type: "can", arb_id, extended, rtr, dlc, data}` or
+/// `#{type: "nmea0183", sentence}`.
+fn message_map(message: &BridgeMessage) -> Map {
+    let mut ret = Map::new();
+
+    match message {
+        BridgeMessage::Can(msg) => {
+            ret.insert("type".into(), "can".into());
+            ret.insert("arb_id".into(), Dynamic::from_int(msg.arb_id() as INT));
+            ret.insert("extended".into(), msg.is_extended().into());
+            ret.insert("rtr".into(), msg.is_rtr().into());
+            ret.insert("dlc".into(), Dynamic::from_int(msg.dlc() as INT));
+            ret.insert("data".into(), Dynamic::from_blob(msg.data().to_vec()));
+        }
+        BridgeMessage::Nmea0183(_, sentence) => {
+            ret.insert("type".into(), "nmea0183".into());
+            ret.insert("sentence".into(), sentence.as_str().into());
+        }
+    }
+
+    ret
+}
+
+/// The message to send from a map returned by the filter, of the same type as the received one,
+/// and the delay it asks for. A CAN identifier is extended if `extended` is set or, by default,
+/// if the received one was or it is over 11 bits. `dlc` only applies to remote frames, data
+/// frames take the length of `data`.
+fn parse_message(
+    ctx: &NativeCallContext,
+    received: &Outgoing,
+    map: &Map,
+) -> Result<(Outgoing, u64), Box<EvalAltResult>> {
+    let message = match received {
+        Outgoing::Can {
+            extended: received_extended,
+            dlc: received_dlc,
+            ..
+        } => {
+            let arb_id = map
+                .get("arb_id")
+                .and_then(|arb_id| arb_id.as_int().ok())
+                .filter(|arb_id| (0..1 << 29).contains(arb_id))
+                .ok_or_else(|| message_error(ctx, "arb_id", "29 bit INT"))?;
+            let extended = match map.get("extended") {
+                Some(extended) => extended
+                    .as_bool()
+                    .map_err(|_| message_error(ctx, "extended", "bool"))?,
+                None => *received_extended || arb_id > 0x7FF,
+            };
+            let rtr = match map.get("rtr") {
+                Some(rtr) => rtr
+                    .as_bool()
+                    .map_err(|_| message_error(ctx, "rtr", "bool"))?,
+                None => false,
+            };
+            let data = map
+                .get("data")
+                .and_then(|data| data.clone().into_blob().ok())
+                .filter(|data| data.len() <= 8)
+                .ok_or_else(|| message_error(ctx, "data", "Blob of up to 8 bytes"))?;
+
+            if !extended && arb_id > 0x7FF {
+                return Err(message_error(ctx, "arb_id", "11 bit INT, unless extended"));
+            }
+
+            // Past 8, the DLC of a data frame still means 8 bytes: a received one is kept while
+            // the data fills them.
+            let dlc = if rtr {
+                int_option(ctx, map, "dlc", 0..=15, *received_dlc as INT)? as u8
+            } else if data.len() == 8 {
+                (*received_dlc).max(8)
+            } else {
+                data.len() as u8
+            };
+
+            Outgoing::Can {
+                arb_id: arb_id as u32,
+                extended,
+                rtr,
+                dlc,
+                data: if rtr { Vec::new() } else { data },
+            }
+        }
+        Outgoing::Nmea0183(sof, _) => {
+            let sentence = map
+                .get("sentence")
+                .and_then(|sentence| sentence.clone().into_string().ok())
+                .filter(|sentence| sentence.is_ascii())
+                .ok_or_else(|| message_error(ctx, "sentence", "ASCII string"))?;
+
+            Outgoing::Nmea0183(*sof, sentence)
+        }
+    };
+
+    let delay = int_option(ctx, map, "delay_ms", 0..=MAX_DELAY_MS, 0)?;
+
+    Ok((message, delay as u64))
+}
+
+/// Sends a message on the Tx port, and returns whether it went out.
+fn send(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    message: &Outgoing,
+) -> Result<bool, Box<EvalAltResult>> {
+    let words = match message {
+        Outgoing::Can {
+            arb_id,
+            extended,
+            rtr,
+            dlc,
+            data,
+        } => TxWords::Can(bytes_to_u32(encode_frame(
+            *arb_id, *extended, *rtr, *dlc, data,
+        ))),
+        // One sample per bit, space driven like a dominant bit.
+        Outgoing::Nmea0183(sof, sentence) => {
+            let line = bridge::nmea0183_line(*sof, sentence);
+            let states = bridge::uart_bits(&line).map(|mark| !mark);
+            TxWords::Inject(waveform::halves(states, 1).map_err(|err| waveform_error(ctx, err))?)
+        }
+    };
+
+    let call = RpcCall::TxSend(words);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxSend(outcome) => Ok(outcome == TxOutcome::Sent),
+        _ => unreachable!(),
+    }
+}
+
+/// Sends a message received at `time`, and records it.
+fn send_recorded(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    time: Instant,
+    message: &Outgoing,
+    report: &mut BridgeReport,
+) -> Result<(), Box<EvalAltResult>> {
+    let sent = send(ctx, call_tx, result_rx, message)?;
+    let latency = Instant::now().saturating_duration_since(time).as_micros();
+    report.record(latency, sent);
+
+    Ok(())
+}
+
+/// Forwards the queued messages through the filter until `deadline`, or `count` messages (0 for
+/// no limit) and those they held back. Held back messages go out when due, while later ones keep
+/// going through; with [`MAX_HELD`] of them, the rest wait in the queue.
+fn forward(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    filter: &FnPtr,
+    deadline: Instant,
+    count: u32,
+    report: &mut BridgeReport,
+) -> Result<(), Box<EvalAltResult>> {
+    let mut held = Held::new();
+
+    while Instant::now() < deadline {
+        if let Some((time, outgoing)) = held.pop_due(Instant::now()) {
+            send_recorded(ctx, call_tx, result_rx, time, &outgoing, report)?;
+            continue;
+        }
+
+        let receiving = count == 0 || report.received < count;
+
+        if !receiving && held.is_empty() {
+            break;
+        }
+
+        let message = if receiving && held.len() < MAX_HELD {
+            bridge::receive()
+        } else {
+            None
+        };
+        let Some((time, message)) = message else {
+            core::hint::spin_loop();
+            continue;
+        };
+
+        report.received += 1;
+        let received = Outgoing::from(&message);
+        let verdict: Dynamic = filter.call_within_context(ctx, (message_map(&message),))?;
+
+        let (outgoing, delay) = if verdict.is_unit() || verdict.as_bool() == Ok(true) {
+            (received.clone(), 0)
+        } else if verdict.as_bool() == Ok(false) {
+            report.dropped += 1;
+            continue;
+        } else if let Some(map) = verdict.clone().try_cast::<Map>() {
+            parse_message(ctx, &received, &map)?
+        } else {
+            return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+                String::from("bool, () or message Map"),
+                verdict.type_name().into(),
+                ctx.call_position(),
+            )));
+        };
+
+        if outgoing != received {
+            report.rewritten += 1;
+        }
+
+        if delay > 0 {
+            held.push(time + Duration::from_millis(delay), (time, outgoing));
+        } else {
+            send_recorded(ctx, call_tx, result_rx, time, &outgoing, report)?;
+        }
+    }
+
+    report.held = held.len() as u32;
+
+    Ok(())
+}
+
+/// Bridges the Rx port to the Tx port through `filter`.
+///
+/// Opens the Trx-Rx tie, and sets Tx up for the Rx mode: "can" at the same baud for "can", and
+/// "inject" at the baud for "nmea0183", one sample per bit. Each received message is handed to
+/// `filter`, which returns `true` or `()` to forward it as is, `false` to drop it, or the message
+/// map, changed, to forward that instead, `delay_ms` (up to 10000) after it was received if set.
+/// `config` takes `duration_ms` (10000 by default) and `count` (messages, 0 for no limit).
+///
+/// Returns `#{received, forwarded, dropped, rewritten, overflow, failed, held, latency_min_us,
+/// latency_mean_us, latency_max_us}`, and prints it. The tie is left open.
+pub(crate) fn repl_bridge_run_config(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    config: Map,
+    filter: FnPtr,
+) -> Result<Map, Box<EvalAltResult>> {
+    let duration = int_option(
+        ctx,
+        &config,
+        "duration_ms",
+        1..=MAX_DURATION_MS,
+        DEFAULT_DURATION_MS,
+    )?;
+    let count = int_option(ctx, &config, "count", 0..=u32::MAX as INT, 0)?;
+
+    let mode = match rpc_call(&ctx, call_tx, result_rx, RpcCall::RxGetMode)? {
+        RpcResult::RxGetMode(mode) => mode,
+        _ => unreachable!(),
+    };
+    let baud = match rpc_call(&ctx, call_tx, result_rx, RpcCall::RxGetBaud)? {
+        RpcResult::RxGetBaud(baud) => baud,
+        _ => unreachable!(),
+    };
+    let tx_mode = match mode {
+        RxMode::Can => TxMode::Can,
+        RxMode::Nmea0183 => TxMode::Inject,
+        _ => {
+            return Err(Box::new(EvalAltResult::ErrorRuntime(
+                "Bridge: Rx must be in \"can\" or \"nmea0183\" mode".into(),
+                ctx.call_position(),
+            )))
+        }
+    };
+
+    for call in [
+        RpcCall::TrxSetTxRxTie(false),
+        RpcCall::TxSetMode(tx_mode),
+        RpcCall::TxSetBaud(baud),
+        RpcCall::TxEnableDisable(true),
+        RpcCall::RxEnableDisable(true),
+    ] {
+        rpc_call(&ctx, call_tx, result_rx, call)?;
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(duration as u64);
+    let mut report = BridgeReport::default();
+
+    bridge::set_started(true);
+    let outcome = forward(
+        ctx,
+        call_tx,
+        result_rx,
+        &filter,
+        deadline,
+        count as u32,
+        &mut report,
+    );
+    report.overflow = bridge::overflow();
+    bridge::set_started(false);
+    outcome?;
+
+    ctx.engine().eval_expression::<()>(&format!(
+        "print(\"bridge: {} received, {} forwarded ({} rewritten, {} failed), {} dropped, {} overflow, {} held, latency {}/{}/{} us\")",
+        report.received,
+        report.forwarded,
+        report.rewritten,
+        report.failed,
+        report.dropped,
+        report.overflow,
+        report.held,
+        report.min,
+        report.mean(),
+        report.max
+    ))?;
+
+    let mut ret = Map::new();
+    for (key, value) in [
+        ("received", report.received),
+        ("forwarded", report.forwarded),
+        ("dropped", report.dropped),
+        ("rewritten", report.rewritten),
+        ("overflow", report.overflow),
+        ("failed", report.failed),
+        ("held", report.held),
+    ] {
+        ret.insert(key.into(), Dynamic::from_int(value as INT));
+    }
+    ret.insert(
+        "latency_min_us".into(),
+        Dynamic::from_int(report.min as INT),
+    );
+    ret.insert(
+        "latency_mean_us".into(),
+        Dynamic::from_int(report.mean() as INT),
+    );
+    ret.insert(
+        "latency_max_us".into(),
+        Dynamic::from_int(report.max as INT),
+    );
+
+    Ok(ret)
+}
+
+pub(crate) fn repl_bridge_run(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    filter: FnPtr,
+) -> Result<Map, Box<EvalAltResult>> {
+    repl_bridge_run_config(ctx, call_tx, result_rx, Map::new(), filter)
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn!(module, call_tx, result_rx, repl_bridge_run, "run", (filter: FnPtr));
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_bridge_run_config,
+        "run",
+        (config: Map, filter: FnPtr)
+    );
+    engine.register_static_module("bridge", module.into());
+}
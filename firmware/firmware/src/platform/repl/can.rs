@@ -1,11 +1,13 @@
 //! Differential injector RPC calls
 
 use crate::{
+    apps::rx::can::MATCH_PATTERN_MAX_LEN,
     platform::repl::rpc::{RpcCallSender, RpcResultReceiver},
     register_repl_fn_no_rpc,
 };
-use alloc::{borrow::ToOwned, boxed::Box};
+use alloc::{borrow::ToOwned, boxed::Box, vec::Vec};
 use bitvec::{order::Msb0, slice::BitSlice, vec::BitVec};
+use core::cmp::min;
 use rhai::{Blob, Engine, EvalAltResult, Module, NativeCallContext, INT};
 
 pub const SOF: bool = false;
@@ -54,9 +56,11 @@ pub fn stuff_bits(bits: &BitSlice<u8, Msb0>) -> BitVec<u8, Msb0> {
             run_len += 1;
 
             if run_len == 5 {
+                // The stuff bit starts the next run.
                 stuffed.push(bit);
                 stuffed.push(!bit);
-                run_len = 0;
+                run_len = 1;
+                prev_bit = !bit;
                 continue;
             }
         } else {
@@ -70,32 +74,10 @@ pub fn stuff_bits(bits: &BitSlice<u8, Msb0>) -> BitVec<u8, Msb0> {
     stuffed
 }
 
-pub(crate) fn repl_can_encode(
-    ctx: &NativeCallContext,
-    arb_id: INT,
-    rtr: bool,
-    payload: Blob,
-) -> Result<Blob, Box<EvalAltResult>> {
-    if payload.len() > 8 {
-        return Err(Box::new(EvalAltResult::ErrorDataTooLarge(
-            "CAN 2.0B payload must be <= 8 bytes.".to_owned(),
-            ctx.call_position(),
-        )));
-    }
-
-    if arb_id > 0x1FFF_FFF {
-        return Err(Box::new(EvalAltResult::ErrorDataTooLarge(
-            "CAN 2.0B identifier must be at most 29 bits.".to_owned(),
-            ctx.call_position(),
-        )));
-    }
-
-    let mut bits = BitVec::<u8, Msb0>::new();
-    let ide = if arb_id > 0x7FF { true } else { false };
-    let arb_id = arb_id as u32;
-    let dlc = payload.len() as u8;
-
-    bits.push(SOF);
+/// Pushes the identifier bits of a CAN 2.0B frame (including SRR and IDE for extended frames),
+/// stopping short of the RTR bit.
+pub fn push_identifier(bits: &mut BitVec<u8, Msb0>, arb_id: u32, extended: bool) {
+    let ide = extended;
 
     if ide {
         let id_a = (arb_id >> ID_B_LEN) & 0x7FF;
@@ -111,17 +93,57 @@ pub(crate) fn repl_can_encode(
         for i in (0..ID_B_LEN).rev() {
             bits.push(((id_b >> i) & 0b1) == 1);
         }
-
-        bits.push(rtr);
-        bits.push(R1);
-        bits.push(R0);
     } else {
         let id_a = (arb_id & 0x7FF) as u16;
 
         for i in (0..ID_A_LEN).rev() {
             bits.push(((id_a >> i) & 0b1) == 1);
         }
+    }
+}
+
+/// Builds the pattern word for [`crate::apps::rx::can::PioCanMatch`]: the number of pattern bits
+/// minus one in the top 5 bits, followed by the stuffed SOF and identifier bits as they appear on
+/// the wire. Identifiers over 11 bits are extended, and truncated to the matcher's window.
+///
+/// Returns the pattern word and the number of bits it covers.
+pub fn id_pattern(arb_id: u32) -> (u32, usize) {
+    let mut bits = BitVec::<u8, Msb0>::new();
+    bits.push(SOF);
+    push_identifier(&mut bits, arb_id, arb_id > 0x7FF);
+
+    let stuffed = stuff_bits(&bits);
+    let len = min(stuffed.len(), MATCH_PATTERN_MAX_LEN);
+    let mut pattern = ((len - 1) as u32) << MATCH_PATTERN_MAX_LEN;
+
+    for (i, bit) in stuffed.iter().take(len).enumerate() {
+        pattern |= (*bit as u32) << (MATCH_PATTERN_MAX_LEN - 1 - i);
+    }
+
+    (pattern, len)
+}
+
+/// The pattern word for [`crate::apps::rx::can::PioCanMatch`] matching any frame, on its SOF.
+pub fn sof_pattern() -> u32 {
+    (SOF as u32) << (MATCH_PATTERN_MAX_LEN - 1)
+}
+
+/// Encodes a CAN 2.0 frame as it goes on the wire (stuffed, with the ACK slot and EOF
+/// recessive), padded to whole bytes with recessive bits. `dlc` is the payload length of data
+/// frames, and the requested length of remote frames (which have no payload).
+pub fn encode_frame(arb_id: u32, extended: bool, rtr: bool, dlc: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bits = BitVec::<u8, Msb0>::new();
+    let ide = extended;
+    let payload = if rtr { &[][..] } else { payload };
 
+    bits.push(SOF);
+    push_identifier(&mut bits, arb_id, ide);
+
+    if ide {
+        bits.push(rtr);
+        bits.push(R1);
+        bits.push(R0);
+    } else {
         bits.push(rtr);
         bits.push(ide);
         bits.push(R0);
@@ -144,9 +166,10 @@ pub(crate) fn repl_can_encode(
         crc_bits.push(((crc >> i) & 0b1) == 1);
     }
 
-    crc_bits.push(CRC_DELIM);
     bits.extend(crc_bits);
+    // Stuffing stops at the end of the CRC sequence.
     let mut stuffed = stuff_bits(&bits);
+    stuffed.push(CRC_DELIM);
     stuffed.push(ACK);
     stuffed.push(ACK_DELIM);
 
@@ -158,7 +181,48 @@ pub(crate) fn repl_can_encode(
         stuffed.push(EOF);
     }
 
-    let blob = stuffed.into_vec();
+    stuffed.into_vec()
+}
+
+pub(crate) fn repl_can_encode(
+    ctx: &NativeCallContext,
+    arb_id: INT,
+    rtr: bool,
+    payload: Blob,
+) -> Result<Blob, Box<EvalAltResult>> {
+    repl_can_encode_extended(ctx, arb_id, arb_id > 0x7FF, rtr, payload)
+}
+
+pub(crate) fn repl_can_encode_extended(
+    ctx: &NativeCallContext,
+    arb_id: INT,
+    extended: bool,
+    rtr: bool,
+    payload: Blob,
+) -> Result<Blob, Box<EvalAltResult>> {
+    if payload.len() > 8 {
+        return Err(Box::new(EvalAltResult::ErrorDataTooLarge(
+            "CAN 2.0B payload must be <= 8 bytes.".to_owned(),
+            ctx.call_position(),
+        )));
+    }
+
+    if arb_id > 0x1FFF_FFF {
+        return Err(Box::new(EvalAltResult::ErrorDataTooLarge(
+            "CAN 2.0B identifier must be at most 29 bits.".to_owned(),
+            ctx.call_position(),
+        )));
+    }
+
+    if !extended && arb_id > 0x7FF {
+        return Err(Box::new(EvalAltResult::ErrorDataTooLarge(
+            "CAN 2.0B standard identifier must be at most 11 bits.".to_owned(),
+            ctx.call_position(),
+        )));
+    }
+
+    // The payload length is the DLC of remote frames too.
+    let blob = encode_frame(arb_id as u32, extended, rtr, payload.len() as u8, &payload);
 
     Ok(blob)
 }
@@ -170,5 +234,11 @@ pub(crate) fn register_functions(
 ) {
     let mut module = Module::new();
     register_repl_fn_no_rpc!(module, repl_can_encode, "encode", (arb_id: INT, rtr: bool, payload: Blob));
+    register_repl_fn_no_rpc!(
+        module,
+        repl_can_encode_extended,
+        "encode",
+        (arb_id: INT, extended: bool, rtr: bool, payload: Blob)
+    );
     engine.register_static_module("can", module.into());
 }
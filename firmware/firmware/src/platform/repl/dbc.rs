@@ -0,0 +1,192 @@
+//! DBC database calls, see `apps::dbc`.
+
+use crate::{
+    apps::dbc::{Database, DbcError},
+    platform::repl::rpc::{RpcCallSender, RpcResultReceiver},
+    register_repl_fn_no_rpc,
+};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec::Vec};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, Map, Module, NativeCallContext, FLOAT, INT};
+
+/// The loaded database, kept across scripts.
+static DATABASE: Mutex<CriticalSectionRawMutex, RefCell<Option<Database>>> =
+    Mutex::new(RefCell::new(None));
+
+fn dbc_error(ctx: &NativeCallContext, err: DbcError) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorRuntime(
+        format!("DBC: {}", err).into(),
+        ctx.call_position(),
+    ))
+}
+
+/// Runs `f` on the loaded database.
+fn with_database<T>(
+    ctx: &NativeCallContext,
+    f: impl FnOnce(&Database) -> Result<T, Box<EvalAltResult>>,
+) -> Result<T, Box<EvalAltResult>> {
+    DATABASE.lock(|database| match database.borrow().as_ref() {
+        Some(database) => f(database),
+        None => Err(Box::new(EvalAltResult::ErrorRuntime(
+            "No DBC loaded, see dbc::load".into(),
+            ctx.call_position(),
+        ))),
+    })
+}
+
+/// Loads a database from the text of a DBC file, replacing the previous one. Returns the number
+/// of messages.
+pub(crate) fn repl_dbc_load(
+    ctx: &NativeCallContext,
+    text: String,
+) -> Result<INT, Box<EvalAltResult>> {
+    let database = Database::parse(&text).map_err(|err| dbc_error(ctx, err))?;
+    let count = database.messages.len() as INT;
+    DATABASE.lock(|current| current.replace(Some(database)));
+
+    Ok(count)
+}
+
+fn decode(
+    ctx: &NativeCallContext,
+    arb_id: INT,
+    extended: bool,
+    data: &[u8],
+) -> Result<Dynamic, Box<EvalAltResult>> {
+    with_database(ctx, |database| {
+        let Some(message) = database.message(arb_id as u32, extended) else {
+            return Ok(Dynamic::UNIT);
+        };
+
+        let mut signals = Map::new();
+        let mut labels = Map::new();
+
+        for (signal, raw) in message.decode(data) {
+            signals.insert(
+                signal.name.as_str().into(),
+                Dynamic::from_float(signal.physical(raw)),
+            );
+
+            if let Some(label) = signal.label(raw) {
+                labels.insert(signal.name.as_str().into(), label.into());
+            }
+        }
+
+        let mut ret = Map::new();
+        ret.insert("name".into(), message.name.as_str().into());
+        ret.insert("signals".into(), signals.into());
+        ret.insert("labels".into(), labels.into());
+
+        Ok(ret.into())
+    })
+}
+
+/// Decodes a frame map (`arb_id`, `data` and optionally `extended`, which defaults to whether the
+/// identifier is over 11 bits) into its message name, physical signal values and the value
+/// descriptions matching them. Returns `()` for unknown messages.
+pub(crate) fn repl_dbc_decode(
+    ctx: &NativeCallContext,
+    frame: Map,
+) -> Result<Dynamic, Box<EvalAltResult>> {
+    let field_error = |field: &str, expected: &str| {
+        Box::new(EvalAltResult::ErrorMismatchDataType(
+            expected.to_owned(),
+            format!("frame.{}", field),
+            ctx.call_position(),
+        ))
+    };
+
+    let arb_id = frame
+        .get("arb_id")
+        .and_then(|arb_id| arb_id.as_int().ok())
+        .ok_or_else(|| field_error("arb_id", "INT"))?;
+    let data = frame
+        .get("data")
+        .and_then(|data| data.clone().into_blob().ok())
+        .ok_or_else(|| field_error("data", "Blob"))?;
+    let extended = match frame.get("extended") {
+        Some(extended) => extended
+            .as_bool()
+            .map_err(|_| field_error("extended", "bool"))?,
+        None => arb_id > 0x7FF,
+    };
+
+    decode(ctx, arb_id, extended, &data)
+}
+
+pub(crate) fn repl_dbc_decode_id(
+    ctx: &NativeCallContext,
+    arb_id: INT,
+    data: Blob,
+) -> Result<Dynamic, Box<EvalAltResult>> {
+    decode(ctx, arb_id, arb_id > 0x7FF, &data)
+}
+
+/// Encodes a message from its signal values, physical or value descriptions, into a frame map
+/// as taken by `dbc::decode`.
+pub(crate) fn repl_dbc_encode(
+    ctx: &NativeCallContext,
+    name: String,
+    values: Map,
+) -> Result<Map, Box<EvalAltResult>> {
+    with_database(ctx, |database| {
+        let message = database
+            .message_by_name(&name)
+            .ok_or_else(|| dbc_error(ctx, DbcError::UnknownMessage))?;
+        let mut physical = Vec::with_capacity(values.len());
+
+        for (signal_name, value) in &values {
+            let signal = message
+                .signals
+                .iter()
+                .find(|signal| signal.name == signal_name.as_str())
+                .ok_or_else(|| dbc_error(ctx, DbcError::UnknownSignal))?;
+
+            let value = if let Ok(value) = value.as_float() {
+                value
+            } else if let Ok(value) = value.as_int() {
+                value as FLOAT
+            } else if let Some(raw) = value
+                .clone()
+                .into_immutable_string()
+                .ok()
+                .and_then(|label| signal.label_raw(&label))
+            {
+                signal.physical(raw)
+            } else {
+                return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+                    String::from("FLOAT, INT or value description"),
+                    format!("{}", value),
+                    ctx.call_position(),
+                )));
+            };
+
+            physical.push((signal_name.as_str(), value));
+        }
+
+        let data = message
+            .encode(&physical)
+            .map_err(|err| dbc_error(ctx, err))?;
+
+        let mut frame = Map::new();
+        frame.insert("arb_id".into(), Dynamic::from_int(message.arb_id as INT));
+        frame.insert("extended".into(), message.extended.into());
+        frame.insert("data".into(), Dynamic::from_blob(data));
+
+        Ok(frame)
+    })
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    _call_tx: RpcCallSender,
+    _result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn_no_rpc!(module, repl_dbc_load, "load", (text: String));
+    register_repl_fn_no_rpc!(module, repl_dbc_decode, "decode", (frame: Map));
+    register_repl_fn_no_rpc!(module, repl_dbc_decode_id, "decode", (arb_id: INT, data: Blob));
+    register_repl_fn_no_rpc!(module, repl_dbc_encode, "encode", (name: String, values: Map));
+    engine.register_static_module("dbc", module.into());
+}
@@ -5,7 +5,7 @@ use crate::{
     },
     register_repl_fn,
 };
-use alloc::{boxed::Box, string::String};
+use alloc::{boxed::Box, string::String, vec::Vec};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
 use embedded_graphics::pixelcolor::Rgb565;
 use rhai::{Engine, EvalAltResult, Module, NativeCallContext, FLOAT, INT};
@@ -17,6 +17,8 @@ pub enum DisplayCommand {
     FillRegion(u16, u16, u16, u16, Rgb565),
     Clear,
     Flush,
+    /// Injector samples to plot and flush.
+    Waveform(Vec<u8>),
 }
 
 pub const DISP_MTU: usize = 1;
@@ -0,0 +1,112 @@
+//! DMX512 calls, see `apps::dmx`.
+
+use crate::{
+    apps::{dmx, tx::waveform},
+    platform::repl::{
+        rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
+        rpc_call,
+        tx::int_option,
+        wave::{samples, waveform_error},
+    },
+    register_repl_fn, register_repl_fn_no_rpc,
+};
+use alloc::{boxed::Box, format};
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, Map, Module, NativeCallContext, FLOAT, INT};
+
+/// Encodes a packet as injector samples, one bit every `width` samples: 250 kbaud at
+/// `tx::set_baud(250000 * width)`. Space is driven with CAN H above CAN L, mark the other way
+/// around. Options are `#{start_code, break_us, mab_us, width}`.
+pub(crate) fn repl_dmx_wave_options(
+    ctx: &NativeCallContext,
+    slots: Blob,
+    options: Map,
+) -> Result<Blob, Box<EvalAltResult>> {
+    if slots.len() > dmx::SLOTS {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Too many slots: {} (at most {})", slots.len(), dmx::SLOTS),
+            ctx.call_position(),
+        )));
+    }
+
+    let start_code = int_option(ctx, &options, "start_code", 0..=0xFF, 0)? as u8;
+    let break_us = int_option(
+        ctx,
+        &options,
+        "break_us",
+        88..=1_000_000,
+        dmx::BREAK_US as INT,
+    )?;
+    let mab_us = int_option(ctx, &options, "mab_us", 8..=1_000_000, dmx::MAB_US as INT)?;
+    let width = samples(ctx, int_option(ctx, &options, "width", 1..=64, 1)?)?;
+
+    // Rounded up to whole bits.
+    let bit_us = dmx::BIT_US as INT;
+    let break_bits = ((break_us + bit_us - 1) / bit_us) as usize;
+    let mab_bits = ((mab_us + bit_us - 1) / bit_us) as usize;
+
+    let bits = break_bits + mab_bits + 11 * (slots.len() + 1);
+    samples(ctx, (bits * width) as INT)?;
+
+    // Space drives the bus like a positive half.
+    let states = dmx::bits(start_code, &slots, break_bits, mab_bits).map(|mark| !mark);
+    waveform::halves(states, width).map_err(|err| waveform_error(ctx, err))
+}
+
+pub(crate) fn repl_dmx_wave(
+    ctx: &NativeCallContext,
+    slots: Blob,
+) -> Result<Blob, Box<EvalAltResult>> {
+    repl_dmx_wave_options(ctx, slots, Map::new())
+}
+
+/// Returns the levels of the last dimmer packet received in "dmx" mode, with the stats since the
+/// mode was set.
+pub(crate) fn repl_dmx_read(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Map, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxDmxRead;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxDmxRead(universe) => {
+            let mut ret = Map::new();
+            ret.insert("packets".into(), Dynamic::from_int(universe.packets as INT));
+            ret.insert(
+                "alternate".into(),
+                Dynamic::from_int(universe.alternate as INT),
+            );
+            ret.insert("errors".into(), Dynamic::from_int(universe.errors as INT));
+            ret.insert(
+                "rate_hz".into(),
+                Dynamic::from_float(universe.refresh_rate() as FLOAT),
+            );
+            ret.insert(
+                "min_interval_us".into(),
+                Dynamic::from_int(universe.min_interval as INT),
+            );
+            ret.insert(
+                "max_interval_us".into(),
+                Dynamic::from_int(universe.max_interval as INT),
+            );
+            ret.insert("slots".into(), Dynamic::from_blob(universe.slots));
+
+            Ok(ret)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn_no_rpc!(module, repl_dmx_wave, "wave", (slots: Blob));
+    register_repl_fn_no_rpc!(module, repl_dmx_wave_options, "wave", (slots: Blob, options: Map));
+    register_repl_fn!(module, call_tx, result_rx, repl_dmx_read, "read", ());
+    engine.register_static_module("dmx", module.into());
+}
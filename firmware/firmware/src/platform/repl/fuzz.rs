@@ -0,0 +1,333 @@
+//! CAN fuzzer calls, see `apps::fuzz`.
+
+use crate::{
+    apps::{
+        candump,
+        fuzz::{FuzzConfig, Heartbeat, Stop, Verdict},
+    },
+    platform::repl::{
+        rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
+        rpc_call,
+    },
+    register_repl_fn,
+};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec::Vec};
+use core::ops::{Range, RangeInclusive};
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, Map, Module, NativeCallContext, INT};
+
+/// Fastest rate, a bit over what 1 Mbit/s classic CAN carries.
+const MAX_RATE: INT = 10_000;
+
+/// Frames kept as the crash candidate on top of those sent within a heartbeat timeout, before a
+/// quiet target is noticed.
+const HISTORY_MARGIN: usize = 16;
+
+/// Longest heartbeat timeout, a minute in ms.
+const MAX_TIMEOUT_MS: INT = 60_000;
+
+/// Most frames kept as the crash candidate, bounding the history's memory.
+const MAX_HISTORY: usize = 1024;
+
+/// Longest run, `count` frames at `rate`, a day in ms.
+const MAX_DURATION_MS: INT = 86_400_000;
+
+fn field_error(ctx: &NativeCallContext, field: &str, expected: &str) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorMismatchDataType(
+        expected.to_owned(),
+        format!("config.{}", field),
+        ctx.call_position(),
+    ))
+}
+
+fn range_error(ctx: &NativeCallContext, field: &str, value: INT) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorArithmetic(
+        format!("Invalid {}: {}", field, value),
+        ctx.call_position(),
+    ))
+}
+
+fn int_field(
+    ctx: &NativeCallContext,
+    config: &Map,
+    field: &str,
+    default: INT,
+) -> Result<INT, Box<EvalAltResult>> {
+    match config.get(field) {
+        Some(value) => value.as_int().map_err(|_| field_error(ctx, field, "INT")),
+        None => Ok(default),
+    }
+}
+
+fn array_field(
+    ctx: &NativeCallContext,
+    config: &Map,
+    field: &str,
+) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    match config.get(field) {
+        Some(value) => value
+            .clone()
+            .into_array()
+            .map_err(|_| field_error(ctx, field, "Array")),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn is_arb_id(arb_id: INT) -> bool {
+    (0..1 << 29).contains(&arb_id)
+}
+
+/// The corpus, from frame maps as made by `dbc::encode` or a candump log.
+fn parse_corpus(
+    ctx: &NativeCallContext,
+    config: &Map,
+) -> Result<Vec<(u32, Vec<u8>)>, Box<EvalAltResult>> {
+    if let Some(log) = config
+        .get("corpus")
+        .and_then(|corpus| corpus.clone().into_immutable_string().ok())
+    {
+        let frames = candump::parse_log(&log).map_err(|err| {
+            Box::new(EvalAltResult::ErrorMismatchDataType(
+                String::from("candump log"),
+                format!("{}", err),
+                ctx.call_position(),
+            ))
+        })?;
+
+        return Ok(frames
+            .into_iter()
+            .map(|frame| (frame.arb_id, frame.data))
+            .collect());
+    }
+
+    array_field(ctx, config, "corpus")?
+        .into_iter()
+        .map(|frame| {
+            let frame = frame
+                .try_cast::<Map>()
+                .ok_or_else(|| field_error(ctx, "corpus", "Array of frame maps or candump log"))?;
+            let arb_id = frame
+                .get("arb_id")
+                .and_then(|arb_id| arb_id.as_int().ok())
+                .filter(|&arb_id| is_arb_id(arb_id))
+                .ok_or_else(|| field_error(ctx, "corpus[].arb_id", "29 bit INT"))?;
+            let data = frame
+                .get("data")
+                .and_then(|data| data.clone().into_blob().ok())
+                .filter(|data| data.len() <= 8)
+                .ok_or_else(|| field_error(ctx, "corpus[].data", "Blob of up to 8 bytes"))?;
+
+            Ok((arb_id as u32, data))
+        })
+        .collect()
+}
+
+/// The identifier ranges, each an INT or a range of them.
+fn parse_ids(ctx: &NativeCallContext, config: &Map) -> Result<Vec<(u32, u32)>, Box<EvalAltResult>> {
+    array_field(ctx, config, "ids")?
+        .into_iter()
+        .map(|ids| {
+            let (lo, hi) = if let Ok(arb_id) = ids.as_int() {
+                (arb_id, arb_id)
+            } else if let Some(range) = ids.clone().try_cast::<Range<INT>>() {
+                (range.start, range.end - 1)
+            } else if let Some(range) = ids.try_cast::<RangeInclusive<INT>>() {
+                (*range.start(), *range.end())
+            } else {
+                return Err(field_error(ctx, "ids", "Array of INT or ranges"));
+            };
+
+            if lo > hi || !is_arb_id(lo) || !is_arb_id(hi) {
+                return Err(Box::new(EvalAltResult::ErrorArithmetic(
+                    format!("Invalid identifier range: {:#X}..={:#X}", lo, hi),
+                    ctx.call_position(),
+                )));
+            }
+
+            Ok((lo as u32, hi as u32))
+        })
+        .collect()
+}
+
+/// Starts fuzzing the bus with frames mutated from a corpus, watching the target's heartbeat. The
+/// run goes on in the background, see [`repl_fuzz_stop`].
+///
+/// `config` takes `seed` (from the TRNG when missing, and printed), `corpus` (frame maps or a
+/// candump log), `ids` (identifiers or ranges of them), `dictionary` (Blobs), `rate` (frames per
+/// second, 100 by default), `count` (frames, 1000 by default), `heartbeat` (identifier),
+/// `timeout` (ms, 1000 by default, at most a minute), `mask` (Blob of heartbeat payload bits that
+/// must not change) and `history` (frames kept as the crash candidate, at most 1024). The frames
+/// must go out within a day at `rate`.
+///
+/// Returns the seed.
+pub(crate) fn repl_fuzz_run(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    config: Map,
+) -> Result<INT, Box<EvalAltResult>> {
+    let rate = int_field(ctx, &config, "rate", 100)?;
+    let count = int_field(ctx, &config, "count", 1000)?;
+    let timeout = int_field(ctx, &config, "timeout", 1000)?;
+
+    if !(1..=MAX_RATE).contains(&rate) {
+        return Err(range_error(ctx, "rate", rate));
+    }
+
+    if count < 0 || count > MAX_DURATION_MS / 1000 * rate {
+        return Err(range_error(ctx, "count", count));
+    }
+
+    if !(1..=MAX_TIMEOUT_MS).contains(&timeout) {
+        return Err(range_error(ctx, "timeout", timeout));
+    }
+
+    let heartbeat = match config.get("heartbeat") {
+        Some(arb_id) => {
+            let arb_id = arb_id
+                .as_int()
+                .ok()
+                .filter(|&arb_id| is_arb_id(arb_id))
+                .ok_or_else(|| field_error(ctx, "heartbeat", "29 bit INT"))?;
+            let mask = match config.get("mask") {
+                Some(mask) => mask
+                    .clone()
+                    .into_blob()
+                    .map_err(|_| field_error(ctx, "mask", "Blob"))?,
+                None => Blob::new(),
+            };
+
+            Some(Heartbeat {
+                arb_id: arb_id as u32,
+                timeout: timeout as u64,
+                mask,
+            })
+        }
+        None => None,
+    };
+
+    let history = match config.get("history") {
+        Some(history) => match history.as_int() {
+            Ok(history) if (1..=MAX_HISTORY as INT).contains(&history) => history as usize,
+            Ok(history) => return Err(range_error(ctx, "history", history)),
+            Err(_) => return Err(field_error(ctx, "history", "INT")),
+        },
+        None => ((rate * timeout / 1000) as usize + HISTORY_MARGIN).min(MAX_HISTORY),
+    };
+
+    let dictionary = array_field(ctx, &config, "dictionary")?
+        .into_iter()
+        .map(|token| {
+            token
+                .into_blob()
+                .ok()
+                .filter(|token| !token.is_empty() && token.len() <= 8)
+                .ok_or_else(|| field_error(ctx, "dictionary", "Array of Blobs of 1 to 8 bytes"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let seed = match config.get("seed") {
+        Some(seed) => seed.as_int().map_err(|_| field_error(ctx, "seed", "INT"))? as u64,
+        None => {
+            let call = RpcCall::SysRandom(8);
+
+            match rpc_call(&ctx, call_tx, result_rx, call)? {
+                RpcResult::SysRandom(bytes) => {
+                    u64::from_le_bytes(bytes.as_slice().try_into().unwrap())
+                }
+                _ => unreachable!(),
+            }
+        }
+    };
+
+    ctx.engine()
+        .eval_expression::<()>(&format!("print(\"fuzz seed: 0x{:016X}\")", seed))?;
+
+    let config = FuzzConfig {
+        seed,
+        corpus: parse_corpus(ctx, &config)?,
+        ids: parse_ids(ctx, &config)?,
+        dictionary,
+        rate: rate as u32,
+        count: count as usize,
+        heartbeat,
+        history,
+    };
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxFuzz(config);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxFuzz => Ok(seed as INT),
+        _ => unreachable!(),
+    }
+}
+
+/// Whether a run is still going.
+pub(crate) fn repl_fuzz_running(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<bool, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxFuzzRunning;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxFuzzRunning(running) => Ok(running),
+        _ => unreachable!(),
+    }
+}
+
+/// Stops the run, if it is still going.
+///
+/// Returns `#{seed, sent, failed, stop, candidate}`, where `stop` is `"done"`, `"stopped"`,
+/// `"quiet"` or `"changed"` and `candidate` is a candump log of the last frames sent when the
+/// target went down, ready for `tx::replay`.
+pub(crate) fn repl_fuzz_stop(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Map, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxFuzzStop;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxFuzzStop(report) => {
+            let stop = match report.stop {
+                Stop::Done => "done",
+                Stop::Stopped => "stopped",
+                Stop::Down(Verdict::Quiet) => "quiet",
+                Stop::Down(Verdict::Changed) => "changed",
+            };
+
+            let mut candidate = String::new();
+            for frame in &report.candidate {
+                candump::write_frame(&mut candidate, frame);
+            }
+
+            let mut ret = Map::new();
+            ret.insert("seed".into(), Dynamic::from_int(report.seed as INT));
+            ret.insert("sent".into(), Dynamic::from_int(report.sent as INT));
+            ret.insert("failed".into(), Dynamic::from_int(report.failed as INT));
+            ret.insert("stop".into(), stop.into());
+            ret.insert("candidate".into(), candidate.into());
+
+            Ok(ret)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn!(module, call_tx, result_rx, repl_fuzz_run, "run", (config: Map));
+    register_repl_fn!(module, call_tx, result_rx, repl_fuzz_running, "running", ());
+    register_repl_fn!(module, call_tx, result_rx, repl_fuzz_stop, "stop", ());
+    engine.register_static_module("fuzz", module.into());
+}
@@ -0,0 +1,348 @@
+//! Voltage glitch calls, see `apps::glitch`.
+
+use crate::{
+    apps::{
+        glitch::{self, Attempt, Axis, Edge, Glitch, Outcome, Tally, Trigger},
+        tx::{
+            overwrite::{MIN_GLITCH_DELAY, MIN_GLITCH_WIDTH},
+            waveform::{self, Level},
+        },
+    },
+    platform::repl::{
+        can,
+        rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
+        rpc_call,
+        wave::level,
+    },
+    register_repl_fn, register_repl_fn_no_rpc,
+};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec::Vec};
+use core::ops::{Range, RangeInclusive};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Map, Module, NativeCallContext, INT};
+
+/// Most attempts in a sweep, each is kept in the returned table.
+const MAX_ATTEMPTS: usize = 10_000;
+
+/// Longest wait for a trigger, a minute in ms.
+const MAX_TIMEOUT_MS: INT = 60_000;
+
+fn field_error(ctx: &NativeCallContext, field: &str, expected: &str) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorMismatchDataType(
+        expected.to_owned(),
+        format!("config.{}", field),
+        ctx.call_position(),
+    ))
+}
+
+fn range_error(ctx: &NativeCallContext, field: &str, value: INT) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorArithmetic(
+        format!("Invalid {}: {}", field, value),
+        ctx.call_position(),
+    ))
+}
+
+fn int_field(
+    ctx: &NativeCallContext,
+    config: &Map,
+    field: &str,
+    default: INT,
+) -> Result<INT, Box<EvalAltResult>> {
+    match config.get(field) {
+        Some(value) => value.as_int().map_err(|_| field_error(ctx, field, "INT")),
+        None => Ok(default),
+    }
+}
+
+/// The injector code for the `h` and `l` levels, 0 V when missing.
+fn parse_code(ctx: &NativeCallContext, config: &Map) -> Result<u8, Box<EvalAltResult>> {
+    let h = match config.get("h") {
+        Some(h) => level(ctx, h)?,
+        None => Level::Drive(0),
+    };
+    let l = match config.get("l") {
+        Some(l) => level(ctx, l)?,
+        None => Level::Drive(0),
+    };
+
+    waveform::code(h, l).map_err(|err| {
+        Box::new(EvalAltResult::ErrorRuntime(
+            format!("Glitch: {}", err).into(),
+            ctx.call_position(),
+        ))
+    })
+}
+
+fn parse_trigger(ctx: &NativeCallContext, config: &Map) -> Result<Trigger, Box<EvalAltResult>> {
+    let Some(trigger) = config.get("trigger") else {
+        return Ok(Trigger::Software);
+    };
+
+    if let Ok(arb_id) = trigger.as_int() {
+        if !(0..1 << 29).contains(&arb_id) {
+            return Err(range_error(ctx, "trigger", arb_id));
+        }
+
+        return Ok(Trigger::Can(can::id_pattern(arb_id as u32).0));
+    }
+
+    match trigger.clone().into_string().as_deref() {
+        Ok("software") => Ok(Trigger::Software),
+        Ok("sao_rising") => Ok(Trigger::Sao(Edge::Rising)),
+        Ok("sao_falling") => Ok(Trigger::Sao(Edge::Falling)),
+        Ok("sof") => Ok(Trigger::Can(can::sof_pattern())),
+        _ => Err(field_error(
+            ctx,
+            "trigger",
+            "[software, sao_rising, sao_falling, sof] or 29 bit INT",
+        )),
+    }
+}
+
+/// An axis from an INT or a range of them, at least `min`.
+fn parse_axis(
+    ctx: &NativeCallContext,
+    config: &Map,
+    field: &str,
+    min: u32,
+) -> Result<Axis, Box<EvalAltResult>> {
+    let value = config
+        .get(field)
+        .ok_or_else(|| field_error(ctx, field, "INT or range"))?;
+
+    let (start, end) = if let Ok(value) = value.as_int() {
+        (value, value)
+    } else if let Some(range) = value.clone().try_cast::<Range<INT>>() {
+        (range.start, range.end - 1)
+    } else if let Some(range) = value.clone().try_cast::<RangeInclusive<INT>>() {
+        (*range.start(), *range.end())
+    } else {
+        return Err(field_error(ctx, field, "INT or range"));
+    };
+
+    if start < min as INT || start > u32::MAX as INT {
+        return Err(range_error(ctx, field, start));
+    }
+
+    if end < start || end > u32::MAX as INT {
+        return Err(range_error(ctx, field, end));
+    }
+
+    let step_field = format!("{}_step", field);
+    let step = int_field(ctx, config, &step_field, 1)?;
+
+    if !(1..=u32::MAX as INT).contains(&step) {
+        return Err(range_error(ctx, &step_field, step));
+    }
+
+    Ok(Axis {
+        start: start as u32,
+        end: end as u32,
+        step: step as u32,
+    })
+}
+
+fn parse_timeout(ctx: &NativeCallContext, config: &Map) -> Result<u64, Box<EvalAltResult>> {
+    let timeout = int_field(ctx, config, "timeout", 1000)?;
+
+    if !(1..=MAX_TIMEOUT_MS).contains(&timeout) {
+        return Err(range_error(ctx, "timeout", timeout));
+    }
+
+    Ok(timeout as u64)
+}
+
+/// Arms the glitch and waits for its trigger. Returns whether it came before the timeout.
+fn run(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    glitch: Glitch,
+) -> Result<bool, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxGlitch(glitch);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxGlitch(fired) => Ok(fired),
+        _ => unreachable!(),
+    }
+}
+
+/// Fires a single glitch.
+///
+/// `config` takes `h` and `l` (levels in volts, or `()` for high impedance, 0 V by default),
+/// `delay` and `width` (system clock cycles, at least 3 each), `trigger` (`"software"` by default,
+/// `"sao_rising"`, `"sao_falling"`, `"sof"` or a CAN identifier) and `timeout` (ms, 1000 by
+/// default, at most a minute).
+///
+/// Returns whether the trigger came before the timeout.
+pub(crate) fn repl_glitch_fire(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    config: Map,
+) -> Result<bool, Box<EvalAltResult>> {
+    let delay = parse_axis(ctx, &config, "delay", MIN_GLITCH_DELAY)?;
+    let width = parse_axis(ctx, &config, "width", MIN_GLITCH_WIDTH)?;
+
+    let glitch = Glitch {
+        code: parse_code(ctx, &config)?,
+        delay: delay.start,
+        width: width.start,
+        trigger: parse_trigger(ctx, &config)?,
+        timeout: parse_timeout(ctx, &config)?,
+    };
+
+    run(ctx, call_tx, result_rx, glitch)
+}
+
+/// Sweeps glitches over a delay/width grid.
+///
+/// `config` takes the same fields as `glitch::fire`, except `delay` and `width` may be ranges,
+/// stepped by `delay_step` and `width_step` (1 by default). Every point is tried `repeat` times (1
+/// by default). After each attempt, `check` is called with `#{delay, width}` and returns
+/// `"normal"`, `"reset"` or `"success"`; attempts whose trigger never came are `"missed"`.
+///
+/// Returns the attempts as an Array of `#{delay, width, outcome}`, and prints a tally.
+pub(crate) fn repl_glitch_sweep(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    config: Map,
+    check: FnPtr,
+) -> Result<Array, Box<EvalAltResult>> {
+    let delays = parse_axis(ctx, &config, "delay", MIN_GLITCH_DELAY)?;
+    let widths = parse_axis(ctx, &config, "width", MIN_GLITCH_WIDTH)?;
+    let repeat = int_field(ctx, &config, "repeat", 1)?;
+
+    if repeat <= 0 {
+        return Err(range_error(ctx, "repeat", repeat));
+    }
+
+    let count = delays
+        .len()
+        .saturating_mul(widths.len())
+        .saturating_mul(repeat as usize);
+
+    if count > MAX_ATTEMPTS {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Too many attempts: {}, at most {}", count, MAX_ATTEMPTS),
+            ctx.call_position(),
+        )));
+    }
+
+    let code = parse_code(ctx, &config)?;
+    let trigger = parse_trigger(ctx, &config)?;
+    let timeout = parse_timeout(ctx, &config)?;
+    let mut attempts = Vec::with_capacity(count);
+
+    for (delay, width) in glitch::grid(delays, widths) {
+        for _ in 0..repeat {
+            let glitch = Glitch {
+                code,
+                delay,
+                width,
+                trigger,
+                timeout,
+            };
+
+            let outcome = if run(ctx, call_tx, result_rx, glitch)? {
+                let mut point = Map::new();
+                point.insert("delay".into(), Dynamic::from_int(delay as INT));
+                point.insert("width".into(), Dynamic::from_int(width as INT));
+
+                let outcome: String = check.call_within_context(ctx, (point,))?;
+                Outcome::parse(&outcome).ok_or_else(|| {
+                    Box::new(EvalAltResult::ErrorMismatchDataType(
+                        String::from("[normal, reset, success]"),
+                        outcome.clone(),
+                        ctx.call_position(),
+                    ))
+                })?
+            } else {
+                Outcome::Missed
+            };
+
+            attempts.push(Attempt {
+                delay,
+                width,
+                outcome,
+            });
+        }
+    }
+
+    let tally = Tally::new(&attempts);
+    ctx.engine().eval_expression::<()>(&format!(
+        "print(\"glitch: {} attempts, {} missed, {} normal, {} reset, {} success\")",
+        attempts.len(),
+        tally.missed,
+        tally.normal,
+        tally.reset,
+        tally.success
+    ))?;
+
+    Ok(attempts
+        .iter()
+        .map(|attempt| {
+            let mut ret = Map::new();
+            ret.insert("delay".into(), Dynamic::from_int(attempt.delay as INT));
+            ret.insert("width".into(), Dynamic::from_int(attempt.width as INT));
+            ret.insert("outcome".into(), attempt.outcome.as_str().into());
+
+            ret.into()
+        })
+        .collect())
+}
+
+/// Formats the attempts from `glitch::sweep` as CSV, `delay,width,outcome`.
+pub(crate) fn repl_glitch_table(
+    ctx: &NativeCallContext,
+    attempts: Array,
+) -> Result<String, Box<EvalAltResult>> {
+    let attempts = attempts
+        .into_iter()
+        .map(|attempt| {
+            let attempt = attempt.try_cast::<Map>()?;
+            let delay = attempt.get("delay")?.as_int().ok()?;
+            let width = attempt.get("width")?.as_int().ok()?;
+            let outcome = attempt.get("outcome")?.clone().into_string().ok()?;
+
+            Some(Attempt {
+                delay: u32::try_from(delay).ok()?,
+                width: u32::try_from(width).ok()?,
+                outcome: Outcome::parse(&outcome)?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            Box::new(EvalAltResult::ErrorMismatchDataType(
+                String::from("Array of #{delay, width, outcome}"),
+                String::from("attempts"),
+                ctx.call_position(),
+            ))
+        })?;
+
+    let mut table = String::new();
+    glitch::write_table(&mut table, &attempts);
+
+    Ok(table)
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn!(module, call_tx, result_rx, repl_glitch_fire, "fire", (config: Map));
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_glitch_sweep,
+        "sweep",
+        (config: Map, check: FnPtr)
+    );
+    register_repl_fn_no_rpc!(module, repl_glitch_table, "table", (attempts: Array));
+    engine.register_static_module("glitch", module.into());
+}
@@ -0,0 +1,209 @@
+//! MIL-STD-1553-style word calls, see `apps::mil1553`.
+//!
+//! Words are given as an INT for a data word, or as `#{sync, data}` with `sync` either
+//! `"command"` (also used by status words) or `"data"`.
+
+use crate::{
+    apps::{
+        mil1553::{self, Command, Sync, Word},
+        tx::waveform,
+    },
+    platform::repl::{
+        rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
+        rpc_call,
+        wave::{sample_product, samples, waveform_error},
+    },
+    register_repl_fn, register_repl_fn_no_rpc,
+};
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Map, Module, NativeCallContext, INT};
+
+fn word_error(ctx: &NativeCallContext, word: &Dynamic) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorMismatchDataType(
+        String::from("16 bit INT or #{sync: [command, data], data}"),
+        word.type_name().into(),
+        ctx.call_position(),
+    ))
+}
+
+fn parse_word(ctx: &NativeCallContext, word: &Dynamic) -> Result<Word, Box<EvalAltResult>> {
+    let data = |data: &Dynamic| {
+        data.as_int()
+            .ok()
+            .filter(|data| (0..=0xFFFF).contains(data))
+            .map(|data| data as u16)
+            .ok_or_else(|| word_error(ctx, word))
+    };
+
+    if word.is_int() {
+        return Ok(Word::data(data(word)?));
+    }
+
+    let map = word
+        .clone()
+        .try_cast::<Map>()
+        .ok_or_else(|| word_error(ctx, word))?;
+    let sync = match map
+        .get("sync")
+        .and_then(|sync| sync.clone().into_string().ok())
+        .as_deref()
+    {
+        Some("command") => Sync::Command,
+        Some("data") => Sync::Data,
+        _ => return Err(word_error(ctx, word)),
+    };
+    let data = data(map.get("data").ok_or_else(|| word_error(ctx, word))?)?;
+
+    Ok(Word { sync, data })
+}
+
+fn word_map(word: &Word) -> Map {
+    let sync = match word.sync {
+        Sync::Command => "command",
+        Sync::Data => "data",
+    };
+
+    let mut ret = Map::new();
+    ret.insert("sync".into(), sync.into());
+    ret.insert("data".into(), Dynamic::from_int(word.data as INT));
+
+    ret
+}
+
+/// Encodes words back to back as bipolar injector samples, `width` samples per half bit.
+pub(crate) fn repl_mil1553_wave_width(
+    ctx: &NativeCallContext,
+    words: Array,
+    width: INT,
+) -> Result<Blob, Box<EvalAltResult>> {
+    let words = words
+        .iter()
+        .map(|word| parse_word(ctx, word))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let width = samples(ctx, width)?;
+    sample_product(ctx, words.len().saturating_mul(mil1553::WORD_HALVES), width)?;
+
+    waveform::halves(words.iter().flat_map(|word| word.halves()), width)
+        .map_err(|err| waveform_error(ctx, err))
+}
+
+pub(crate) fn repl_mil1553_wave(
+    ctx: &NativeCallContext,
+    words: Array,
+) -> Result<Blob, Box<EvalAltResult>> {
+    repl_mil1553_wave_width(ctx, words, 1)
+}
+
+/// Builds a command word. `count` is the number of data words (0 for 32) or the mode code.
+pub(crate) fn repl_mil1553_command(
+    ctx: &NativeCallContext,
+    rt: INT,
+    transmit: bool,
+    subaddress: INT,
+    count: INT,
+) -> Result<INT, Box<EvalAltResult>> {
+    for (field, value) in [("rt", rt), ("subaddress", subaddress), ("count", count)] {
+        if !(0..32).contains(&value) {
+            return Err(Box::new(EvalAltResult::ErrorArithmetic(
+                format!("Invalid {}: {}", field, value),
+                ctx.call_position(),
+            )));
+        }
+    }
+
+    let command = Command {
+        rt: rt as u8,
+        transmit,
+        subaddress: subaddress as u8,
+        count: count as u8,
+    };
+
+    Ok(command.to_word() as INT)
+}
+
+/// Splits a command word into `#{rt, transmit, subaddress, count, mode_code}`.
+pub(crate) fn repl_mil1553_parse_command(
+    ctx: &NativeCallContext,
+    word: INT,
+) -> Result<Map, Box<EvalAltResult>> {
+    if !(0..=0xFFFF).contains(&word) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid word: {:#X}", word),
+            ctx.call_position(),
+        )));
+    }
+
+    let command = Command::from_word(word as u16);
+
+    let mut ret = Map::new();
+    ret.insert("rt".into(), Dynamic::from_int(command.rt as INT));
+    ret.insert("transmit".into(), Dynamic::from_bool(command.transmit));
+    ret.insert(
+        "subaddress".into(),
+        Dynamic::from_int(command.subaddress as INT),
+    );
+    ret.insert("count".into(), Dynamic::from_int(command.count as INT));
+    ret.insert(
+        "mode_code".into(),
+        Dynamic::from_bool(command.is_mode_code()),
+    );
+
+    Ok(ret)
+}
+
+/// Returns the words received in "mil1553" mode since the last call, as `#{sync, data}`. Words
+/// with the wrong parity have `error: "parity"`, broken bits are `#{error: "manchester"}`.
+pub(crate) fn repl_mil1553_read(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Array, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxMil1553Read;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxMil1553Read(received) => Ok(received
+            .iter()
+            .map(|result| match result {
+                Ok(word) => word_map(word).into(),
+                Err(mil1553::Error::Parity(word)) => {
+                    let mut ret = word_map(word);
+                    ret.insert("error".into(), "parity".into());
+                    ret.into()
+                }
+                Err(mil1553::Error::Manchester(_)) => {
+                    let mut ret = Map::new();
+                    ret.insert("error".into(), "manchester".into());
+                    ret.into()
+                }
+            })
+            .collect()),
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn_no_rpc!(module, repl_mil1553_wave, "wave", (words: Array));
+    register_repl_fn_no_rpc!(
+        module,
+        repl_mil1553_wave_width,
+        "wave",
+        (words: Array, width: INT)
+    );
+    register_repl_fn_no_rpc!(
+        module,
+        repl_mil1553_command,
+        "command",
+        (rt: INT, transmit: bool, subaddress: INT, count: INT)
+    );
+    register_repl_fn_no_rpc!(module, repl_mil1553_parse_command, "parse_command", (word: INT));
+    register_repl_fn!(module, call_tx, result_rx, repl_mil1553_read, "read", ());
+    engine.register_static_module("mil1553", module.into());
+}
@@ -1,18 +1,26 @@
 pub mod accel;
 pub mod batt;
+pub mod bridge;
 pub mod can;
 pub mod common;
 pub mod console;
+pub mod dbc;
 pub mod display;
+pub mod dmx;
+pub mod fuzz;
+pub mod glitch;
 pub mod input;
 pub mod led;
+pub mod mil1553;
 pub mod nmea2000;
 pub mod rpc;
 pub mod rx;
 pub mod sao;
+pub mod seatalk;
 pub mod sys;
 pub mod trx;
 pub mod tx;
+pub mod wave;
 
 use crate::platform::repl::rpc::{
     CallId, RpcCall, RpcCallSender, RpcError, RpcResult, RpcResultReceiver, ToEndpoint,
@@ -260,8 +268,16 @@ pub fn make_engine(call_tx: RpcCallSender, result_rx: RpcResultReceiver) -> Engi
     batt::register_functions(&mut engine, call_tx, result_rx);
     trx::register_functions(&mut engine, call_tx, result_rx);
     tx::register_functions(&mut engine, call_tx, result_rx);
+    wave::register_functions(&mut engine, call_tx, result_rx);
     rx::register_functions(&mut engine, call_tx, result_rx);
     can::register_functions(&mut engine, call_tx, result_rx);
+    dbc::register_functions(&mut engine, call_tx, result_rx);
+    fuzz::register_functions(&mut engine, call_tx, result_rx);
+    glitch::register_functions(&mut engine, call_tx, result_rx);
+    mil1553::register_functions(&mut engine, call_tx, result_rx);
+    dmx::register_functions(&mut engine, call_tx, result_rx);
+    bridge::register_functions(&mut engine, call_tx, result_rx);
+    seatalk::register_functions(&mut engine, call_tx, result_rx);
     nmea2000::register_functions(&mut engine, call_tx, result_rx);
 
     engine
@@ -2,8 +2,23 @@
 
 use crate::{
     apps::{
-        rx::RxMode,
-        tx::{TxMode, TxWords},
+        dmx::Universe,
+        fuzz::{FuzzConfig, FuzzReport},
+        glitch::{Glitch, Trigger},
+        mil1553::{self, Word},
+        react::{self, ReactReport},
+        rx::{
+            analyze::Report,
+            raw::{CaptureConfig, ExportFormat},
+            RxMode,
+        },
+        seatalk::{self, Received},
+        tx::{
+            can_pio::TxOutcome,
+            replay::ReplayReport,
+            schedule::{EntryConfig, EntryStats},
+            TxMode, TxWords,
+        },
     },
     platform::{
         bq25895,
@@ -26,9 +41,10 @@ use crate::{
         },
     },
 };
-use alloc::{borrow::ToOwned, string::String, vec};
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
 use defmt::{debug, warn, Format};
 use embassy_embedded_hal::shared_bus::{asynch::i2c::I2cDevice, I2cDeviceError};
+use embassy_futures::select::{select3, Either3};
 use embassy_rp::{
     i2c,
     peripherals::{I2C0, TRNG},
@@ -224,6 +240,7 @@ pub enum RpcEndpoint {
     DisplayClear,
     DisplayFlush,
     DisplayReset,
+    DisplayWaveform,
     ConsoleWrite,
     LedSet,
     AccelRead,
@@ -259,9 +276,44 @@ pub enum RpcEndpoint {
     TxGetMode,
     TxSetMode,
     TxSend,
+    TxSetRetries,
+    TxArmOverwrite,
+    TxDisarmOverwrite,
+    TxReplay,
+    TxReplayStop,
+    TxReplayRunning,
+    TxFuzz,
+    TxFuzzStop,
+    TxFuzzRunning,
+    TxLoop,
+    TxStream,
+    TxStop,
+    TxGlitch,
+    TxReact,
+    TxReactStop,
+    TxSchedule,
+    TxScheduleEnable,
+    TxUnschedule,
+    TxScheduleStats,
     RxEnableDisable,
     RxSetMode,
     RxGetMode,
+    RxSetMatch,
+    RxSetAck,
+    RxSetBaud,
+    RxGetBaud,
+    RxSetBitTiming,
+    RxAutobaud,
+    RxCapture,
+    RxExport,
+    RxAnalyze,
+    RxPcapStart,
+    RxPcapStop,
+    RxCandumpStart,
+    RxCandumpStop,
+    RxMil1553Read,
+    RxDmxRead,
+    RxSeatalkRead,
 }
 
 pub trait AppControl {
@@ -304,6 +356,7 @@ pub enum RpcCall {
     DisplayClear,
     DisplayFlush,
     DisplayReset,
+    DisplayWaveform(Blob),
     ConsoleWrite(String),
     LedSet(usize, RGB8),
     AccelRead,
@@ -339,9 +392,46 @@ pub enum RpcCall {
     TxGetMode,
     TxSetMode(TxMode),
     TxSend(TxWords),
+    TxSetRetries(u8),
+    TxArmOverwrite(u32, u32, u32),
+    TxDisarmOverwrite,
+    /// Frames, each with its offset in µs from the start of the replay.
+    TxReplay(Vec<(u64, TxWords)>),
+    TxReplayStop,
+    TxReplayRunning,
+    TxFuzz(FuzzConfig),
+    TxFuzzStop,
+    TxFuzzRunning,
+    TxLoop(Vec<u8>),
+    TxStream,
+    TxStop,
+    TxGlitch(Glitch),
+    TxReact(react::Trigger, TxWords),
+    TxReactStop,
+    TxSchedule(EntryConfig),
+    TxScheduleEnable(u32, bool),
+    TxUnschedule(u32),
+    TxScheduleStats,
     RxEnableDisable(bool),
     RxSetMode(RxMode),
     RxGetMode,
+    RxSetAck(Option<(u32, u32)>),
+    RxSetBaud(u32),
+    RxGetBaud,
+    RxSetBitTiming(u8, u8),
+    RxAutobaud,
+    /// Capture configuration and trigger timeout in ms.
+    RxCapture(CaptureConfig, u64),
+    RxExport(ExportFormat, usize, usize),
+    /// Capture duration in ms.
+    RxAnalyze(u64),
+    RxPcapStart,
+    RxPcapStop,
+    RxCandumpStart,
+    RxCandumpStop,
+    RxMil1553Read,
+    RxDmxRead,
+    RxSeatalkRead,
 }
 
 impl Format for RpcCall {
@@ -367,6 +457,7 @@ impl ToEndpoint for RpcCall {
             RpcCall::DisplayClear => RpcEndpoint::DisplayClear,
             RpcCall::DisplayFlush => RpcEndpoint::DisplayFlush,
             RpcCall::DisplayReset => RpcEndpoint::DisplayReset,
+            RpcCall::DisplayWaveform(_) => RpcEndpoint::DisplayWaveform,
             RpcCall::ConsoleWrite(_) => RpcEndpoint::ConsoleWrite,
             RpcCall::LedSet(_, _) => RpcEndpoint::LedSet,
             RpcCall::AccelRead => RpcEndpoint::AccelRead,
@@ -404,9 +495,43 @@ impl ToEndpoint for RpcCall {
             RpcCall::TxGetMode => RpcEndpoint::TxGetMode,
             RpcCall::TxSetMode(_) => RpcEndpoint::TxSetMode,
             RpcCall::TxSend(_) => RpcEndpoint::TxSend,
+            RpcCall::TxSetRetries(_) => RpcEndpoint::TxSetRetries,
+            RpcCall::TxArmOverwrite(_, _, _) => RpcEndpoint::TxArmOverwrite,
+            RpcCall::TxDisarmOverwrite => RpcEndpoint::TxDisarmOverwrite,
+            RpcCall::TxReplay(_) => RpcEndpoint::TxReplay,
+            RpcCall::TxReplayStop => RpcEndpoint::TxReplayStop,
+            RpcCall::TxReplayRunning => RpcEndpoint::TxReplayRunning,
+            RpcCall::TxFuzz(_) => RpcEndpoint::TxFuzz,
+            RpcCall::TxFuzzStop => RpcEndpoint::TxFuzzStop,
+            RpcCall::TxFuzzRunning => RpcEndpoint::TxFuzzRunning,
+            RpcCall::TxLoop(_) => RpcEndpoint::TxLoop,
+            RpcCall::TxStream => RpcEndpoint::TxStream,
+            RpcCall::TxStop => RpcEndpoint::TxStop,
+            RpcCall::TxGlitch(_) => RpcEndpoint::TxGlitch,
+            RpcCall::TxReact(_, _) => RpcEndpoint::TxReact,
+            RpcCall::TxReactStop => RpcEndpoint::TxReactStop,
+            RpcCall::TxSchedule(_) => RpcEndpoint::TxSchedule,
+            RpcCall::TxScheduleEnable(_, _) => RpcEndpoint::TxScheduleEnable,
+            RpcCall::TxUnschedule(_) => RpcEndpoint::TxUnschedule,
+            RpcCall::TxScheduleStats => RpcEndpoint::TxScheduleStats,
             RpcCall::RxEnableDisable(_) => RpcEndpoint::RxEnableDisable,
             RpcCall::RxSetMode(_) => RpcEndpoint::RxSetMode,
             RpcCall::RxGetMode => RpcEndpoint::RxGetMode,
+            RpcCall::RxSetAck(_) => RpcEndpoint::RxSetAck,
+            RpcCall::RxSetBaud(_) => RpcEndpoint::RxSetBaud,
+            RpcCall::RxGetBaud => RpcEndpoint::RxGetBaud,
+            RpcCall::RxSetBitTiming(_, _) => RpcEndpoint::RxSetBitTiming,
+            RpcCall::RxAutobaud => RpcEndpoint::RxAutobaud,
+            RpcCall::RxCapture(_, _) => RpcEndpoint::RxCapture,
+            RpcCall::RxExport(..) => RpcEndpoint::RxExport,
+            RpcCall::RxAnalyze(_) => RpcEndpoint::RxAnalyze,
+            RpcCall::RxPcapStart => RpcEndpoint::RxPcapStart,
+            RpcCall::RxPcapStop => RpcEndpoint::RxPcapStop,
+            RpcCall::RxCandumpStart => RpcEndpoint::RxCandumpStart,
+            RpcCall::RxCandumpStop => RpcEndpoint::RxCandumpStop,
+            RpcCall::RxMil1553Read => RpcEndpoint::RxMil1553Read,
+            RpcCall::RxDmxRead => RpcEndpoint::RxDmxRead,
+            RpcCall::RxSeatalkRead => RpcEndpoint::RxSeatalkRead,
         }
     }
 }
@@ -433,6 +558,7 @@ pub enum RpcResult {
     DisplayClear,
     DisplayFlush,
     DisplayReset,
+    DisplayWaveform,
     ConsoleWrite,
     LedSet,
     AccelRead(Data),
@@ -467,10 +593,48 @@ pub enum RpcResult {
     TxSetBaud,
     TxGetMode(TxMode),
     TxSetMode,
-    TxSend,
+    TxSend(TxOutcome),
+    TxSetRetries,
+    TxArmOverwrite,
+    TxDisarmOverwrite,
+    /// Frames sent and failed.
+    TxReplay,
+    TxReplayStop(ReplayReport),
+    TxReplayRunning(bool),
+    TxFuzz,
+    TxFuzzStop(FuzzReport),
+    TxFuzzRunning(bool),
+    TxLoop,
+    TxStream,
+    /// Samples streamed and underruns.
+    TxStop(u64, u32),
+    /// Whether the trigger came before the timeout.
+    TxGlitch(bool),
+    TxReact,
+    TxReactStop(ReactReport),
+    TxSchedule,
+    TxScheduleEnable,
+    TxUnschedule,
+    TxScheduleStats(Vec<EntryStats>),
     RxEnableDisable,
     RxSetMode,
     RxGetMode(RxMode),
+    RxSetMatch,
+    RxSetAck,
+    RxSetBaud,
+    RxGetBaud(u32),
+    RxSetBitTiming,
+    RxAutobaud(RxMode, u32),
+    RxCapture(usize),
+    RxExport(Blob),
+    RxAnalyze(Report),
+    RxPcapStart,
+    RxPcapStop(Blob),
+    RxCandumpStart,
+    RxCandumpStop(String),
+    RxMil1553Read(Vec<Result<Word, mil1553::Error>>),
+    RxDmxRead(Universe),
+    RxSeatalkRead(Vec<Result<Received, seatalk::Error>>),
 }
 
 impl Format for RpcResult {
@@ -496,6 +660,7 @@ impl ToEndpoint for RpcResult {
             RpcResult::DisplayClear => RpcEndpoint::DisplayClear,
             RpcResult::DisplayFlush => RpcEndpoint::DisplayFlush,
             RpcResult::DisplayReset => RpcEndpoint::DisplayReset,
+            RpcResult::DisplayWaveform => RpcEndpoint::DisplayWaveform,
             RpcResult::ConsoleWrite => RpcEndpoint::ConsoleWrite,
             RpcResult::LedSet => RpcEndpoint::LedSet,
             RpcResult::AccelRead(_) => RpcEndpoint::AccelRead,
@@ -530,10 +695,45 @@ impl ToEndpoint for RpcResult {
             RpcResult::TxSetBaud => RpcEndpoint::TxSetBaud,
             RpcResult::TxGetMode(_) => RpcEndpoint::TxGetMode,
             RpcResult::TxSetMode => RpcEndpoint::TxSetMode,
-            RpcResult::TxSend => RpcEndpoint::TxSend,
+            RpcResult::TxSend(_) => RpcEndpoint::TxSend,
+            RpcResult::TxSetRetries => RpcEndpoint::TxSetRetries,
+            RpcResult::TxArmOverwrite => RpcEndpoint::TxArmOverwrite,
+            RpcResult::TxDisarmOverwrite => RpcEndpoint::TxDisarmOverwrite,
+            RpcResult::TxReplay => RpcEndpoint::TxReplay,
+            RpcResult::TxReplayStop(_) => RpcEndpoint::TxReplayStop,
+            RpcResult::TxReplayRunning(_) => RpcEndpoint::TxReplayRunning,
+            RpcResult::TxFuzz => RpcEndpoint::TxFuzz,
+            RpcResult::TxFuzzStop(_) => RpcEndpoint::TxFuzzStop,
+            RpcResult::TxFuzzRunning(_) => RpcEndpoint::TxFuzzRunning,
+            RpcResult::TxLoop => RpcEndpoint::TxLoop,
+            RpcResult::TxStream => RpcEndpoint::TxStream,
+            RpcResult::TxStop(_, _) => RpcEndpoint::TxStop,
+            RpcResult::TxGlitch(_) => RpcEndpoint::TxGlitch,
+            RpcResult::TxReact => RpcEndpoint::TxReact,
+            RpcResult::TxReactStop(_) => RpcEndpoint::TxReactStop,
+            RpcResult::TxSchedule => RpcEndpoint::TxSchedule,
+            RpcResult::TxScheduleEnable => RpcEndpoint::TxScheduleEnable,
+            RpcResult::TxUnschedule => RpcEndpoint::TxUnschedule,
+            RpcResult::TxScheduleStats(_) => RpcEndpoint::TxScheduleStats,
             RpcResult::RxEnableDisable => RpcEndpoint::RxEnableDisable,
             RpcResult::RxSetMode => RpcEndpoint::RxSetMode,
             RpcResult::RxGetMode(_) => RpcEndpoint::RxGetMode,
+            RpcResult::RxSetMatch => RpcEndpoint::RxSetMatch,
+            RpcResult::RxSetAck => RpcEndpoint::RxSetAck,
+            RpcResult::RxSetBaud => RpcEndpoint::RxSetBaud,
+            RpcResult::RxGetBaud(_) => RpcEndpoint::RxGetBaud,
+            RpcResult::RxSetBitTiming => RpcEndpoint::RxSetBitTiming,
+            RpcResult::RxAutobaud(_, _) => RpcEndpoint::RxAutobaud,
+            RpcResult::RxCapture(_) => RpcEndpoint::RxCapture,
+            RpcResult::RxExport(_) => RpcEndpoint::RxExport,
+            RpcResult::RxAnalyze(_) => RpcEndpoint::RxAnalyze,
+            RpcResult::RxPcapStart => RpcEndpoint::RxPcapStart,
+            RpcResult::RxPcapStop(_) => RpcEndpoint::RxPcapStop,
+            RpcResult::RxCandumpStart => RpcEndpoint::RxCandumpStart,
+            RpcResult::RxCandumpStop(_) => RpcEndpoint::RxCandumpStop,
+            RpcResult::RxMil1553Read(_) => RpcEndpoint::RxMil1553Read,
+            RpcResult::RxDmxRead(_) => RpcEndpoint::RxDmxRead,
+            RpcResult::RxSeatalkRead(_) => RpcEndpoint::RxSeatalkRead,
         }
     }
 }
@@ -625,6 +825,10 @@ pub async fn repl_rpc(
     app_ack_rx: AppAckReceiver,
     call_rx: RpcCallReceiver,
     result_tx: RpcResultSender,
+    usb_call_rx: RpcCallReceiver,
+    usb_result_tx: RpcResultSender,
+    gs_usb_call_rx: RpcCallReceiver,
+    gs_usb_result_tx: RpcResultSender,
     display_tx: DisplaySender,
     led_tx: LedSender,
     tx_tx: TxSender,
@@ -665,7 +869,18 @@ pub async fn repl_rpc(
             crate::HEAP.free(),
             call_count
         );
-        let call = call_rx.receive().await;
+        // Answer each call on the channel of the client that made it.
+        let (call, result_tx) = match select3(
+            call_rx.receive(),
+            usb_call_rx.receive(),
+            gs_usb_call_rx.receive(),
+        )
+        .await
+        {
+            Either3::First(call) => (call, result_tx),
+            Either3::Second(call) => (call, usb_result_tx),
+            Either3::Third(call) => (call, gs_usb_result_tx),
+        };
         call_count += 1;
         warn!("Got RPC Call {:?}!", call);
         debug!(
@@ -815,6 +1030,12 @@ pub async fn repl_rpc(
                 let result = (call_count, outcome);
                 result_tx.send(result).await;
             }
+            RpcCall::DisplayWaveform(wave) => {
+                display_tx.send(DisplayCommand::Waveform(wave)).await;
+                let outcome = Ok(RpcResult::DisplayWaveform);
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
             RpcCall::ConsoleWrite(text) => {
                 display_tx.send(DisplayCommand::ConsoleWrite(text)).await;
                 let outcome = Ok(RpcResult::ConsoleWrite);
@@ -1132,6 +1353,152 @@ pub async fn repl_rpc(
                 let result = (call_count, outcome);
                 result_tx.send(result).await;
             }
+            RpcCall::TxReplay(frames) => {
+                tx_tx.send(TxCommand::Replay(frames)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxReplayStop => {
+                tx_tx.send(TxCommand::ReplayStop).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxReplayRunning => {
+                tx_tx.send(TxCommand::ReplayRunning).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxFuzz(config) => {
+                tx_tx.send(TxCommand::Fuzz(config)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxFuzzStop => {
+                tx_tx.send(TxCommand::FuzzStop).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxFuzzRunning => {
+                tx_tx.send(TxCommand::FuzzRunning).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxLoop(samples) => {
+                tx_tx.send(TxCommand::Loop(samples)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxStream => {
+                tx_tx.send(TxCommand::Stream).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxStop => {
+                tx_tx.send(TxCommand::Stop).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxReact(trigger, words) => {
+                tx_tx.send(TxCommand::React(trigger, words)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxReactStop => {
+                tx_tx.send(TxCommand::ReactStop).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxSchedule(config) => {
+                tx_tx.send(TxCommand::Schedule(config)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxScheduleEnable(arb_id, enabled) => {
+                tx_tx.send(TxCommand::ScheduleEnable(arb_id, enabled)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxUnschedule(arb_id) => {
+                tx_tx.send(TxCommand::Unschedule(arb_id)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxScheduleStats => {
+                tx_tx.send(TxCommand::ScheduleStats).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxGlitch(glitch) => {
+                let outcome = if let Trigger::Can(pattern) = glitch.trigger {
+                    // NOTE: The matcher lives on the receiver, so it has to be armed first.
+                    rx_tx.send(RxCommand::SetMatch(Some(pattern))).await;
+                    let outcome = match rx_ack.wait().await {
+                        Ok(_) => {
+                            tx_tx.send(TxCommand::Glitch(glitch)).await;
+                            tx_ack.wait().await
+                        }
+                        Err(err) => Err(err),
+                    };
+                    rx_tx.send(RxCommand::SetMatch(None)).await;
+                    let _ = rx_ack.wait().await;
+                    outcome
+                } else {
+                    tx_tx.send(TxCommand::Glitch(glitch)).await;
+                    tx_ack.wait().await
+                };
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxSetRetries(retries) => {
+                tx_tx.send(TxCommand::SetRetries(retries)).await;
+                let outcome = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxArmOverwrite(pattern, delay, width) => {
+                // NOTE: The matcher lives on the receiver, so it has to be armed first.
+                rx_tx.send(RxCommand::SetMatch(Some(pattern))).await;
+                let outcome = match rx_ack.wait().await {
+                    Ok(_) => {
+                        tx_tx.send(TxCommand::ArmOverwrite(delay, width)).await;
+                        let outcome = tx_ack.wait().await;
+
+                        // Nothing to trigger, so the matcher should not keep raising it.
+                        if outcome.is_err() {
+                            rx_tx.send(RxCommand::SetMatch(None)).await;
+                            let _ = rx_ack.wait().await;
+                        }
+
+                        outcome
+                    }
+                    Err(err) => Err(err),
+                };
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::TxDisarmOverwrite => {
+                tx_tx.send(TxCommand::DisarmOverwrite).await;
+                let outcome = tx_ack.wait().await;
+                rx_tx.send(RxCommand::SetMatch(None)).await;
+                let _ = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
             RpcCall::RxEnableDisable(enabled) => {
                 rx_tx.send(RxCommand::EnableDisable(enabled)).await;
                 let outcome = rx_ack.wait().await;
@@ -1150,6 +1517,111 @@ pub async fn repl_rpc(
                 let result = (call_count, outcome);
                 result_tx.send(result).await;
             }
+            RpcCall::RxSetBaud(baud) => {
+                rx_tx.send(RxCommand::SetBaud(baud)).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxGetBaud => {
+                rx_tx.send(RxCommand::GetBaud).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxSetBitTiming(sample_point, sjw) => {
+                rx_tx.send(RxCommand::SetBitTiming(sample_point, sjw)).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxAutobaud => {
+                rx_tx.send(RxCommand::Autobaud).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxCapture(config, timeout_ms) => {
+                rx_tx.send(RxCommand::Capture(config, timeout_ms)).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxExport(format, from, count) => {
+                rx_tx.send(RxCommand::Export(format, from, count)).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxAnalyze(duration_ms) => {
+                rx_tx.send(RxCommand::Analyze(duration_ms)).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxPcapStart => {
+                rx_tx.send(RxCommand::PcapStart).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxPcapStop => {
+                rx_tx.send(RxCommand::PcapStop).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxCandumpStart => {
+                rx_tx.send(RxCommand::CandumpStart).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxCandumpStop => {
+                rx_tx.send(RxCommand::CandumpStop).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxMil1553Read => {
+                rx_tx.send(RxCommand::Mil1553Read).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxDmxRead => {
+                rx_tx.send(RxCommand::DmxRead).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxSeatalkRead => {
+                rx_tx.send(RxCommand::SeatalkRead).await;
+                let outcome = rx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxSetAck(Some(filter)) => {
+                // NOTE: The ACK is driven by the transmitter, so it has to be armed first.
+                tx_tx.send(TxCommand::ArmAck).await;
+                let outcome = match tx_ack.wait().await {
+                    Ok(_) => {
+                        rx_tx.send(RxCommand::SetAck(Some(filter))).await;
+                        rx_ack.wait().await
+                    }
+                    Err(err) => Err(err),
+                };
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
+            RpcCall::RxSetAck(None) => {
+                rx_tx.send(RxCommand::SetAck(None)).await;
+                let outcome = rx_ack.wait().await;
+                tx_tx.send(TxCommand::DisarmOverwrite).await;
+                let _ = tx_ack.wait().await;
+                let result = (call_count, outcome);
+                result_tx.send(result).await;
+            }
         }
 
         debug!(
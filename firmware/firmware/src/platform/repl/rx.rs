@@ -1,16 +1,23 @@
 //! Differential receiver RPC calls
 
 use crate::{
-    apps::rx::RxMode,
+    apps::rx::{
+        analyze::Encoding,
+        raw::{CaptureConfig, ExportFormat, Trigger},
+        RxMode,
+    },
     platform::repl::{
         rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
         rpc_call,
     },
     register_repl_fn,
 };
-use alloc::{borrow::ToOwned, boxed::Box, string::String};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
-use rhai::{Engine, EvalAltResult, ImmutableString, Module, NativeCallContext};
+use rhai::{
+    Array, Blob, Dynamic, Engine, EvalAltResult, ImmutableString, Map, Module, NativeCallContext,
+    FLOAT, INT,
+};
 
 // TODO: Move all channels to common.rs
 #[derive(Debug, Clone)]
@@ -18,6 +25,24 @@ pub enum RxCommand {
     EnableDisable(bool),
     SetMode(RxMode),
     GetMode,
+    SetMatch(Option<u32>),
+    SetAck(Option<(u32, u32)>),
+    SetBaud(u32),
+    GetBaud,
+    SetBitTiming(u8, u8),
+    Autobaud,
+    /// Capture configuration and trigger timeout in ms.
+    Capture(CaptureConfig, u64),
+    Export(ExportFormat, usize, usize),
+    /// Capture duration in ms.
+    Analyze(u64),
+    PcapStart,
+    PcapStop,
+    CandumpStart,
+    CandumpStop,
+    Mil1553Read,
+    DmxRead,
+    SeatalkRead,
 }
 
 pub const RX_MTU: usize = 1;
@@ -74,9 +99,13 @@ pub(crate) fn repl_rx_set_mode(
         "nmea0183" => RxMode::Nmea0183,
         "modbus" => RxMode::Modbus,
         "can" => RxMode::Can,
+        "raw" => RxMode::Raw,
+        "mil1553" => RxMode::Mil1553,
+        "dmx" => RxMode::Dmx,
+        "seatalk" => RxMode::Seatalk,
         _ => {
             return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
-                String::from("[nmea0183, modbus, can]"),
+                String::from("[nmea0183, modbus, can, raw, mil1553, dmx, seatalk]"),
                 mode.to_owned(),
                 ctx.call_position(),
             )))
@@ -102,6 +131,10 @@ pub(crate) fn repl_rx_get_mode(
             RxMode::Nmea0183 => "nmea0183",
             RxMode::Modbus => "modbus",
             RxMode::Can => "can",
+            RxMode::Raw => "raw",
+            RxMode::Mil1553 => "mil1553",
+            RxMode::Dmx => "dmx",
+            RxMode::Seatalk => "seatalk",
         },
         _ => {
             unreachable!()
@@ -111,6 +144,398 @@ pub(crate) fn repl_rx_get_mode(
     Ok(ImmutableString::from(mode))
 }
 
+pub(crate) fn repl_rx_set_baud(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    baud: INT,
+) -> Result<(), Box<EvalAltResult>> {
+    if baud <= 0 || baud > u32::MAX as INT {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid baud: {}", baud),
+            ctx.call_position(),
+        )));
+    }
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxSetBaud(baud as u32);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_rx_get_baud(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<INT, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxGetBaud;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxGetBaud(baud) => Ok(baud as INT),
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+pub(crate) fn repl_rx_set_bit_timing(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    sample_point: INT,
+    sjw: INT,
+) -> Result<(), Box<EvalAltResult>> {
+    if !(0..=100).contains(&sample_point) || !(0..=100).contains(&sjw) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            String::from("sample_point and sjw are percentages of the bit time"),
+            ctx.call_position(),
+        )));
+    }
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxSetBitTiming(sample_point as u8, sjw as u8);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_rx_autobaud(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Map, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxAutobaud;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxAutobaud(mode, baud) => {
+            let mode = match mode {
+                RxMode::Nmea0183 => "nmea0183",
+                RxMode::Modbus => "modbus",
+                RxMode::Can => "can",
+                RxMode::Raw => "raw",
+                RxMode::Mil1553 => "mil1553",
+                RxMode::Dmx => "dmx",
+                RxMode::Seatalk => "seatalk",
+            };
+            let mut ret = Map::new();
+            ret.insert("mode".into(), mode.into());
+            ret.insert("baud".into(), Dynamic::from_int(baud as INT));
+
+            Ok(ret)
+        }
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+fn capture(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    rate: INT,
+    depth: INT,
+    pre_trigger: INT,
+    trigger: Trigger,
+    timeout_secs: FLOAT,
+) -> Result<INT, Box<EvalAltResult>> {
+    if rate <= 0 || rate > u32::MAX as INT || depth <= 0 || pre_trigger < 0 || timeout_secs < 0.0 {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            String::from(
+                "rate and depth must be positive, pre_trigger and timeout_secs not negative",
+            ),
+            ctx.call_position(),
+        )));
+    }
+
+    let config = CaptureConfig {
+        rate: rate as u32,
+        depth: depth as usize,
+        pre_trigger: pre_trigger as usize,
+        trigger,
+    };
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxCapture(config, (timeout_secs * 1000.0) as u64);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxCapture(len) => Ok(len as INT),
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+pub(crate) fn repl_rx_capture(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    rate: INT,
+    depth: INT,
+    pre_trigger: INT,
+    trigger: String,
+    timeout_secs: FLOAT,
+) -> Result<INT, Box<EvalAltResult>> {
+    let trigger = match trigger.to_lowercase().as_str() {
+        "immediate" => Trigger::Immediate,
+        "rising" => Trigger::Rising,
+        "falling" => Trigger::Falling,
+        "edge" => Trigger::Edge,
+        _ => {
+            return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+                String::from("[immediate, rising, falling, edge]"),
+                trigger.to_owned(),
+                ctx.call_position(),
+            )))
+        }
+    };
+
+    capture(
+        ctx,
+        call_tx,
+        result_rx,
+        rate,
+        depth,
+        pre_trigger,
+        trigger,
+        timeout_secs,
+    )
+}
+
+pub(crate) fn repl_rx_capture_pattern(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    rate: INT,
+    depth: INT,
+    pre_trigger: INT,
+    pattern: INT,
+    mask: INT,
+    timeout_secs: FLOAT,
+) -> Result<INT, Box<EvalAltResult>> {
+    if !(0..=u32::MAX as INT).contains(&pattern) || !(0..=u32::MAX as INT).contains(&mask) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            String::from("pattern and mask must fit in 32 bits"),
+            ctx.call_position(),
+        )));
+    }
+
+    let trigger = Trigger::Pattern {
+        pattern: pattern as u32,
+        mask: mask as u32,
+    };
+
+    capture(
+        ctx,
+        call_tx,
+        result_rx,
+        rate,
+        depth,
+        pre_trigger,
+        trigger,
+        timeout_secs,
+    )
+}
+
+pub(crate) fn repl_rx_export(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    format: String,
+) -> Result<Blob, Box<EvalAltResult>> {
+    repl_rx_export_chunk(ctx, call_tx, result_rx, format, 0, INT::MAX)
+}
+
+pub(crate) fn repl_rx_export_chunk(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    format: String,
+    from: INT,
+    count: INT,
+) -> Result<Blob, Box<EvalAltResult>> {
+    if from < 0 || count < 0 {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            String::from("from and count must not be negative"),
+            ctx.call_position(),
+        )));
+    }
+
+    let format = match format.to_lowercase().as_str() {
+        "vcd" => ExportFormat::Vcd,
+        "sigrok" => ExportFormat::Sigrok,
+        _ => {
+            return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+                String::from("[vcd, sigrok]"),
+                format.to_owned(),
+                ctx.call_position(),
+            )))
+        }
+    };
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let from = usize::try_from(from).unwrap_or(usize::MAX);
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    let call = RpcCall::RxExport(format, from, count);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxExport(data) => Ok(data),
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+pub(crate) fn repl_rx_analyze(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    duration_secs: FLOAT,
+) -> Result<Map, Box<EvalAltResult>> {
+    if duration_secs <= 0.0 {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid duration: {}", duration_secs),
+            ctx.call_position(),
+        )));
+    }
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxAnalyze((duration_secs * 1000.0) as u64);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxAnalyze(report) => {
+            let encoding = match report.encoding {
+                Encoding::Unknown => "unknown",
+                Encoding::Uart => "uart",
+                Encoding::Can => "can",
+                Encoding::Manchester => "manchester",
+                Encoding::Pwm => "pwm",
+            };
+            let mut histogram = Array::new();
+
+            for bin in report.histogram {
+                let mut entry = Map::new();
+                entry.insert("width_ns".into(), Dynamic::from_int(bin.width as INT));
+                entry.insert("count".into(), Dynamic::from_int(bin.count as INT));
+                histogram.push(entry.into());
+            }
+
+            let mut ret = Map::new();
+            ret.insert("edges".into(), Dynamic::from_int(report.edges as INT));
+            ret.insert("encoding".into(), encoding.into());
+            ret.insert("bit_rate".into(), Dynamic::from_int(report.bit_rate as INT));
+            ret.insert("unit_ns".into(), Dynamic::from_int(report.unit as INT));
+            ret.insert("idle_high".into(), report.idle_high.into());
+            ret.insert("histogram".into(), histogram.into());
+
+            Ok(ret)
+        }
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+pub(crate) fn repl_rx_pcap_start(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxPcapStart;
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_rx_pcap_stop(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Blob, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxPcapStop;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxPcapStop(data) => Ok(data),
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+pub(crate) fn repl_rx_candump_start(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxCandumpStart;
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_rx_candump_stop(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<ImmutableString, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxCandumpStop;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxCandumpStop(log) => Ok(ImmutableString::from(log)),
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+pub(crate) fn repl_rx_set_ack(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    enabled: bool,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxSetAck(enabled.then_some((0, 0)));
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_rx_set_ack_filter(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    arb_id: INT,
+    mask: INT,
+) -> Result<(), Box<EvalAltResult>> {
+    if !(0..1 << 29).contains(&arb_id) || !(0..1 << 29).contains(&mask) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            String::from("arb_id and mask must fit in 29 bits"),
+            ctx.call_position(),
+        )));
+    }
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxSetAck(Some((arb_id as u32, mask as u32)));
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
 pub(crate) fn register_functions(
     engine: &mut Engine,
     call_tx: RpcCallSender,
@@ -130,6 +555,112 @@ pub(crate) fn register_functions(
         (mode: String)
     );
     register_repl_fn!(module, call_tx, result_rx, repl_rx_get_mode, "get_mode", ());
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_set_baud,
+        "set_baud",
+        (baud: INT)
+    );
+    register_repl_fn!(module, call_tx, result_rx, repl_rx_get_baud, "get_baud", ());
+    register_repl_fn!(module, call_tx, result_rx, repl_rx_autobaud, "autobaud", ());
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_set_bit_timing,
+        "set_bit_timing",
+        (sample_point: INT, sjw: INT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_set_ack,
+        "set_ack",
+        (enabled: bool)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_set_ack_filter,
+        "set_ack",
+        (arb_id: INT, mask: INT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_capture,
+        "capture",
+        (rate: INT, depth: INT, pre_trigger: INT, trigger: String, timeout_secs: FLOAT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_capture_pattern,
+        "capture",
+        (rate: INT, depth: INT, pre_trigger: INT, pattern: INT, mask: INT, timeout_secs: FLOAT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_export,
+        "export",
+        (format: String)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_export_chunk,
+        "export",
+        (format: String, from: INT, count: INT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_analyze,
+        "analyze",
+        (duration_secs: FLOAT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_pcap_start,
+        "pcap_start",
+        ()
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_pcap_stop,
+        "pcap_stop",
+        ()
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_candump_start,
+        "candump_start",
+        ()
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_rx_candump_stop,
+        "candump_stop",
+        ()
+    );
 
     engine.register_static_module("rx", module.into());
 }
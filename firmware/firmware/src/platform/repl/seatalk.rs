@@ -0,0 +1,124 @@
+//! SeaTalk1 calls, see `apps::seatalk`.
+
+use crate::{
+    apps::seatalk::{self, AutopilotMode, Datagram, Received},
+    platform::repl::{
+        rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
+        rpc_call,
+    },
+    register_repl_fn,
+};
+use alloc::{boxed::Box, format};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Module, NativeCallContext, FLOAT, INT};
+
+fn datagram_map(received: &Received) -> Map {
+    let mut ret = Map::new();
+
+    let kind = match Datagram::decode(&received.data) {
+        Some(Datagram::Depth(depth)) => {
+            ret.insert(
+                "depth_ft".into(),
+                Dynamic::from_float(depth as FLOAT / 10.0),
+            );
+            "depth"
+        }
+        Some(Datagram::Speed(speed)) => {
+            ret.insert(
+                "speed_kn".into(),
+                Dynamic::from_float(speed as FLOAT / 100.0),
+            );
+            "speed"
+        }
+        Some(Datagram::WindAngle(angle)) => {
+            ret.insert("angle".into(), Dynamic::from_float(angle as FLOAT / 2.0));
+            "wind_angle"
+        }
+        Some(Datagram::WindSpeed(speed)) => {
+            ret.insert(
+                "speed_kn".into(),
+                Dynamic::from_float(speed as FLOAT / 10.0),
+            );
+            "wind_speed"
+        }
+        Some(Datagram::Heading { heading, rudder }) => {
+            ret.insert("heading".into(), Dynamic::from_int(heading as INT));
+
+            if let Some(rudder) = rudder {
+                ret.insert("rudder".into(), Dynamic::from_int(rudder as INT));
+            }
+
+            "heading"
+        }
+        Some(Datagram::Autopilot {
+            heading,
+            course,
+            mode,
+            rudder,
+        }) => {
+            let mode = match mode {
+                AutopilotMode::Standby => "standby",
+                AutopilotMode::Auto => "auto",
+                AutopilotMode::Vane => "vane",
+                AutopilotMode::Track => "track",
+            };
+
+            ret.insert("heading".into(), Dynamic::from_int(heading as INT));
+            ret.insert("course".into(), Dynamic::from_float(course as FLOAT / 2.0));
+            ret.insert("mode".into(), mode.into());
+            ret.insert("rudder".into(), Dynamic::from_int(rudder as INT));
+            "autopilot"
+        }
+        None => "unknown",
+    };
+
+    let nmea: Array = received
+        .sentences
+        .iter()
+        .map(|(sentence, chksum)| format!("${}*{:02X}", sentence, chksum).into())
+        .collect();
+
+    ret.insert("type".into(), kind.into());
+    ret.insert("data".into(), Dynamic::from_blob(received.data.clone()));
+    ret.insert("nmea".into(), nmea.into());
+
+    ret
+}
+
+/// Returns the datagrams received in "seatalk" mode since the last call, as `#{type, data,
+/// nmea}` with the decoded fields of known types. Datagrams cut short by a collision are
+/// `#{error: "truncated", length}`.
+pub(crate) fn repl_seatalk_read(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Array, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::RxSeatalkRead;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::RxSeatalkRead(received) => Ok(received
+            .iter()
+            .map(|result| match result {
+                Ok(received) => datagram_map(received).into(),
+                Err(seatalk::Error::Truncated(length)) => {
+                    let mut ret = Map::new();
+                    ret.insert("error".into(), "truncated".into());
+                    ret.insert("length".into(), Dynamic::from_int(*length as INT));
+                    ret.into()
+                }
+            })
+            .collect()),
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn!(module, call_tx, result_rx, repl_seatalk_read, "read", ());
+    engine.register_static_module("seatalk", module.into());
+}
@@ -1,17 +1,32 @@
 //! Differential injector RPC calls
 
 use crate::{
-    apps::tx::{TxMode, TxWords},
+    apps::{
+        candump,
+        fuzz::FuzzConfig,
+        glitch::Glitch,
+        react::Trigger,
+        tx::{
+            can_pio::TxOutcome,
+            overwrite::MIN_OFFSET_AFTER_MATCH,
+            schedule::{Checksum, EntryConfig},
+            TxMode, TxWords,
+        },
+    },
     platform::repl::{
+        can,
         rpc::{RpcCall, RpcCallSender, RpcResult, RpcResultReceiver},
         rpc_call,
     },
     register_repl_fn,
 };
-use alloc::{borrow::ToOwned, boxed::Box, string::String, vec::Vec};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String, vec, vec::Vec};
 use defmt::{debug, warn};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
-use rhai::{Blob, Engine, EvalAltResult, ImmutableString, Module, NativeCallContext, INT};
+use rhai::{
+    Array, Blob, Dynamic, Engine, EvalAltResult, FnPtr, ImmutableString, Map, Module,
+    NativeCallContext, FLOAT, INT,
+};
 
 pub const L_Z0: u8 = 0b000_0_000_1;
 pub const L_0V: u8 = 0b000_0_000_0;
@@ -43,6 +58,30 @@ pub enum TxCommand {
     GetMode,
     SetMode(TxMode),
     Send(TxWords),
+    SetRetries(u8),
+    ArmOverwrite(u32, u32),
+    ArmAck,
+    DisarmOverwrite,
+    /// Frames, each with its offset in µs from the start of the replay.
+    Replay(Vec<(u64, TxWords)>),
+    ReplayStop,
+    ReplayRunning,
+    Fuzz(FuzzConfig),
+    FuzzStop,
+    FuzzRunning,
+    /// Injector samples played over and over until stopped.
+    Loop(Vec<u8>),
+    /// Plays injector samples from the USB stream interface until stopped.
+    Stream,
+    Stop,
+    Glitch(Glitch),
+    /// Payload sent whenever the receiver matches the trigger.
+    React(Trigger, TxWords),
+    ReactStop,
+    Schedule(EntryConfig),
+    ScheduleEnable(u32, bool),
+    Unschedule(u32),
+    ScheduleStats,
 }
 
 pub const TX_MTU: usize = 1;
@@ -154,7 +193,7 @@ pub(crate) fn repl_tx_get_mode(
     Ok(ImmutableString::from(mode))
 }
 
-fn bytes_to_u32(mut bytes: Vec<u8>) -> Vec<u32> {
+pub(crate) fn bytes_to_u32(mut bytes: Vec<u8>) -> Vec<u32> {
     if bytes.len() == 0 {
         return Vec::new();
     }
@@ -181,7 +220,7 @@ pub(crate) fn repl_tx_send(
     call_tx: RpcCallSender,
     result_rx: RpcResultReceiver,
     data: Blob,
-) -> Result<(), Box<EvalAltResult>> {
+) -> Result<ImmutableString, Box<EvalAltResult>> {
     // Construct the RpcCall and send it non-blocking (errors if unable to send).
     let call = RpcCall::TxGetMode;
     let result = rpc_call(&ctx, call_tx, result_rx, call)?;
@@ -194,11 +233,639 @@ pub(crate) fn repl_tx_send(
     };
 
     let call = RpcCall::TxSend(words);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    let outcome = match result {
+        RpcResult::TxSend(outcome) => match outcome {
+            TxOutcome::Sent => "sent",
+            TxOutcome::ArbitrationLost => "arbitration_lost",
+            TxOutcome::NoAck => "no_ack",
+            TxOutcome::BitError => "bit_error",
+            TxOutcome::BusBusy => "bus_busy",
+        },
+        _ => unreachable!(),
+    };
+
+    Ok(ImmutableString::from(outcome))
+}
+
+pub(crate) fn repl_tx_set_retries(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    retries: INT,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    if retries < 0 || retries > u8::MAX as INT {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            "".to_owned(),
+            ctx.call_position(),
+        )));
+    }
+
+    let call = RpcCall::TxSetRetries(retries as u8);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_tx_arm_overwrite(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    arb_id: INT,
+    bit_offset: INT,
+    bit_count: INT,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    if arb_id < 0 || arb_id > 0x1FFF_FFFF || bit_count <= 0 {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            "".to_owned(),
+            ctx.call_position(),
+        )));
+    }
+
+    // NOTE: `bit_offset` counts stuffed bits on the wire from the SOF.
+    let (pattern, len) = can::id_pattern(arb_id as u32);
+    let delay = bit_offset - len as INT;
+
+    if delay < MIN_OFFSET_AFTER_MATCH as INT {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!(
+                "bit_offset must be at least {}",
+                len as u32 + MIN_OFFSET_AFTER_MATCH
+            ),
+            ctx.call_position(),
+        )));
+    }
+
+    let call = RpcCall::TxArmOverwrite(pattern, delay as u32, bit_count as u32);
     let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
 
     Ok(())
 }
 
+pub(crate) fn repl_tx_disarm_overwrite(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxDisarmOverwrite;
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_tx_replay_filtered(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    log: String,
+    speed: FLOAT,
+    arb_id: INT,
+    mask: INT,
+) -> Result<INT, Box<EvalAltResult>> {
+    if speed.is_nan() || speed <= 0.0 {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid speed: {}", speed),
+            ctx.call_position(),
+        )));
+    }
+
+    if !(0..1 << 29).contains(&arb_id) || !(0..1 << 29).contains(&mask) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            String::from("arb_id and mask must fit in 29 bits"),
+            ctx.call_position(),
+        )));
+    }
+
+    let frames = candump::parse_log(&log).map_err(|err| {
+        Box::new(EvalAltResult::ErrorMismatchDataType(
+            String::from("candump log"),
+            format!("{}", err),
+            ctx.call_position(),
+        ))
+    })?;
+    let start = frames.first().map_or(0, |frame| frame.timestamp);
+
+    // NOTE: Frames are encoded up front, so the timing only depends on the transmitter.
+    let frames: Vec<_> = frames
+        .into_iter()
+        .filter(|frame| (frame.arb_id ^ arb_id as u32) & mask as u32 == 0)
+        .map(|frame| {
+            let offset = (frame.timestamp.saturating_sub(start) as FLOAT / speed) as u64;
+            let bytes = can::encode_frame(
+                frame.arb_id,
+                frame.extended,
+                frame.rtr,
+                frame.dlc,
+                &frame.data,
+            );
+
+            (offset, TxWords::Can(bytes_to_u32(bytes)))
+        })
+        .collect();
+
+    let count = frames.len();
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxReplay(frames);
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxReplay => Ok(count as INT),
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn repl_tx_replay_speed(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    log: String,
+    speed: FLOAT,
+) -> Result<INT, Box<EvalAltResult>> {
+    repl_tx_replay_filtered(ctx, call_tx, result_rx, log, speed, 0, 0)
+}
+
+pub(crate) fn repl_tx_replay(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    log: String,
+) -> Result<INT, Box<EvalAltResult>> {
+    repl_tx_replay_filtered(ctx, call_tx, result_rx, log, 1.0, 0, 0)
+}
+
+pub(crate) fn repl_tx_replaying(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<bool, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxReplayRunning;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxReplayRunning(running) => Ok(running),
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn repl_tx_replay_stop(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Map, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxReplayStop;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxReplayStop(report) => {
+            let mut ret = Map::new();
+            ret.insert("sent".into(), Dynamic::from_int(report.sent as INT));
+            ret.insert("failed".into(), Dynamic::from_int(report.failed as INT));
+            ret.insert("skipped".into(), Dynamic::from_int(report.skipped as INT));
+
+            Ok(ret)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Plays injector samples over and over from the DMA, until `tx::stop`.
+pub(crate) fn repl_tx_send_loop(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    data: Blob,
+) -> Result<(), Box<EvalAltResult>> {
+    if data.is_empty() {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            String::from("Nothing to loop"),
+            ctx.call_position(),
+        )));
+    }
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxLoop(data);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+/// Plays injector samples written by the host to the USB stream interface, until `tx::stop`.
+pub(crate) fn repl_tx_stream(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxStream;
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+/// Stops a loop or a stream, returning `#{samples, underruns}` for a stream.
+pub(crate) fn repl_tx_stop(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Map, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxStop;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxStop(samples, underruns) => {
+            let mut ret = Map::new();
+            ret.insert("samples".into(), Dynamic::from_int(samples as INT));
+            ret.insert("underruns".into(), Dynamic::from_int(underruns as INT));
+
+            Ok(ret)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn trigger_error(ctx: &NativeCallContext) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorMismatchDataType(
+        String::from("#{arb_id, mask}, #{nmea}, #{unit, function} or #{bytes}"),
+        String::from("trigger"),
+        ctx.call_position(),
+    ))
+}
+
+/// The reaction trigger, from one of `#{arb_id, mask}` (mask defaults to all 29 bits),
+/// `#{nmea}`, `#{unit, function}` or `#{bytes}`.
+fn parse_trigger(ctx: &NativeCallContext, trigger: &Map) -> Result<Trigger, Box<EvalAltResult>> {
+    let int = |field: &str, max: INT| {
+        trigger
+            .get(field)
+            .map(|value| {
+                value
+                    .as_int()
+                    .ok()
+                    .filter(|value| (0..=max).contains(value))
+                    .ok_or_else(|| trigger_error(ctx))
+            })
+            .transpose()
+    };
+
+    if let Some(arb_id) = int("arb_id", 0x1FFF_FFFF)? {
+        let mask = int("mask", 0x1FFF_FFFF)?.unwrap_or(0x1FFF_FFFF);
+
+        return Ok(Trigger::Can {
+            arb_id: arb_id as u32,
+            mask: mask as u32,
+        });
+    }
+
+    if let Some(prefix) = trigger.get("nmea") {
+        let prefix = prefix
+            .clone()
+            .into_string()
+            .map_err(|_| trigger_error(ctx))?;
+        let prefix = prefix.trim_start_matches(['$', '!']);
+
+        if prefix.is_empty() {
+            return Err(trigger_error(ctx));
+        }
+
+        return Ok(Trigger::Nmea0183(prefix.as_bytes().to_vec()));
+    }
+
+    if let (Some(unit), Some(function)) = (int("unit", 0xFF)?, int("function", 0xFF)?) {
+        return Ok(Trigger::Modbus {
+            unit: unit as u8,
+            function: function as u8,
+        });
+    }
+
+    match trigger.get("bytes").map(|bytes| bytes.clone().into_blob()) {
+        Some(Ok(bytes)) if !bytes.is_empty() => Ok(Trigger::Bytes(bytes)),
+        _ => Err(trigger_error(ctx)),
+    }
+}
+
+/// Sends `data` (as for `tx::send`) every time the receiver matches `trigger`, from the tasks
+/// themselves, until `tx::react_stop`.
+pub(crate) fn repl_tx_react(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    trigger: Map,
+    data: Blob,
+) -> Result<(), Box<EvalAltResult>> {
+    let trigger = parse_trigger(ctx, &trigger)?;
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxGetMode;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    let words = match result {
+        RpcResult::TxGetMode(TxMode::Inject) => TxWords::Inject(data),
+        RpcResult::TxGetMode(TxMode::Can) => TxWords::Can(bytes_to_u32(data)),
+        _ => unreachable!(),
+    };
+
+    let call = RpcCall::TxReact(trigger, words);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+/// Stops reacting, returning `#{matched, sent, failed, min_us, max_us, mean_us}`.
+pub(crate) fn repl_tx_react_stop(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Map, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxReactStop;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxReactStop(report) => {
+            let mut ret = Map::new();
+            ret.insert("matched".into(), Dynamic::from_int(report.matched as INT));
+            ret.insert("sent".into(), Dynamic::from_int(report.sent as INT));
+            ret.insert("failed".into(), Dynamic::from_int(report.failed as INT));
+            ret.insert("min_us".into(), Dynamic::from_int(report.min as INT));
+            ret.insert("max_us".into(), Dynamic::from_int(report.max as INT));
+            ret.insert("mean_us".into(), Dynamic::from_int(report.mean() as INT));
+
+            Ok(ret)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Most payloads of a scheduled frame made by a function, one per cycle.
+const MAX_SCHEDULE_CYCLES: INT = 256;
+
+/// Longest period of a scheduled frame, a day in ms.
+const MAX_SCHEDULE_PERIOD_MS: INT = 86_400_000;
+
+fn option_error(ctx: &NativeCallContext, option: &str, expected: &str) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorMismatchDataType(
+        expected.to_owned(),
+        format!("options.{}", option),
+        ctx.call_position(),
+    ))
+}
+
+/// An INT from the options, within `range`.
+pub(crate) fn int_option(
+    ctx: &NativeCallContext,
+    options: &Map,
+    option: &str,
+    range: core::ops::RangeInclusive<INT>,
+    default: INT,
+) -> Result<INT, Box<EvalAltResult>> {
+    match options.get(option) {
+        Some(value) => value
+            .as_int()
+            .ok()
+            .filter(|value| range.contains(value))
+            .ok_or_else(|| {
+                option_error(
+                    ctx,
+                    option,
+                    &format!("INT in {}..={}", range.start(), range.end()),
+                )
+            }),
+        None => Ok(default),
+    }
+}
+
+/// A payload of up to 8 bytes.
+fn payload(ctx: &NativeCallContext, payload: Dynamic) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    payload
+        .into_blob()
+        .ok()
+        .filter(|payload| payload.len() <= 8)
+        .ok_or_else(|| {
+            Box::new(EvalAltResult::ErrorMismatchDataType(
+                String::from("Blob of up to 8 bytes"),
+                String::from("payload"),
+                ctx.call_position(),
+            ))
+        })
+}
+
+/// Sends a CAN frame every `period_ms`, from the Tx task, until `tx::unschedule`.
+///
+/// `payload` is a Blob, or a function building a fixed table of payloads: it is called with the
+/// cycle number for each of `cycles` cycles (16 by default) up front, and its Blobs are then sent
+/// in turn over and over, without calling it again. `period_ms` is at most a day. `options`
+/// takes `priority` (higher first when frames are due at once, 0 by default), `cycles`,
+/// `extended` (whether the identifier is, by default if over 11 bits), `counter` (`#{byte,
+/// mask}`, a rolling counter within `mask`, 0x0F by default) and `checksum` (`#{byte, kind}`,
+/// "xor", "sum" or "crc8" of the other bytes, written after the counter).
+pub(crate) fn repl_tx_schedule_options(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    arb_id: INT,
+    period_ms: INT,
+    payload_fn_or_blob: Dynamic,
+    options: Map,
+) -> Result<(), Box<EvalAltResult>> {
+    if !(0..1 << 29).contains(&arb_id) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid identifier: {:#X}", arb_id),
+            ctx.call_position(),
+        )));
+    }
+
+    if !(1..=MAX_SCHEDULE_PERIOD_MS).contains(&period_ms) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid period: {}", period_ms),
+            ctx.call_position(),
+        )));
+    }
+
+    let extended = match options.get("extended") {
+        Some(extended) => extended
+            .as_bool()
+            .map_err(|_| option_error(ctx, "extended", "bool"))?,
+        None => arb_id > 0x7FF,
+    };
+
+    if !extended && arb_id > 0x7FF {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid standard identifier: {:#X}", arb_id),
+            ctx.call_position(),
+        )));
+    }
+
+    let payloads = if let Some(payload_fn) = payload_fn_or_blob.clone().try_cast::<FnPtr>() {
+        let cycles = int_option(ctx, &options, "cycles", 1..=MAX_SCHEDULE_CYCLES, 16)?;
+
+        (0..cycles)
+            .map(|cycle| payload(ctx, payload_fn.call_within_context(ctx, (cycle,))?))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        vec![payload(ctx, payload_fn_or_blob)?]
+    };
+
+    let counter = match options.get("counter") {
+        Some(counter) => {
+            let counter = counter
+                .clone()
+                .try_cast::<Map>()
+                .ok_or_else(|| option_error(ctx, "counter", "#{byte, mask}"))?;
+            let byte = int_option(ctx, &counter, "byte", 0..=7, -1)?;
+            let mask = int_option(ctx, &counter, "mask", 1..=0xFF, 0x0F)?;
+
+            if byte < 0 {
+                return Err(option_error(ctx, "counter.byte", "INT in 0..=7"));
+            }
+
+            Some((byte as usize, mask as u8))
+        }
+        None => None,
+    };
+
+    let checksum = match options.get("checksum") {
+        Some(checksum) => {
+            let checksum = checksum
+                .clone()
+                .try_cast::<Map>()
+                .ok_or_else(|| option_error(ctx, "checksum", "#{byte, kind}"))?;
+            let byte = int_option(ctx, &checksum, "byte", 0..=7, -1)?;
+            let kind = match checksum
+                .get("kind")
+                .and_then(|kind| kind.clone().into_string().ok())
+                .as_deref()
+            {
+                Some("xor") => Checksum::Xor,
+                Some("sum") => Checksum::Sum,
+                Some("crc8") => Checksum::Crc8,
+                _ => return Err(option_error(ctx, "checksum.kind", "[xor, sum, crc8]")),
+            };
+
+            if byte < 0 {
+                return Err(option_error(ctx, "checksum.byte", "INT in 0..=7"));
+            }
+
+            Some((byte as usize, kind))
+        }
+        None => None,
+    };
+
+    let config = EntryConfig {
+        arb_id: arb_id as u32,
+        extended,
+        period: period_ms as u64,
+        priority: int_option(
+            ctx,
+            &options,
+            "priority",
+            i32::MIN as INT..=i32::MAX as INT,
+            0,
+        )? as i32,
+        payloads,
+        counter,
+        checksum,
+    };
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxSchedule(config);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_tx_schedule(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    arb_id: INT,
+    period_ms: INT,
+    payload_fn_or_blob: Dynamic,
+) -> Result<(), Box<EvalAltResult>> {
+    repl_tx_schedule_options(
+        ctx,
+        call_tx,
+        result_rx,
+        arb_id,
+        period_ms,
+        payload_fn_or_blob,
+        Map::new(),
+    )
+}
+
+/// Pauses or resumes a scheduled frame, resuming sends it right away.
+pub(crate) fn repl_tx_schedule_enable(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    arb_id: INT,
+    enabled: bool,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxScheduleEnable(arb_id as u32, enabled);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn repl_tx_unschedule(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    arb_id: INT,
+) -> Result<(), Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxUnschedule(arb_id as u32);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+/// Returns an Array of `#{arb_id, period_ms, enabled, sent, failed, missed, jitter_max_us,
+/// jitter_mean_us}`, by identifier.
+pub(crate) fn repl_tx_schedule_stats(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<Array, Box<EvalAltResult>> {
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::TxScheduleStats;
+    let result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    match result {
+        RpcResult::TxScheduleStats(stats) => Ok(stats
+            .iter()
+            .map(|stats| {
+                let mut ret = Map::new();
+                ret.insert("arb_id".into(), Dynamic::from_int(stats.arb_id as INT));
+                ret.insert("period_ms".into(), Dynamic::from_int(stats.period as INT));
+                ret.insert("enabled".into(), Dynamic::from_bool(stats.enabled));
+                ret.insert("sent".into(), Dynamic::from_int(stats.sent as INT));
+                ret.insert("failed".into(), Dynamic::from_int(stats.failed as INT));
+                ret.insert("missed".into(), Dynamic::from_int(stats.missed as INT));
+                ret.insert(
+                    "jitter_max_us".into(),
+                    Dynamic::from_int(stats.jitter_max as INT),
+                );
+                ret.insert(
+                    "jitter_mean_us".into(),
+                    Dynamic::from_int(stats.jitter_mean() as INT),
+                );
+
+                ret.into()
+            })
+            .collect()),
+        _ => unreachable!(),
+    }
+}
+
 pub(crate) fn register_functions(
     engine: &mut Engine,
     call_tx: RpcCallSender,
@@ -241,6 +908,122 @@ pub(crate) fn register_functions(
         (mode: String)
     );
     register_repl_fn!(module, call_tx, result_rx, repl_tx_send, "send", (data: Blob));
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_set_retries,
+        "set_retries",
+        (retries: INT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_arm_overwrite,
+        "arm_overwrite",
+        (arb_id: INT, bit_offset: INT, bit_count: INT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_disarm_overwrite,
+        "disarm_overwrite",
+        ()
+    );
+    register_repl_fn!(module, call_tx, result_rx, repl_tx_replay, "replay", (log: String));
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_replay_speed,
+        "replay",
+        (log: String, speed: FLOAT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_replay_filtered,
+        "replay",
+        (log: String, speed: FLOAT, arb_id: INT, mask: INT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_replaying,
+        "replaying",
+        ()
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_replay_stop,
+        "replay_stop",
+        ()
+    );
+    register_repl_fn!(module, call_tx, result_rx, repl_tx_send_loop, "send_loop", (data: Blob));
+    register_repl_fn!(module, call_tx, result_rx, repl_tx_stream, "stream", ());
+    register_repl_fn!(module, call_tx, result_rx, repl_tx_stop, "stop", ());
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_react,
+        "react",
+        (trigger: Map, data: Blob)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_react_stop,
+        "react_stop",
+        ()
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_schedule,
+        "schedule",
+        (arb_id: INT, period_ms: INT, payload_fn_or_blob: Dynamic)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_schedule_options,
+        "schedule",
+        (arb_id: INT, period_ms: INT, payload_fn_or_blob: Dynamic, options: Map)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_schedule_enable,
+        "schedule_enable",
+        (arb_id: INT, enabled: bool)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_unschedule,
+        "unschedule",
+        (arb_id: INT)
+    );
+    register_repl_fn!(
+        module,
+        call_tx,
+        result_rx,
+        repl_tx_schedule_stats,
+        "schedule_stats",
+        ()
+    );
 
     engine.register_static_module("tx", module.into());
 }
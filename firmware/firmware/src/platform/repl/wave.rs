@@ -0,0 +1,220 @@
+//! Injector waveform calls, see `apps::tx::waveform`.
+//!
+//! Every call returns a Blob of injector codes, so waveforms are put together with `+` and sent
+//! with `tx::send` in "inject" mode.
+
+use crate::{
+    apps::tx::waveform::{self, Encoding, Level, WaveformError},
+    platform::repl::{
+        rpc::{RpcCall, RpcCallSender, RpcResultReceiver},
+        rpc_call,
+    },
+    register_repl_fn, register_repl_fn_no_rpc,
+};
+use alloc::{borrow::ToOwned, boxed::Box, format, string::String};
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Module, NativeCallContext, FLOAT, INT};
+
+/// Largest waveform made at once.
+const MAX_SAMPLES: INT = 64 * 1024;
+
+pub(crate) fn waveform_error(ctx: &NativeCallContext, err: WaveformError) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorRuntime(
+        format!("Waveform: {}", err).into(),
+        ctx.call_position(),
+    ))
+}
+
+pub(crate) fn samples(ctx: &NativeCallContext, samples: INT) -> Result<usize, Box<EvalAltResult>> {
+    if !(0..=MAX_SAMPLES).contains(&samples) {
+        return Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid sample count: {}", samples),
+            ctx.call_position(),
+        )));
+    }
+
+    Ok(samples as usize)
+}
+
+/// Checks the sample count of `count` runs of `each` samples, as [`samples`] does.
+pub(crate) fn sample_product(
+    ctx: &NativeCallContext,
+    count: usize,
+    each: usize,
+) -> Result<usize, Box<EvalAltResult>> {
+    match count
+        .checked_mul(each)
+        .and_then(|total| INT::try_from(total).ok())
+    {
+        Some(total) => samples(ctx, total),
+        None => Err(Box::new(EvalAltResult::ErrorArithmetic(
+            format!("Invalid sample count: {} * {}", count, each),
+            ctx.call_position(),
+        ))),
+    }
+}
+
+/// A level in volts, or `()` for high impedance.
+pub(crate) fn level(ctx: &NativeCallContext, volts: &Dynamic) -> Result<Level, Box<EvalAltResult>> {
+    if volts.is_unit() {
+        return Ok(Level::HiZ);
+    }
+
+    let volts = if let Ok(volts) = volts.as_float() {
+        volts
+    } else if let Ok(volts) = volts.as_int() {
+        volts as FLOAT
+    } else {
+        return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+            String::from("FLOAT volts or () for high impedance"),
+            volts.type_name().to_owned(),
+            ctx.call_position(),
+        )));
+    };
+
+    Level::from_volts(volts).map_err(|_| {
+        Box::new(EvalAltResult::ErrorArithmetic(
+            format!("No injector level at {} V", volts),
+            ctx.call_position(),
+        ))
+    })
+}
+
+/// A `[h, l]` state.
+fn state(ctx: &NativeCallContext, state: &Array) -> Result<(Level, Level), Box<EvalAltResult>> {
+    match state.as_slice() {
+        [h, l] => Ok((level(ctx, h)?, level(ctx, l)?)),
+        _ => Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+            String::from("[h, l]"),
+            format!("Array of {}", state.len()),
+            ctx.call_position(),
+        ))),
+    }
+}
+
+/// Holds CAN H and CAN L at levels in volts (0, 1, 1.5 up to 4), or at high impedance with `()`.
+pub(crate) fn repl_wave_hold(
+    ctx: &NativeCallContext,
+    h: Dynamic,
+    l: Dynamic,
+    count: INT,
+) -> Result<Blob, Box<EvalAltResult>> {
+    let count = samples(ctx, count)?;
+
+    waveform::hold(level(ctx, &h)?, level(ctx, &l)?, count)
+        .map_err(|err| waveform_error(ctx, err))
+}
+
+/// Goes from one `[h, l]` state to another, through the nearest levels.
+pub(crate) fn repl_wave_ramp(
+    ctx: &NativeCallContext,
+    from: Array,
+    to: Array,
+    count: INT,
+) -> Result<Blob, Box<EvalAltResult>> {
+    let count = samples(ctx, count)?;
+
+    waveform::ramp(state(ctx, &from)?, state(ctx, &to)?, count)
+        .map_err(|err| waveform_error(ctx, err))
+}
+
+/// A dominant CAN pulse.
+pub(crate) fn repl_wave_pulse(
+    ctx: &NativeCallContext,
+    width: INT,
+) -> Result<Blob, Box<EvalAltResult>> {
+    Ok(waveform::pulse(samples(ctx, width)?))
+}
+
+/// Encodes a pattern like `"0110_1"` as CAN dominant and recessive states, `width` samples per
+/// bit.
+pub(crate) fn repl_wave_bits_width(
+    ctx: &NativeCallContext,
+    pattern: String,
+    encoding: String,
+    width: INT,
+) -> Result<Blob, Box<EvalAltResult>> {
+    let encoding = match encoding.to_lowercase().as_str() {
+        "nrz" => Encoding::Nrz,
+        "stuffed" => Encoding::Stuffed,
+        "manchester" => Encoding::Manchester,
+        "biphase" => Encoding::Biphase,
+        _ => {
+            return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+                String::from("[nrz, stuffed, manchester, biphase]"),
+                encoding.to_owned(),
+                ctx.call_position(),
+            )))
+        }
+    };
+
+    // Every bit takes up to two widths, with Manchester or a stuff bit after it.
+    let width = samples(ctx, width)?;
+    sample_product(ctx, pattern.len().saturating_mul(2), width)?;
+
+    waveform::bits(&pattern, encoding, width).map_err(|err| waveform_error(ctx, err))
+}
+
+pub(crate) fn repl_wave_bits(
+    ctx: &NativeCallContext,
+    pattern: String,
+    encoding: String,
+) -> Result<Blob, Box<EvalAltResult>> {
+    repl_wave_bits_width(ctx, pattern, encoding, 1)
+}
+
+pub(crate) fn repl_wave_repeat(
+    ctx: &NativeCallContext,
+    wave: Blob,
+    times: INT,
+) -> Result<Blob, Box<EvalAltResult>> {
+    let times = samples(ctx, times)?;
+    sample_product(ctx, wave.len(), times)?;
+
+    Ok(waveform::repeat(&wave, times))
+}
+
+/// Errors on the first sample driving a line at high impedance, for hand-made waveforms.
+pub(crate) fn repl_wave_check(
+    ctx: &NativeCallContext,
+    wave: Blob,
+) -> Result<(), Box<EvalAltResult>> {
+    waveform::check(&wave).map_err(|err| waveform_error(ctx, err))
+}
+
+/// Plots a waveform on the display.
+pub(crate) fn repl_wave_preview(
+    ctx: &NativeCallContext,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    wave: Blob,
+) -> Result<(), Box<EvalAltResult>> {
+    waveform::check(&wave).map_err(|err| waveform_error(ctx, err))?;
+
+    // Construct the RpcCall and send it non-blocking (errors if unable to send).
+    let call = RpcCall::DisplayWaveform(wave);
+    let _result = rpc_call(&ctx, call_tx, result_rx, call)?;
+
+    Ok(())
+}
+
+pub(crate) fn register_functions(
+    engine: &mut Engine,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    let mut module = Module::new();
+    register_repl_fn_no_rpc!(module, repl_wave_hold, "hold", (h: Dynamic, l: Dynamic, count: INT));
+    register_repl_fn_no_rpc!(module, repl_wave_ramp, "ramp", (from: Array, to: Array, count: INT));
+    register_repl_fn_no_rpc!(module, repl_wave_pulse, "pulse", (width: INT));
+    register_repl_fn_no_rpc!(module, repl_wave_bits, "bits", (pattern: String, encoding: String));
+    register_repl_fn_no_rpc!(
+        module,
+        repl_wave_bits_width,
+        "bits",
+        (pattern: String, encoding: String, width: INT)
+    );
+    register_repl_fn_no_rpc!(module, repl_wave_repeat, "repeat", (wave: Blob, times: INT));
+    register_repl_fn_no_rpc!(module, repl_wave_check, "check", (wave: Blob));
+    register_repl_fn!(module, call_tx, result_rx, repl_wave_preview, "preview", (wave: Blob));
+    engine.register_static_module("wave", module.into());
+}
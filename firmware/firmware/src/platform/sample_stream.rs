@@ -0,0 +1,43 @@
+//! Vendor class with a single bulk OUT endpoint, carrying injector samples for `tx::stream`.
+//!
+//! Every byte is one injector code, see `apps::tx::inject`. The host is held off with NAKs while
+//! the injector is behind, so it can write as fast as it likes.
+
+use embassy_usb::{
+    driver::{Driver, Endpoint, EndpointError, EndpointOut},
+    Builder,
+};
+
+const VENDOR_CLASS: u8 = 0xFF;
+
+/// Tells the interface apart from gs_usb, which is all 0xFF.
+const SUBCLASS: u8 = 0x53;
+
+const MAX_PACKET_SIZE: u16 = 64;
+
+pub struct SampleStreamClass<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+}
+
+impl<'d, D: Driver<'d>> SampleStreamClass<'d, D> {
+    pub fn new(builder: &mut Builder<'d, D>) -> Self {
+        let mut func = builder.function(VENDOR_CLASS, SUBCLASS, 0);
+        let mut interface = func.interface();
+        let mut alt = interface.alt_setting(VENDOR_CLASS, SUBCLASS, 0, None);
+        let read_ep = alt.endpoint_bulk_out(None, MAX_PACKET_SIZE);
+
+        Self { read_ep }
+    }
+
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await;
+    }
+
+    /// Waits for a packet of samples.
+    pub async fn read_packet(
+        &mut self,
+        buf: &mut [u8; MAX_PACKET_SIZE as usize],
+    ) -> Result<usize, EndpointError> {
+        self.read_ep.read(buf).await
+    }
+}
@@ -1,5 +1,9 @@
 use crate::platform::{
-    async_io_on_sync_io::AsyncOutputPin, msc::class::MassStorageClass, sdmmc::spi::SdCard,
+    async_io_on_sync_io::AsyncOutputPin,
+    gs_usb::{self, GsUsbClass},
+    msc::class::MassStorageClass,
+    sample_stream::SampleStreamClass,
+    sdmmc::spi::SdCard,
 };
 
 use super::{irqs::Irqs, msc::class as msc, shared_spi_bus::SharedSpiBusWithConfig};
@@ -23,6 +27,9 @@ pub fn initialize(
     UsbDevice<'static, Driver<'static, USB>>,
     CdcAcmClass<'static, Driver<'static, USB>>,
     CdcAcmClass<'static, Driver<'static, USB>>,
+    CdcAcmClass<'static, Driver<'static, USB>>,
+    GsUsbClass<'static, Driver<'static, USB>>,
+    SampleStreamClass<'static, Driver<'static, USB>>,
     MassStorageClass<
         'static,
         Driver<'static, USB>,
@@ -50,14 +57,14 @@ pub fn initialize(
     // Create embassy-usb DeviceBuilder using the driver and config.
     // It needs some buffers for building the descriptors.
     let mut builder = {
-        static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+        static CONFIG_DESCRIPTOR: StaticCell<[u8; 512]> = StaticCell::new();
         static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
         static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
 
         let builder = embassy_usb::Builder::new(
             driver,
             config,
-            CONFIG_DESCRIPTOR.init([0; 256]),
+            CONFIG_DESCRIPTOR.init([0; 512]),
             BOS_DESCRIPTOR.init([0; 256]),
             &mut [], // no msos descriptors
             CONTROL_BUF.init([0; 64]),
@@ -78,6 +85,24 @@ pub fn initialize(
         CdcAcmClass::new(&mut builder, state, 64)
     };
 
+    // Live capture, see `apps::rx::stream`.
+    let stream = {
+        static STATE: StaticCell<cdc_acm::State> = StaticCell::new();
+        let state = STATE.init(cdc_acm::State::new());
+        CdcAcmClass::new(&mut builder, state, 64)
+    };
+
+    // Native SocketCAN, see `apps::gs_usb`.
+    let gs_usb = {
+        static STATE: StaticCell<gs_usb::State> = StaticCell::new();
+        let state = STATE.init(gs_usb::State::new());
+
+        GsUsbClass::new(&mut builder, state)
+    };
+
+    // Injector samples, see `tx::stream`.
+    let sample_stream = SampleStreamClass::new(&mut builder);
+
     let storage = {
         static STATE: StaticCell<msc::State> = StaticCell::new();
         let state = STATE.init(msc::State::new());
@@ -88,7 +113,7 @@ pub fn initialize(
     // Build the USB device.
     let usb = builder.build();
 
-    (usb, logger, cli, storage)
+    (usb, logger, cli, stream, gs_usb, sample_stream, storage)
 }
 
 #[embassy_executor::task]
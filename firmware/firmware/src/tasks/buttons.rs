@@ -0,0 +1,31 @@
+use crate::platform::{
+    buttons::{ButtonSender, BUTTONS},
+    i2c_io_expander::{models::tcal9539::TCAL9539, pin::Pin},
+};
+use defmt::debug;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_futures::select;
+use embassy_rp::{i2c, peripherals::I2C0};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embedded_hal_async::digital::Wait;
+
+/// Sends the button presses, in the order of [`BUTTONS`]. The buttons are active low.
+#[embassy_executor::task]
+pub async fn buttons_task(
+    mut pins: [Pin<
+        CriticalSectionRawMutex,
+        I2cDevice<'static, CriticalSectionRawMutex, i2c::I2c<'static, I2C0, i2c::Async>>,
+        TCAL9539,
+    >; BUTTONS.len()],
+    button_tx: ButtonSender,
+) -> ! {
+    loop {
+        let presses = pins.each_mut().map(|pin| pin.wait_for_falling_edge());
+        let (_, index) = select::select_array(presses).await;
+
+        debug!("Button pressed: {:?}", BUTTONS[index]);
+
+        // Dropped while the queue is full, nobody is looking.
+        let _ = button_tx.try_send(BUTTONS[index]);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    apps::{
+        gs_usb::{self, Command, HostFrame},
+        rx::RxMode,
+        tx::{can_pio::TxOutcome, TxMode, TxWords},
+    },
+    platform::{
+        gs_usb::{GsUsbClass, Sender},
+        repl::{
+            can::encode_frame,
+            rpc::{RpcCall, RpcCallSender, RpcError, RpcResult, RpcResultReceiver},
+            tx::bytes_to_u32,
+        },
+    },
+};
+use alloc::vec;
+use defmt::{info, warn};
+use embassy_futures::select::{self, Either3};
+use embassy_rp::{peripherals::USB, usb::Driver};
+use embassy_time::Instant;
+
+/// Bridges the gs_usb channel to the receiver and the injector.
+#[embassy_executor::task]
+pub async fn gs_usb_task(
+    class: GsUsbClass<'static, Driver<'static, USB>>,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> ! {
+    let (mut sender, mut receiver, commands) = class.split();
+    let mut started = false;
+    let mut timestamps = false;
+    // Counted as a CAN controller's transmit error counter, saturating instead of going bus-off.
+    let mut tx_errors: u8 = 0;
+
+    loop {
+        match select::select3(
+            receiver.read_frame(),
+            commands.receive(),
+            gs_usb::RX_FRAMES.receive(),
+        )
+        .await
+        {
+            Either3::First(Ok(frame)) => {
+                if !started {
+                    warn!("Dropping a gs_usb frame sent before start");
+                    continue;
+                }
+
+                let outcome = transmit(&frame, call_tx, result_rx)
+                    .await
+                    .unwrap_or_else(|err| {
+                        // Not sent at all, e.g. in listen-only mode.
+                        warn!("gs_usb transmit failed: {:?}", err);
+                        TxOutcome::BusBusy
+                    });
+
+                if outcome == TxOutcome::Sent {
+                    tx_errors = tx_errors.saturating_sub(1);
+                } else {
+                    tx_errors = tx_errors.saturating_add(8);
+                }
+
+                // Failures reach the host as error frames, ahead of the echo: the driver waits
+                // for every frame to be echoed back, sent or not, before reusing its slot.
+                if let Some(error) = HostFrame::from_tx_outcome(outcome, tx_errors) {
+                    write_frame(&mut sender, &error, Instant::now(), timestamps).await;
+                }
+
+                write_frame(&mut sender, &frame, Instant::now(), timestamps).await;
+            }
+            Either3::First(Err(_)) => {
+                // USB went away.
+                if started {
+                    stop(call_tx, result_rx).await;
+                    started = false;
+                }
+
+                receiver.wait_connection().await;
+            }
+            Either3::Second(command) => {
+                info!("gs_usb command: {:?}", command);
+
+                match command {
+                    Command::Start {
+                        timing,
+                        listen_only,
+                        timestamps: enabled,
+                    } => {
+                        // The receiver and transmitter belong to the user, which we may
+                        // already be.
+                        let _ = call(call_tx, result_rx, RpcCall::SysAssumeControl).await;
+
+                        let mut calls = vec![
+                            RpcCall::RxSetMode(RxMode::Can),
+                            RpcCall::RxSetBaud(timing.baud()),
+                            RpcCall::RxSetBitTiming(timing.sample_point(), timing.sjw()),
+                            RpcCall::RxEnableDisable(true),
+                        ];
+
+                        if !listen_only {
+                            calls.extend([
+                                RpcCall::TxSetMode(TxMode::Can),
+                                RpcCall::TxSetBaud(timing.baud()),
+                                RpcCall::TxEnableDisable(true),
+                            ]);
+                        }
+
+                        for rpc_call in calls {
+                            if let Err(err) = call(call_tx, result_rx, rpc_call).await {
+                                // Carry on, the driver has no way to hear about it.
+                                warn!("gs_usb start failed: {:?}", err);
+                            }
+                        }
+
+                        started = true;
+                        timestamps = enabled;
+                        tx_errors = 0;
+                        gs_usb::set_started(true);
+                    }
+                    Command::Stop => {
+                        if started {
+                            stop(call_tx, result_rx).await;
+                            started = false;
+                        }
+                    }
+                    Command::SetTermination(on) => {
+                        // SEL_0 alone picks the 120 ohm resistor, neither leaves the bus open.
+                        let _ = call(call_tx, result_rx, RpcCall::SysAssumeControl).await;
+
+                        if let Err(err) =
+                            call(call_tx, result_rx, RpcCall::TrxSetTerm(on, false)).await
+                        {
+                            warn!("gs_usb termination failed: {:?}", err);
+                        }
+                    }
+                }
+            }
+            Either3::Third((time, msg)) => {
+                let mut frame = HostFrame::from_message(&msg);
+
+                if gs_usb::take_overflow() {
+                    frame.set_overflow();
+                }
+
+                write_frame(&mut sender, &frame, time, timestamps).await;
+            }
+        }
+    }
+}
+
+async fn write_frame(
+    sender: &mut Sender<'static, Driver<'static, USB>>,
+    frame: &HostFrame,
+    time: Instant,
+    timestamps: bool,
+) {
+    let timestamp = timestamps.then(|| time.as_micros() as u32);
+
+    if sender.write_frame(frame, timestamp).await.is_err() {
+        warn!("Unable to send a gs_usb frame");
+    }
+}
+
+async fn stop(call_tx: RpcCallSender, result_rx: RpcResultReceiver) {
+    gs_usb::set_started(false);
+
+    // The transmitter is off in listen-only mode, which is fine.
+    let _ = call(call_tx, result_rx, RpcCall::TxEnableDisable(false)).await;
+    let _ = call(call_tx, result_rx, RpcCall::RxEnableDisable(false)).await;
+}
+
+async fn transmit(
+    frame: &HostFrame,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> Result<TxOutcome, RpcError> {
+    let words = TxWords::Can(bytes_to_u32(encode_frame(
+        frame.arb_id(),
+        frame.is_extended(),
+        frame.is_rtr(),
+        frame.dlc,
+        frame.data(),
+    )));
+
+    match call(call_tx, result_rx, RpcCall::TxSend(words)).await? {
+        RpcResult::TxSend(outcome) => Ok(outcome),
+        _ => unreachable!(),
+    }
+}
+
+async fn call(
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    call: RpcCall,
+) -> Result<RpcResult, RpcError> {
+    call_tx.send(call).await;
+    result_rx.receive().await.1
+}
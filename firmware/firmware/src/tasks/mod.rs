@@ -1,11 +1,15 @@
 //! Top-level module for all embassy-executor tasks.
 
 pub mod batt;
+pub mod buttons;
 pub mod ctrl;
+pub mod gs_usb;
 pub mod irq;
 pub mod log;
 pub mod repl;
 pub mod rx;
+pub mod sample_stream;
+pub mod stream;
 pub mod tx;
 #[cfg(feature = "wifi")]
 pub mod wifi;
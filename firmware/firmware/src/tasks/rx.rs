@@ -1,26 +1,36 @@
 use crate::{
-    apps::rx::{
-        can::{self},
-        nmea0183, RxController, RxMode, RxWord, SerialParser,
+    apps::{
+        bridge, candump, dmx, fuzz, gs_usb, mil1553, react,
+        rx::{
+            can::{self},
+            manchester, nmea0183,
+            pcapng::Recorder,
+            stream::Streamer,
+            RxController, RxMode, RxSetup, RxWord, SerialParser,
+        },
+        seatalk::{self, Datagram, Received},
+        sniffer,
     },
     platform::{
         i2c_io_expander::{models::pca9536::PCA9536, pin::Pin},
         repl::{
             common::AckSignal,
-            rpc::RpcResult,
+            rpc::{RpcError, RpcResult},
             rx::{RxCommand, RxReceiver},
         },
     },
 };
-use defmt::{debug, error, warn};
+use alloc::{string::String, vec::Vec};
+use defmt::{debug, error, info, warn};
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
-use embassy_futures::select::{self, Either};
+use embassy_futures::select::{self, Either4};
 use embassy_rp::{
     i2c,
     peripherals::{DMA_CH4, I2C0, PIN_9, PIO2, UART1},
     Peri,
 };
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
 
 #[embassy_executor::task]
 pub async fn rx_task(
@@ -42,14 +52,35 @@ pub async fn rx_task(
         unsafe { RxController::new(RxMode::Nmea0183, uart, pio, dma, rx_pin, pwr_receiver).await };
     let mut nmea0183_parser = nmea0183::Parser::new();
     let mut can_parser = can::Parser::new();
+    let mut recorder: Option<Recorder> = None;
+    let mut candump_log: Option<String> = None;
+    let mut streamer = Streamer::new();
+    let mut mil1553_decoder = mil1553::Decoder::new();
+    let mut mil1553_received = Vec::new();
+    let mut dmx_parser = dmx::Parser::new();
+    let mut seatalk_parser = seatalk::Parser::new();
+    let mut seatalk_converter = seatalk::Converter::new();
+    let mut seatalk_received = Vec::new();
+    // The user's setup, while the sniffer or the DMX monitor borrows the receiver.
+    let mut borrowed: Option<RxSetup> = None;
 
     loop {
-        match select::select(ctrl.read_word(), rx_rx.receive()).await {
-            Either::First(Some(word)) => {
+        match select::select4(
+            ctrl.read_word(),
+            rx_rx.receive(),
+            sniffer::REQUEST.wait(),
+            dmx::REQUEST.wait(),
+        )
+        .await
+        {
+            Either4::First(Some(word)) => {
                 assert_eq!(RxMode::from(word), ctrl.mode());
 
                 match word {
                     RxWord::Nmea0183(word) => {
+                        // Reactions go first, ahead of parsing and logging.
+                        react::observe_byte(RxMode::Nmea0183, ctrl.baud(), word);
+
                         match nmea0183_parser.parse_word(word) {
                             Some(Ok((sof, message, chksum))) => {
                                 warn!(
@@ -58,6 +89,15 @@ pub async fn rx_task(
                                     message.as_str(),
                                     chksum
                                 );
+
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder
+                                        .record_nmea0183(ctrl.baud(), sof, &message, chksum)
+                                        .await;
+                                }
+
+                                streamer.send_nmea0183(sof, &message, chksum);
+                                bridge::forward_nmea0183(sof, &message);
                             }
                             Some(Err(err)) => {
                                 error!("Error parsing NMEA-0183 message: {}", err);
@@ -68,10 +108,37 @@ pub async fn rx_task(
                         }
                     }
                     RxWord::Modbus(word) => {
+                        react::observe_byte(RxMode::Modbus, ctrl.baud(), word);
+
                         // TODO
+
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder.record_modbus(ctrl.baud(), word).await;
+                        }
+
+                        streamer.send_modbus(ctrl.baud(), word);
                     }
                     RxWord::Can(word) => match can_parser.parse_word(word) {
-                        Some(Ok(msg)) => {}
+                        Some(Ok(msg)) => {
+                            react::observe_can(&msg);
+                            info!("CAN message: {:?}", msg);
+
+                            if let Some(recorder) = recorder.as_mut() {
+                                recorder.record_can(ctrl.baud(), &msg).await;
+                            }
+
+                            if let Some(log) = candump_log.as_mut() {
+                                if log.len() < candump::MAX_LOG {
+                                    candump::write_message(log, Instant::now(), &msg);
+                                }
+                            }
+
+                            streamer.send_can(&msg);
+                            gs_usb::forward(&msg);
+                            bridge::forward_can(&msg);
+                            sniffer::record(&msg);
+                            fuzz::observe(&msg);
+                        }
                         Some(Err(err)) => {
                             error!("Error parsing CAN message: {}", err);
                         }
@@ -79,33 +146,340 @@ pub async fn rx_task(
                             // Not enough data for parsing.
                         }
                     },
+                    RxWord::Mil1553(word) => {
+                        for half in manchester::halves(word) {
+                            let Some(result) = mil1553_decoder.push(half) else {
+                                continue;
+                            };
+
+                            match &result {
+                                Ok(word) => info!("MIL-STD-1553 word: {:?}", word),
+                                Err(err) => error!("Error decoding MIL-STD-1553 word: {}", err),
+                            }
+
+                            if mil1553_received.len() < mil1553::MAX_RECEIVED {
+                                mil1553_received.push(result);
+                            } else {
+                                warn!("Dropped MIL-STD-1553 word, mil1553::read is behind");
+                            }
+                        }
+                    }
+                    RxWord::Dmx(word) => {
+                        if let Some(result) = dmx_parser.parse_word(word) {
+                            match &result {
+                                Ok(packet) => debug!(
+                                    "DMX512 packet: start code {:02X}, {} slots",
+                                    packet.start_code,
+                                    packet.slots.len()
+                                ),
+                                Err(err) => error!("Error parsing DMX512 packet: {}", err),
+                            }
+
+                            dmx::record(&result, Instant::now());
+                        }
+                    }
+                    RxWord::Seatalk(word) => {
+                        if let Some(result) = seatalk_parser.parse_word(word) {
+                            let result = result.map(|data| {
+                                let sentences = match Datagram::decode(&data) {
+                                    Some(datagram) => {
+                                        info!("SeaTalk datagram: {:?}", datagram);
+                                        seatalk_converter.convert(&datagram)
+                                    }
+                                    None => {
+                                        debug!("SeaTalk datagram: {:02X}", data.as_slice());
+                                        Vec::new()
+                                    }
+                                };
+
+                                Received { data, sentences }
+                            });
+
+                            match &result {
+                                Ok(received) => {
+                                    for (sentence, chksum) in &received.sentences {
+                                        if let Some(recorder) = recorder.as_mut() {
+                                            recorder
+                                                .record_nmea0183(
+                                                    ctrl.baud(),
+                                                    b'$',
+                                                    sentence,
+                                                    *chksum,
+                                                )
+                                                .await;
+                                        }
+
+                                        streamer.send_nmea0183(b'$', sentence, *chksum);
+                                    }
+                                }
+                                Err(err) => error!("Error parsing SeaTalk datagram: {}", err),
+                            }
+
+                            if seatalk_received.len() < seatalk::MAX_RECEIVED {
+                                seatalk_received.push(result);
+                            } else {
+                                warn!("Dropped SeaTalk datagram, seatalk::read is behind");
+                            }
+                        }
+                    }
                 }
             }
-            Either::First(None) => {
+            Either4::First(None) => {
                 warn!("Got None back from Rx read word!");
             }
-            Either::Second(cmd) => match cmd {
-                RxCommand::EnableDisable(enabled) => {
-                    debug!("EnableDisable: {}", enabled);
+            Either4::Second(cmd) => {
+                // The user took the receiver over from a view, as the view found it.
+                if let Some(setup) = borrowed.take() {
+                    unsafe { ctrl.restore(setup) }.await;
+                }
+
+                match cmd {
+                    RxCommand::EnableDisable(enabled) => {
+                        debug!("EnableDisable: {}", enabled);
 
-                    if enabled {
-                        ctrl.enable().await;
-                    } else {
-                        ctrl.disable().await;
+                        if enabled {
+                            ctrl.enable().await;
+                        } else {
+                            ctrl.disable().await;
+                        }
+
+                        rx_ack.signal(Ok(RpcResult::RxEnableDisable));
                     }
+                    RxCommand::SetMode(mode) => {
+                        debug!("SetMode: {:?}", mode);
+                        unsafe { ctrl.set_mode(mode).await };
+
+                        if mode == RxMode::Dmx {
+                            dmx_parser.reset();
+                            dmx::clear();
+                        }
+
+                        if mode == RxMode::Seatalk {
+                            seatalk_parser.reset();
+                            seatalk_converter.reset();
+                        }
 
-                    rx_ack.signal(Ok(RpcResult::RxEnableDisable));
+                        rx_ack.signal(Ok(RpcResult::RxSetMode));
+                    }
+                    RxCommand::GetMode => {
+                        debug!("GetMode");
+                        rx_ack.signal(Ok(RpcResult::RxGetMode(ctrl.mode())))
+                    }
+                    RxCommand::SetMatch(pattern) => {
+                        debug!("SetMatch: {:?}", pattern);
+
+                        let outcome = ctrl
+                            .set_match(pattern)
+                            .map_err(|err| {
+                                RpcError::ErrorDataRace(defmt::format!(
+                                    "Unable to set the identifier match: {}",
+                                    err
+                                ))
+                            })
+                            .map(|_| RpcResult::RxSetMatch);
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::SetBaud(baud) => {
+                        debug!("SetBaud: {}", baud);
+
+                        let outcome = if ctrl.mode() == RxMode::Mil1553 {
+                            unsafe { ctrl.set_manchester_baud(baud) }.await
+                        } else {
+                            let timing = ctrl.can_timing();
+                            unsafe {
+                                ctrl.set_can_timing(baud, timing.sample_point(), timing.sjw())
+                            }
+                            .await
+                        };
+                        let outcome = outcome
+                            .map_err(|err| {
+                                RpcError::ErrorArithmetic(defmt::format!(
+                                    "Unable to set the baud: {}",
+                                    err
+                                ))
+                            })
+                            .map(|_| RpcResult::RxSetBaud);
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::GetBaud => {
+                        debug!("GetBaud");
+                        rx_ack.signal(Ok(RpcResult::RxGetBaud(ctrl.baud())))
+                    }
+                    RxCommand::SetBitTiming(sample_point, sjw) => {
+                        debug!("SetBitTiming: {} {}", sample_point, sjw);
+
+                        let baud = ctrl.can_timing().baud();
+                        let outcome = unsafe { ctrl.set_can_timing(baud, sample_point, sjw) }
+                            .await
+                            .map_err(|err| {
+                                RpcError::ErrorArithmetic(defmt::format!(
+                                    "Unable to set the bit timing: {}",
+                                    err
+                                ))
+                            })
+                            .map(|_| RpcResult::RxSetBitTiming);
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::Autobaud => {
+                        debug!("Autobaud");
+
+                        let outcome = unsafe { ctrl.autobaud().await }
+                            .map_err(|err| {
+                                RpcError::ErrorDataRace(defmt::format!(
+                                    "Unable to detect the bus: {}",
+                                    err
+                                ))
+                            })
+                            .map(|(mode, baud)| RpcResult::RxAutobaud(mode, baud));
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::Capture(config, timeout_ms) => {
+                        debug!("Capture: {:?} {}ms", config, timeout_ms);
+
+                        let outcome = ctrl
+                            .capture(&config, Duration::from_millis(timeout_ms))
+                            .await
+                            .map_err(|err| {
+                                RpcError::ErrorDataRace(defmt::format!(
+                                    "Unable to capture: {}",
+                                    err
+                                ))
+                            })
+                            .map(RpcResult::RxCapture);
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::Export(format, from, count) => {
+                        debug!("Export: {:?} {}+{}", format, from, count);
+
+                        let outcome = ctrl
+                            .export(format, from, count)
+                            .map_err(|err| {
+                                RpcError::ErrorDataRace(defmt::format!(
+                                    "Unable to export the capture: {}",
+                                    err
+                                ))
+                            })
+                            .map(RpcResult::RxExport);
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::Analyze(duration_ms) => {
+                        debug!("Analyze: {}ms", duration_ms);
+
+                        let outcome = unsafe { ctrl.analyze(Duration::from_millis(duration_ms)) }
+                            .await
+                            .map_err(|err| {
+                                RpcError::ErrorDataRace(defmt::format!(
+                                    "Unable to analyze the signal: {}",
+                                    err
+                                ))
+                            })
+                            .map(RpcResult::RxAnalyze);
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::PcapStart => {
+                        debug!("PcapStart");
+                        recorder = Some(Recorder::new().await);
+                        rx_ack.signal(Ok(RpcResult::RxPcapStart));
+                    }
+                    RxCommand::PcapStop => {
+                        debug!("PcapStop");
+
+                        let outcome = match recorder.take() {
+                            Some(recorder) => {
+                                Ok(RpcResult::RxPcapStop(recorder.finish(ctrl.baud()).await))
+                            }
+                            None => Err(RpcError::ErrorDataRace(defmt::format!(
+                                "No PCAPNG recording in progress"
+                            ))),
+                        };
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::CandumpStart => {
+                        debug!("CandumpStart");
+                        candump_log = Some(String::new());
+                        rx_ack.signal(Ok(RpcResult::RxCandumpStart));
+                    }
+                    RxCommand::CandumpStop => {
+                        debug!("CandumpStop");
+
+                        let outcome = match candump_log.take() {
+                            Some(log) => Ok(RpcResult::RxCandumpStop(log)),
+                            None => Err(RpcError::ErrorDataRace(defmt::format!(
+                                "No candump log in progress"
+                            ))),
+                        };
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::SetAck(filter) => {
+                        debug!("SetAck: {:?}", filter);
+
+                        let outcome = unsafe { ctrl.set_ack(filter).await }
+                            .map_err(|err| {
+                                RpcError::ErrorDataRace(defmt::format!(
+                                    "Unable to set ACK mode: {}",
+                                    err
+                                ))
+                            })
+                            .map(|_| RpcResult::RxSetAck);
+                        rx_ack.signal(outcome);
+                    }
+                    RxCommand::Mil1553Read => {
+                        debug!("Mil1553Read");
+                        let received = core::mem::take(&mut mil1553_received);
+                        rx_ack.signal(Ok(RpcResult::RxMil1553Read(received)));
+                    }
+                    RxCommand::DmxRead => {
+                        debug!("DmxRead");
+                        rx_ack.signal(Ok(RpcResult::RxDmxRead(dmx::universe())));
+                    }
+                    RxCommand::SeatalkRead => {
+                        debug!("SeatalkRead");
+                        let received = core::mem::take(&mut seatalk_received);
+                        rx_ack.signal(Ok(RpcResult::RxSeatalkRead(received)));
+                    }
+                }
+            }
+            Either4::Third(Some(baud)) => {
+                debug!("Sniffer: {}", baud);
+
+                borrowed.get_or_insert(ctrl.setup());
+                let timing = ctrl.can_timing();
+                unsafe { ctrl.set_mode(RxMode::Can).await };
+
+                // Listening only, which also keeps any bitrate allowed.
+                if let Err(err) = unsafe { ctrl.set_ack(None) }.await {
+                    error!("Unable to leave ACK mode: {}", err);
+                }
+
+                if let Err(err) =
+                    unsafe { ctrl.set_can_timing(baud, timing.sample_point(), timing.sjw()) }.await
+                {
+                    error!("Unable to set the sniffer baud: {}", err);
                 }
-                RxCommand::SetMode(mode) => {
-                    debug!("SetMode: {:?}", mode);
-                    unsafe { ctrl.set_mode(mode).await };
-                    rx_ack.signal(Ok(RpcResult::RxSetMode));
+
+                ctrl.enable().await;
+            }
+            Either4::Third(None) => {
+                debug!("Sniffer closed");
+
+                if let Some(setup) = borrowed.take() {
+                    unsafe { ctrl.restore(setup) }.await;
                 }
-                RxCommand::GetMode => {
-                    debug!("GetMode");
-                    rx_ack.signal(Ok(RpcResult::RxGetMode(ctrl.mode())))
+            }
+            Either4::Fourth(true) => {
+                debug!("DMX monitor");
+                borrowed.get_or_insert(ctrl.setup());
+                unsafe { ctrl.set_mode(RxMode::Dmx).await };
+                dmx_parser.reset();
+                ctrl.enable().await;
+            }
+            Either4::Fourth(false) => {
+                debug!("DMX monitor closed");
+
+                if let Some(setup) = borrowed.take() {
+                    unsafe { ctrl.restore(setup) }.await;
                 }
-            },
+            }
         }
     }
 }
@@ -0,0 +1,17 @@
+use crate::{apps::tx::inject::STREAM, platform::sample_stream::SampleStreamClass};
+use embassy_rp::{peripherals::USB, usb::Driver};
+
+/// Moves injector samples from the host into the stream pipe, see `tx::stream`.
+#[embassy_executor::task]
+pub async fn sample_stream_task(mut class: SampleStreamClass<'static, Driver<'static, USB>>) -> ! {
+    let mut buf = [0_u8; 64];
+
+    loop {
+        class.wait_connection().await;
+
+        // Blocks while the pipe is full, which NAKs the host until the injector catches up.
+        while let Ok(len) = class.read_packet(&mut buf).await {
+            STREAM.write_all(&buf[..len]).await;
+        }
+    }
+}
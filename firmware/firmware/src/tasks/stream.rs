@@ -0,0 +1,234 @@
+use crate::{
+    apps::{
+        gvret::{self, Gvret},
+        rx::{pcapng::LinkType, stream, RxMode},
+        slcan::{Frame, Request, Slcan, Step, STATUS_DATA_OVERRUN},
+        tx::{can_pio::TxOutcome, TxMode, TxWords},
+    },
+    platform::repl::{
+        can::encode_frame,
+        rpc::{RpcCall, RpcCallSender, RpcError, RpcResult, RpcResultReceiver},
+        tx::bytes_to_u32,
+    },
+};
+use alloc::vec;
+use defmt::{info, warn};
+use embassy_futures::select::{self, Either};
+use embassy_rp::{peripherals::USB, usb::Driver};
+use embassy_time::Instant;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, Sender};
+
+/// What the port turned into once the host sent something.
+enum Adapter {
+    Slcan(Slcan),
+    Gvret(Gvret),
+}
+
+impl Adapter {
+    /// Tells the protocols apart by the first byte: SavvyCAN starts with the GVRET binary mode
+    /// switch.
+    fn new(first: u8) -> Self {
+        if first == gvret::BINARY_MODE {
+            info!("GVRET session started");
+            Adapter::Gvret(Gvret::new())
+        } else {
+            info!("SLCAN session started");
+            Adapter::Slcan(Slcan::new())
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        match self {
+            Adapter::Slcan(slcan) => slcan.is_open(),
+            Adapter::Gvret(gvret) => gvret.is_open(),
+        }
+    }
+}
+
+/// Serves the stream USB serial port: the live stream while the host only listens, and an SLCAN
+/// or GVRET adapter from the first byte it sends until it closes the port.
+#[embassy_executor::task]
+pub async fn stream_task(
+    class: CdcAcmClass<'static, Driver<'static, USB>>,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> ! {
+    let (mut sender, mut receiver) = class.split();
+    let mut adapter: Option<Adapter> = None;
+    let mut buf = [0_u8; 64];
+
+    loop {
+        match select::select(receiver.read_packet(&mut buf), stream::read_frame()).await {
+            Either::First(Ok(len)) => {
+                for &byte in &buf[..len] {
+                    let reply = match adapter.get_or_insert_with(|| Adapter::new(byte)) {
+                        Adapter::Slcan(slcan) => match slcan.push(byte) {
+                            Some(Step::Reply(reply)) => reply,
+                            Some(Step::Request(request)) => {
+                                let ok = carry_out(&request, call_tx, result_rx).await;
+                                slcan.complete(&request, ok)
+                            }
+                            None => continue,
+                        },
+                        Adapter::Gvret(gvret) => {
+                            let micros = Instant::now().as_micros() as u32;
+
+                            match gvret.push(byte, micros) {
+                                Some(Step::Reply(reply)) => reply,
+                                Some(Step::Request(request)) => {
+                                    let ok = carry_out(&request, call_tx, result_rx).await;
+                                    gvret.complete(&request, ok);
+                                    continue;
+                                }
+                                None => continue,
+                            }
+                        }
+                    };
+
+                    write_all(&mut sender, &reply).await;
+                }
+            }
+            Either::First(Err(_)) => {
+                // USB went away.
+                close(&mut adapter, call_tx, result_rx).await;
+                receiver.wait_connection().await;
+            }
+            Either::Second(frame) => {
+                if adapter.is_some() && !sender.dtr() {
+                    // The host closed the port without closing the channel.
+                    close(&mut adapter, call_tx, result_rx).await;
+                    continue;
+                }
+
+                let Some(adapter) = adapter.as_mut() else {
+                    // Dropped while the port is closed.
+                    if sender.dtr() {
+                        write_all(&mut sender, &frame).await;
+                    }
+
+                    continue;
+                };
+
+                if stream::take_overrun() {
+                    if let Adapter::Slcan(slcan) = adapter {
+                        slcan.flag(STATUS_DATA_OVERRUN);
+                    }
+                }
+
+                let (link, time, packet) = stream::decode(&frame);
+
+                if !adapter.is_open() || link != LinkType::SocketCan.code() {
+                    continue;
+                }
+
+                let Some(frame) = Frame::from_socketcan(packet) else {
+                    continue;
+                };
+
+                let line = match adapter {
+                    Adapter::Slcan(slcan) => slcan.format_frame(&frame, time / 1000),
+                    Adapter::Gvret(gvret) => gvret.format_frame(&frame, time as u32),
+                };
+                write_all(&mut sender, &line).await;
+            }
+        }
+    }
+}
+
+/// Ends the adapter session, if any, closing its channel.
+async fn close(
+    adapter: &mut Option<Adapter>,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) {
+    if let Some(adapter) = adapter.take() {
+        if adapter.is_open() {
+            carry_out(&Request::Close, call_tx, result_rx).await;
+        }
+
+        info!("Adapter session ended");
+    }
+}
+
+async fn write_all(sender: &mut Sender<'static, Driver<'static, USB>>, data: &[u8]) {
+    for chunk in data.chunks(sender.max_packet_size() as usize) {
+        if sender.write_packet(chunk).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn call(
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+    call: RpcCall,
+) -> Result<RpcResult, RpcError> {
+    call_tx.send(call).await;
+    result_rx.receive().await.1
+}
+
+/// Carries out an SLCAN or GVRET request through the RPC runtime, like the REPL would.
+async fn carry_out(
+    request: &Request,
+    call_tx: RpcCallSender,
+    result_rx: RpcResultReceiver,
+) -> bool {
+    let calls = match request {
+        Request::Open {
+            bitrate,
+            listen_only,
+        } => {
+            // The receiver and transmitter belong to the user, which we may already be.
+            let _ = call(call_tx, result_rx, RpcCall::SysAssumeControl).await;
+
+            let mut calls = vec![
+                RpcCall::RxSetMode(RxMode::Can),
+                RpcCall::RxSetBaud(*bitrate),
+                RpcCall::RxEnableDisable(true),
+            ];
+
+            if !listen_only {
+                calls.extend([
+                    RpcCall::TxSetMode(TxMode::Can),
+                    RpcCall::TxSetBaud(*bitrate),
+                    RpcCall::TxEnableDisable(true),
+                ]);
+            }
+
+            calls
+        }
+        Request::Close => vec![
+            RpcCall::TxEnableDisable(false),
+            RpcCall::RxEnableDisable(false),
+        ],
+        Request::Transmit(frame) => {
+            let words = TxWords::Can(bytes_to_u32(encode_frame(
+                frame.arb_id,
+                frame.extended,
+                frame.rtr,
+                frame.dlc,
+                &frame.data,
+            )));
+
+            vec![RpcCall::TxSend(words)]
+        }
+    };
+
+    for rpc_call in calls {
+        match call(call_tx, result_rx, rpc_call).await {
+            Ok(RpcResult::TxSend(outcome)) if outcome != TxOutcome::Sent => {
+                warn!("Adapter transmit failed: {:?}", outcome);
+                return false;
+            }
+            Ok(_) => {}
+            // Closing goes on regardless, the transmitter may not have been enabled.
+            Err(_) if *request == Request::Close => {}
+            Err(err) => {
+                warn!("Adapter request failed: {:?}", err);
+                return false;
+            }
+        }
+    }
+
+    true
+}
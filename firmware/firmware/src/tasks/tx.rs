@@ -1,29 +1,40 @@
 use crate::{
-    apps::tx::{TxController, TxMode},
+    apps::{
+        fuzz::Campaign,
+        glitch::{Edge, Glitch, Trigger},
+        react::{self, ReactReport},
+        tx::{
+            can_pio::TxOutcome, replay::Replay, schedule::Schedule, TxController, TxMode, TxWords,
+        },
+    },
     platform::{
         i2c_io_expander::{
             self,
+            inner_pin::InnerPin,
             models::{pca9536::PCA9536, tcal9539::TCAL9539},
         },
         repl::{
+            can,
             common::AckSignal,
             rpc::{RpcError, RpcResult},
-            tx::{TxCommand, TxReceiver},
+            tx::{bytes_to_u32, TxCommand, TxReceiver},
         },
     },
 };
-use alloc::string::String;
+use alloc::{string::String, sync::Arc};
 use defmt::{debug, warn};
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_futures::select::{select4, Either4};
 use embassy_rp::{
     i2c,
     peripherals::{
-        DMA_CH5, I2C0, PIN_18, PIN_19, PIN_20, PIN_21, PIN_22, PIN_23, PIN_24, PIN_25, PIN_26,
-        PIN_27, PIN_28, PIO1,
+        DMA_CH5, DMA_CH6, I2C0, PIN_18, PIN_19, PIN_20, PIN_21, PIN_22, PIN_23, PIN_24, PIN_25,
+        PIN_26, PIN_27, PIN_28, PIO1,
     },
     Peri,
 };
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 
 #[embassy_executor::task]
 pub async fn tx_task(
@@ -31,6 +42,7 @@ pub async fn tx_task(
     tx_ack: &'static AckSignal,
     pio: Peri<'static, PIO1>,
     dma: Peri<'static, DMA_CH5>,
+    ctrl_dma: Peri<'static, DMA_CH6>,
     l_z0: Peri<'static, PIN_18>,
     l_v2: Peri<'static, PIN_19>,
     l_v1: Peri<'static, PIN_20>,
@@ -57,6 +69,7 @@ pub async fn tx_task(
         I2cDevice<'static, CriticalSectionRawMutex, i2c::I2c<'static, I2C0, i2c::Async>>,
         PCA9536,
     >,
+    sao_trigger: Arc<InnerPin<CriticalSectionRawMutex>>,
 ) -> ! {
     warn!("IN THE TX TASK!");
 
@@ -64,6 +77,7 @@ pub async fn tx_task(
         TxController::new(
             TxMode::Can,
             dma,
+            ctrl_dma,
             pio,
             l_z0,
             l_v2,
@@ -98,8 +112,102 @@ pub async fn tx_task(
     // Bit order
     // H_V0 H_V1 H_V2 H_Z0 L_V0 L_V1 L_V2 L_Z0
 
+    // The payload sent when the receiver matches, and its latencies.
+    let mut reaction: Option<(TxWords, ReactReport)> = None;
+    let mut schedule = Schedule::new();
+    let mut campaign: Option<Campaign> = None;
+    let mut replay: Option<Replay> = None;
+
     loop {
-        match tx_rx.receive().await {
+        // A stream is fed, reactions, scheduled, fuzzed and replayed frames are sent between
+        // commands.
+        let next_due = [
+            schedule.next_due(),
+            campaign.as_ref().and_then(Campaign::next_due),
+            replay.as_ref().and_then(Replay::next_due),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(Instant::MAX);
+        let command = match select4(
+            tx_rx.receive(),
+            ctrl.feed(),
+            react::fired(),
+            Timer::at(next_due),
+        )
+        .await
+        {
+            Either4::First(command) => command,
+            Either4::Second(()) => continue,
+            Either4::Third(seen) => {
+                if let Some((words, report)) = reaction.as_mut() {
+                    // NOTE: Tx may have been disabled, switched over or taken up since.
+                    let ready = ctrl.is_enabled()
+                        && ctrl.mode() == words.mode()
+                        && !ctrl.is_playing()
+                        && !ctrl.is_overwrite_armed();
+                    let sent =
+                        ready && matches!(ctrl.send(words.clone()).await, Ok(TxOutcome::Sent));
+                    // Sent once the frame is out, so this is to its end on the bus.
+                    report.record(sent.then(|| seen.elapsed().as_micros()));
+                }
+
+                continue;
+            }
+            Either4::Fourth(()) => {
+                let start = Instant::now();
+
+                // One at a time, the next of those due goes on the next turn.
+                if let Some((index, arb_id, extended, payload)) = schedule.due(start) {
+                    let ready = ctrl.is_enabled()
+                        && ctrl.mode() == TxMode::Can
+                        && !ctrl.is_playing()
+                        && !ctrl.is_overwrite_armed();
+                    let bytes =
+                        can::encode_frame(arb_id, extended, false, payload.len() as u8, &payload);
+                    let sent = ready
+                        && matches!(
+                            ctrl.send(TxWords::Can(bytes_to_u32(bytes))).await,
+                            Ok(TxOutcome::Sent)
+                        );
+                    schedule.done(index, start, sent);
+                }
+
+                if let Some(frame) = campaign.as_mut().and_then(|campaign| campaign.step(start)) {
+                    let ready = ctrl.is_enabled()
+                        && ctrl.mode() == TxMode::Can
+                        && !ctrl.is_playing()
+                        && !ctrl.is_overwrite_armed();
+                    let bytes = can::encode_frame(
+                        frame.arb_id,
+                        frame.extended,
+                        frame.rtr,
+                        frame.dlc,
+                        &frame.data,
+                    );
+                    let sent = ready
+                        && matches!(
+                            ctrl.send(TxWords::Can(bytes_to_u32(bytes))).await,
+                            Ok(TxOutcome::Sent)
+                        );
+                    campaign.as_mut().unwrap().done(sent);
+                }
+
+                if let Some(words) = replay.as_mut().and_then(|replay| replay.due(start)) {
+                    let ready = ctrl.is_enabled()
+                        && ctrl.mode() == TxMode::Can
+                        && !ctrl.is_playing()
+                        && !ctrl.is_overwrite_armed();
+                    let sent = ready && matches!(ctrl.send(words).await, Ok(TxOutcome::Sent));
+                    replay.as_mut().unwrap().done(sent);
+                }
+
+                continue;
+            }
+        };
+
+        match command {
             TxCommand::EnableDisable(enabled) => {
                 debug!("Tx EnableDisable {}", enabled);
 
@@ -142,15 +250,311 @@ pub async fn tx_task(
             TxCommand::Send(words) => {
                 debug!("Tx Send {:?}", words.mode());
 
-                if ctrl.is_enabled() {
-                    ctrl.send(words).await;
-                    tx_ack.signal(Ok(RpcResult::TxSend));
+                if ctrl.is_playing() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is playing, stop it first!",
+                    ))));
+                } else if ctrl.is_overwrite_armed() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx overwrite is armed!",
+                    ))));
+                } else if ctrl.is_enabled() {
+                    let outcome = ctrl
+                        .send(words)
+                        .await
+                        .map_err(|err| {
+                            RpcError::ErrorMismatchDataType(
+                                String::from("CAN frame"),
+                                defmt::format!("{}", err),
+                            )
+                        })
+                        .map(RpcResult::TxSend);
+                    tx_ack.signal(outcome);
                 } else {
                     tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
                         "tx is not enabled!",
                     ))));
                 }
             }
+            TxCommand::Replay(frames) => {
+                debug!("Tx Replay {} frames", frames.len());
+
+                if replay.as_ref().is_some_and(|replay| !replay.is_finished()) {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is replaying, stop it first!",
+                    ))));
+                } else if ctrl.is_overwrite_armed() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx overwrite is armed!",
+                    ))));
+                } else if ctrl.is_enabled() && ctrl.mode() == TxMode::Can {
+                    replay = Some(Replay::new(frames, Instant::now()));
+                    tx_ack.signal(Ok(RpcResult::TxReplay));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled in can mode!",
+                    ))));
+                }
+            }
+            TxCommand::ReplayStop => {
+                debug!("Tx ReplayStop");
+
+                let outcome = match replay.take() {
+                    Some(mut replay) => {
+                        replay.stop();
+                        Ok(RpcResult::TxReplayStop(replay.report()))
+                    }
+                    None => Err(RpcError::ErrorDataRace(String::from("No replay"))),
+                };
+                tx_ack.signal(outcome);
+            }
+            TxCommand::ReplayRunning => {
+                debug!("Tx ReplayRunning");
+
+                let running = replay.as_ref().is_some_and(|replay| !replay.is_finished());
+                tx_ack.signal(Ok(RpcResult::TxReplayRunning(running)));
+            }
+            TxCommand::Fuzz(config) => {
+                debug!(
+                    "Tx Fuzz {} frames, seed {=u64:#x}",
+                    config.count, config.seed
+                );
+
+                if campaign
+                    .as_ref()
+                    .is_some_and(|campaign| !campaign.is_finished())
+                {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "fuzz is running, stop it first!",
+                    ))));
+                } else if ctrl.is_overwrite_armed() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx overwrite is armed!",
+                    ))));
+                } else if ctrl.is_enabled() && ctrl.mode() == TxMode::Can {
+                    campaign = Some(Campaign::new(config, Instant::now()));
+                    tx_ack.signal(Ok(RpcResult::TxFuzz));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled in can mode!",
+                    ))));
+                }
+            }
+            TxCommand::FuzzStop => {
+                debug!("Tx FuzzStop");
+
+                let outcome = match campaign.take() {
+                    Some(mut campaign) => {
+                        campaign.stop();
+                        Ok(RpcResult::TxFuzzStop(campaign.report()))
+                    }
+                    None => Err(RpcError::ErrorDataRace(String::from("No fuzz run"))),
+                };
+                tx_ack.signal(outcome);
+            }
+            TxCommand::FuzzRunning => {
+                debug!("Tx FuzzRunning");
+
+                let running = campaign
+                    .as_ref()
+                    .is_some_and(|campaign| !campaign.is_finished());
+                tx_ack.signal(Ok(RpcResult::TxFuzzRunning(running)));
+            }
+            TxCommand::Loop(samples) => {
+                debug!("Tx Loop {} samples", samples.len());
+
+                if ctrl.is_enabled() && ctrl.mode() == TxMode::Inject {
+                    ctrl.send_loop(samples);
+                    tx_ack.signal(Ok(RpcResult::TxLoop));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled in inject mode!",
+                    ))));
+                }
+            }
+            TxCommand::Stream => {
+                debug!("Tx Stream");
+
+                if ctrl.is_enabled() && ctrl.mode() == TxMode::Inject {
+                    ctrl.stream();
+                    tx_ack.signal(Ok(RpcResult::TxStream));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled in inject mode!",
+                    ))));
+                }
+            }
+            TxCommand::Stop => {
+                debug!("Tx Stop");
+
+                let (samples, underruns) = ctrl.stop();
+                tx_ack.signal(Ok(RpcResult::TxStop(samples, underruns)));
+            }
+            TxCommand::SetRetries(retries) => {
+                debug!("Tx SetRetries {}", retries);
+                ctrl.set_retries(retries);
+                tx_ack.signal(Ok(RpcResult::TxSetRetries));
+            }
+            TxCommand::ArmOverwrite(delay, width) => {
+                debug!("Tx ArmOverwrite {} {}", delay, width);
+
+                if ctrl.is_enabled() && ctrl.mode() == TxMode::Can {
+                    let outcome = ctrl
+                        .arm_overwrite(delay, width)
+                        .map_err(|err| {
+                            RpcError::ErrorArithmetic(defmt::format!("Invalid overwrite: {}", err))
+                        })
+                        .map(|_| RpcResult::TxArmOverwrite);
+                    tx_ack.signal(outcome);
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled in can mode!",
+                    ))));
+                }
+            }
+            TxCommand::ArmAck => {
+                debug!("Tx ArmAck");
+
+                if ctrl.is_enabled() && ctrl.mode() == TxMode::Can {
+                    ctrl.arm_ack();
+                    tx_ack.signal(Ok(RpcResult::TxArmOverwrite));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled in can mode!",
+                    ))));
+                }
+            }
+            TxCommand::DisarmOverwrite => {
+                debug!("Tx DisarmOverwrite");
+                ctrl.disarm_overwrite();
+                tx_ack.signal(Ok(RpcResult::TxDisarmOverwrite));
+            }
+            TxCommand::React(trigger, words) => {
+                debug!("Tx React {:?}", trigger);
+
+                if ctrl.is_playing() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is playing, stop it first!",
+                    ))));
+                } else if ctrl.is_overwrite_armed() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx overwrite is armed!",
+                    ))));
+                } else if ctrl.is_enabled() && ctrl.mode() == words.mode() {
+                    reaction = Some((words, ReactReport::default()));
+                    react::arm(trigger);
+                    tx_ack.signal(Ok(RpcResult::TxReact));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled in the payload's mode!",
+                    ))));
+                }
+            }
+            TxCommand::ReactStop => {
+                debug!("Tx ReactStop");
+
+                let matched = react::disarm();
+                let outcome = match reaction.take() {
+                    Some((_, report)) => {
+                        Ok(RpcResult::TxReactStop(ReactReport { matched, ..report }))
+                    }
+                    None => Err(RpcError::ErrorDataRace(String::from("No reaction armed"))),
+                };
+                tx_ack.signal(outcome);
+            }
+            TxCommand::Schedule(config) => {
+                debug!(
+                    "Tx Schedule {=u32:#x} every {}ms",
+                    config.arb_id, config.period
+                );
+                schedule.add(config, Instant::now());
+                tx_ack.signal(Ok(RpcResult::TxSchedule));
+            }
+            TxCommand::ScheduleEnable(arb_id, enabled) => {
+                debug!("Tx ScheduleEnable {=u32:#x} {}", arb_id, enabled);
+
+                if schedule.set_enabled(arb_id, enabled, Instant::now()) {
+                    tx_ack.signal(Ok(RpcResult::TxScheduleEnable));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "No such scheduled frame",
+                    ))));
+                }
+            }
+            TxCommand::Unschedule(arb_id) => {
+                debug!("Tx Unschedule {=u32:#x}", arb_id);
+
+                if schedule.remove(arb_id) {
+                    tx_ack.signal(Ok(RpcResult::TxUnschedule));
+                } else {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "No such scheduled frame",
+                    ))));
+                }
+            }
+            TxCommand::ScheduleStats => {
+                debug!("Tx ScheduleStats");
+                tx_ack.signal(Ok(RpcResult::TxScheduleStats(schedule.stats())));
+            }
+            TxCommand::Glitch(glitch) => {
+                debug!("Tx Glitch {:?}", glitch);
+
+                if ctrl.is_playing() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is playing, stop it first!",
+                    ))));
+                } else if ctrl.is_overwrite_armed() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx overwrite is armed!",
+                    ))));
+                } else if !ctrl.is_enabled() {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "tx is not enabled!",
+                    ))));
+                } else if matches!(glitch.trigger, Trigger::Sao(_))
+                    && !sao_trigger.state.lock().await.direction
+                {
+                    tx_ack.signal(Err(RpcError::ErrorDataRace(String::from(
+                        "sao gpio 1 is not an input!",
+                    ))));
+                } else {
+                    let outcome = run_glitch(&mut ctrl, &sao_trigger, glitch).await;
+                    ctrl.disarm_overwrite();
+                    tx_ack.signal(outcome);
+                }
+            }
         }
     }
 }
+
+/// Arms the glitch and waits for its trigger, for the configured timeout.
+async fn run_glitch(
+    ctrl: &mut TxController<'_, PIO1>,
+    sao_trigger: &InnerPin<CriticalSectionRawMutex>,
+    glitch: Glitch,
+) -> Result<RpcResult, RpcError> {
+    ctrl.arm_glitch(glitch.code, glitch.delay, glitch.width)
+        .map_err(|err| RpcError::ErrorArithmetic(defmt::format!("Invalid glitch: {}", err)))?;
+
+    // NOTE: Edges from before the glitch was armed do not count.
+    sao_trigger.clear();
+
+    let fired = with_timeout(Duration::from_millis(glitch.timeout), async {
+        match glitch.trigger {
+            Trigger::Software => ctrl.fire_glitch(),
+            Trigger::Sao(edge) => {
+                let level = edge == Edge::Rising;
+                while sao_trigger.wait().await != level {}
+                ctrl.fire_glitch();
+            }
+            // The receiver's matcher raises the trigger.
+            Trigger::Can(_) => {}
+        }
+
+        ctrl.wait_glitch().await;
+    })
+    .await
+    .is_ok();
+
+    Ok(RpcResult::TxGlitch(fired))
+}
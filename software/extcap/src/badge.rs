@@ -0,0 +1,281 @@
+//! The badge's two serial ports: the REPL, which sets the receiver up, and the stream, which
+//! carries the received frames.
+
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Receiver modes, one extcap interface each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Can,
+    Nmea0183,
+    Modbus,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 3] = [Mode::Can, Mode::Nmea0183, Mode::Modbus];
+
+    /// The mode's name in `rx::set_mode`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Can => "can",
+            Mode::Nmea0183 => "nmea0183",
+            Mode::Modbus => "modbus",
+        }
+    }
+
+    /// The pcap link type the badge streams the mode as.
+    pub fn link_type(&self) -> u16 {
+        match self {
+            Mode::Can => 227,
+            Mode::Nmea0183 => 147,
+            Mode::Modbus => 148,
+        }
+    }
+
+    pub fn default_termination(&self) -> Termination {
+        match self {
+            Mode::Can | Mode::Nmea0183 => Termination::R120,
+            Mode::Modbus => Termination::R220,
+        }
+    }
+}
+
+/// Termination resistor settings, see the `trx::TERM_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    Open,
+    R120,
+    R220,
+    R13,
+}
+
+impl Termination {
+    pub const ALL: [Termination; 4] = [
+        Termination::Open,
+        Termination::R120,
+        Termination::R220,
+        Termination::R13,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Termination::Open => "open",
+            Termination::R120 => "120",
+            Termination::R220 => "220",
+            Termination::R13 => "13",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Termination> {
+        Termination::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    fn constant(&self) -> &'static str {
+        match self {
+            Termination::Open => "OPEN",
+            Termination::R120 => "120R",
+            Termination::R220 => "220R",
+            Termination::R13 => "13R",
+        }
+    }
+}
+
+/// The REPL lines setting the receiver up for a capture.
+pub fn setup_script(mode: Mode, baud: Option<u32>, termination: Termination) -> Vec<String> {
+    let term = termination.constant();
+    let mut script = vec![
+        "rx::disable()".to_string(),
+        format!("trx::set_term(trx::TERM_{term}_0, trx::TERM_{term}_1)"),
+        format!("rx::set_mode(\"{}\")", mode.name()),
+    ];
+
+    // Only the CAN rate is settable, the serial modes keep theirs (or `rx::autobaud`'s).
+    if let (Mode::Can, Some(baud)) = (mode, baud) {
+        script.push(format!("rx::set_baud({baud})"));
+    }
+
+    script.push("rx::enable()".to_string());
+
+    script
+}
+
+/// Runs the lines on the REPL, failing on the first one that does not evaluate to `()`.
+pub fn run_script<P: Read + Write>(repl: &mut P, script: &[String]) -> io::Result<()> {
+    // Ctrl-C, which starts a session or clears a pending input, then skip the banner.
+    repl.write_all(b"\x03")?;
+    thread::sleep(Duration::from_millis(300));
+    drain(repl)?;
+
+    // The receiver and transmitter belong to the user once they take control of the apps, which
+    // fails when they already have it.
+    repl.write_all(b"sys::assume_control()\r")?;
+    read_result(repl)?;
+
+    for line in script {
+        repl.write_all(format!("{line}\r").as_bytes())?;
+
+        let result = read_result(repl)?;
+
+        if result != "()" {
+            return Err(io::Error::other(format!("`{line}` failed: {result}")));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads until the REPL prints a result (`=> ...`).
+fn read_result<P: Read>(repl: &mut P) -> io::Result<String> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut output = String::new();
+    let mut buf = [0; 256];
+
+    while Instant::now() < deadline {
+        let len = match repl.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err),
+        };
+        output.push_str(&String::from_utf8_lossy(&buf[..len]));
+
+        // Complete lines only.
+        let lines = output.rsplit_once('\n').map_or("", |(lines, _)| lines);
+        let result = lines
+            .lines()
+            .find_map(|line| line.trim_start_matches("repl> ").strip_prefix("=> "));
+
+        if let Some(result) = result {
+            return Ok(result.trim().to_string());
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "no answer from the REPL",
+    ))
+}
+
+fn drain<P: Read>(repl: &mut P) -> io::Result<()> {
+    let mut buf = [0; 256];
+
+    loop {
+        match repl.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Opens one of the badge's serial ports, raising DTR, without which the badge drops output.
+pub fn open(path: &str, timeout: Duration) -> io::Result<Box<dyn serialport::SerialPort>> {
+    let mut port = serialport::new(path, 115_200).timeout(timeout).open()?;
+    port.write_data_terminal_ready(true)?;
+
+    Ok(port)
+}
+
+mod test {
+    #[test]
+    fn test_setup_script() {
+        use super::{setup_script, Mode, Termination};
+
+        assert_eq!(
+            setup_script(Mode::Can, Some(500_000), Termination::R120),
+            [
+                "rx::disable()",
+                "trx::set_term(trx::TERM_120R_0, trx::TERM_120R_1)",
+                "rx::set_mode(\"can\")",
+                "rx::set_baud(500000)",
+                "rx::enable()",
+            ]
+        );
+        // The baud is left alone outside of "can".
+        assert_eq!(
+            setup_script(Mode::Modbus, Some(9600), Termination::Open),
+            [
+                "rx::disable()",
+                "trx::set_term(trx::TERM_OPEN_0, trx::TERM_OPEN_1)",
+                "rx::set_mode(\"modbus\")",
+                "rx::enable()",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_script() {
+        use super::run_script;
+
+        /// Answers every line with the next of its results.
+        struct Repl {
+            results: Vec<&'static str>,
+            output: Vec<u8>,
+            input: Vec<u8>,
+        }
+
+        impl std::io::Read for Repl {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.output.is_empty() {
+                    return Err(std::io::ErrorKind::TimedOut.into());
+                }
+
+                let len = buf.len().min(self.output.len());
+                buf[..len].copy_from_slice(&self.output[..len]);
+                self.output.drain(..len);
+
+                Ok(len)
+            }
+        }
+
+        impl std::io::Write for Repl {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.input.extend_from_slice(buf);
+
+                if buf.ends_with(b"\r") {
+                    let result = self.results.remove(0);
+                    self.output
+                        .extend_from_slice(format!("repl> => {result}\n").as_bytes());
+                } else {
+                    self.output.extend_from_slice(b"banner\nrepl> ");
+                }
+
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let script = ["rx::disable()".to_string(), "rx::enable()".to_string()];
+
+        let mut repl = Repl {
+            results: vec!["()", "()", "()"],
+            output: Vec::new(),
+            input: Vec::new(),
+        };
+        run_script(&mut repl, &script).unwrap();
+        assert_eq!(
+            repl.input,
+            b"\x03sys::assume_control()\rrx::disable()\rrx::enable()\r"
+        );
+
+        let mut repl = Repl {
+            results: vec![
+                "App context is already owned!",
+                "()",
+                "Runtime error: Rx is busy",
+            ],
+            output: Vec::new(),
+            input: Vec::new(),
+        };
+        let err = run_script(&mut repl, &script).unwrap_err();
+        assert!(err.to_string().contains("Rx is busy"));
+    }
+}
@@ -0,0 +1,188 @@
+//! A stand-in for the badge, streaming the frames of a candump log (e.g. from
+//! `rx::candump_stop`) as the firmware would, to try the capture path without hardware.
+
+use crate::frame::Frame;
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    thread,
+    time::{Duration, Instant},
+};
+
+const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+
+/// Parses a candump log (`(seconds.micros) can0 123#DEADBEEF` lines) into SocketCAN frames.
+pub fn parse_candump(log: &str) -> io::Result<Vec<Frame>> {
+    log.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            parse_line(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {} is not a candump frame", i + 1),
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Frame> {
+    let mut fields = line.split_whitespace();
+    let timestamp = fields.next()?.strip_prefix('(')?.strip_suffix(')')?;
+    let _interface = fields.next()?;
+    let (id, data) = fields.next()?.split_once('#')?;
+
+    let (secs, fraction) = timestamp.split_once('.')?;
+    let fraction = fraction.get(..fraction.len().min(6))?;
+    let micros = fraction.parse::<u64>().ok()? * 10_u64.pow(6 - fraction.len() as u32);
+    let timestamp = secs.parse::<u64>().ok()? * 1_000_000 + micros;
+
+    if id.is_empty() || id.len() > 8 {
+        return None;
+    }
+
+    let mut can_id = u32::from_str_radix(id, 16).ok()?;
+
+    if can_id > 0x1FFF_FFFF || (id.len() <= 3 && can_id > 0x7FF) {
+        return None;
+    }
+
+    if id.len() > 3 {
+        can_id |= CAN_EFF_FLAG;
+    }
+
+    let data = match data.strip_prefix('R') {
+        Some(_) => {
+            can_id |= CAN_RTR_FLAG;
+            Vec::new()
+        }
+        None => parse_hex(data)?,
+    };
+
+    if data.len() > 8 {
+        return None;
+    }
+
+    let mut packet = vec![0; 16];
+    packet[0..4].copy_from_slice(&can_id.to_be_bytes());
+    packet[4] = data.len() as u8;
+    packet[8..8 + data.len()].copy_from_slice(&data);
+
+    Some(Frame {
+        link_type: LINKTYPE_CAN_SOCKETCAN,
+        timestamp,
+        packet,
+    })
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Reads as the encoded stream of the given frames, then as end of file.
+pub struct Fake {
+    frames: VecDeque<Frame>,
+    /// Whether to keep the original spacing of the frames.
+    realtime: bool,
+    start: Option<(Instant, u64)>,
+    pending: Vec<u8>,
+}
+
+impl Fake {
+    pub fn new(frames: Vec<Frame>, realtime: bool) -> Self {
+        Self {
+            frames: frames.into(),
+            realtime,
+            start: None,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl Read for Fake {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let Some(frame) = self.frames.pop_front() else {
+                return Ok(0);
+            };
+
+            if self.realtime {
+                let (start, first) = *self.start.get_or_insert((Instant::now(), frame.timestamp));
+                let due = start + Duration::from_micros(frame.timestamp.saturating_sub(first));
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+            }
+
+            self.pending = frame.encode();
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+
+        Ok(len)
+    }
+}
+
+mod test {
+    #[test]
+    fn test_parse_candump() {
+        use super::parse_candump;
+
+        let frames = parse_candump(
+            "(1.000001) can0 123#DEADBEEF\n\n(1.5) can0 18FEF100#R\n(2.000000) can0 7FF#\n",
+        )
+        .unwrap();
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].timestamp, 1_000_001);
+        assert_eq!(
+            frames[0].packet,
+            [0, 0, 0x01, 0x23, 4, 0, 0, 0, 0xDE, 0xAD, 0xBE, 0xEF, 0, 0, 0, 0]
+        );
+        assert_eq!(frames[1].timestamp, 1_500_000);
+        // Extended and remote.
+        assert_eq!(frames[1].packet[0..5], [0xD8, 0xFE, 0xF1, 0x00, 0]);
+        assert_eq!(frames[2].packet[0..5], [0, 0, 0x07, 0xFF, 0]);
+
+        assert!(parse_candump("(1.0) can0 800#00").is_err());
+        assert!(parse_candump("can0 123#00").is_err());
+    }
+
+    #[test]
+    fn test_fake_stream() {
+        use super::{parse_candump, Fake};
+        use crate::frame::Decoder;
+        use std::io::Read;
+
+        let frames = parse_candump("(0.1) can0 001#01\n(0.2) can0 002#0202\n").unwrap();
+        let mut fake = Fake::new(frames.clone(), false);
+        let mut decoder = Decoder::new();
+        let mut decoded = Vec::new();
+        // Smaller than a frame, to split them.
+        let mut buf = [0; 5];
+
+        loop {
+            let len = fake.read(&mut buf).unwrap();
+
+            if len == 0 {
+                break;
+            }
+
+            decoder.push(&buf[..len]);
+            decoded.extend(decoder.next_frame());
+        }
+
+        assert_eq!(decoded, frames);
+    }
+}
@@ -0,0 +1,172 @@
+//! The badge's stream framing, see `apps::rx::stream` in the firmware.
+//!
+//! A 14-byte header (`0xDC 0x33` magic, link type, packet length and timestamp in microseconds,
+//! all little-endian) followed by the packet.
+
+pub const MAGIC: [u8; 2] = [0xDC, 0x33];
+
+pub const HEADER_LEN: usize = 14;
+
+/// Longest packet the badge sends, anything longer is taken as a false sync.
+pub const MAX_PACKET: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// pcap `LINKTYPE_*`.
+    pub link_type: u16,
+    /// Microseconds since the badge booted.
+    pub timestamp: u64,
+    pub packet: Vec<u8>,
+}
+
+impl Frame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.packet.len());
+
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&self.link_type.to_le_bytes());
+        buf.extend_from_slice(&(self.packet.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+        buf.extend_from_slice(&self.packet);
+
+        buf
+    }
+}
+
+/// Reassembles frames from the byte stream, resynchronizing on the magic after garbage.
+#[derive(Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    /// Bytes skipped while looking for the magic.
+    skipped: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The next complete frame, if any.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        loop {
+            let start = match self.buf.windows(2).position(|w| w == MAGIC) {
+                Some(start) => start,
+                None => {
+                    // Keep a trailing first half of the magic.
+                    let keep = usize::from(self.buf.last() == Some(&MAGIC[0]));
+                    self.discard(self.buf.len() - keep);
+                    return None;
+                }
+            };
+            self.discard(start);
+
+            if self.buf.len() < HEADER_LEN {
+                return None;
+            }
+
+            let link_type = u16::from_le_bytes([self.buf[2], self.buf[3]]);
+            let len = u16::from_le_bytes([self.buf[4], self.buf[5]]) as usize;
+
+            if len > MAX_PACKET {
+                self.discard(1);
+                continue;
+            }
+
+            if self.buf.len() < HEADER_LEN + len {
+                return None;
+            }
+
+            let mut timestamp = [0; 8];
+            timestamp.copy_from_slice(&self.buf[6..HEADER_LEN]);
+            let packet = self.buf[HEADER_LEN..HEADER_LEN + len].to_vec();
+            self.buf.drain(..HEADER_LEN + len);
+
+            return Some(Frame {
+                link_type,
+                timestamp: u64::from_le_bytes(timestamp),
+                packet,
+            });
+        }
+    }
+
+    fn discard(&mut self, n: usize) {
+        self.skipped += n;
+        self.buf.drain(..n);
+    }
+}
+
+mod test {
+    #[test]
+    fn test_round_trip() {
+        use super::{Decoder, Frame};
+
+        let frames = [
+            Frame {
+                link_type: 227,
+                timestamp: 0x0102_0304_0506,
+                packet: vec![0x23, 0x01, 0, 0, 2, 0, 0, 0, 0xDE, 0xAD, 0, 0, 0, 0, 0, 0],
+            },
+            Frame {
+                link_type: 147,
+                timestamp: 42,
+                packet: b"$GPGLL,4916.45,N*2D\r\n".to_vec(),
+            },
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut decoded = Vec::new();
+
+        // Byte by byte, to hit every partial state.
+        for byte in frames.iter().flat_map(Frame::encode) {
+            decoder.push(&[byte]);
+            decoded.extend(decoder.next_frame());
+        }
+
+        assert_eq!(decoded, frames);
+        assert_eq!(decoder.skipped(), 0);
+    }
+
+    #[test]
+    fn test_header_layout() {
+        use super::Frame;
+
+        let frame = Frame {
+            link_type: 148,
+            timestamp: 1,
+            packet: vec![0xAA],
+        };
+
+        assert_eq!(
+            frame.encode(),
+            [0xDC, 0x33, 148, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0xAA]
+        );
+    }
+
+    #[test]
+    fn test_resync() {
+        use super::{Decoder, Frame};
+
+        let frame = Frame {
+            link_type: 148,
+            timestamp: 7,
+            packet: vec![1, 2, 3],
+        };
+
+        let mut decoder = Decoder::new();
+        // Garbage, a false magic with an oversized length, then the frame.
+        decoder.push(&[0x00, 0xDC, 0xDC, 0x33, 0x00, 0x00, 0xFF, 0xFF]);
+        decoder.push(&frame.encode());
+
+        assert_eq!(decoder.next_frame(), Some(frame));
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.skipped(), 2 + 6);
+    }
+}
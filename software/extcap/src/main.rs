@@ -0,0 +1,380 @@
+//! Wireshark extcap for live captures from the MHV DC33 badge.
+//!
+//! Copy (or link) the binary into Wireshark's extcap folder (Help > About > Folders), and the
+//! badge's receiver shows up as one interface per mode. Starting a capture sets the receiver up
+//! over the REPL serial port, then decodes the stream serial port into pcap on Wireshark's FIFO.
+//!
+//! The `dc33-replay` interface streams a candump log instead, to try it all without a badge.
+
+mod badge;
+mod fake;
+mod frame;
+mod pcap;
+
+use badge::{Mode, Termination};
+use fake::Fake;
+use frame::Decoder;
+use pcap::PcapWriter;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    process::ExitCode,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const REPLAY: &str = "dc33-replay";
+
+/// The ports of a badge alone on Linux: logger, REPL, then stream.
+const DEFAULT_REPL: &str = "/dev/ttyACM1";
+const DEFAULT_STREAM: &str = "/dev/ttyACM2";
+
+const DEFAULT_BAUD: u32 = 250_000;
+
+/// Options taking a value, the others are flags.
+const VALUED: [&str; 9] = [
+    "--extcap-interface",
+    "--extcap-version",
+    "--extcap-capture-filter",
+    "--fifo",
+    "--repl",
+    "--stream",
+    "--baud",
+    "--termination",
+    "--log",
+];
+
+#[derive(Debug, Default)]
+struct Args {
+    interfaces: bool,
+    dlts: bool,
+    config: bool,
+    capture: bool,
+    interface: Option<String>,
+    fifo: Option<String>,
+    repl: Option<String>,
+    stream: Option<String>,
+    baud: Option<u32>,
+    termination: Option<Termination>,
+    log: Option<String>,
+    realtime: bool,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None if VALUED.contains(&arg.as_str()) => {
+                let value = args.next().ok_or(format!("{arg} needs a value"))?;
+                (arg, Some(value))
+            }
+            None => (arg, None),
+        };
+
+        match (name.as_str(), value) {
+            ("--extcap-interfaces", None) => parsed.interfaces = true,
+            ("--extcap-dlts", None) => parsed.dlts = true,
+            ("--extcap-config", None) => parsed.config = true,
+            ("--capture", None) => parsed.capture = true,
+            ("--realtime", None) => parsed.realtime = true,
+            ("--extcap-interface", value) => parsed.interface = value,
+            ("--fifo", value) => parsed.fifo = value,
+            ("--repl", value) => parsed.repl = value,
+            ("--stream", value) => parsed.stream = value,
+            ("--log", value) => parsed.log = value,
+            ("--baud", Some(value)) => {
+                parsed.baud = Some(value.parse().map_err(|_| format!("bad baud {value}"))?)
+            }
+            ("--termination", Some(value)) => {
+                parsed.termination =
+                    Some(Termination::from_name(&value).ok_or(format!("bad termination {value}"))?)
+            }
+            // Not supported, but passed along by Wireshark.
+            ("--extcap-version" | "--extcap-capture-filter", _) => {}
+            (name, _) => return Err(format!("unknown option {name}")),
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn interface_name(mode: Mode) -> String {
+    format!("dc33-{}", mode.name())
+}
+
+fn mode_of(interface: &str) -> Option<Mode> {
+    Mode::ALL
+        .into_iter()
+        .find(|&mode| interface_name(mode) == interface)
+}
+
+/// The link type of an interface, the replay only carries CAN.
+fn link_type_of(interface: &str) -> Option<u16> {
+    match interface {
+        REPLAY => Some(Mode::Can.link_type()),
+        _ => mode_of(interface).map(|mode| mode.link_type()),
+    }
+}
+
+fn print_interfaces() {
+    println!("extcap {{version={VERSION}}}{{display=MHV DC33 badge}}");
+
+    for mode in Mode::ALL {
+        println!(
+            "interface {{value={}}}{{display=DC33 badge, {} receiver}}",
+            interface_name(mode),
+            mode.name()
+        );
+    }
+
+    println!("interface {{value={REPLAY}}}{{display=DC33 badge, candump log replay}}");
+}
+
+fn print_dlts(interface: &str) -> Result<(), String> {
+    let link_type = link_type_of(interface).ok_or(format!("unknown interface {interface}"))?;
+    let (name, display) = match link_type {
+        227 => ("CAN_SOCKETCAN", "SocketCAN"),
+        147 => ("USER0", "NMEA-0183 (DLT_USER0)"),
+        _ => ("USER1", "Modbus RTU (DLT_USER1)"),
+    };
+
+    println!("dlt {{number={link_type}}}{{name={name}}}{{display={display}}}");
+
+    Ok(())
+}
+
+fn print_config(interface: &str) -> Result<(), String> {
+    if interface == REPLAY {
+        println!("arg {{number=0}}{{call=--log}}{{display=candump log}}{{type=fileselect}}{{mustexist=true}}{{required=true}}");
+        println!("arg {{number=1}}{{call=--realtime}}{{display=Keep the original timing}}{{type=boolflag}}{{default=true}}");
+
+        return Ok(());
+    }
+
+    let mode = mode_of(interface).ok_or(format!("unknown interface {interface}"))?;
+
+    println!("arg {{number=0}}{{call=--repl}}{{display=REPL port}}{{type=string}}{{default={DEFAULT_REPL}}}");
+    println!("arg {{number=1}}{{call=--stream}}{{display=Stream port}}{{type=string}}{{default={DEFAULT_STREAM}}}");
+    println!("arg {{number=2}}{{call=--termination}}{{display=Termination}}{{type=selector}}");
+
+    for termination in Termination::ALL {
+        let display = match termination {
+            Termination::Open => "Open",
+            Termination::R120 => "120R",
+            Termination::R220 => "220R",
+            Termination::R13 => "13R",
+        };
+        println!(
+            "value {{arg=2}}{{value={}}}{{display={display}}}{{default={}}}",
+            termination.name(),
+            termination == mode.default_termination()
+        );
+    }
+
+    if mode == Mode::Can {
+        println!("arg {{number=3}}{{call=--baud}}{{display=Bit rate}}{{type=integer}}{{range=10000,1000000}}{{default={DEFAULT_BAUD}}}");
+    }
+
+    Ok(())
+}
+
+fn unix_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_micros() as u64)
+}
+
+/// Decodes the stream into pcap until it ends or the sink is closed, keeping the frames of the
+/// given link type. Timestamps are moved so the first frame is now.
+fn capture<R: Read, W: Write>(source: &mut R, sink: W, link_type: u16) -> io::Result<usize> {
+    let mut pcap = PcapWriter::new(sink, link_type)?;
+    let mut decoder = Decoder::new();
+    let mut epoch = None;
+    let mut count = 0;
+    let mut buf = [0; 4096];
+
+    loop {
+        let len = match source.read(&mut buf) {
+            Ok(0) => return Ok(count),
+            Ok(len) => len,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+
+        let skipped = decoder.skipped();
+        decoder.push(&buf[..len]);
+
+        while let Some(frame) = decoder.next_frame() {
+            if frame.link_type != link_type {
+                continue;
+            }
+
+            let epoch = *epoch.get_or_insert_with(|| unix_micros().saturating_sub(frame.timestamp));
+
+            match pcap.write_packet(epoch + frame.timestamp, &frame.packet) {
+                Ok(()) => count += 1,
+                // Wireshark stopped the capture.
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(count),
+                Err(err) => return Err(err),
+            }
+        }
+
+        if decoder.skipped() > skipped {
+            eprintln!(
+                "dc33-extcap: lost sync, skipped {} bytes",
+                decoder.skipped() - skipped
+            );
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    if args.interfaces {
+        print_interfaces();
+        return Ok(());
+    }
+
+    let interface = args.interface.as_deref().ok_or("no --extcap-interface")?;
+
+    if args.dlts {
+        return print_dlts(interface);
+    }
+
+    if args.config {
+        return print_config(interface);
+    }
+
+    if !args.capture {
+        return Err("nothing to do".to_string());
+    }
+
+    let link_type = link_type_of(interface).ok_or(format!("unknown interface {interface}"))?;
+    let fifo = args.fifo.as_deref().ok_or("no --fifo")?;
+    let sink = OpenOptions::new()
+        .write(true)
+        .open(fifo)
+        .map_err(|err| format!("{fifo}: {err}"))?;
+
+    let captured = if interface == REPLAY {
+        let path = args.log.as_deref().ok_or("no --log")?;
+        let log = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+        let frames = fake::parse_candump(&log).map_err(|err| format!("{path}: {err}"))?;
+
+        capture(&mut Fake::new(frames, args.realtime), sink, link_type)
+    } else {
+        let mode = mode_of(interface).ok_or(format!("unknown interface {interface}"))?;
+        let repl_path = args.repl.as_deref().unwrap_or(DEFAULT_REPL);
+        let stream_path = args.stream.as_deref().unwrap_or(DEFAULT_STREAM);
+        let termination = args.termination.unwrap_or(mode.default_termination());
+
+        // Open the stream first, so nothing is dropped once the receiver is enabled.
+        let mut stream = badge::open(stream_path, Duration::from_millis(100))
+            .map_err(|err| format!("{stream_path}: {err}"))?;
+        let mut repl = badge::open(repl_path, Duration::from_millis(100))
+            .map_err(|err| format!("{repl_path}: {err}"))?;
+
+        let script =
+            badge::setup_script(mode, Some(args.baud.unwrap_or(DEFAULT_BAUD)), termination);
+        badge::run_script(&mut repl, &script).map_err(|err| format!("{repl_path}: {err}"))?;
+
+        capture(&mut stream, sink, link_type)
+    };
+
+    captured.map(|_| ()).map_err(|err| err.to_string())
+}
+
+fn main() -> ExitCode {
+    let result = parse_args(env::args().skip(1)).and_then(run);
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            // Wireshark shows what extcaps print on stderr.
+            eprintln!("dc33-extcap: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+mod test {
+    #[test]
+    fn test_parse_args() {
+        use super::{parse_args, Termination};
+
+        let args = [
+            "--capture",
+            "--extcap-interface",
+            "dc33-can",
+            "--fifo=/tmp/fifo",
+            "--extcap-version=4.2",
+            "--baud",
+            "500000",
+            "--termination",
+            "open",
+        ];
+        let args = parse_args(args.map(String::from)).unwrap();
+
+        assert!(args.capture);
+        assert_eq!(args.interface.as_deref(), Some("dc33-can"));
+        assert_eq!(args.fifo.as_deref(), Some("/tmp/fifo"));
+        assert_eq!(args.baud, Some(500_000));
+        assert_eq!(args.termination, Some(Termination::Open));
+
+        assert!(parse_args(["--baud".to_string()]).is_err());
+        assert!(parse_args(["--termination=47".to_string()]).is_err());
+        assert!(parse_args(["--bogus".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_capture_replay() {
+        use super::{capture, fake::parse_candump, fake::Fake, frame::Frame};
+
+        let frames = parse_candump("(10.0) can0 001#01\n(10.25) can0 002#0202\n").unwrap();
+        let mut stream = Vec::new();
+
+        // Frames of another link type are skipped.
+        stream.extend(frames[0].encode());
+        stream.extend(
+            Frame {
+                link_type: 147,
+                timestamp: 10_100_000,
+                packet: b"$GPGLL*00\r\n".to_vec(),
+            }
+            .encode(),
+        );
+        stream.extend(frames[1].encode());
+
+        let mut pcap = Vec::new();
+        let count = capture(&mut stream.as_slice(), &mut pcap, 227).unwrap();
+
+        assert_eq!(count, 2);
+        // The header, then two 16-byte records of 16-byte frames.
+        assert_eq!(pcap.len(), 24 + 2 * (16 + 16));
+        assert_eq!(pcap[20..24], [227, 0, 0, 0]);
+        assert_eq!(&pcap[40..56], frames[0].packet.as_slice());
+        assert_eq!(&pcap[72..88], frames[1].packet.as_slice());
+
+        // The spacing is kept.
+        let micros = |record: &[u8]| {
+            let secs = u32::from_le_bytes(record[0..4].try_into().unwrap()) as u64;
+            let micros = u32::from_le_bytes(record[4..8].try_into().unwrap()) as u64;
+            secs * 1_000_000 + micros
+        };
+        assert_eq!(micros(&pcap[56..]) - micros(&pcap[24..]), 250_000);
+
+        let mut fake = Fake::new(frames, false);
+        assert_eq!(capture(&mut fake, Vec::new(), 227).unwrap(), 2);
+    }
+}
@@ -0,0 +1,73 @@
+//! Classic pcap output, which is what Wireshark reads from an extcap FIFO.
+
+use std::io::{self, Write};
+
+const MAGIC_MICROSECONDS: u32 = 0xA1B2_C3D4;
+
+const SNAPLEN: u32 = 65535;
+
+pub struct PcapWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the global header for a capture of the given link type.
+    pub fn new(mut inner: W, link_type: u16) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+
+        header.extend_from_slice(&MAGIC_MICROSECONDS.to_le_bytes());
+        // Version 2.4.
+        header.extend_from_slice(&2_u16.to_le_bytes());
+        header.extend_from_slice(&4_u16.to_le_bytes());
+        // Time zone and accuracy, both unused.
+        header.extend_from_slice(&0_u32.to_le_bytes());
+        header.extend_from_slice(&0_u32.to_le_bytes());
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&(link_type as u32).to_le_bytes());
+
+        inner.write_all(&header)?;
+        inner.flush()?;
+
+        Ok(Self { inner })
+    }
+
+    /// Writes a packet, timestamped in microseconds since the Unix epoch, and flushes it so it
+    /// shows up live.
+    pub fn write_packet(&mut self, timestamp: u64, packet: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(16 + packet.len());
+
+        record.extend_from_slice(&((timestamp / 1_000_000) as u32).to_le_bytes());
+        record.extend_from_slice(&((timestamp % 1_000_000) as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(packet);
+
+        self.inner.write_all(&record)?;
+        self.inner.flush()
+    }
+}
+
+mod test {
+    #[test]
+    fn test_layout() {
+        use super::PcapWriter;
+
+        let mut pcap = Vec::new();
+        PcapWriter::new(&mut pcap, 227)
+            .unwrap()
+            .write_packet(1_500_000, b"abc")
+            .unwrap();
+
+        assert_eq!(pcap[0..4], [0xD4, 0xC3, 0xB2, 0xA1]);
+        assert_eq!(pcap[4..8], [2, 0, 4, 0]);
+        assert_eq!(pcap[8..16], [0; 8]);
+        assert_eq!(pcap[16..20], [0xFF, 0xFF, 0, 0]);
+        assert_eq!(pcap[20..24], [227, 0, 0, 0]);
+        // 1.5 s, then the captured and original lengths.
+        assert_eq!(pcap[24..28], [1, 0, 0, 0]);
+        assert_eq!(pcap[28..32], 500_000_u32.to_le_bytes());
+        assert_eq!(pcap[32..36], [3, 0, 0, 0]);
+        assert_eq!(pcap[36..40], [3, 0, 0, 0]);
+        assert_eq!(&pcap[40..], b"abc");
+    }
+}